@@ -0,0 +1,119 @@
+#ifndef LEAPFROG_H
+#define LEAPFROG_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result codes of the C interface. Negative values are errors.
+ */
+typedef enum LeapfrogStatus {
+  /**
+   * The call succeeded.
+   */
+  LEAPFROG_STATUS_OK = 0,
+  /**
+   * The join is exhausted, there is no current key.
+   */
+  LEAPFROG_STATUS_END = 1,
+  /**
+   * A required pointer argument was NULL.
+   */
+  LEAPFROG_STATUS_NULL_ARGUMENT = -1,
+  /**
+   * An input array is not sorted in strictly ascending order.
+   */
+  LEAPFROG_STATUS_UNSORTED_INPUT = -2,
+  /**
+   * The seek key is smaller than the current key.
+   */
+  LEAPFROG_STATUS_INVALID_SEEK = -3,
+  /**
+   * The join failed unexpectedly; the handle must not be used other than
+   * to destroy it.
+   */
+  LEAPFROG_STATUS_INTERNAL = -4,
+} LeapfrogStatus;
+
+/**
+ * Opaque join handle.
+ */
+typedef struct LeapfrogJoinHandle LeapfrogJoinHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a join over `num_sources` strictly ascending arrays, i.e. sorted
+ * and without duplicates. `sources[i]` points to `lengths[i]` keys; it may
+ * be NULL if the length is 0. On success the new handle is stored in `*out`.
+ *
+ * # Safety
+ *
+ * `sources` and `lengths` must point to `num_sources` elements each, every
+ * non-NULL `sources[i]` to `lengths[i]` readable keys, and `out` must be
+ * writable.
+ */
+LeapfrogStatus leapfrog_join_create(const int64_t *const *sources,
+                                    const size_t *lengths,
+                                    size_t num_sources,
+                                    LeapfrogJoinHandle **out);
+
+/**
+ * Stores the current key in `*out`, or returns End if the join is exhausted.
+ *
+ * # Safety
+ *
+ * `join` must be a live handle and `out` must be writable.
+ */
+LeapfrogStatus leapfrog_join_key(const LeapfrogJoinHandle *join, int64_t *out);
+
+/**
+ * Advances to the next common key. Returns End if the join is (or becomes)
+ * exhausted.
+ *
+ * # Safety
+ *
+ * `join` must be a live handle.
+ */
+LeapfrogStatus leapfrog_join_next(LeapfrogJoinHandle *join);
+
+/**
+ * Fast-forwards to the first common key >= `key`. The key must not be
+ * smaller than the current key. Returns End if no such key exists.
+ *
+ * # Safety
+ *
+ * `join` must be a live handle.
+ */
+LeapfrogStatus leapfrog_join_seek(LeapfrogJoinHandle *join, int64_t key);
+
+/**
+ * Returns true if the join is exhausted or `join` is NULL.
+ *
+ * # Safety
+ *
+ * `join` must be NULL or a live handle.
+ */
+bool leapfrog_join_at_end(const LeapfrogJoinHandle *join);
+
+/**
+ * Releases a handle. Passing NULL is a no-op.
+ *
+ * # Safety
+ *
+ * `join` must be NULL or a live handle, which must not be used afterwards.
+ */
+void leapfrog_join_destroy(LeapfrogJoinHandle *join);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LEAPFROG_H */
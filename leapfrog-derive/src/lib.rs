@@ -0,0 +1,135 @@
+//! `#[derive(Relation)]` for the leapfrog crate. Use it through the `derive`
+//! feature of leapfrog, which re-exports it as `leapfrog::Relation`.
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, parse_macro_input};
+
+/// Derives leapfrog::Relation for a struct with named fields.
+///
+/// Every field marked `#[relation(key)]` gets a sorted index in the generated
+/// `<Struct>Index` type, with two methods named after the field: `<field>()`
+/// returns a LinearIterator over its distinct keys, ready to be joined, and
+/// `<field>_rows(key)` returns the rows with that key.
+#[proc_macro_derive(Relation, attributes(relation))]
+pub fn derive_relation(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "Relation cannot be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "Relation requires named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "Relation can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut keys = Vec::new();
+    for field in fields {
+        let mut is_key = false;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("relation")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("key") {
+                    is_key = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `key`"))
+                }
+            })?;
+        }
+        if is_key {
+            keys.push((field.ident.clone().unwrap(), field.ty.clone()));
+        }
+    }
+    if keys.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Relation needs at least one field marked #[relation(key)]",
+        ));
+    }
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    let index = format_ident!("{}Index", name);
+    let doc = format!("Sorted per-field indexes over a slice of [`{name}`].");
+
+    let mut fields = Vec::new();
+    let mut inits = Vec::new();
+    let mut methods = Vec::new();
+    for (field, ty) in &keys {
+        let rows = format_ident!("{}_rows", field);
+        let distinct = format_ident!("{}_keys", field);
+        fields.push(quote! {
+            #rows: ::std::vec::Vec<&'a #name>,
+            #distinct: ::std::vec::Vec<#ty>,
+        });
+        inits.push(quote! {
+            let mut #rows: ::std::vec::Vec<&'a #name> = rows.iter().collect();
+            #rows.sort_by_key(|row| row.#field);
+            let mut #distinct: ::std::vec::Vec<#ty> = #rows.iter().map(|row| row.#field).collect();
+            #distinct.dedup();
+        });
+        let iter_doc = format!("Iterator over the distinct values of `{field}`.");
+        let rows_doc = format!("The rows whose `{field}` equals `key`.");
+        methods.push(quote! {
+            #[doc = #iter_doc]
+            pub fn #field(&self) -> ::leapfrog::LinearIterator<'_, #ty> {
+                ::leapfrog::LinearIterator::new(&self.#distinct)
+            }
+
+            #[doc = #rows_doc]
+            pub fn #rows(&self, key: #ty) -> &[&'a #name] {
+                let start = self.#rows.partition_point(|row| row.#field < key);
+                let end = self.#rows.partition_point(|row| row.#field <= key);
+                &self.#rows[start..end]
+            }
+        });
+    }
+    let names = keys.iter().flat_map(|(field, _)| {
+        [
+            format_ident!("{}_rows", field),
+            format_ident!("{}_keys", field),
+        ]
+    });
+
+    Ok(quote! {
+        #[doc = #doc]
+        #vis struct #index<'a> {
+            #(#fields)*
+        }
+
+        impl<'a> #index<'a> {
+            #(#methods)*
+        }
+
+        impl ::leapfrog::relation::Relation for #name {
+            type Index<'a> = #index<'a>;
+
+            fn index<'a>(rows: &'a [Self]) -> #index<'a> {
+                #(#inits)*
+                #index { #(#names),* }
+            }
+        }
+    })
+}
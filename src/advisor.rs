@@ -0,0 +1,361 @@
+//! Index advisor: which attribute orders of relations to materialize.
+//!
+//! A trie can only be descended in attribute order, so every atom of a
+//! query needs a copy of its relation sorted such that the attributes come
+//! in the order the query binds them (see TrieRelation::permuted()).
+//! Materializing all permutations blindly costs arity! copies per relation,
+//! though a few usually suffice: the classic example are the six access
+//! patterns of RDF triples, which three orders cover.
+//!
+//! The advisor takes a workload of accesses, each an ordered list of groups
+//! of attributes that are bound one group after the other, in any order
+//! within a group. It recommends, per relation, the fewest orders that
+//! satisfy every access, which minimizes the storage since every order of a
+//! relation costs the same.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdvisorError {
+    UnknownRelation(String),
+    /// An access names an attribute that the relation does not have, or
+    /// names an attribute twice.
+    InvalidAccess {
+        access: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for AdvisorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdvisorError::UnknownRelation(name) => write!(f, "unknown relation {name:?}"),
+            AdvisorError::InvalidAccess { access, reason } => {
+                write!(f, "invalid access {access}: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AdvisorError {}
+
+/// An access to a relation: the groups of attributes in the order they are
+/// bound. Attributes in no group are bound last.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub relation: String,
+    pub groups: Vec<Vec<usize>>,
+}
+
+/// A recommended index: the relation sorted by `order`, i.e. attribute i of
+/// the index is attribute `order[i]` of the relation.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Index {
+    pub relation: String,
+    pub order: Vec<usize>,
+}
+
+/// Advice lists the recommended indexes and which one serves each access.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Advice {
+    pub indexes: Vec<Index>,
+    /// Per access, the index into `indexes` serving it.
+    pub assignments: Vec<usize>,
+    /// Number of tuples stored by all indexes.
+    pub storage: usize,
+}
+
+struct RelationInfo {
+    arity: usize,
+    len: usize,
+}
+
+/// IndexAdvisor collects relations and a workload of accesses to them.
+#[derive(Default)]
+pub struct IndexAdvisor {
+    relations: BTreeMap<String, RelationInfo>,
+    accesses: Vec<Access>,
+}
+
+impl IndexAdvisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the relation `name` with `len` tuples of `arity` attributes.
+    pub fn relation(mut self, name: &str, arity: usize, len: usize) -> Self {
+        self.relations
+            .insert(name.to_string(), RelationInfo { arity, len });
+        self
+    }
+
+    /// Adds an access binding the attribute groups of `relation` one after
+    /// the other.
+    pub fn access(mut self, relation: &str, groups: &[&[usize]]) -> Self {
+        self.accesses.push(Access {
+            relation: relation.to_string(),
+            groups: groups.iter().map(|g| g.to_vec()).collect(),
+        });
+        self
+    }
+
+    /// Adds the accesses of a query with the given atoms, evaluated in the
+    /// variable order `order`. Terms of atoms that are not in `order` are
+    /// constants, bound before all variables.
+    pub fn query(mut self, atoms: &[(&str, &[&str])], order: &[&str]) -> Self {
+        for (relation, terms) in atoms {
+            let position = |t: &&str| order.iter().position(|v| v == t);
+            let constants: Vec<usize> = (0..terms.len())
+                .filter(|&a| position(&terms[a]).is_none())
+                .collect();
+            let mut variables: Vec<usize> = (0..terms.len())
+                .filter(|&a| position(&terms[a]).is_some())
+                .collect();
+            variables.sort_by_key(|&a| position(&terms[a]));
+            let groups = (!constants.is_empty()).then_some(constants);
+            self.accesses.push(Access {
+                relation: relation.to_string(),
+                groups: groups
+                    .into_iter()
+                    .chain(variables.into_iter().map(|a| vec![a]))
+                    .collect(),
+            });
+        }
+        self
+    }
+
+    pub fn accesses(&self) -> &[Access] {
+        &self.accesses
+    }
+
+    /// Recommends the fewest indexes per relation covering all accesses.
+    pub fn advise(&self) -> Result<Advice, AdvisorError> {
+        // Per relation, the indices of its accesses.
+        let mut workload: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        let mut normalized = Vec::with_capacity(self.accesses.len());
+        for (i, access) in self.accesses.iter().enumerate() {
+            let info = self
+                .relations
+                .get(&access.relation)
+                .ok_or_else(|| AdvisorError::UnknownRelation(access.relation.clone()))?;
+            normalized.push(normalize(i, access, info.arity)?);
+            workload.entry(&access.relation).or_default().push(i);
+        }
+
+        let mut indexes = Vec::new();
+        let mut assignments = vec![0; self.accesses.len()];
+        let mut storage = 0;
+        for (relation, accesses) in workload {
+            let groups: Vec<&[Vec<usize>]> = accesses.iter().map(|&i| &normalized[i][..]).collect();
+            let orders = cover(&groups);
+            for (&i, groups) in accesses.iter().zip(&groups) {
+                let order = orders.iter().position(|o| compatible(o, groups)).unwrap();
+                assignments[i] = indexes.len() + order;
+            }
+            storage += orders.len() * self.relations[relation].len;
+            indexes.extend(orders.into_iter().map(|order| Index {
+                relation: relation.to_string(),
+                order,
+            }));
+        }
+        Ok(Advice {
+            indexes,
+            assignments,
+            storage,
+        })
+    }
+}
+
+/// Checks the attributes of an access and appends the group of attributes
+/// it leaves unbound.
+fn normalize(i: usize, access: &Access, arity: usize) -> Result<Vec<Vec<usize>>, AdvisorError> {
+    let invalid = |reason: String| AdvisorError::InvalidAccess { access: i, reason };
+    let mut seen = vec![false; arity];
+    for &a in access.groups.iter().flatten() {
+        if a >= arity {
+            return Err(invalid(format!(
+                "attribute {a} out of range for arity {arity}"
+            )));
+        }
+        if std::mem::replace(&mut seen[a], true) {
+            return Err(invalid(format!("attribute {a} is listed twice")));
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = access
+        .groups
+        .iter()
+        .filter(|g| !g.is_empty())
+        .cloned()
+        .collect();
+    let rest: Vec<usize> = (0..arity).filter(|&a| !seen[a]).collect();
+    if !rest.is_empty() {
+        groups.push(rest);
+    }
+    Ok(groups)
+}
+
+/// Checks whether `order` lists the attributes of every group before those
+/// of the following groups.
+fn compatible(order: &[usize], groups: &[Vec<usize>]) -> bool {
+    let mut start = 0;
+    groups.iter().all(|group| {
+        let prefix = &order[start..start + group.len()];
+        start += group.len();
+        prefix.iter().all(|a| group.contains(a))
+    })
+}
+
+/// The orders compatible with an access, in lexicographic order.
+fn orders(groups: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut orders = vec![Vec::new()];
+    for group in groups {
+        let mut group = group.clone();
+        group.sort_unstable();
+        let mut extended = Vec::new();
+        for order in &orders {
+            for permutation in permutations(&group) {
+                let mut order = order.clone();
+                order.extend(permutation);
+                extended.push(order);
+            }
+        }
+        orders = extended;
+    }
+    orders
+}
+
+/// All permutations of the sorted `items`, in lexicographic order.
+fn permutations(items: &[usize]) -> Vec<Vec<usize>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+    let mut result = Vec::new();
+    for (i, &first) in items.iter().enumerate() {
+        let mut rest = items.to_vec();
+        rest.remove(i);
+        for mut tail in permutations(&rest) {
+            tail.insert(0, first);
+            result.push(tail);
+        }
+    }
+    result
+}
+
+/// Finds a smallest set of orders such that every access is compatible with
+/// one of them, by branch and bound: the first access not covered yet must
+/// be covered by one of its compatible orders.
+fn cover(accesses: &[&[Vec<usize>]]) -> Vec<Vec<usize>> {
+    fn search(
+        accesses: &[&[Vec<usize>]],
+        chosen: &mut Vec<Vec<usize>>,
+        best: &mut Vec<Vec<usize>>,
+    ) {
+        let uncovered = accesses
+            .iter()
+            .find(|groups| !chosen.iter().any(|o| compatible(o, groups)));
+        let Some(groups) = uncovered else {
+            *best = chosen.clone();
+            return;
+        };
+        if chosen.len() + 1 >= best.len() {
+            return;
+        }
+        for order in orders(groups) {
+            chosen.push(order);
+            search(accesses, chosen, best);
+            chosen.pop();
+        }
+    }
+
+    // One order per access is always enough.
+    let mut best: Vec<Vec<usize>> = Vec::new();
+    for groups in accesses {
+        if !best.iter().any(|o| compatible(o, groups)) {
+            best.push(orders(groups).swap_remove(0));
+        }
+    }
+    search(accesses, &mut Vec::new(), &mut best);
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S: usize = 0;
+    const P: usize = 1;
+    const O: usize = 2;
+
+    #[test]
+    fn test_rdf_access_patterns() {
+        let advisor = IndexAdvisor::new()
+            .relation("triples", 3, 1000)
+            .access("triples", &[&[S]])
+            .access("triples", &[&[P]])
+            .access("triples", &[&[O]])
+            .access("triples", &[&[S, P]])
+            .access("triples", &[&[P, O]])
+            .access("triples", &[&[S, O]]);
+        let advice = advisor.advise().unwrap();
+        assert_eq!(advice.indexes.len(), 3);
+        assert_eq!(advice.storage, 3000);
+        for (access, &index) in advisor.accesses().iter().zip(&advice.assignments) {
+            let groups = normalize(0, access, 3).unwrap();
+            assert!(compatible(&advice.indexes[index].order, &groups));
+        }
+    }
+
+    #[test]
+    fn test_query_workload() {
+        // Triangles on edges, and the neighbors of a given vertex.
+        let advice = IndexAdvisor::new()
+            .relation("edge", 2, 100)
+            .relation("vertex", 1, 10)
+            .query(
+                &[
+                    ("edge", &["a", "b"]),
+                    ("edge", &["b", "c"]),
+                    ("edge", &["a", "c"]),
+                ],
+                &["a", "b", "c"],
+            )
+            .query(&[("edge", &["x", "42"]), ("vertex", &["x"])], &["x"])
+            .advise()
+            .unwrap();
+        assert_eq!(
+            advice.indexes,
+            vec![
+                Index {
+                    relation: "edge".to_string(),
+                    order: vec![0, 1]
+                },
+                Index {
+                    relation: "edge".to_string(),
+                    order: vec![1, 0]
+                },
+                Index {
+                    relation: "vertex".to_string(),
+                    order: vec![0]
+                },
+            ]
+        );
+        assert_eq!(advice.assignments, vec![0, 0, 0, 1, 2]);
+        assert_eq!(advice.storage, 210);
+    }
+
+    #[test]
+    fn test_advisor_errors() {
+        let advisor = IndexAdvisor::new().relation("r", 2, 5);
+        assert_eq!(
+            advisor.access("s", &[&[0]]).advise(),
+            Err(AdvisorError::UnknownRelation("s".to_string()))
+        );
+        let advisor = IndexAdvisor::new().relation("r", 2, 5);
+        let error = advisor.access("r", &[&[0], &[0]]).advise().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid access 0: attribute 0 is listed twice"
+        );
+    }
+}
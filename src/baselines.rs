@@ -0,0 +1,162 @@
+//! Reference intersections to compare the leapfrog join against.
+//!
+//! - HashSet: builds a hash set of the smallest input and probes the keys of
+//!   every other input into it, keeping only the hits.
+//! - SortMerge: merges the inputs pairwise, smallest first, so every step
+//!   reads the previous result and the next input to the end.
+//!
+//! Algorithm::intersect() runs any of them, or the leapfrog join, with the
+//! contract of intersect(): the sources are sorted and free of duplicates,
+//! and the result is in ascending order. compare() times all of them on the
+//! same sources.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::hint::black_box;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Leapfrog,
+    HashSet,
+    SortMerge,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 3] = [
+        Algorithm::Leapfrog,
+        Algorithm::HashSet,
+        Algorithm::SortMerge,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Leapfrog => "leapfrog",
+            Algorithm::HashSet => "hash_set",
+            Algorithm::SortMerge => "sort_merge",
+        }
+    }
+
+    /// Returns the keys common to all sorted sources, in ascending order.
+    pub fn intersect<T: Ord + Hash + Copy>(self, sources: &[&[T]]) -> Vec<T> {
+        match self {
+            Algorithm::Leapfrog => crate::intersect(sources.to_vec()),
+            Algorithm::HashSet => hash_intersect(sources),
+            Algorithm::SortMerge => sort_merge_intersect(sources),
+        }
+    }
+}
+
+/// Intersects the sources with hash sets. The result is in the order of the
+/// largest source, i.e. ascending for sorted sources.
+pub fn hash_intersect<T: Hash + Eq + Copy>(sources: &[&[T]]) -> Vec<T> {
+    let mut sources = sources.to_vec();
+    sources.sort_by_key(|s| s.len());
+    let Some((smallest, rest)) = sources.split_first() else {
+        return Vec::new();
+    };
+    let mut set: HashSet<T> = smallest.iter().copied().collect();
+    let mut result: Vec<T> = smallest.to_vec();
+    for source in rest {
+        result = source.iter().copied().filter(|k| set.contains(k)).collect();
+        set = result.iter().copied().collect();
+    }
+    result
+}
+
+/// Intersects sorted sources by merging them two at a time, smallest first.
+pub fn sort_merge_intersect<T: Ord + Copy>(sources: &[&[T]]) -> Vec<T> {
+    let mut sources = sources.to_vec();
+    sources.sort_by_key(|s| s.len());
+    let Some((smallest, rest)) = sources.split_first() else {
+        return Vec::new();
+    };
+    let mut result = smallest.to_vec();
+    for source in rest {
+        result = merge(&result, source);
+    }
+    result
+}
+
+fn merge<T: Ord + Copy>(a: &[T], b: &[T]) -> Vec<T> {
+    let mut result = Vec::with_capacity(a.len().min(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                result.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    result
+}
+
+/// The timing of one algorithm, see compare().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timing {
+    pub algorithm: Algorithm,
+    pub rows: usize,
+    pub median_ns: u64,
+}
+
+/// Runs every algorithm `repetitions` times, at least once, on the sorted
+/// sources and returns their median times.
+pub fn compare<T: Ord + Hash + Copy>(sources: &[&[T]], repetitions: usize) -> Vec<Timing> {
+    Algorithm::ALL
+        .into_iter()
+        .map(|algorithm| {
+            let mut rows = 0;
+            let mut times: Vec<u64> = (0..repetitions.max(1))
+                .map(|_| {
+                    let start = Instant::now();
+                    rows = black_box(algorithm.intersect(sources)).len();
+                    start.elapsed().as_nanos() as u64
+                })
+                .collect();
+            times.sort_unstable();
+            Timing {
+                algorithm,
+                rows,
+                median_ns: times[times.len() / 2],
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_baselines_agree() {
+        let a: Vec<u32> = (0..1000).step_by(2).collect();
+        let b: Vec<u32> = (0..1000).step_by(3).collect();
+        let c: Vec<u32> = (500..700).collect();
+        let expected: Vec<u32> = (500..700).filter(|k| k % 6 == 0).collect();
+        for algorithm in Algorithm::ALL {
+            assert_eq!(
+                algorithm.intersect(&[&a, &b, &c]),
+                expected,
+                "{algorithm:?}"
+            );
+            assert!(algorithm.intersect(&[&a, &[]]).is_empty(), "{algorithm:?}");
+        }
+        assert!(hash_intersect::<u32>(&[]).is_empty());
+        assert_eq!(sort_merge_intersect(&[&a[..]]), a);
+    }
+
+    #[test]
+    fn test_compare() {
+        let a: Vec<u64> = (0..10_000).collect();
+        let b: Vec<u64> = (0..10_000).step_by(7).collect();
+        let timings = compare(&[&a, &b], 3);
+        let algorithms: Vec<Algorithm> = timings.iter().map(|t| t.algorithm).collect();
+        assert_eq!(algorithms, Algorithm::ALL);
+        assert!(timings.iter().all(|t| t.rows == b.len()));
+    }
+}
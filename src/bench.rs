@@ -0,0 +1,438 @@
+//! Benchmark harness with preset workloads.
+//!
+//! Every Workload generates its inputs deterministically from a scale, so
+//! that the same preset measures the same join in every release:
+//!
+//! - DenseOverlap: three inputs that cover most of a small key space.
+//! - SparseOverlap: three inputs spread thinly over a large key space.
+//! - SkewedArity: eight inputs, each a random half of the one before.
+//! - Triangles: the triangle query over a random graph.
+//!
+//! Workload::prepare() returns the generated inputs, whose run() can be
+//! timed by any harness, e.g. criterion. Bench::run() times the presets on
+//! its own and returns a Report, which is written and read as tab-separated
+//! lines, and which Report::compare() compares with an earlier one.
+
+use std::fmt;
+use std::hint::black_box;
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+
+use crate::LeapFrogJoin;
+use crate::query::Query;
+use crate::random::Rng;
+use crate::trie::TrieRelation;
+
+/// The first line of every report, followed by a tab and the format version.
+const MAGIC: &str = "leapfrog-bench";
+const VERSION: u32 = 1;
+const COLUMNS: &str = "workload\tscale\trepetitions\trows\tmin_ns\tmedian_ns\tmean_ns";
+
+#[derive(Debug)]
+pub enum BenchError {
+    Io(io::Error),
+    /// Line `line`, counted from 1, is not part of a report.
+    Format {
+        line: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BenchError::Io(e) => write!(f, "I/O error: {e}"),
+            BenchError::Format { line, reason } => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for BenchError {}
+
+impl From<io::Error> for BenchError {
+    fn from(e: io::Error) -> Self {
+        BenchError::Io(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Workload {
+    DenseOverlap,
+    SparseOverlap,
+    SkewedArity,
+    Triangles,
+}
+
+impl Workload {
+    pub const ALL: [Workload; 4] = [
+        Workload::DenseOverlap,
+        Workload::SparseOverlap,
+        Workload::SkewedArity,
+        Workload::Triangles,
+    ];
+
+    /// The name of the workload in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Workload::DenseOverlap => "dense_overlap",
+            Workload::SparseOverlap => "sparse_overlap",
+            Workload::SkewedArity => "skewed_arity",
+            Workload::Triangles => "triangles",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Workload> {
+        Workload::ALL.into_iter().find(|w| w.name() == name)
+    }
+
+    /// Generates the inputs of the workload, with about `scale` keys in the
+    /// largest input, or `scale` edges for Triangles, seeded with the scale.
+    pub fn prepare(self, scale: usize) -> Prepared {
+        self.prepare_seeded(scale, scale as u64)
+    }
+
+    /// Generates the inputs like prepare(), from the random numbers of
+    /// `seed`.
+    pub fn prepare_seeded(self, scale: usize, seed: u64) -> Prepared {
+        let mut rng = Rng::new(seed);
+        let inputs = match self {
+            Workload::DenseOverlap => (0..3)
+                .map(|_| sorted_keys(&mut rng, scale, scale * 5 / 4))
+                .collect(),
+            Workload::SparseOverlap => (0..3)
+                .map(|_| sorted_keys(&mut rng, scale, scale * 10))
+                .collect(),
+            Workload::SkewedArity => {
+                let mut inputs = vec![sorted_keys(&mut rng, scale, scale * 2)];
+                for _ in 1..8 {
+                    let last = inputs.last().unwrap();
+                    inputs.push(
+                        last.iter()
+                            .copied()
+                            .filter(|_| rng.next_u64() & 1 == 0)
+                            .collect(),
+                    );
+                }
+                inputs
+            }
+            Workload::Triangles => {
+                let vertices = (scale as u64 / 4).max(4);
+                let edges = (0..scale).map(|_| {
+                    let (a, b) = (rng.below(vertices), rng.below(vertices));
+                    [a.min(b) as u32, a.max(b) as u32]
+                });
+                let edges = TrieRelation::new(2, edges.filter(|[a, b]| a != b));
+                return Prepared {
+                    workload: self,
+                    scale,
+                    data: Data::Graph(edges),
+                };
+            }
+        };
+        Prepared {
+            workload: self,
+            scale,
+            data: Data::Sets(inputs),
+        }
+    }
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+enum Data {
+    Sets(Vec<Vec<u64>>),
+    Graph(TrieRelation<u32>),
+}
+
+/// The generated inputs of a workload, see Workload::prepare().
+pub struct Prepared {
+    workload: Workload,
+    scale: usize,
+    data: Data,
+}
+
+impl Prepared {
+    pub fn workload(&self) -> Workload {
+        self.workload
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// The key sets of the intersection workloads, empty for Triangles.
+    pub fn inputs(&self) -> Vec<&[u64]> {
+        match &self.data {
+            Data::Sets(inputs) => inputs.iter().map(Vec::as_slice).collect(),
+            Data::Graph(_) => Vec::new(),
+        }
+    }
+
+    /// Runs the join once and returns the number of results.
+    pub fn run(&self) -> u64 {
+        match &self.data {
+            Data::Sets(inputs) => {
+                let inputs = inputs.iter().map(Vec::as_slice).collect();
+                let mut join = LeapFrogJoin::new(inputs);
+                let mut rows = 0;
+                while !join.at_end() {
+                    black_box(join.key());
+                    rows += 1;
+                    join.next();
+                }
+                rows
+            }
+            Data::Graph(edges) => {
+                let join = Query::new()
+                    .atom(edges, &["a", "b"])
+                    .atom(edges, &["b", "c"])
+                    .atom(edges, &["a", "c"])
+                    .execute()
+                    .expect("Triangle query is valid");
+                join.map(black_box).count() as u64
+            }
+        }
+    }
+}
+
+/// Bench times workloads on their own, without a harness.
+#[derive(Clone, Copy, Debug)]
+pub struct Bench {
+    scale: usize,
+    repetitions: usize,
+    warmup: usize,
+}
+
+impl Default for Bench {
+    fn default() -> Self {
+        Self {
+            scale: 100_000,
+            repetitions: 10,
+            warmup: 1,
+        }
+    }
+}
+
+impl Bench {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn scale(mut self, scale: usize) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Sets how often every workload is timed. At least once.
+    pub fn repetitions(mut self, repetitions: usize) -> Self {
+        self.repetitions = repetitions.max(1);
+        self
+    }
+
+    /// Sets how often every workload runs untimed first.
+    pub fn warmup(mut self, warmup: usize) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    pub fn run(&self, workloads: &[Workload]) -> Report {
+        let results = workloads
+            .iter()
+            .map(|&workload| self.measure(&workload.prepare(self.scale)))
+            .collect();
+        Report {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            results,
+        }
+    }
+
+    /// Times the prepared inputs of a workload.
+    pub fn measure(&self, prepared: &Prepared) -> BenchResult {
+        for _ in 0..self.warmup {
+            prepared.run();
+        }
+        let mut rows = 0;
+        let mut times: Vec<u64> = (0..self.repetitions)
+            .map(|_| {
+                let start = Instant::now();
+                rows = prepared.run();
+                start.elapsed().as_nanos() as u64
+            })
+            .collect();
+        times.sort_unstable();
+        BenchResult {
+            workload: prepared.workload.name().to_string(),
+            scale: prepared.scale,
+            repetitions: times.len(),
+            rows,
+            min_ns: times[0],
+            median_ns: times[times.len() / 2],
+            mean_ns: times.iter().sum::<u64>() / times.len() as u64,
+        }
+    }
+}
+
+/// The timings of one workload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BenchResult {
+    pub workload: String,
+    pub scale: usize,
+    pub repetitions: usize,
+    /// The number of results of the join, which must not change between
+    /// releases.
+    pub rows: u64,
+    pub min_ns: u64,
+    pub median_ns: u64,
+    pub mean_ns: u64,
+}
+
+/// The results of a benchmark run, and the crate version that produced them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub version: String,
+    pub results: Vec<BenchResult>,
+}
+
+/// How a workload performs in a report compared to a baseline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub workload: String,
+    pub scale: usize,
+    /// The baseline's median time divided by the report's: above 1 is faster.
+    pub speedup: f64,
+    /// Whether both runs found the same number of results.
+    pub rows_match: bool,
+}
+
+impl Report {
+    /// Writes the report as a line `leapfrog-bench <format> <version>`, a
+    /// line of column names and one line per result, with tab-separated
+    /// fields.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "{MAGIC}\t{VERSION}\t{}", self.version)?;
+        writeln!(w, "{COLUMNS}")?;
+        for r in &self.results {
+            writeln!(
+                w,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                r.workload, r.scale, r.repetitions, r.rows, r.min_ns, r.median_ns, r.mean_ns
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads a report written by write_to().
+    pub fn read_from<R: BufRead>(r: R) -> Result<Self, BenchError> {
+        let format = |line, reason: &str| BenchError::Format {
+            line,
+            reason: reason.to_string(),
+        };
+        let mut lines = r.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let version = match header.split('\t').collect::<Vec<_>>()[..] {
+            [MAGIC, v, version] if v == VERSION.to_string() => version.to_string(),
+            [MAGIC, _, _] => return Err(format(1, "unsupported format version")),
+            _ => return Err(format(1, "not a benchmark report")),
+        };
+        if lines.next().transpose()?.as_deref() != Some(COLUMNS) {
+            return Err(format(2, "unexpected column names"));
+        }
+        let mut results = Vec::new();
+        for (i, text) in lines.enumerate() {
+            let text = text?;
+            let (workload, numbers) = text.split_once('\t').unwrap_or((&text, ""));
+            let numbers: Option<Vec<u64>> = numbers.split('\t').map(|n| n.parse().ok()).collect();
+            let Some(&[scale, repetitions, rows, min_ns, median_ns, mean_ns]) = numbers.as_deref()
+            else {
+                return Err(format(i + 3, "expected a name and six numbers"));
+            };
+            results.push(BenchResult {
+                workload: workload.to_string(),
+                scale: scale as usize,
+                repetitions: repetitions as usize,
+                rows,
+                min_ns,
+                median_ns,
+                mean_ns,
+            });
+        }
+        Ok(Report { version, results })
+    }
+
+    /// Compares every result with the one of `baseline` for the same
+    /// workload and scale. Results without a counterpart are left out.
+    pub fn compare(&self, baseline: &Report) -> Vec<Comparison> {
+        self.results
+            .iter()
+            .filter_map(|r| {
+                let b = baseline
+                    .results
+                    .iter()
+                    .find(|b| b.workload == r.workload && b.scale == r.scale)?;
+                Some(Comparison {
+                    workload: r.workload.clone(),
+                    scale: r.scale,
+                    speedup: b.median_ns as f64 / r.median_ns.max(1) as f64,
+                    rows_match: b.rows == r.rows,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Returns `len` random keys below `range`, sorted and without duplicates,
+/// so possibly fewer.
+fn sorted_keys(rng: &mut Rng, len: usize, range: usize) -> Vec<u64> {
+    let range = range.max(1) as u64;
+    let mut keys: Vec<u64> = (0..len).map(|_| rng.below(range)).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intersect;
+
+    #[test]
+    fn test_workloads() {
+        for workload in Workload::ALL {
+            let prepared = workload.prepare(2000);
+            assert_eq!(Workload::from_name(workload.name()), Some(workload));
+            let rows = prepared.run();
+            assert_eq!(workload.prepare(2000).run(), rows);
+            if workload != Workload::Triangles {
+                assert_eq!(rows, intersect(prepared.inputs()).len() as u64);
+            }
+            assert!(rows > 0, "{workload} has no results");
+        }
+    }
+
+    #[test]
+    fn test_report_round_trip() {
+        let report = Bench::new()
+            .scale(500)
+            .repetitions(3)
+            .warmup(0)
+            .run(&[Workload::DenseOverlap, Workload::Triangles]);
+        let mut text = Vec::new();
+        report.write_to(&mut text).unwrap();
+        let read = Report::read_from(text.as_slice()).unwrap();
+        assert_eq!(read, report);
+
+        let comparison = read.compare(&report);
+        assert_eq!(comparison.len(), 2);
+        assert!(comparison.iter().all(|c| c.speedup == 1.0 && c.rows_match));
+
+        let error = Report::read_from("leapfrog-bench\t1\t0.1.0\nworkload\n".as_bytes());
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "line 2: unexpected column names"
+        );
+    }
+}
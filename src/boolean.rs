@@ -0,0 +1,372 @@
+//! Boolean expressions over named sorted sets.
+//!
+//! A BoolExpr combines sets with AND, OR and NOT, e.g.
+//! `set("rust").and([set("db"), set("joins").or([set("search")])])`, the
+//! core of search and filtering systems. compile() turns it into a tree of
+//! sorted sources: AND becomes a LeapFrogJoin of its operands, OR a
+//! UnionIterator and NOT the subtracted side of a DifferenceIterator.
+//! evaluate() iterates the result lazily in ascending order, so taking the
+//! first few matches only does the work for those.
+//!
+//! BoolPlan holds an expression normalized once and checked to be bounded,
+//! so that it only needs to be compiled over the sets on every evaluation.
+//! BoolPlan::cached() keeps plans in a PlanCache, keyed by the normalized
+//! expression: nested ANDs and ORs are flattened, their operands sorted and
+//! deduplicated, and double negations dropped, so equivalent spellings of
+//! a template share a plan.
+//!
+//! NOT has no complement to draw from, so it may only appear as an operand
+//! of an AND with at least one other operand that is not a NOT: `a AND NOT
+//! b` is a - b, while `NOT b` or `a OR NOT b` fail with
+//! BooleanError::Unbounded.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::difference::DifferenceIterator;
+use crate::plancache::PlanCache;
+use crate::union::UnionIterator;
+use crate::{LeapFrogJoin, LinearIterator, Seekable};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BoolExpr {
+    Set(String),
+    And(Vec<BoolExpr>),
+    Or(Vec<BoolExpr>),
+    Not(Box<BoolExpr>),
+}
+
+/// Refers to the set `name`.
+pub fn set(name: &str) -> BoolExpr {
+    BoolExpr::Set(name.to_string())
+}
+
+/// Matches the keys `expr` does not, within an AND.
+pub fn not(expr: BoolExpr) -> BoolExpr {
+    BoolExpr::Not(Box::new(expr))
+}
+
+impl BoolExpr {
+    /// Matches the keys this and all of `others` match.
+    pub fn and(self, others: impl IntoIterator<Item = BoolExpr>) -> BoolExpr {
+        let mut operands = vec![self];
+        operands.extend(others);
+        BoolExpr::And(operands)
+    }
+
+    /// Matches the keys this or any of `others` match.
+    pub fn or(self, others: impl IntoIterator<Item = BoolExpr>) -> BoolExpr {
+        let mut operands = vec![self];
+        operands.extend(others);
+        BoolExpr::Or(operands)
+    }
+
+    /// Returns the expression with nested ANDs and ORs flattened, their
+    /// operands sorted and deduplicated, single operands unwrapped and
+    /// double negations dropped. It matches the same keys.
+    pub fn normalized(&self) -> BoolExpr {
+        let flatten = |operands: &[BoolExpr], and: bool| {
+            let mut flat = Vec::new();
+            for operand in operands.iter().map(BoolExpr::normalized) {
+                match operand {
+                    BoolExpr::And(inner) if and => flat.extend(inner),
+                    BoolExpr::Or(inner) if !and => flat.extend(inner),
+                    operand => flat.push(operand),
+                }
+            }
+            flat.sort();
+            flat.dedup();
+            flat
+        };
+        match self {
+            BoolExpr::Set(_) => self.clone(),
+            BoolExpr::And(operands) => match flatten(operands, true) {
+                mut single if single.len() == 1 => single.pop().unwrap(),
+                operands => BoolExpr::And(operands),
+            },
+            BoolExpr::Or(operands) => match flatten(operands, false) {
+                mut single if single.len() == 1 => single.pop().unwrap(),
+                operands => BoolExpr::Or(operands),
+            },
+            BoolExpr::Not(operand) => match operand.normalized() {
+                BoolExpr::Not(inner) => *inner,
+                operand => not(operand),
+            },
+        }
+    }
+
+    /// Checks that the expression matches finitely many keys.
+    fn check_bounded(&self) -> Result<(), BooleanError> {
+        match self {
+            BoolExpr::Set(_) => Ok(()),
+            BoolExpr::Or(operands) => operands.iter().try_for_each(BoolExpr::check_bounded),
+            BoolExpr::And(operands) => {
+                if operands.iter().all(|o| matches!(o, BoolExpr::Not(_))) {
+                    return Err(BooleanError::Unbounded(self.clone()));
+                }
+                operands.iter().try_for_each(|o| match o {
+                    BoolExpr::Not(operand) => operand.check_bounded(),
+                    operand => operand.check_bounded(),
+                })
+            }
+            BoolExpr::Not(_) => Err(BooleanError::Unbounded(self.clone())),
+        }
+    }
+
+    /// The names of the sets the expression refers to, in order of first
+    /// appearance.
+    pub fn sets(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_sets(&mut names);
+        names
+    }
+
+    fn collect_sets<'e>(&'e self, names: &mut Vec<&'e str>) {
+        match self {
+            BoolExpr::Set(name) if !names.contains(&name.as_str()) => names.push(name),
+            BoolExpr::Set(_) => {}
+            BoolExpr::And(operands) | BoolExpr::Or(operands) => {
+                operands.iter().for_each(|o| o.collect_sets(names))
+            }
+            BoolExpr::Not(operand) => operand.collect_sets(names),
+        }
+    }
+}
+
+impl fmt::Display for BoolExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operands = |f: &mut fmt::Formatter<'_>, operands: &[BoolExpr], op: &str| {
+            for (i, operand) in operands.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {op} ")?;
+                }
+                match operand {
+                    BoolExpr::And(_) | BoolExpr::Or(_) => write!(f, "({operand})")?,
+                    _ => write!(f, "{operand}")?,
+                }
+            }
+            Ok(())
+        };
+        match self {
+            BoolExpr::Set(name) => write!(f, "{name}"),
+            BoolExpr::And(o) => operands(f, o, "AND"),
+            BoolExpr::Or(o) => operands(f, o, "OR"),
+            BoolExpr::Not(operand) => match **operand {
+                BoolExpr::And(_) | BoolExpr::Or(_) => write!(f, "NOT ({operand})"),
+                _ => write!(f, "NOT {operand}"),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BooleanError {
+    UnknownSet(String),
+    /// A subexpression matches infinitely many keys: a NOT outside of an
+    /// AND with other operands, or an AND without any operand but NOTs.
+    Unbounded(BoolExpr),
+}
+
+impl fmt::Display for BooleanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BooleanError::UnknownSet(name) => write!(f, "unknown set {name:?}"),
+            BooleanError::Unbounded(expr) => write!(f, "{expr} matches unboundedly many keys"),
+        }
+    }
+}
+
+impl std::error::Error for BooleanError {}
+
+/// A compiled expression: a sorted source of the keys it matches.
+pub type Source<'a, K> = Box<dyn Seekable<Key = K> + 'a>;
+
+/// Compiles `expr` into a sorted source over the sorted, duplicate-free
+/// `sets`.
+pub fn compile<'a, K: Ord + Copy + 'a>(
+    expr: &BoolExpr,
+    sets: &HashMap<&str, &'a [K]>,
+) -> Result<Source<'a, K>, BooleanError> {
+    match expr {
+        BoolExpr::Set(name) => {
+            let keys = sets
+                .get(name.as_str())
+                .ok_or_else(|| BooleanError::UnknownSet(name.clone()))?;
+            Ok(Box::new(LinearIterator::new(keys)))
+        }
+        BoolExpr::Or(operands) => {
+            let operands: Result<Vec<_>, _> = operands.iter().map(|o| compile(o, sets)).collect();
+            Ok(Box::new(UnionIterator::new(operands?)))
+        }
+        BoolExpr::And(operands) => {
+            let (negated, positive): (Vec<&BoolExpr>, Vec<&BoolExpr>) =
+                operands.iter().partition(|o| matches!(o, BoolExpr::Not(_)));
+            if positive.is_empty() {
+                return Err(BooleanError::Unbounded(expr.clone()));
+            }
+            let positive: Result<Vec<_>, _> = positive.iter().map(|o| compile(o, sets)).collect();
+            let mut source: Source<'a, K> = match positive? {
+                mut single if single.len() == 1 => single.pop().unwrap(),
+                positive => Box::new(LeapFrogJoin::from_iters(positive)),
+            };
+            if !negated.is_empty() {
+                let subtracted = negated.iter().map(|o| match o {
+                    BoolExpr::Not(operand) => compile(operand, sets),
+                    _ => unreachable!("Operands were partitioned"),
+                });
+                let subtracted = UnionIterator::new(subtracted.collect::<Result<Vec<_>, _>>()?);
+                source = Box::new(DifferenceIterator::new(source, subtracted));
+            }
+            Ok(source)
+        }
+        BoolExpr::Not(_) => Err(BooleanError::Unbounded(expr.clone())),
+    }
+}
+
+/// BoolPlan is a normalized expression checked to be bounded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BoolPlan {
+    expr: BoolExpr,
+}
+
+impl BoolPlan {
+    pub fn new(expr: &BoolExpr) -> Result<Self, BooleanError> {
+        let expr = expr.normalized();
+        expr.check_bounded()?;
+        Ok(Self { expr })
+    }
+
+    /// Returns the plan of `expr` from `cache`, or plans and caches it.
+    /// `version` identifies the sets, e.g. Catalog::version().
+    pub fn cached(
+        cache: &PlanCache<BoolPlan>,
+        expr: &BoolExpr,
+        version: u64,
+    ) -> Result<Arc<Self>, BooleanError> {
+        let expr = expr.normalized();
+        cache.get_or_compile(&expr.to_string(), version, || {
+            expr.check_bounded()?;
+            Ok(Self { expr: expr.clone() })
+        })
+    }
+
+    /// The normalized expression.
+    pub fn expr(&self) -> &BoolExpr {
+        &self.expr
+    }
+
+    /// Returns the keys the expression matches over `sets`, see evaluate().
+    pub fn evaluate<'a, K: Ord + Copy + 'a>(
+        &self,
+        sets: &HashMap<&str, &'a [K]>,
+    ) -> Result<Matches<'a, K>, BooleanError> {
+        evaluate(&self.expr, sets)
+    }
+}
+
+/// Matches iterates the keys an expression matches in ascending order.
+pub struct Matches<'a, K> {
+    source: Source<'a, K>,
+}
+
+impl<K: Ord + Copy> Iterator for Matches<'_, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        if self.source.at_end() {
+            return None;
+        }
+        let key = self.source.key();
+        self.source.next();
+        Some(key)
+    }
+}
+
+/// Returns the keys `expr` matches over `sets`, computed as they are
+/// iterated.
+pub fn evaluate<'a, K: Ord + Copy + 'a>(
+    expr: &BoolExpr,
+    sets: &HashMap<&str, &'a [K]>,
+) -> Result<Matches<'a, K>, BooleanError> {
+    Ok(Matches {
+        source: compile(expr, sets)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let rust: Vec<u32> = (0..100).filter(|d| d % 2 == 0).collect();
+        let db: Vec<u32> = (0..100).filter(|d| d % 3 == 0).collect();
+        let joins: Vec<u32> = (0..100).filter(|d| d % 5 == 0).collect();
+        let retracted: Vec<u32> = vec![0, 30, 31, 90];
+        let sets = HashMap::from([
+            ("rust", &rust[..]),
+            ("db", &db[..]),
+            ("joins", &joins[..]),
+            ("retracted", &retracted[..]),
+        ]);
+
+        let expr = set("rust").and([set("db").or([set("joins")]), not(set("retracted"))]);
+        assert_eq!(expr.to_string(), "rust AND (db OR joins) AND NOT retracted");
+        assert_eq!(expr.sets(), ["rust", "db", "joins", "retracted"]);
+        let expected: Vec<u32> = (0..100)
+            .filter(|d| d % 2 == 0 && (d % 3 == 0 || d % 5 == 0) && !retracted.contains(d))
+            .collect();
+        assert_eq!(
+            evaluate(&expr, &sets).unwrap().collect::<Vec<_>>(),
+            expected
+        );
+
+        // Lazy: the first matches come without evaluating the rest.
+        let expr = set("db").and([not(set("rust").and([set("joins")]))]);
+        let first: Vec<u32> = evaluate(&expr, &sets).unwrap().take(3).collect();
+        assert_eq!(first, [3, 6, 9]);
+    }
+
+    #[test]
+    fn test_plans() {
+        let expr = set("b").and([set("a").and([not(not(set("c")))]), set("b")]);
+        assert_eq!(expr.normalized(), set("a").and([set("b"), set("c")]));
+        assert_eq!(
+            not(set("a").or([set("b").or([set("a")])])).normalized(),
+            not(set("a").or([set("b")]))
+        );
+
+        let (a, b, c) = ([1u32, 2, 3, 4], [2u32, 3, 4], [3u32, 4, 5]);
+        let sets = HashMap::from([("a", &a[..]), ("b", &b[..]), ("c", &c[..])]);
+        let cache = PlanCache::new(8);
+        let plan = BoolPlan::cached(&cache, &expr, 1).unwrap();
+        assert_eq!(plan.evaluate(&sets).unwrap().collect::<Vec<_>>(), [3, 4]);
+        let respelled = set("c").and([set("b"), set("a")]);
+        assert!(Arc::ptr_eq(
+            &BoolPlan::cached(&cache, &respelled, 1).unwrap(),
+            &plan
+        ));
+        assert_eq!(cache.stats().hits, 1);
+        assert!(BoolPlan::new(&set("a").or([not(set("b"))])).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let keys = [1u32, 2];
+        let sets = HashMap::from([("a", &keys[..])]);
+        let unbounded = set("a").or([not(set("a"))]);
+        assert_eq!(
+            evaluate(&unbounded, &sets).err(),
+            Some(BooleanError::Unbounded(not(set("a"))))
+        );
+        assert_eq!(
+            evaluate(&BoolExpr::And(vec![not(set("a"))]), &sets).err(),
+            Some(BooleanError::Unbounded(BoolExpr::And(vec![not(set("a"))])))
+        );
+        assert_eq!(
+            evaluate(&set("a").and([set("b")]), &sets).err(),
+            Some(BooleanError::UnknownSet("b".to_string()))
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! A block cache shared by sources.
+//!
+//! Sources that read their keys in blocks, e.g. IndexSource from files or
+//! object stores, can keep the blocks in a BlockCache that other sources
+//! share, so that a join over data an earlier join has read does not read
+//! it again, while the memory of all of them together stays bounded.
+//!
+//! Blocks are identified by their file, interned as a FileId, and their
+//! offset in it, and hold the bytes the source has verified, e.g. decoded
+//! from a compressed block, or checked against a checksum.
+//!
+//! The cache evicts the least recently used blocks (Policy::Lru), or keeps
+//! two segments (Policy::Segmented): new blocks enter a probationary
+//! segment, and blocks hit there move to a protected one, so a single scan
+//! over a large file does not evict the blocks a workload keeps coming
+//! back to.
+//!
+//! BlockCache::clear_on() drops all blocks whenever a MemoryPressure is
+//! signaled, since they can be read again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::memory::MemoryPressure;
+
+/// How a BlockCache evicts blocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    Lru,
+    /// Segmented LRU, with `protected` of the capacity, between 0 and 1,
+    /// for blocks that have been hit.
+    Segmented {
+        protected: f64,
+    },
+}
+
+/// Identifies a file in a BlockCache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FileId(u64);
+
+/// Counters describing how a BlockCache was used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    /// Number of bytes cached.
+    pub bytes: usize,
+    /// Number of blocks cached.
+    pub blocks: usize,
+}
+
+type BlockId = (FileId, u64);
+
+struct Entry {
+    bytes: Arc<[u8]>,
+    tick: u64,
+    protected: bool,
+}
+
+/// A segment of the cache, its blocks ordered by last use.
+#[derive(Default)]
+struct Segment {
+    order: BTreeMap<u64, BlockId>,
+    bytes: usize,
+}
+
+#[derive(Default)]
+struct Inner {
+    files: HashMap<String, FileId>,
+    entries: HashMap<BlockId, Entry>,
+    probation: Segment,
+    protected: Segment,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl Inner {
+    fn segment(&mut self, protected: bool) -> &mut Segment {
+        if protected {
+            &mut self.protected
+        } else {
+            &mut self.probation
+        }
+    }
+
+    /// Moves block `id` to the most recent end of a segment.
+    fn touch(&mut self, id: BlockId, protected: bool) {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(&id).unwrap();
+        let (old_tick, was_protected, len) = (entry.tick, entry.protected, entry.bytes.len());
+        entry.tick = tick;
+        entry.protected = protected;
+        let old = self.segment(was_protected);
+        old.order.remove(&old_tick);
+        old.bytes -= len;
+        let new = self.segment(protected);
+        new.order.insert(tick, id);
+        new.bytes += len;
+    }
+
+    /// Removes the least recently used block of a segment.
+    fn pop_oldest(&mut self, protected: bool) -> Option<BlockId> {
+        let (_, id) = self.segment(protected).order.pop_first()?;
+        let entry = self.entries.remove(&id).unwrap();
+        self.segment(protected).bytes -= entry.bytes.len();
+        self.stats.evictions += 1;
+        Some(id)
+    }
+}
+
+/// BlockCache is a size-bounded cache of blocks, shared through an Arc by
+/// the sources reading them.
+pub struct BlockCache {
+    capacity: usize,
+    policy: Policy,
+    inner: Mutex<Inner>,
+}
+
+impl BlockCache {
+    /// Creates an LRU cache of up to `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, Policy::Lru)
+    }
+
+    pub fn with_policy(capacity: usize, policy: Policy) -> Self {
+        if let Policy::Segmented { protected } = policy {
+            assert!(
+                (0.0..=1.0).contains(&protected),
+                "Protected fraction must be between 0 and 1"
+            );
+        }
+        Self {
+            capacity,
+            policy,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the ID of the file called `name`, e.g. its path or URL. All
+    /// sources reading the same file must use the same name.
+    pub fn file_id(&self, name: &str) -> FileId {
+        let mut inner = self.inner.lock().unwrap();
+        let next = FileId(inner.files.len() as u64);
+        *inner.files.entry(name.to_string()).or_insert(next)
+    }
+
+    /// Returns the block at `offset` in `file`, if cached.
+    pub fn get(&self, file: FileId, offset: u64) -> Option<Arc<[u8]>> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = (file, offset);
+        let Some(entry) = inner.entries.get(&id) else {
+            inner.stats.misses += 1;
+            return None;
+        };
+        let bytes = entry.bytes.clone();
+        inner.stats.hits += 1;
+        let protected = matches!(self.policy, Policy::Segmented { .. });
+        inner.touch(id, protected);
+        self.evict(&mut inner);
+        Some(bytes)
+    }
+
+    /// Returns whether the block at `offset` in `file` is cached, without
+    /// counting a hit or miss.
+    pub fn contains(&self, file: FileId, offset: u64) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .contains_key(&(file, offset))
+    }
+
+    /// Caches `bytes` as the block at `offset` in `file`. Blocks larger than
+    /// the cache are not cached.
+    pub fn insert(&self, file: FileId, offset: u64, bytes: Arc<[u8]>) {
+        if bytes.len() > self.capacity {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let id = (file, offset);
+        if let Some(old) = inner.entries.remove(&id) {
+            let segment = inner.segment(old.protected);
+            segment.order.remove(&old.tick);
+            segment.bytes -= old.bytes.len();
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.probation.order.insert(tick, id);
+        inner.probation.bytes += bytes.len();
+        inner.entries.insert(
+            id,
+            Entry {
+                bytes,
+                tick,
+                protected: false,
+            },
+        );
+        self.evict(&mut inner);
+    }
+
+    /// Returns the block at `offset` in `file`, loading and caching it if it
+    /// is not cached.
+    pub fn get_or_load<E>(
+        &self,
+        file: FileId,
+        offset: u64,
+        load: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Arc<[u8]>, E> {
+        if let Some(bytes) = self.get(file, offset) {
+            return Ok(bytes);
+        }
+        let bytes: Arc<[u8]> = load()?.into();
+        self.insert(file, offset, bytes.clone());
+        Ok(bytes)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            bytes: inner.probation.bytes + inner.protected.bytes,
+            blocks: inner.entries.len(),
+            ..inner.stats
+        }
+    }
+
+    /// Drops all blocks, keeping file IDs and counters.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.probation = Segment::default();
+        inner.protected = Segment::default();
+    }
+
+    /// Clears the cache on every signal of `pressure`, for as long as the
+    /// cache lives.
+    pub fn clear_on(self: &Arc<Self>, pressure: &MemoryPressure) {
+        let cache = Arc::downgrade(self);
+        pressure.on_signal(move || cache.upgrade().map(|cache| cache.clear()).is_some());
+    }
+
+    fn evict(&self, inner: &mut Inner) {
+        if let Policy::Segmented { protected } = self.policy {
+            let limit = (self.capacity as f64 * protected) as usize;
+            while inner.protected.bytes > limit {
+                // Demote the oldest protected block to probation.
+                let (_, &id) = inner.protected.order.first_key_value().unwrap();
+                inner.touch(id, false);
+            }
+        }
+        while inner.probation.bytes + inner.protected.bytes > self.capacity {
+            if inner.pop_oldest(false).is_none() {
+                inner.pop_oldest(true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(byte: u8) -> Arc<[u8]> {
+        vec![byte; 10].into()
+    }
+
+    #[test]
+    fn test_lru() {
+        let cache = BlockCache::new(30);
+        let (a, b) = (cache.file_id("a"), cache.file_id("b"));
+        assert_eq!(cache.file_id("a"), a);
+        cache.insert(a, 0, block(1));
+        cache.insert(b, 0, block(2));
+        cache.insert(a, 10, block(3));
+        assert_eq!(cache.get(a, 0).as_deref(), Some(&[1; 10][..]));
+        cache.insert(b, 10, block(4));
+        assert!(cache.get(b, 0).is_none());
+        assert!(cache.get(a, 0).is_some());
+        cache.insert(a, 20, vec![0; 31].into());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 1,
+                evictions: 1,
+                bytes: 30,
+                blocks: 3,
+            }
+        );
+        let loaded = cache.get_or_load(b, 0, || Ok::<_, ()>(vec![5; 10]));
+        assert_eq!(loaded.unwrap()[0], 5);
+        assert_eq!(cache.get_or_load(b, 0, || Err(())).unwrap()[0], 5);
+    }
+
+    #[test]
+    fn test_segmented() {
+        let cache = BlockCache::with_policy(40, Policy::Segmented { protected: 0.5 });
+        let file = cache.file_id("f");
+        cache.insert(file, 0, block(0));
+        cache.insert(file, 1, block(1));
+        cache.get(file, 0);
+        cache.get(file, 1);
+        // A scan over more blocks than fit evicts only unprotected ones.
+        for offset in 100..110 {
+            cache.insert(file, offset, block(2));
+        }
+        assert!(cache.get(file, 0).is_some());
+        assert!(cache.get(file, 1).is_some());
+        assert_eq!(cache.stats().blocks, 4);
+        assert_eq!(cache.stats().evictions, 8);
+    }
+}
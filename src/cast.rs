@@ -0,0 +1,368 @@
+//! Joining key columns of different, but compatible types.
+//!
+//! A CastIterator converts the keys of the wrapped iterator with a KeyCast,
+//! e.g. from u32 to u64, so it can be joined with sources of the target type.
+//! Casts must preserve the order of keys. Seeks are translated back into the
+//! source type with KeyCast::lower_bound().
+//!
+//! Keys that cannot be cast (overflow, or a string missing from the
+//! dictionary) end the iterator; the reason is available from
+//! CastIterator::error().
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::Seekable;
+use crate::zonemap::Zone;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CastError {
+    /// The key does not fit into the target type.
+    Overflow(String),
+    /// The string is not contained in the dictionary.
+    NotInDictionary(String),
+}
+
+impl fmt::Display for CastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CastError::Overflow(key) => write!(f, "key {key} overflows the target type"),
+            CastError::NotInDictionary(key) => write!(f, "key {key:?} is not in the dictionary"),
+        }
+    }
+}
+
+impl std::error::Error for CastError {}
+
+/// KeyCast converts keys of type `From` into keys of type `To`, preserving
+/// their order.
+pub trait KeyCast<From, To> {
+    fn cast(&self, key: From) -> Result<To, CastError>;
+
+    /// Returns the least key whose cast is >= `key`, or None if there is no
+    /// such key.
+    fn lower_bound(&self, key: To) -> Option<From>;
+}
+
+/// Casts between integer types. Widening casts never fail; narrowing casts
+/// fail for keys that do not fit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IntCast;
+
+macro_rules! impl_int_cast {
+    ($($from:ty => $to:ty),*) => {$(
+        impl KeyCast<$from, $to> for IntCast {
+            fn cast(&self, key: $from) -> Result<$to, CastError> {
+                <$to>::try_from(key).map_err(|_| CastError::Overflow(key.to_string()))
+            }
+
+            fn lower_bound(&self, key: $to) -> Option<$from> {
+                match <$from>::try_from(key) {
+                    Ok(key) => Some(key),
+                    // Below the range of the source type.
+                    Err(_) if key < 0 as $to => Some(<$from>::MIN),
+                    Err(_) => None,
+                }
+            }
+        }
+    )*};
+}
+
+impl_int_cast!(
+    u8 => u16, u8 => u32, u8 => u64, u16 => u32, u16 => u64, u32 => u64,
+    i8 => i16, i8 => i32, i8 => i64, i16 => i32, i16 => i64, i32 => i64,
+    u32 => i64, u64 => u32, i64 => i32
+);
+
+/// Days since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date(pub i32);
+
+/// Microseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub i64);
+
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// Casts a date to the timestamp of its midnight.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DateToTimestamp;
+
+impl KeyCast<Date, Timestamp> for DateToTimestamp {
+    fn cast(&self, key: Date) -> Result<Timestamp, CastError> {
+        (key.0 as i64)
+            .checked_mul(MICROS_PER_DAY)
+            .map(Timestamp)
+            .ok_or_else(|| CastError::Overflow(format!("{key:?}")))
+    }
+
+    fn lower_bound(&self, key: Timestamp) -> Option<Date> {
+        let days = key.0.div_euclid(MICROS_PER_DAY) + (key.0.rem_euclid(MICROS_PER_DAY) > 0) as i64;
+        match i32::try_from(days) {
+            Ok(days) => Some(Date(days)),
+            Err(_) if days < 0 => Some(Date(i32::MIN)),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Dictionary maps strings to codes. Codes are assigned in sorted order of
+/// the strings, so that casting to codes preserves the order of keys.
+#[derive(Clone, Debug, Default)]
+pub struct Dictionary {
+    values: Vec<String>,
+    codes: HashMap<String, u32>,
+}
+
+impl Dictionary {
+    pub fn new<S: Into<String>>(values: impl IntoIterator<Item = S>) -> Self {
+        let mut values: Vec<String> = values.into_iter().map(Into::into).collect();
+        values.sort();
+        values.dedup();
+        assert!(values.len() <= u32::MAX as usize, "Dictionary is too large");
+        let codes = values
+            .iter()
+            .enumerate()
+            .map(|(code, value)| (value.clone(), code as u32))
+            .collect();
+        Self { values, codes }
+    }
+
+    pub fn code(&self, value: &str) -> Option<u32> {
+        self.codes.get(value).copied()
+    }
+
+    pub fn value(&self, code: u32) -> Option<&str> {
+        self.values.get(code as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl<'a> KeyCast<&'a str, u32> for &'a Dictionary {
+    fn cast(&self, key: &'a str) -> Result<u32, CastError> {
+        self.code(key)
+            .ok_or_else(|| CastError::NotInDictionary(key.to_string()))
+    }
+
+    fn lower_bound(&self, key: u32) -> Option<&'a str> {
+        self.value(key)
+    }
+}
+
+/// CastIterator presents the keys of `I` cast to `To`.
+pub struct CastIterator<I: Seekable, C, To> {
+    iter: I,
+    cast: C,
+    key: Option<To>,
+    error: Option<CastError>,
+}
+
+impl<I, C, To> CastIterator<I, C, To>
+where
+    I: Seekable,
+    C: KeyCast<I::Key, To>,
+    To: Ord + Copy,
+{
+    /// Wraps `iter`. Fails if its first key cannot be cast.
+    pub fn new(iter: I, cast: C) -> Result<Self, CastError> {
+        let mut result = Self {
+            iter,
+            cast,
+            key: None,
+            error: None,
+        };
+        result.load();
+        match result.error.take() {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
+    }
+
+    /// The error that ended the iterator early, if any.
+    pub fn error(&self) -> Option<&CastError> {
+        self.error.as_ref()
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+
+    fn load(&mut self) {
+        self.key = None;
+        if self.iter.at_end() {
+            return;
+        }
+        match self.cast.cast(self.iter.key()) {
+            Ok(key) => self.key = Some(key),
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+impl<I, C, To> Seekable for CastIterator<I, C, To>
+where
+    I: Seekable,
+    C: KeyCast<I::Key, To>,
+    To: Ord + Copy,
+{
+    type Key = To;
+
+    fn key(&self) -> To {
+        self.key.expect("Iterator is at end")
+    }
+
+    fn next(&mut self) {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.iter.next();
+        self.load();
+    }
+
+    fn seek(&mut self, seek_key: To) {
+        assert!(!self.at_end(), "Iterator is at end");
+        match self.cast.lower_bound(seek_key) {
+            Some(key) => {
+                if self.iter.key() < key {
+                    self.iter.seek(key);
+                }
+                self.load();
+            }
+            None => self.key = None,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.key.is_none()
+    }
+
+    fn zone_map(&self) -> Option<Vec<Zone<To>>> {
+        self.iter
+            .zone_map()?
+            .into_iter()
+            .map(|zone| {
+                Some(Zone {
+                    min: self.cast.cast(zone.min).ok()?,
+                    max: self.cast.cast(zone.max).ok()?,
+                })
+            })
+            .collect()
+    }
+
+    /// A key that fails to cast ends the iterator early, so only the bound
+    /// carries over.
+    fn estimate(&self) -> Option<usize> {
+        if self.at_end() {
+            Some(0)
+        } else {
+            self.iter.estimate()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LeapFrogJoin, LinearIterator};
+
+    fn join<K: Ord + Copy>(iters: Vec<Box<dyn Seekable<Key = K> + '_>>) -> Vec<K> {
+        let mut join = LeapFrogJoin::from_iters(iters);
+        let mut result = vec![];
+        while !join.at_end() {
+            result.push(join.key());
+            join.next();
+        }
+        result
+    }
+
+    #[test]
+    fn test_widening_cast_join() {
+        let narrow: Vec<i32> = vec![-5, 0, 3, 7, 1 << 20];
+        let wide: Vec<i64> = vec![-5, 3, 1 << 20, 1 << 40];
+        let result = join(vec![
+            Box::new(CastIterator::new(LinearIterator::new(&narrow), IntCast).unwrap()),
+            Box::new(LinearIterator::new(&wide)),
+        ]);
+        assert_eq!(result, vec![-5, 3, 1 << 20]);
+
+        // Seeking beyond the range of the source type ends the iterator.
+        let keys: Vec<u32> = vec![1, u32::MAX];
+        let mut iter: CastIterator<_, _, u64> =
+            CastIterator::new(LinearIterator::new(&keys), IntCast).unwrap();
+        iter.seek(u32::MAX as u64);
+        assert_eq!(iter.key(), u32::MAX as u64);
+        iter.seek(u32::MAX as u64 + 1);
+        assert!(iter.at_end());
+        assert_eq!(iter.error(), None);
+    }
+
+    #[test]
+    fn test_narrowing_cast_overflow() {
+        let keys: Vec<u64> = vec![1, 2, 1 << 40];
+        let mut iter: CastIterator<_, _, u32> =
+            CastIterator::new(LinearIterator::new(&keys), IntCast).unwrap();
+        iter.next();
+        assert_eq!(iter.key(), 2);
+        iter.next();
+        assert!(iter.at_end());
+        assert_eq!(
+            iter.error(),
+            Some(&CastError::Overflow("1099511627776".to_string()))
+        );
+
+        let keys: Vec<i64> = vec![i64::MIN, 0];
+        let result: Result<CastIterator<_, _, i32>, _> =
+            CastIterator::new(LinearIterator::new(&keys), IntCast);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_date_to_timestamp() {
+        let dates = [Date(-1), Date(0), Date(2), Date(3)];
+        let timestamps = [
+            Timestamp(-MICROS_PER_DAY),
+            Timestamp(1),
+            Timestamp(2 * MICROS_PER_DAY),
+        ];
+        let result = join(vec![
+            Box::new(CastIterator::new(LinearIterator::new(&dates), DateToTimestamp).unwrap()),
+            Box::new(LinearIterator::new(&timestamps)),
+        ]);
+        assert_eq!(
+            result,
+            vec![Timestamp(-MICROS_PER_DAY), Timestamp(2 * MICROS_PER_DAY)]
+        );
+        assert_eq!(DateToTimestamp.lower_bound(Timestamp(1)), Some(Date(1)));
+        assert_eq!(DateToTimestamp.lower_bound(Timestamp(-1)), Some(Date(0)));
+    }
+
+    #[test]
+    fn test_dictionary_cast() {
+        let dict = Dictionary::new(["pear", "apple", "fig", "kiwi", "apple"]);
+        assert_eq!(dict.len(), 4);
+        assert_eq!(dict.code("apple"), Some(0));
+        assert_eq!(dict.value(3), Some("pear"));
+
+        let strings = ["apple", "kiwi", "pear"];
+        let codes: Vec<u32> = vec![1, 2, 3];
+        let result = join(vec![
+            Box::new(CastIterator::new(LinearIterator::new(&strings), &dict).unwrap()),
+            Box::new(LinearIterator::new(&codes)),
+        ]);
+        assert_eq!(result, vec![2, 3]);
+
+        let unknown = ["apple", "banana"];
+        let mut iter = CastIterator::new(LinearIterator::new(&unknown), &dict).unwrap();
+        iter.next();
+        assert!(iter.at_end());
+        assert_eq!(
+            iter.error().map(|e| e.to_string()),
+            Some("key \"banana\" is not in the dictionary".to_string())
+        );
+    }
+}
@@ -0,0 +1,795 @@
+//! A catalog of named relations.
+//!
+//! A Catalog registers relations under names, each with a schema naming
+//! its attributes, statistics gathered when it is registered, and where it
+//! is stored. Queries name relations instead of passing them around:
+//! Catalog::run() resolves the atoms of a DatabaseQuery against the
+//! catalog, which keeps its relations in a Database that materializes the
+//! permutations they need.
+//!
+//! With the `fs` feature, Catalog::save() writes the catalog to a
+//! directory: every relation held in memory as an index file `<name>.lftr`
+//! (see TrieRelation::write_to()), and a manifest `catalog.tsv` listing the
+//! relations with their schemas, statistics, metadata and files.
+//! Catalog::load() reads it back. Files of relations registered with
+//! Catalog::register_file() stay where they are.
+//!
+//! The manifest starts with the line `leapfrog-catalog\t1`, followed by one
+//! tab-separated line per fact, with comma-separated lists:
+//!
+//! ```text
+//! relation <name> <file> <rows> <attributes> <distinct keys per attribute>
+//! sorted <name> <attributes>
+//! unique <name> <attributes>
+//! dependency <name> <from> <to>
+//! ```
+//!
+//! File paths inside the catalog directory are relative to it, all others
+//! are absolute.
+//!
+//! Catalog::run() caches the plan of every query, i.e. the permutations
+//! its atoms are routed to, in a PlanCache keyed by the query and the
+//! version of the catalog, which every registration and every materialized
+//! permutation advances. Repeated queries skip planning until the catalog
+//! changes.
+//!
+//! Services embedding the engine for several tenants can install an
+//! authorizer with Catalog::set_authorizer(). It is asked per relation and
+//! operation on behalf of a principal whenever Catalog::run_as(),
+//! Catalog::entry_as() or Catalog::names_as() access a relation, and may
+//! allow the access, deny it, or restrict it to the rows a filter accepts,
+//! e.g. those of the principal's tenant. Restricted queries run on private
+//! copies of the visible rows. The methods without a principal act for the
+//! embedder itself and are never restricted.
+
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::fs::{self, File};
+use std::io;
+#[cfg(feature = "fs")]
+use std::io::{BufRead, BufReader, BufWriter, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::database::{Database, DatabaseError, DatabaseQuery, Route};
+use crate::metadata::Metadata;
+use crate::persist::PersistError;
+#[cfg(feature = "fs")]
+use crate::persist::PersistKey;
+use crate::plancache::{PlanCache, PlanCacheStats};
+use crate::trie::TrieRelation;
+
+#[cfg(feature = "fs")]
+const MAGIC: &str = "leapfrog-catalog";
+#[cfg(feature = "fs")]
+const VERSION: u32 = 1;
+
+/// The number of query plans a catalog caches.
+pub const PLAN_CACHE_ENTRIES: usize = 256;
+
+/// The file name of the manifest in a catalog directory.
+pub const MANIFEST: &str = "catalog.tsv";
+
+#[derive(Debug)]
+pub enum CatalogError {
+    /// A relation or attribute name is empty or contains characters that
+    /// cannot be stored.
+    InvalidName(String),
+    /// A schema does not have one name per attribute of its relation.
+    Schema {
+        relation: String,
+        expected: usize,
+        found: usize,
+    },
+    Database(DatabaseError),
+    Persist {
+        relation: String,
+        error: PersistError,
+    },
+    /// The authorizer denied an operation.
+    Denied {
+        principal: String,
+        relation: String,
+        operation: Operation,
+    },
+    Io(io::Error),
+    /// Line `line` of a manifest, counted from 1, is invalid.
+    Format {
+        line: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::InvalidName(name) => write!(f, "invalid name {name:?}"),
+            CatalogError::Schema {
+                relation,
+                expected,
+                found,
+            } => write!(
+                f,
+                "relation {relation:?} has arity {expected}, but its schema names {found} attributes"
+            ),
+            CatalogError::Database(error) => write!(f, "{error}"),
+            CatalogError::Persist { relation, error } => {
+                write!(f, "relation {relation:?}: {error}")
+            }
+            CatalogError::Denied {
+                principal,
+                relation,
+                operation,
+            } => write!(
+                f,
+                "{principal:?} may not {} relation {relation:?}",
+                operation.name()
+            ),
+            CatalogError::Io(e) => write!(f, "I/O error: {e}"),
+            CatalogError::Format { line, reason } => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl From<DatabaseError> for CatalogError {
+    fn from(error: DatabaseError) -> Self {
+        CatalogError::Database(error)
+    }
+}
+
+impl From<io::Error> for CatalogError {
+    fn from(e: io::Error) -> Self {
+        CatalogError::Io(e)
+    }
+}
+
+/// Schema names the attributes of a relation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schema {
+    attributes: Vec<String>,
+}
+
+impl Schema {
+    pub fn new(attributes: &[&str]) -> Self {
+        Self {
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.attributes.len()
+    }
+
+    pub fn attributes(&self) -> &[String] {
+        &self.attributes
+    }
+
+    /// Returns the position of the attribute `name`.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.attributes.iter().position(|a| a == name)
+    }
+}
+
+/// Statistics of a relation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Statistics {
+    pub rows: usize,
+    /// Number of distinct keys per attribute.
+    pub distinct: Vec<usize>,
+}
+
+impl Statistics {
+    pub fn of<K: Ord + Copy>(relation: &TrieRelation<K>) -> Self {
+        let distinct = (0..relation.arity())
+            .map(|a| {
+                let mut keys = relation.column(a).to_vec();
+                keys.sort_unstable();
+                keys.dedup();
+                keys.len()
+            })
+            .collect();
+        Self {
+            rows: relation.len(),
+            distinct,
+        }
+    }
+}
+
+/// Where the relation of an entry is stored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Storage {
+    /// Only in memory, until the catalog is saved.
+    Memory,
+    /// In an index file.
+    File(PathBuf),
+}
+
+/// Entry describes a registered relation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub schema: Schema,
+    pub statistics: Statistics,
+    pub storage: Storage,
+}
+
+/// An operation on a relation that the authorizer decides on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Reading its rows in a query.
+    Read,
+    /// Learning that it exists, its schema and its statistics.
+    Describe,
+}
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Read => "read",
+            Operation::Describe => "describe",
+        }
+    }
+}
+
+/// Request is what the authorizer decides on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Request<'r> {
+    pub principal: &'r str,
+    pub relation: &'r str,
+    pub operation: Operation,
+}
+
+/// Accepts the rows, in the attribute order of their relation, that a
+/// principal may see.
+pub type RowFilter<K> = Arc<dyn Fn(&[K]) -> bool + Send + Sync>;
+
+/// The decision of the authorizer.
+pub enum Access<K> {
+    Allow,
+    Deny,
+    /// Allows the operation on the rows the filter accepts. Describing the
+    /// relation reports the statistics of these rows.
+    Rows(RowFilter<K>),
+}
+
+type Authorizer<K> = Box<dyn Fn(&Request<'_>) -> Access<K> + Send + Sync>;
+
+/// Catalog is a set of named relations and their descriptions.
+pub struct Catalog<K> {
+    database: Database<K>,
+    entries: BTreeMap<String, Entry>,
+    authorizer: Option<Authorizer<K>>,
+    plans: PlanCache<Vec<Route>>,
+}
+
+impl<K> Default for Catalog<K> {
+    fn default() -> Self {
+        Self {
+            database: Database::default(),
+            entries: BTreeMap::new(),
+            authorizer: None,
+            plans: PlanCache::new(PLAN_CACHE_ENTRIES),
+        }
+    }
+}
+
+impl<K: Ord + Copy> Catalog<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `relation` in memory as `name`, replacing any relation of
+    /// that name. Relation names consist of ASCII letters, digits, `_` and
+    /// `-`; attribute names must not contain tabs, commas or line breaks.
+    pub fn register(
+        &mut self,
+        name: &str,
+        schema: Schema,
+        relation: TrieRelation<K>,
+    ) -> Result<(), CatalogError> {
+        self.insert(name, schema, relation, Storage::Memory)
+    }
+
+    fn insert(
+        &mut self,
+        name: &str,
+        schema: Schema,
+        relation: TrieRelation<K>,
+        storage: Storage,
+    ) -> Result<(), CatalogError> {
+        check_name(name, |c| c.is_ascii_alphanumeric() || c == '_' || c == '-')?;
+        for attribute in schema.attributes() {
+            check_name(attribute, |c| !matches!(c, '\t' | ',' | '\n' | '\r'))?;
+        }
+        if schema.arity() != relation.arity() {
+            return Err(CatalogError::Schema {
+                relation: name.to_string(),
+                expected: relation.arity(),
+                found: schema.arity(),
+            });
+        }
+        let statistics = Statistics::of(&relation);
+        self.database.insert(name, relation)?;
+        let entry = Entry {
+            schema,
+            statistics,
+            storage,
+        };
+        self.entries.insert(name.to_string(), entry);
+        Ok(())
+    }
+
+    /// Declares the metadata of the relation `name`, see
+    /// Database::set_metadata().
+    pub fn set_metadata(&mut self, name: &str, metadata: Metadata) -> Result<(), CatalogError> {
+        Ok(self.database.set_metadata(name, metadata)?)
+    }
+
+    /// Removes the relation `name`. Its file, if any, is left in place.
+    pub fn remove(&mut self, name: &str) -> Option<Entry> {
+        self.database.remove(name);
+        self.entries.remove(name)
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.get(name)
+    }
+
+    /// The names of the registered relations, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// The database holding the relations, e.g. to take snapshots or to
+    /// materialize permutations ahead of queries.
+    pub fn database(&self) -> &Database<K> {
+        &self.database
+    }
+
+    /// The version of the catalog, which changes with every write.
+    pub fn version(&self) -> u64 {
+        self.database.snapshot().version()
+    }
+
+    /// Runs `query` on the relations it names, with its plan from the plan
+    /// cache if it was planned at the current version.
+    pub fn run(&self, query: &DatabaseQuery) -> Result<Vec<Vec<K>>, CatalogError> {
+        let key = format!("{:?}", query.normalized());
+        let mut snapshot = self.database.snapshot();
+        let routes = match self.plans.get(&key, snapshot.version()) {
+            Some(routes) => routes,
+            None => {
+                self.database.prepare(query)?;
+                snapshot = self.database.snapshot();
+                let routes = Arc::new(snapshot.route(query)?);
+                self.plans.insert(&key, snapshot.version(), routes.clone());
+                routes
+            }
+        };
+        let query = snapshot.query_routed(query, &routes, |name, order| {
+            snapshot.permutation(name, order)
+        })?;
+        Ok(query.run().map_err(DatabaseError::from)?)
+    }
+
+    pub fn plan_stats(&self) -> PlanCacheStats {
+        self.plans.stats()
+    }
+
+    /// Makes the `*_as()` methods ask `authorizer` before every access.
+    pub fn set_authorizer(
+        &mut self,
+        authorizer: impl Fn(&Request<'_>) -> Access<K> + Send + Sync + 'static,
+    ) {
+        self.authorizer = Some(Box::new(authorizer));
+    }
+
+    /// Asks the authorizer, if any, whether `principal` may perform
+    /// `operation` on the relation `name`, and returns the filter of the
+    /// rows it may see, if restricted.
+    fn authorize(
+        &self,
+        principal: &str,
+        name: &str,
+        operation: Operation,
+    ) -> Result<Option<RowFilter<K>>, CatalogError> {
+        let Some(authorizer) = &self.authorizer else {
+            return Ok(None);
+        };
+        let request = Request {
+            principal,
+            relation: name,
+            operation,
+        };
+        match authorizer(&request) {
+            Access::Allow => Ok(None),
+            Access::Rows(filter) => Ok(Some(filter)),
+            Access::Deny => Err(CatalogError::Denied {
+                principal: principal.to_string(),
+                relation: name.to_string(),
+                operation,
+            }),
+        }
+    }
+
+    /// Runs `query` on behalf of `principal`, on the rows the authorizer
+    /// lets it read. Fails if it may not read one of the relations.
+    pub fn run_as(
+        &self,
+        principal: &str,
+        query: &DatabaseQuery,
+    ) -> Result<Vec<Vec<K>>, CatalogError> {
+        let mut filters = BTreeMap::new();
+        for (name, _) in self.database.snapshot().orders(query)? {
+            if let Some(filter) = self.authorize(principal, &name, Operation::Read)? {
+                filters.insert(name, filter);
+            }
+        }
+        // Permutations of whole relations are shared with other queries,
+        // those of the visible rows are built for this query alone.
+        self.database.prepare(query)?;
+        let snapshot = self.database.snapshot();
+        let mut visible = BTreeMap::new();
+        for (name, order) in snapshot.orders(query)? {
+            if let Some(filter) = filters.get(&name) {
+                let relation = snapshot.relation(&name).expect("Routed relations exist");
+                let rows = visible_rows(relation, filter);
+                visible.insert((name, order.clone()), rows.permuted(&order));
+            }
+        }
+        let query = snapshot.query_on(query, |name, order| {
+            if filters.contains_key(name) {
+                visible.get(&(name.to_string(), order.to_vec()))
+            } else {
+                snapshot.permutation(name, order)
+            }
+        })?;
+        Ok(query.run().map_err(DatabaseError::from)?)
+    }
+
+    /// Returns the entry of the relation `name` if `principal` may describe
+    /// it.
+    pub fn entry_as(&self, principal: &str, name: &str) -> Result<Entry, CatalogError> {
+        let entry = self
+            .entries
+            .get(name)
+            .ok_or_else(|| DatabaseError::UnknownRelation(name.to_string()))?;
+        let mut entry = entry.clone();
+        if let Some(filter) = self.authorize(principal, name, Operation::Describe)? {
+            let snapshot = self.database.snapshot();
+            let relation = snapshot
+                .relation(name)
+                .expect("Entries are in the database");
+            entry.statistics = Statistics::of(&visible_rows(relation, &filter));
+        }
+        Ok(entry)
+    }
+
+    /// The names of the relations `principal` may describe, sorted.
+    pub fn names_as<'c>(&'c self, principal: &'c str) -> impl Iterator<Item = &'c str> {
+        self.names()
+            .filter(move |name| self.authorize(principal, name, Operation::Describe).is_ok())
+    }
+}
+
+#[cfg(feature = "fs")]
+impl<K: PersistKey> Catalog<K> {
+    /// Registers the relation stored in the index file at `path` as `name`.
+    pub fn register_file(
+        &mut self,
+        name: &str,
+        schema: Schema,
+        path: impl AsRef<Path>,
+    ) -> Result<(), CatalogError> {
+        let path = std::path::absolute(path)?;
+        let relation = read_relation(name, &path)?;
+        self.insert(name, schema, relation, Storage::File(path))
+    }
+
+    /// Saves the catalog to `dir`, which is created if missing. Relations
+    /// in memory are written to it and are stored there from now on.
+    pub fn save(&mut self, dir: impl AsRef<Path>) -> Result<(), CatalogError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let snapshot = self.database.snapshot();
+        for (name, entry) in &mut self.entries {
+            if entry.storage == Storage::Memory {
+                let path = dir.join(format!("{name}.lftr"));
+                let relation = snapshot
+                    .relation(name)
+                    .expect("Entries are in the database");
+                relation.write_to(BufWriter::new(File::create(&path)?))?;
+                entry.storage = Storage::File(path);
+            }
+        }
+
+        let mut w = BufWriter::new(File::create(dir.join(MANIFEST))?);
+        writeln!(w, "{MAGIC}\t{VERSION}")?;
+        for (name, entry) in &self.entries {
+            let Storage::File(path) = &entry.storage else {
+                unreachable!("All relations were written")
+            };
+            let path = match path.strip_prefix(dir) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => std::path::absolute(path)?,
+            };
+            writeln!(
+                w,
+                "relation\t{name}\t{}\t{}\t{}\t{}",
+                path.display(),
+                entry.statistics.rows,
+                entry.schema.attributes.join(","),
+                list(&entry.statistics.distinct)
+            )?;
+            let metadata = snapshot
+                .metadata(name)
+                .expect("Entries are in the database");
+            if !metadata.sorted_attributes().is_empty() {
+                writeln!(w, "sorted\t{name}\t{}", list(metadata.sorted_attributes()))?;
+            }
+            for key in metadata.unique_keys() {
+                writeln!(w, "unique\t{name}\t{}", list(key))?;
+            }
+            for dependency in metadata.dependencies() {
+                let (from, to) = (list(&dependency.from), list(&dependency.to));
+                writeln!(w, "dependency\t{name}\t{from}\t{to}")?;
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Loads a catalog saved to `dir`.
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, CatalogError> {
+        let dir = dir.as_ref();
+        let format = |line, reason: &str| CatalogError::Format {
+            line,
+            reason: reason.to_string(),
+        };
+        let mut lines = BufReader::new(File::open(dir.join(MANIFEST))?).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        match header.split('\t').collect::<Vec<_>>()[..] {
+            [MAGIC, v] if v == VERSION.to_string() => {}
+            [MAGIC, _] => return Err(format(1, "unsupported format version")),
+            _ => return Err(format(1, "not a catalog manifest")),
+        }
+        let mut catalog = Self::new();
+        let mut metadata: BTreeMap<String, Metadata> = BTreeMap::new();
+        for (i, text) in lines.enumerate() {
+            let (text, line) = (text?, i + 2);
+            let fields: Vec<&str> = text.split('\t').collect();
+            let attributes =
+                |field: &str| parse_list(field).ok_or_else(|| format(line, "invalid list"));
+            match fields[..] {
+                ["relation", name, file, rows, names, distinct] => {
+                    let path = dir.join(file);
+                    let relation = read_relation(name, &path)?;
+                    let names: Vec<&str> = names.split(',').filter(|n| !n.is_empty()).collect();
+                    catalog.insert(name, Schema::new(&names), relation, Storage::File(path))?;
+                    // Keep the statistics of the manifest rather than those
+                    // gathered again, which are the same for unchanged files.
+                    let statistics = Statistics {
+                        rows: rows
+                            .parse()
+                            .map_err(|_| format(line, "invalid row count"))?,
+                        distinct: attributes(distinct)?,
+                    };
+                    catalog.entries.get_mut(name).unwrap().statistics = statistics;
+                }
+                [
+                    fact @ ("sorted" | "unique" | "dependency"),
+                    name,
+                    ref values @ ..,
+                ] => {
+                    if !catalog.entries.contains_key(name) {
+                        return Err(format(line, "metadata of an unknown relation"));
+                    }
+                    let declared = metadata.remove(name).unwrap_or_default();
+                    let declared = match (fact, values) {
+                        ("sorted", [sorted]) => declared.sorted_by(&attributes(sorted)?),
+                        ("unique", [key]) => declared.unique(&attributes(key)?),
+                        ("dependency", [from, to]) => {
+                            declared.dependency(&attributes(from)?, &attributes(to)?)
+                        }
+                        _ => return Err(format(line, "wrong number of fields")),
+                    };
+                    metadata.insert(name.to_string(), declared);
+                }
+                _ => return Err(format(line, "unknown fact")),
+            }
+        }
+        for (name, metadata) in metadata {
+            catalog.set_metadata(&name, metadata)?;
+        }
+        Ok(catalog)
+    }
+}
+
+/// Returns the rows of `relation` that `filter` accepts.
+fn visible_rows<K: Ord + Copy>(
+    relation: &TrieRelation<K>,
+    filter: &RowFilter<K>,
+) -> TrieRelation<K> {
+    let arity = relation.arity();
+    let rows = (0..relation.len())
+        .map(|r| {
+            (0..arity)
+                .map(|a| relation.column(a)[r])
+                .collect::<Vec<_>>()
+        })
+        .filter(|row| filter(row));
+    // The rows stay sorted and distinct.
+    Metadata::new()
+        .sorted_by(&(0..arity).collect::<Vec<_>>())
+        .unique(&(0..arity).collect::<Vec<_>>())
+        .build(arity, rows)
+}
+
+fn check_name(name: &str, valid: impl Fn(char) -> bool) -> Result<(), CatalogError> {
+    if !name.is_empty() && name.chars().all(valid) {
+        Ok(())
+    } else {
+        Err(CatalogError::InvalidName(name.to_string()))
+    }
+}
+
+#[cfg(feature = "fs")]
+fn read_relation<K: PersistKey>(name: &str, path: &Path) -> Result<TrieRelation<K>, CatalogError> {
+    let persist_error = |error| CatalogError::Persist {
+        relation: name.to_string(),
+        error,
+    };
+    let file = File::open(path).map_err(|e| persist_error(PersistError::Io(e)))?;
+    TrieRelation::read_from(BufReader::new(file)).map_err(persist_error)
+}
+
+#[cfg(feature = "fs")]
+fn list(values: &[usize]) -> String {
+    let values: Vec<String> = values.iter().map(usize::to_string).collect();
+    values.join(",")
+}
+
+#[cfg(feature = "fs")]
+fn parse_list(text: &str) -> Option<Vec<usize>> {
+    text.split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> Catalog<u32> {
+        let mut catalog = Catalog::new();
+        let follows = TrieRelation::new(2, [[1, 2], [2, 3], [1, 3], [3, 1]]);
+        let users = TrieRelation::new(2, [[1, 30], [2, 25], [3, 30]]);
+        catalog
+            .register("follows", Schema::new(&["follower", "followee"]), follows)
+            .unwrap();
+        catalog
+            .register("users", Schema::new(&["id", "age"]), users)
+            .unwrap();
+        catalog
+            .set_metadata("users", Metadata::new().unique(&[0]))
+            .unwrap();
+        catalog
+    }
+
+    #[test]
+    fn test_catalog() {
+        let catalog = catalog();
+        assert_eq!(catalog.names().collect::<Vec<_>>(), ["follows", "users"]);
+        let users = catalog.entry("users").unwrap();
+        assert_eq!(users.schema.position("age"), Some(1));
+        assert_eq!(
+            users.statistics,
+            Statistics {
+                rows: 3,
+                distinct: vec![3, 2]
+            }
+        );
+        assert_eq!(users.storage, Storage::Memory);
+
+        // Ages of the followees of users aged 30.
+        let query = DatabaseQuery::new()
+            .atom("users", &["a", "x"])
+            .atom("follows", &["a", "b"])
+            .atom("users", &["b", "y"])
+            .order(&["a", "b", "x", "y"]);
+        let results = catalog.run(&query).unwrap();
+        assert_eq!(results.len(), 4);
+        // Planning materialized a permutation, the plan is cached after it.
+        let version = catalog.version();
+        assert_eq!(catalog.run(&query).unwrap(), results);
+        assert_eq!(catalog.version(), version);
+        assert_eq!(catalog.plan_stats().hits, 1);
+
+        let mut invalid = Catalog::new();
+        let relation = TrieRelation::new(1, [[1u32]]);
+        assert!(matches!(
+            invalid.register("a b", Schema::new(&["x"]), relation.clone()),
+            Err(CatalogError::InvalidName(_))
+        ));
+        assert!(matches!(
+            invalid.register("r", Schema::new(&["x", "y"]), relation),
+            Err(CatalogError::Schema { expected: 1, .. })
+        ));
+        assert!(matches!(
+            catalog.run(&DatabaseQuery::new().atom("likes", &["a", "b"])),
+            Err(CatalogError::Database(DatabaseError::UnknownRelation(_)))
+        ));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("leapfrog-catalog-{}", std::process::id()));
+        let mut catalog = catalog();
+        catalog.save(&dir).unwrap();
+        assert_eq!(
+            catalog.entry("users").unwrap().storage,
+            Storage::File(dir.join("users.lftr"))
+        );
+
+        let loaded = Catalog::<u32>::load(&dir).unwrap();
+        for name in ["follows", "users"] {
+            assert_eq!(loaded.entry(name), catalog.entry(name));
+            let snapshot = (loaded.database().snapshot(), catalog.database().snapshot());
+            assert_eq!(snapshot.0.relation(name), snapshot.1.relation(name));
+            assert_eq!(snapshot.0.metadata(name), snapshot.1.metadata(name));
+        }
+
+        fs::write(
+            dir.join(MANIFEST),
+            "leapfrog-catalog\t1\nsorted\tusers\t0\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Catalog::<u32>::load(&dir),
+            Err(CatalogError::Format { line: 2, .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_authorizer() {
+        let mut catalog = catalog();
+        // Principals see the users of their age only, and "guest" sees no
+        // follows.
+        catalog.set_authorizer(|request| match (request.relation, request.principal) {
+            ("follows", "guest") => Access::Deny,
+            ("users", principal) => {
+                let age: u32 = principal.parse().unwrap_or(0);
+                Access::Rows(Arc::new(move |row: &[u32]| row[1] == age))
+            }
+            _ => Access::Allow,
+        });
+        let query = DatabaseQuery::new()
+            .atom("users", &["a", "x"])
+            .atom("follows", &["a", "b"])
+            .atom("users", &["b", "y"]);
+        assert_eq!(catalog.run(&query).unwrap().len(), 4);
+        assert_eq!(
+            catalog.run_as("30", &query).unwrap(),
+            [vec![1, 30, 3, 30], vec![3, 30, 1, 30]]
+        );
+        assert!(catalog.run_as("25", &query).unwrap().is_empty());
+        assert!(matches!(
+            catalog.run_as("guest", &query),
+            Err(CatalogError::Denied {
+                operation: Operation::Read,
+                ..
+            })
+        ));
+
+        assert_eq!(catalog.entry_as("25", "users").unwrap().statistics.rows, 1);
+        assert_eq!(catalog.names_as("guest").collect::<Vec<_>>(), ["users"]);
+        assert_eq!(catalog.names_as("30").count(), 2);
+    }
+}
@@ -0,0 +1,184 @@
+//! One join input made of range-partitioned shards.
+
+use crate::Seekable;
+use crate::zonemap::Zone;
+
+/// ChainSource presents shards that cover disjoint, ascending key ranges as
+/// one sorted source.
+///
+/// The shard index holds the first key of every shard, so a seek jumps
+/// straight to the shard that may contain the seek key with a binary search,
+/// without touching the shards in between.
+pub struct ChainSource<I: Seekable> {
+    shards: Vec<I>,
+    first_keys: Vec<I::Key>,
+    current: usize,
+}
+
+impl<I: Seekable> ChainSource<I> {
+    /// Creates a chain over `shards`, all positioned at their first key and
+    /// ordered by key range. Empty shards are dropped.
+    pub fn new(shards: Vec<I>) -> Self {
+        let shards: Vec<I> = shards.into_iter().filter(|s| !s.at_end()).collect();
+        let first_keys: Vec<I::Key> = shards.iter().map(|s| s.key()).collect();
+        assert!(
+            first_keys.windows(2).all(|w| w[0] < w[1]),
+            "Shards must be ordered by key range"
+        );
+        Self {
+            shards,
+            first_keys,
+            current: 0,
+        }
+    }
+
+    pub fn into_shards(self) -> Vec<I> {
+        self.shards
+    }
+
+    /// Moves on to the next shard if the current one is exhausted.
+    fn skip_exhausted(&mut self) {
+        if self.shards[self.current].at_end() && self.current + 1 < self.shards.len() {
+            self.current += 1;
+        }
+    }
+}
+
+impl<I: Seekable> Seekable for ChainSource<I> {
+    type Key = I::Key;
+
+    fn key(&self) -> I::Key {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.shards[self.current].key()
+    }
+
+    fn next(&mut self) {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.shards[self.current].next();
+        self.skip_exhausted();
+    }
+
+    fn seek(&mut self, seek_key: I::Key) {
+        assert!(!self.at_end(), "Iterator is at end");
+        // Last shard whose first key is <= seek_key.
+        let target = self
+            .first_keys
+            .partition_point(|&key| key <= seek_key)
+            .saturating_sub(1)
+            .max(self.current);
+        self.current = target;
+        if self.shards[target].key() < seek_key {
+            self.shards[target].seek(seek_key);
+        }
+        self.skip_exhausted();
+    }
+
+    fn at_end(&self) -> bool {
+        self.shards.get(self.current).is_none_or(|s| s.at_end())
+    }
+
+    /// The zones of all shards, if every shard has a zone map.
+    fn zone_map(&self) -> Option<Vec<Zone<I::Key>>> {
+        let mut zones = Vec::new();
+        for shard in &self.shards {
+            zones.extend(shard.zone_map()?);
+        }
+        Some(zones)
+    }
+
+    fn estimate(&self) -> Option<usize> {
+        self.shards[self.current.min(self.shards.len())..]
+            .iter()
+            .map(|shard| shard.estimate())
+            .sum()
+    }
+
+    /// The bound of the last shard, as shards are ordered by key range.
+    fn upper_bound(&self) -> Option<I::Key> {
+        self.shards.last()?.upper_bound()
+    }
+
+    /// The shards are disjoint, so their counts add up.
+    fn exact_len_hint(&self) -> Option<usize> {
+        self.shards[self.current.min(self.shards.len())..]
+            .iter()
+            .map(|shard| shard.exact_len_hint())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "time")]
+    use crate::trace::Tracer;
+    use crate::{LeapFrogJoin, LinearIterator, intersect};
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_chain_source() {
+        let shard1 = [1, 3, 5];
+        let shard2 = [];
+        let shard3 = [10, 12];
+        let shard4 = [20, 21];
+        let chain = |tracer: &std::rc::Rc<Tracer>| {
+            ChainSource::new(vec![
+                tracer.wrap("shard1", LinearIterator::new(&shard1)),
+                tracer.wrap("shard2", LinearIterator::new(&shard2)),
+                tracer.wrap("shard3", LinearIterator::new(&shard3)),
+                tracer.wrap("shard4", LinearIterator::new(&shard4)),
+            ])
+        };
+
+        let tracer = Tracer::new();
+        let mut iter = chain(&tracer);
+        let mut keys = vec![];
+        while !iter.at_end() {
+            keys.push(iter.key());
+            iter.next();
+        }
+        assert_eq!(keys, vec![1, 3, 5, 10, 12, 20, 21]);
+
+        // Seeking into the last shard does not touch the ones in between.
+        let tracer = Tracer::new();
+        let mut iter = chain(&tracer);
+        iter.seek(21);
+        assert_eq!(iter.key(), 21);
+        assert_eq!(tracer.len(), 1);
+        iter.seek(22);
+        assert!(iter.at_end());
+
+        let mut iter = chain(&tracer);
+        iter.seek(6);
+        assert_eq!(iter.key(), 10);
+        iter.seek(13);
+        assert_eq!(iter.key(), 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "Shards must be ordered by key range")]
+    fn test_chain_source_unordered() {
+        let shard1 = [5, 6];
+        let shard2 = [1, 2];
+        ChainSource::new(vec![
+            LinearIterator::new(&shard1),
+            LinearIterator::new(&shard2),
+        ]);
+    }
+
+    #[test]
+    fn test_chain_join() {
+        let tab: Vec<i32> = (0..300).filter(|x| x % 7 == 0).collect();
+        let all: Vec<i32> = (0..300).filter(|x| x % 2 == 0).collect();
+        let mut join = LeapFrogJoin::from_iters(vec![
+            ChainSource::new(vec![LinearIterator::new(&tab)]),
+            ChainSource::new(all.chunks(25).map(LinearIterator::new).collect()),
+        ]);
+        let mut result = vec![];
+        while !join.at_end() {
+            result.push(join.key());
+            join.next();
+        }
+        assert_eq!(result, intersect(vec![&tab, &all]));
+    }
+}
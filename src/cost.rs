@@ -0,0 +1,628 @@
+//! Cost model and planner.
+//!
+//! Every backend has a cost per seek(), per next() and per decoded key. The
+//! planner uses these to order the inputs of a join and to choose between
+//! two strategies:
+//!
+//! - Leapfrog: the leapfrog join, which seeks. Every input is sought at most
+//!   about once per key of the smallest input.
+//! - Scan: a merge that only calls next(), which reads every key of every
+//!   input, but never pays for a seek.
+//!
+//! The defaults are rough guesses; CostModel::calibrate() measures the
+//! in-memory slice backend on the current machine instead, with the `time`
+//! feature.
+//!
+//! If every input comes with a histogram, the planner only counts the keys
+//! inside the range all inputs overlap in, since the join skips everything
+//! outside of it with a single seek, and estimates the output size.
+//!
+//! CostModel::plan_with_hints() lets experts override the planner, e.g. to
+//! reproduce a plan regression: JoinHints force the leading input or the
+//! strategy, forbid a hash fallback, or fix the degree of parallelism. Hints
+//! that do not fit the inputs are errors; hints this planner cannot act on
+//! come back as HintWarnings next to the plan instead of being silently
+//! dropped.
+
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "time")]
+use std::hint::black_box;
+#[cfg(feature = "time")]
+use std::sync::OnceLock;
+#[cfg(feature = "time")]
+use std::time::Instant;
+
+#[cfg(feature = "time")]
+use crate::LinearIterator;
+use crate::histogram::{Histogram, common_range, estimate_intersection_size};
+use crate::{LeapFrogJoin, Seekable};
+
+/// Backend name of LinearIterator.
+pub const SLICE_BACKEND: &str = "slice";
+/// Backend name of RemoteIterator.
+pub const REMOTE_BACKEND: &str = "remote";
+
+/// Costs of one backend in nanoseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackendCosts {
+    pub seek: f64,
+    pub next: f64,
+    pub decode: f64,
+}
+
+/// CostModel maps backend names to their costs.
+#[derive(Clone, Debug)]
+pub struct CostModel {
+    backends: HashMap<String, BackendCosts>,
+    fallback: BackendCosts,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        let mut model = Self::new(BackendCosts {
+            seek: 50.0,
+            next: 2.0,
+            decode: 1.0,
+        });
+        model.set(
+            SLICE_BACKEND,
+            BackendCosts {
+                seek: 20.0,
+                next: 1.0,
+                decode: 0.0,
+            },
+        );
+        model.set(
+            REMOTE_BACKEND,
+            BackendCosts {
+                seek: 100_000.0,
+                next: 10.0,
+                decode: 5.0,
+            },
+        );
+        model
+    }
+}
+
+impl CostModel {
+    /// Creates a model in which every backend has the `fallback` costs.
+    pub fn new(fallback: BackendCosts) -> Self {
+        Self {
+            backends: HashMap::new(),
+            fallback,
+        }
+    }
+
+    pub fn set(&mut self, backend: &str, costs: BackendCosts) {
+        self.backends.insert(backend.to_string(), costs);
+    }
+
+    pub fn costs(&self, backend: &str) -> BackendCosts {
+        self.backends.get(backend).copied().unwrap_or(self.fallback)
+    }
+
+    /// Returns the default model with the slice backend measured by a quick
+    /// micro-benchmark (a few milliseconds).
+    #[cfg(feature = "time")]
+    pub fn calibrate() -> Self {
+        const LEN: usize = 1 << 16;
+        const STRIDE: u64 = 16;
+        let keys: Vec<u64> = (0..LEN as u64).collect();
+
+        let start = Instant::now();
+        let mut iter = LinearIterator::new(&keys);
+        while !iter.at_end() {
+            black_box(iter.key());
+            iter.next();
+        }
+        let next = start.elapsed().as_nanos() as f64 / LEN as f64;
+
+        let start = Instant::now();
+        let mut iter = LinearIterator::new(&keys);
+        let mut seeks = 0;
+        while !iter.at_end() {
+            let key = black_box(iter.key());
+            iter.seek(key + STRIDE);
+            seeks += 1;
+        }
+        let seek = start.elapsed().as_nanos() as f64 / seeks as f64;
+
+        let mut model = Self::default();
+        model.set(
+            SLICE_BACKEND,
+            BackendCosts {
+                seek,
+                next,
+                decode: 0.0,
+            },
+        );
+        model
+    }
+
+    /// Returns a model calibrated on first use and cached for the lifetime of
+    /// the process.
+    #[cfg(feature = "time")]
+    pub fn calibrated() -> &'static CostModel {
+        static MODEL: OnceLock<CostModel> = OnceLock::new();
+        MODEL.get_or_init(Self::calibrate)
+    }
+
+    /// Estimates the cost of both strategies and returns the cheaper plan.
+    pub fn plan(&self, inputs: &[InputStats<'_>]) -> Plan {
+        let estimate = self.estimate(inputs);
+        let (strategy, estimated_cost) = if estimate.scan < estimate.leapfrog {
+            (Strategy::Scan, estimate.scan)
+        } else {
+            (Strategy::Leapfrog, estimate.leapfrog)
+        };
+        Plan {
+            order: estimate.order,
+            strategy,
+            estimated_cost,
+            estimated_rows: estimate.rows,
+        }
+    }
+
+    /// Plans like plan() and applies `hints` on top, returning the plan
+    /// together with warnings about the hints that had no effect.
+    pub fn plan_with_hints(
+        &self,
+        inputs: &[InputStats<'_>],
+        hints: &JoinHints,
+    ) -> Result<(Plan, Vec<HintWarning>), HintError> {
+        if let Some(input) = hints.leading.filter(|&i| i >= inputs.len()) {
+            return Err(HintError::Leading {
+                input,
+                inputs: inputs.len(),
+            });
+        }
+        if hints.parallelism == Some(0) {
+            return Err(HintError::Parallelism);
+        }
+
+        let estimate = self.estimate(inputs);
+        let mut plan = self.plan(inputs);
+        let mut warnings = Vec::new();
+        if let Some(strategy) = hints.strategy {
+            plan.strategy = strategy;
+            plan.estimated_cost = match strategy {
+                Strategy::Leapfrog => estimate.leapfrog,
+                Strategy::Scan => estimate.scan,
+            };
+        }
+        if let Some(input) = hints.leading {
+            if plan.strategy == Strategy::Scan {
+                warnings.push(HintWarning::LeadingIgnoredByScan);
+            }
+            plan.order.retain(|&i| i != input);
+            plan.order.insert(0, input);
+        }
+        if hints.forbid_hash {
+            warnings.push(HintWarning::NoHashFallback);
+        }
+        if let Some(degree) = hints.parallelism.filter(|&d| d > 1) {
+            warnings.push(HintWarning::Serial { degree });
+        }
+        Ok((plan, warnings))
+    }
+
+    fn estimate(&self, inputs: &[InputStats<'_>]) -> Estimate {
+        let costs: Vec<BackendCosts> = inputs.iter().map(|i| self.costs(i.backend)).collect();
+        let histograms: Option<Vec<&Histogram>> = inputs.iter().map(|i| i.histogram).collect();
+        let (lens, estimated_rows) = match histograms {
+            Some(histograms) if !histograms.is_empty() => {
+                let lens = match common_range(&histograms) {
+                    Some((lo, hi)) => histograms
+                        .iter()
+                        .map(|h| h.estimate_range(lo, hi))
+                        .collect(),
+                    None => vec![0.0; histograms.len()],
+                };
+                (lens, estimate_intersection_size(&histograms))
+            }
+            _ => {
+                let lens: Vec<f64> = inputs.iter().map(|i| i.len as f64).collect();
+                let min_len = lens.iter().copied().fold(f64::INFINITY, f64::min);
+                (lens, if inputs.is_empty() { 0.0 } else { min_len })
+            }
+        };
+        let min_len = lens.iter().copied().fold(f64::INFINITY, f64::min);
+
+        let leapfrog: f64 = lens
+            .iter()
+            .zip(&costs)
+            .map(|(&len, c)| len.min(min_len + 1.0) * (c.seek + c.decode))
+            .sum();
+        let scan: f64 = inputs
+            .iter()
+            .zip(&costs)
+            .map(|(i, c)| i.len as f64 * (c.next + c.decode))
+            .sum();
+
+        // Most selective inputs first; among equally selective ones the
+        // cheapest to seek.
+        let mut order: Vec<usize> = (0..inputs.len()).collect();
+        order.sort_by(|&a, &b| {
+            lens[a]
+                .total_cmp(&lens[b])
+                .then(costs[a].seek.total_cmp(&costs[b].seek))
+        });
+
+        Estimate {
+            order,
+            leapfrog,
+            scan,
+            rows: estimated_rows,
+        }
+    }
+}
+
+/// The costs of both strategies, before choosing one.
+struct Estimate {
+    order: Vec<usize>,
+    leapfrog: f64,
+    scan: f64,
+    rows: f64,
+}
+
+/// What the planner knows about one join input.
+#[derive(Clone, Copy, Debug)]
+pub struct InputStats<'a> {
+    pub backend: &'a str,
+    pub len: usize,
+    pub histogram: Option<&'a Histogram>,
+}
+
+impl<'a> InputStats<'a> {
+    pub fn new(backend: &'a str, len: usize) -> Self {
+        Self {
+            backend,
+            len,
+            histogram: None,
+        }
+    }
+
+    pub fn with_histogram(backend: &'a str, histogram: &'a Histogram) -> Self {
+        Self {
+            backend,
+            len: histogram.len(),
+            histogram: Some(histogram),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    Leapfrog,
+    Scan,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+    /// Input indices in the order they are handed to the join.
+    pub order: Vec<usize>,
+    pub strategy: Strategy,
+    /// Estimated cost in nanoseconds.
+    pub estimated_cost: f64,
+    /// Estimated number of result keys.
+    pub estimated_rows: f64,
+}
+
+/// Overrides of the planner, see CostModel::plan_with_hints().
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JoinHints {
+    /// The input the join leads with, i.e. seeks all others to.
+    pub leading: Option<usize>,
+    pub strategy: Option<Strategy>,
+    /// Never fall back to hashing an input.
+    pub forbid_hash: bool,
+    /// Threads the join runs on.
+    pub parallelism: Option<usize>,
+}
+
+impl JoinHints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn leading(mut self, input: usize) -> Self {
+        self.leading = Some(input);
+        self
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    pub fn forbid_hash(mut self) -> Self {
+        self.forbid_hash = true;
+        self
+    }
+
+    pub fn parallelism(mut self, degree: usize) -> Self {
+        self.parallelism = Some(degree);
+        self
+    }
+}
+
+/// Hints that contradict the inputs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HintError {
+    /// The leading input does not exist.
+    Leading { input: usize, inputs: usize },
+    /// A degree of parallelism of 0.
+    Parallelism,
+}
+
+impl fmt::Display for HintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HintError::Leading { input, inputs } => {
+                write!(f, "leading input {input} of only {inputs} inputs")
+            }
+            HintError::Parallelism => write!(f, "parallelism must be at least 1"),
+        }
+    }
+}
+
+impl std::error::Error for HintError {}
+
+/// Hints that are valid but had no effect on the plan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HintWarning {
+    /// The scan reads every input in full, whichever leads.
+    LeadingIgnoredByScan,
+    /// Neither strategy hashes, so there is no fallback to forbid.
+    NoHashFallback,
+    /// Plans execute on one thread.
+    Serial { degree: usize },
+}
+
+impl fmt::Display for HintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HintWarning::LeadingIgnoredByScan => {
+                write!(f, "the leading input does not matter to a scan")
+            }
+            HintWarning::NoHashFallback => write!(f, "the planner never falls back to hashing"),
+            HintWarning::Serial { degree } => {
+                write!(f, "parallelism {degree} ignored, plans execute serially")
+            }
+        }
+    }
+}
+
+/// Runs `plan` over `iters`, which must correspond to the planned inputs.
+/// The leapfrog join visits them in the planned order, see
+/// LeapFrogJoin::from_iters_ordered().
+pub fn execute<I: Seekable>(plan: &Plan, iters: Vec<I>) -> Vec<I::Key> {
+    assert_eq!(plan.order.len(), iters.len(), "Plan does not match inputs");
+    match plan.strategy {
+        Strategy::Leapfrog => {
+            let mut join = LeapFrogJoin::from_iters_ordered(iters, &plan.order);
+            let mut result = Vec::new();
+            while !join.at_end() {
+                result.push(join.key());
+                join.next();
+            }
+            result
+        }
+        Strategy::Scan => scan_intersect(iters),
+    }
+}
+
+/// Intersects the inputs by a merge that only ever calls next().
+fn scan_intersect<I: Seekable>(mut iters: Vec<I>) -> Vec<I::Key> {
+    let mut result = Vec::new();
+    if iters.is_empty() {
+        return result;
+    }
+    'outer: loop {
+        if iters.iter().any(|iter| iter.at_end()) {
+            break;
+        }
+        let max_key = iters.iter().map(|iter| iter.key()).max().unwrap();
+        for iter in iters.iter_mut() {
+            while iter.key() < max_key {
+                iter.next();
+                if iter.at_end() {
+                    break 'outer;
+                }
+            }
+        }
+        if iters.iter().all(|iter| iter.key() == max_key) {
+            result.push(max_key);
+            for iter in iters.iter_mut() {
+                iter.next();
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{LinearIterator, intersect};
+
+    #[test]
+    fn test_plan_prefers_leapfrog_for_skewed_inputs() {
+        let model = CostModel::default();
+        let plan = model.plan(&[
+            InputStats::new(SLICE_BACKEND, 1_000_000),
+            InputStats::new(SLICE_BACKEND, 10),
+        ]);
+        assert_eq!(plan.strategy, Strategy::Leapfrog);
+        assert_eq!(plan.order, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_plan_prefers_scan_for_expensive_seeks() {
+        let model = CostModel::default();
+        let plan = model.plan(&[
+            InputStats::new(REMOTE_BACKEND, 100),
+            InputStats::new(REMOTE_BACKEND, 100),
+        ]);
+        assert_eq!(plan.strategy, Strategy::Scan);
+    }
+
+    #[test]
+    fn test_plan_with_histograms() {
+        // Large, but sparse in the range it shares with the other input.
+        let wide: Vec<i64> = (0..99_000).chain((99_000..101_000).step_by(10)).collect();
+        let narrow: Vec<i64> = (99_000..101_000).collect();
+        let hw = Histogram::build(&wide, 1000);
+        let hn = Histogram::build(&narrow, 32);
+        let plan = CostModel::default().plan(&[
+            InputStats::with_histogram(SLICE_BACKEND, &hn),
+            InputStats::with_histogram(SLICE_BACKEND, &hw),
+        ]);
+        assert_eq!(plan.order, vec![1, 0]);
+        assert!((plan.estimated_rows - 200.0).abs() < 20.0, "{plan:?}");
+    }
+
+    #[test]
+    fn test_execute_strategies_agree() {
+        let tab1: Vec<i32> = (0..300).filter(|x| x % 2 == 0).collect();
+        let tab2: Vec<i32> = (0..300).filter(|x| x % 7 < 3).collect();
+        let expected = intersect(vec![&tab1, &tab2]);
+        for strategy in [Strategy::Leapfrog, Strategy::Scan] {
+            let plan = Plan {
+                order: vec![1, 0],
+                strategy,
+                estimated_cost: 0.0,
+                estimated_rows: 0.0,
+            };
+            let iters = vec![LinearIterator::new(&tab1), LinearIterator::new(&tab2)];
+            assert_eq!(execute(&plan, iters), expected);
+        }
+    }
+
+    #[test]
+    fn test_plan_with_hints() {
+        let model = CostModel::default();
+        let inputs = [
+            InputStats::new(SLICE_BACKEND, 1_000_000),
+            InputStats::new(SLICE_BACKEND, 10),
+            InputStats::new(SLICE_BACKEND, 1000),
+        ];
+        let (plan, warnings) = model.plan_with_hints(&inputs, &JoinHints::new()).unwrap();
+        assert_eq!(plan, model.plan(&inputs));
+        assert!(warnings.is_empty());
+
+        let hints = JoinHints::new().leading(0);
+        let (plan, warnings) = model.plan_with_hints(&inputs, &hints).unwrap();
+        assert_eq!(plan.order, vec![0, 1, 2]);
+        assert!(warnings.is_empty());
+
+        let hints = JoinHints::new()
+            .strategy(Strategy::Scan)
+            .leading(2)
+            .forbid_hash()
+            .parallelism(4);
+        let (plan, warnings) = model.plan_with_hints(&inputs, &hints).unwrap();
+        assert_eq!(plan.strategy, Strategy::Scan);
+        assert!(plan.estimated_cost > model.plan(&inputs).estimated_cost);
+        assert_eq!(plan.order, vec![2, 1, 0]);
+        assert_eq!(
+            warnings,
+            [
+                HintWarning::LeadingIgnoredByScan,
+                HintWarning::NoHashFallback,
+                HintWarning::Serial { degree: 4 },
+            ]
+        );
+
+        let leading = model.plan_with_hints(&inputs, &JoinHints::new().leading(3));
+        assert_eq!(
+            leading.err(),
+            Some(HintError::Leading {
+                input: 3,
+                inputs: 3
+            })
+        );
+        let parallelism = model.plan_with_hints(&inputs, &JoinHints::new().parallelism(0));
+        assert_eq!(parallelism.err(), Some(HintError::Parallelism));
+    }
+
+    /// Logs its seeks as (input, seek key) to a log shared by all inputs.
+    struct Logged<'a> {
+        iter: LinearIterator<'a, i32>,
+        input: usize,
+        log: Rc<RefCell<Vec<(usize, i32)>>>,
+    }
+
+    impl Seekable for Logged<'_> {
+        type Key = i32;
+
+        fn key(&self) -> i32 {
+            self.iter.key()
+        }
+
+        fn next(&mut self) {
+            self.iter.next()
+        }
+
+        fn seek(&mut self, seek_key: i32) {
+            self.log.borrow_mut().push((self.input, seek_key));
+            self.iter.seek(seek_key)
+        }
+
+        fn at_end(&self) -> bool {
+            self.iter.at_end()
+        }
+    }
+
+    #[test]
+    fn test_execute_with_leading_hint() {
+        let all: Vec<i32> = (0..100).collect();
+        let tens: Vec<i32> = (10..100).step_by(10).collect();
+        let upper: Vec<i32> = (50..100).collect();
+        let sources: [&[i32]; 3] = [&all, &tens, &upper];
+        let inputs = sources.map(|s| InputStats::new(SLICE_BACKEND, s.len()));
+        let run = |plan: &Plan| {
+            let log = Rc::new(RefCell::new(vec![]));
+            let iters = (0..sources.len())
+                .map(|input| Logged {
+                    iter: LinearIterator::new(sources[input]),
+                    input,
+                    log: log.clone(),
+                })
+                .collect();
+            let keys = execute(plan, iters);
+            assert_eq!(keys, intersect(sources.to_vec()));
+            log.borrow()[0]
+        };
+
+        let model = CostModel::default();
+        let leapfrog = JoinHints::new().strategy(Strategy::Leapfrog);
+        let (plan, _) = model.plan_with_hints(&inputs, &leapfrog).unwrap();
+        assert_eq!(plan.order, vec![1, 2, 0]);
+        // Input 1 leads with 10, and input 0 catches up to input 2 first.
+        assert_eq!(run(&plan), (0, 50));
+
+        let (plan, warnings) = model
+            .plan_with_hints(&inputs, &leapfrog.leading(2))
+            .unwrap();
+        assert!(warnings.is_empty());
+        // Input 2 leads, so the next input is sought to its first key.
+        assert_eq!(run(&plan), (1, 50));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_calibrate() {
+        let costs = CostModel::calibrated().costs(SLICE_BACKEND);
+        assert!(costs.next > 0.0);
+        assert!(costs.seek > 0.0);
+        assert_eq!(
+            CostModel::calibrated().costs("unknown"),
+            CostModel::default().costs("unknown")
+        );
+    }
+}
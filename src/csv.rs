@@ -0,0 +1,246 @@
+//! CSV columns as join sources.
+//!
+//! CsvReader picks one column of a CSV file, by index or header name, and
+//! turns it into a CsvSource: a Seekable that parses keys with the strict
+//! rules of the ingest module as it reads records, so a file can be joined
+//! without loading it first. Quoting and escaping follow RFC 4180, as
+//! implemented by the csv crate.
+//!
+//! How the column gets in order, and what happens on errors, is up to
+//! KeySource, which CsvSource is an instance of.
+
+use std::fmt;
+use std::io::Read;
+use std::marker::PhantomData;
+
+use ::csv::{ReaderBuilder, StringRecord};
+
+use crate::ingest::{
+    KeyReader, KeySource, Order, ParseError, ParseErrorKind, ParseKey, parse_field,
+};
+
+#[derive(Debug)]
+pub enum CsvError {
+    Csv(::csv::Error),
+    Parse(ParseError),
+    /// No header names the column.
+    UnknownColumn(String),
+    /// The key at `line` is smaller than the one before it.
+    Unsorted {
+        line: u64,
+    },
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::Csv(e) => write!(f, "CSV error: {e}"),
+            CsvError::Parse(e) => write!(f, "{e}"),
+            CsvError::UnknownColumn(name) => write!(f, "no column named {name:?}"),
+            CsvError::Unsorted { line } => {
+                write!(f, "line {line}: key is smaller than the one before")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+impl From<::csv::Error> for CsvError {
+    fn from(e: ::csv::Error) -> Self {
+        CsvError::Csv(e)
+    }
+}
+
+impl From<ParseError> for CsvError {
+    fn from(e: ParseError) -> Self {
+        CsvError::Parse(e)
+    }
+}
+
+/// The column to read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    /// Counted from 0.
+    Index(usize),
+    /// Requires a header.
+    Name(String),
+}
+
+/// CsvReader configures how a column is read.
+pub struct CsvReader<R> {
+    reader: R,
+    column: Column,
+    delimiter: u8,
+    has_headers: bool,
+    order: Order,
+}
+
+impl<R: Read> CsvReader<R> {
+    /// Creates a reader of the first column of comma-separated records with
+    /// a header, verifying that it is sorted.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            column: Column::Index(0),
+            delimiter: b',',
+            has_headers: true,
+            order: Order::Verify,
+        }
+    }
+
+    pub fn column(mut self, column: Column) -> Self {
+        self.column = column;
+        self
+    }
+
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Opens the column as a source, positioned at its first key. With
+    /// Order::Sort, this reads the whole column.
+    pub fn source<K: ParseKey + Ord + Copy>(self) -> Result<CsvSource<R, K>, CsvError> {
+        let mut records = ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .from_reader(self.reader);
+        let column = match self.column {
+            Column::Index(column) => column,
+            Column::Name(name) => match records.headers()?.iter().position(|h| h == name) {
+                Some(column) => column,
+                None => return Err(CsvError::UnknownColumn(name)),
+            },
+        };
+        let keys = CsvKeys {
+            records,
+            record: StringRecord::new(),
+            column,
+            _key: PhantomData,
+        };
+        KeySource::new(keys, self.order)
+    }
+}
+
+/// CsvSource is a sorted column of a CSV file, see CsvReader.
+pub type CsvSource<R, K> = KeySource<CsvKeys<R, K>>;
+
+/// The keys of a column, in file order.
+pub struct CsvKeys<R, K> {
+    records: ::csv::Reader<R>,
+    record: StringRecord,
+    column: usize,
+    _key: PhantomData<K>,
+}
+
+impl<R: Read, K: ParseKey + Ord + Copy> KeyReader for CsvKeys<R, K> {
+    type Key = K;
+    type Error = CsvError;
+
+    fn read_key(&mut self) -> Result<Option<K>, CsvError> {
+        if !self.records.read_record(&mut self.record)? {
+            return Ok(None);
+        }
+        let line = self.record.position().map_or(0, |p| p.line()) as usize;
+        let column = self.column + 1;
+        match self.record.get(self.column) {
+            Some(text) => Ok(Some(parse_field(text, line, column)?)),
+            None => Err(ParseError {
+                line,
+                column,
+                text: String::new(),
+                kind: ParseErrorKind::MissingColumn,
+            }
+            .into()),
+        }
+    }
+
+    fn unsorted(&self) -> CsvError {
+        let line = self.record.position().map_or(0, |p| p.line());
+        CsvError::Unsorted { line }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LeapFrogJoin, LinearIterator, Seekable};
+
+    fn keys<R: Read>(mut source: CsvSource<R, u32>) -> Vec<u32> {
+        let mut keys = vec![];
+        while !source.at_end() {
+            keys.push(source.key());
+            source.next();
+        }
+        assert!(source.error().is_none(), "{:?}", source.error());
+        keys
+    }
+
+    #[test]
+    fn test_csv_source() {
+        let csv = "name,id\n\"Smith, J.\",3\n\"say \"\"hi\"\"\",5\nx,5\n\"multi\nline\",9\n";
+        let source = CsvReader::new(csv.as_bytes())
+            .column(Column::Name("id".into()))
+            .source()
+            .unwrap();
+        assert_eq!(keys(source), [3, 5, 9]);
+
+        let source = CsvReader::new("7;1\n9;4\n2;6\n".as_bytes())
+            .delimiter(b';')
+            .has_headers(false)
+            .order(Order::Sort)
+            .source::<u32>()
+            .unwrap();
+        assert_eq!(source.exact_len_hint(), Some(3));
+        assert_eq!(keys(source), [2, 7, 9]);
+
+        let others = [1, 2, 5, 9];
+        let source = CsvReader::new(csv.as_bytes()).column(Column::Index(1));
+        let mut join = LeapFrogJoin::from_iters(vec![
+            Box::new(source.source::<u32>().unwrap()) as Box<dyn Seekable<Key = u32>>,
+            Box::new(LinearIterator::new(&others)),
+        ]);
+        join.skip_to(4);
+        assert_eq!(join.into_keys().collect::<Vec<_>>(), [5, 9]);
+    }
+
+    #[test]
+    fn test_csv_errors() {
+        let csv = "id\n1\n3\n2\n";
+        let mut source = CsvReader::new(csv.as_bytes()).source::<u32>().unwrap();
+        source.next();
+        source.next();
+        assert!(source.at_end());
+        assert!(matches!(
+            source.error(),
+            Some(CsvError::Unsorted { line: 4 })
+        ));
+        let source = CsvReader::new(csv.as_bytes()).order(Order::Trust);
+        assert_eq!(keys(source.source().unwrap()), [1, 3, 2]);
+
+        let error = CsvReader::new("id\n1\n1x\n".as_bytes())
+            .order(Order::Sort)
+            .source::<u32>()
+            .err();
+        let Some(CsvError::Parse(e)) = error else {
+            panic!("expected a parse error, got {error:?}");
+        };
+        assert_eq!((e.line, e.column), (3, 1));
+        let error = CsvReader::new("id\n".as_bytes())
+            .column(Column::Name("key".into()))
+            .source::<u32>()
+            .err();
+        assert!(matches!(error, Some(CsvError::UnknownColumn(_))));
+    }
+}
@@ -0,0 +1,1022 @@
+//! A container of named relations and their materialized permutations.
+//!
+//! Every atom of a triejoin needs its relation sorted in the order the query
+//! binds its attributes. A Database stores each relation once in its
+//! original attribute order, plus any permutations that were materialized
+//! for it, and routes queries to them: a DatabaseQuery names relations and
+//! lists variables per attribute in any order, and Database::query() turns
+//! it into a Query over the matching permutations.
+//!
+//! Missing permutations are built by Database::prepare(), or explicitly by
+//! Database::materialize(), e.g. for the orders an IndexAdvisor recommends.
+//! Building calls the progress callback while copying rows and once when
+//! done, since large relations can take a while.
+//!
+//! Reads go through snapshots with snapshot isolation: Database::snapshot()
+//! returns the current version, which stays unchanged while joins read it.
+//! Writers copy the map of relations, which shares the relations themselves,
+//! change the copy and swap it in as the next version atomically. Writers
+//! are serialized, so none of their changes are lost.
+//!
+//! With the `threads` feature, Database::build_in_background() builds a
+//! permutation on its own thread, with a BuildHandle to watch its progress
+//! or cancel it. Until it is published, Snapshot::fallback() runs queries
+//! on private copies sorted for the query alone.
+//!
+//! Database::set_metadata() declares sort orders, unique keys and
+//! functional dependencies of a relation, see Metadata. They are checked
+//! against the relation when declared, and permutations whose order the
+//! rows already have are built by copying the columns without sorting.
+//!
+//! A Database created with Database::with_budget() accounts the relations
+//! and permutations it stores, and the tuples staged while building them,
+//! against a MemoryBudget. Writes that would exceed it fail with
+//! DatabaseError::Memory and leave the current version unchanged. Memory is
+//! released once the last snapshot holding a relation is dropped.
+
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "threads")]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+#[cfg(feature = "threads")]
+use std::thread::{self, JoinHandle};
+
+use crate::memory::{Category, MemoryBudget, MemoryError, Reservation};
+use crate::metadata::{Metadata, MetadataError};
+use crate::query::{Query, QueryError};
+use crate::trie::TrieRelation;
+
+/// Rows copied between two progress reports.
+const PROGRESS_ROWS: usize = 1 << 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DatabaseError {
+    UnknownRelation(String),
+    /// An atom has the wrong number of variables.
+    Arity {
+        relation: String,
+        expected: usize,
+        found: usize,
+    },
+    /// An order is not a permutation of the attributes of the relation.
+    InvalidOrder {
+        relation: String,
+        order: Vec<usize>,
+    },
+    /// A query needs a permutation that was not materialized.
+    NotMaterialized {
+        relation: String,
+        order: Vec<usize>,
+    },
+    Query(QueryError),
+    /// A background build was cancelled.
+    Cancelled,
+    /// A relation was replaced while a permutation of it was built.
+    RelationChanged(String),
+    Memory(MemoryError),
+    /// Metadata does not hold for a relation.
+    Metadata {
+        relation: String,
+        error: MetadataError,
+    },
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::UnknownRelation(name) => write!(f, "unknown relation {name:?}"),
+            DatabaseError::Arity {
+                relation,
+                expected,
+                found,
+            } => write!(
+                f,
+                "relation {relation:?} has arity {expected}, but an atom has {found} variables"
+            ),
+            DatabaseError::InvalidOrder { relation, order } => {
+                write!(f, "{order:?} is not an attribute order of {relation:?}")
+            }
+            DatabaseError::NotMaterialized { relation, order } => {
+                write!(f, "order {order:?} of {relation:?} is not materialized")
+            }
+            DatabaseError::Query(error) => write!(f, "{error}"),
+            DatabaseError::Cancelled => write!(f, "build cancelled"),
+            DatabaseError::RelationChanged(name) => {
+                write!(f, "relation {name:?} changed during the build")
+            }
+            DatabaseError::Memory(error) => write!(f, "{error}"),
+            DatabaseError::Metadata { relation, error } => {
+                write!(f, "metadata of {relation:?}: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<QueryError> for DatabaseError {
+    fn from(error: QueryError) -> Self {
+        DatabaseError::Query(error)
+    }
+}
+
+impl From<MemoryError> for DatabaseError {
+    fn from(error: MemoryError) -> Self {
+        DatabaseError::Memory(error)
+    }
+}
+
+/// Progress of building a permutation: `done` of `total` rows were copied.
+/// The last report of a build has `done == total`, after sorting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildProgress<'p> {
+    pub relation: &'p str,
+    pub order: &'p [usize],
+    pub done: usize,
+    pub total: usize,
+}
+
+type BuildProgressFn = Box<dyn FnMut(&BuildProgress<'_>) + Send>;
+
+/// A stored trie and the memory reserved for it, shared by all versions
+/// holding it.
+#[derive(Clone)]
+struct Accounted<K> {
+    trie: Arc<TrieRelation<K>>,
+    _reservation: Option<Arc<Reservation>>,
+}
+
+impl<K> Accounted<K> {
+    fn new(trie: TrieRelation<K>, reservation: Option<Reservation>) -> Self {
+        Self {
+            trie: Arc::new(trie),
+            _reservation: reservation.map(Arc::new),
+        }
+    }
+}
+
+/// A relation and the permutations materialized for it.
+#[derive(Clone)]
+struct Stored<K> {
+    relation: Accounted<K>,
+    /// The materialized permutations by attribute order, without the
+    /// original order.
+    permutations: HashMap<Vec<usize>, Accounted<K>>,
+    metadata: Arc<Metadata>,
+}
+
+/// Snapshot is an immutable version of a Database. Writes to the database
+/// create new versions and never change a snapshot.
+pub struct Snapshot<K> {
+    version: u64,
+    relations: HashMap<String, Stored<K>>,
+    budget: Option<Arc<MemoryBudget>>,
+}
+
+impl<K: Ord + Copy> Snapshot<K> {
+    /// Number of writes before this version.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the relation `name` in its original attribute order.
+    pub fn relation(&self, name: &str) -> Option<&TrieRelation<K>> {
+        self.relations
+            .get(name)
+            .map(|stored| &*stored.relation.trie)
+    }
+
+    /// Returns the metadata declared for the relation `name`.
+    pub fn metadata(&self, name: &str) -> Option<&Metadata> {
+        self.relations.get(name).map(|stored| &*stored.metadata)
+    }
+
+    /// Returns the relation `name` sorted by `order`, if materialized.
+    pub fn permutation(&self, name: &str, order: &[usize]) -> Option<&TrieRelation<K>> {
+        let stored = self.relations.get(name)?;
+        if order.is_sorted() && order.len() == stored.relation.trie.arity() {
+            Some(&stored.relation.trie)
+        } else {
+            stored.permutations.get(order).map(|p| &*p.trie)
+        }
+    }
+
+    /// The materialized orders of the relation `name`, except the original
+    /// one, sorted.
+    pub fn materialized(&self, name: &str) -> Vec<&[usize]> {
+        let mut orders: Vec<&[usize]> = self
+            .relations
+            .get(name)
+            .into_iter()
+            .flat_map(|stored| stored.permutations.keys().map(Vec::as_slice))
+            .collect();
+        orders.sort_unstable();
+        orders
+    }
+
+    /// Returns `query` as a Query over the permutations it needs, which must
+    /// be materialized. Filters and other conditions can be added to the
+    /// result before executing it.
+    pub fn query(&self, query: &DatabaseQuery) -> Result<Query<'_, K>, DatabaseError> {
+        self.query_on(query, |name, order| self.permutation(name, order))
+    }
+
+    /// Builds private copies of the permutations `query` needs and that are
+    /// not materialized, to query them while they are built in the
+    /// background. The copies count against the budget of the database
+    /// until the fallback is dropped.
+    pub fn fallback(&self, query: &DatabaseQuery) -> Result<Fallback<'_, K>, DatabaseError> {
+        let mut temporary = HashMap::new();
+        for route in self.route(query)? {
+            if self.permutation(&route.relation, &route.order).is_none() {
+                let stored = &self.relations[&route.relation];
+                let permutation = build_accounted(
+                    &self.budget,
+                    &stored.relation.trie,
+                    &stored.metadata,
+                    &route.order,
+                    &mut |_| true,
+                )?;
+                temporary.insert((route.relation, route.order), permutation);
+            }
+        }
+        Ok(Fallback {
+            snapshot: self,
+            temporary,
+        })
+    }
+
+    /// Returns `query` as a Query over the relations `lookup` returns.
+    pub(crate) fn query_on<'s>(
+        &self,
+        query: &DatabaseQuery,
+        lookup: impl Fn(&str, &[usize]) -> Option<&'s TrieRelation<K>>,
+    ) -> Result<Query<'s, K>, DatabaseError> {
+        self.query_routed(query, &self.route(query)?, lookup)
+    }
+
+    /// Returns `query` as a Query like query_on(), along `routes` computed
+    /// by route() before.
+    pub(crate) fn query_routed<'s>(
+        &self,
+        query: &DatabaseQuery,
+        routes: &[Route],
+        lookup: impl Fn(&str, &[usize]) -> Option<&'s TrieRelation<K>>,
+    ) -> Result<Query<'s, K>, DatabaseError> {
+        let mut result = Query::new();
+        for (route, atom) in routes.iter().zip(&query.atoms) {
+            let relation = lookup(&route.relation, &route.order).ok_or_else(|| {
+                DatabaseError::NotMaterialized {
+                    relation: route.relation.clone(),
+                    order: route.order.clone(),
+                }
+            })?;
+            let variables: Vec<&str> = route.variables.iter().map(String::as_str).collect();
+            result = match atom.kind {
+                AtomKind::Join => result.atom(relation, &variables),
+                AtomKind::Exists => result.exists(relation, &variables),
+                AtomKind::Not => result.not(relation, &variables),
+            };
+        }
+        let order = query.variable_order();
+        let order: Vec<&str> = order.iter().map(String::as_str).collect();
+        Ok(result.order(&order))
+    }
+
+    /// Per atom of `query`, the relation and the attribute order it needs.
+    pub(crate) fn orders(
+        &self,
+        query: &DatabaseQuery,
+    ) -> Result<Vec<(String, Vec<usize>)>, DatabaseError> {
+        let routes = self.route(query)?;
+        Ok(routes.into_iter().map(|r| (r.relation, r.order)).collect())
+    }
+
+    /// Per atom of `query`, the relation, the attribute order it needs and
+    /// its variables in that order.
+    pub(crate) fn route(&self, query: &DatabaseQuery) -> Result<Vec<Route>, DatabaseError> {
+        let order = query.variable_order();
+        let position = |v: &String| order.iter().position(|o| o == v);
+        query
+            .atoms
+            .iter()
+            .map(|atom| {
+                let relation = self
+                    .relation(&atom.relation)
+                    .ok_or_else(|| DatabaseError::UnknownRelation(atom.relation.clone()))?;
+                if relation.arity() != atom.variables.len() {
+                    return Err(DatabaseError::Arity {
+                        relation: atom.relation.clone(),
+                        expected: relation.arity(),
+                        found: atom.variables.len(),
+                    });
+                }
+                // Local variables of probed atoms sort last.
+                let mut attributes: Vec<usize> = (0..atom.variables.len()).collect();
+                attributes.sort_by_key(|&a| position(&atom.variables[a]).unwrap_or(usize::MAX));
+                let variables = attributes.iter().map(|&a| atom.variables[a].clone());
+                Ok(Route {
+                    relation: atom.relation.clone(),
+                    variables: variables.collect(),
+                    order: attributes,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Fallback holds the permutations a query needs that a snapshot lacks.
+pub struct Fallback<'s, K> {
+    snapshot: &'s Snapshot<K>,
+    temporary: HashMap<(String, Vec<usize>), Accounted<K>>,
+}
+
+impl<K: Ord + Copy> Fallback<'_, K> {
+    /// Returns `query` as a Query over the permutations of the snapshot and
+    /// the private copies, which must include all permutations `query`
+    /// needs, e.g. since the fallback was built for it.
+    pub fn query(&self, query: &DatabaseQuery) -> Result<Query<'_, K>, DatabaseError> {
+        self.snapshot.query_on(query, |name, order| {
+            let key = (name.to_string(), order.to_vec());
+            self.snapshot
+                .permutation(name, order)
+                .or_else(|| self.temporary.get(&key).map(|p| &*p.trie))
+        })
+    }
+
+    /// Number of permutations the snapshot lacked.
+    pub fn built(&self) -> usize {
+        self.temporary.len()
+    }
+}
+
+/// BuildHandle watches a permutation built in the background.
+#[cfg(feature = "threads")]
+pub struct BuildHandle {
+    state: Arc<BuildState>,
+    thread: JoinHandle<Result<(), DatabaseError>>,
+}
+
+#[cfg(feature = "threads")]
+struct BuildState {
+    done: AtomicUsize,
+    total: usize,
+    cancelled: AtomicBool,
+}
+
+#[cfg(feature = "threads")]
+impl BuildHandle {
+    /// Rows copied so far and in total.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.state.done.load(Ordering::Relaxed), self.state.total)
+    }
+
+    /// Asks the build to stop. It stops at the next progress report.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits until the permutation is published, or the build failed.
+    pub fn wait(self) -> Result<(), DatabaseError> {
+        self.thread.join().expect("Build thread panicked")
+    }
+}
+
+/// Database holds named relations and their materialized permutations.
+/// Readers take snapshots; writers are serialized and publish a new
+/// snapshot atomically when done.
+pub struct Database<K> {
+    current: RwLock<Arc<Snapshot<K>>>,
+    /// Held by writers, together with the progress callback for builds.
+    writer: Mutex<Option<BuildProgressFn>>,
+    budget: Option<Arc<MemoryBudget>>,
+}
+
+impl<K> Default for Database<K> {
+    fn default() -> Self {
+        Self {
+            current: RwLock::new(Arc::new(Snapshot {
+                version: 0,
+                relations: HashMap::new(),
+                budget: None,
+            })),
+            writer: Mutex::new(None),
+            budget: None,
+        }
+    }
+}
+
+impl<K: Ord + Copy> Database<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a database that accounts its tries and build buffers against
+    /// `budget`.
+    pub fn with_budget(budget: Arc<MemoryBudget>) -> Self {
+        let db = Self::default();
+        let mut snapshot = db.current.write().unwrap();
+        Arc::get_mut(&mut snapshot).unwrap().budget = Some(budget.clone());
+        drop(snapshot);
+        Self {
+            budget: Some(budget),
+            ..db
+        }
+    }
+
+    pub fn budget(&self) -> Option<&Arc<MemoryBudget>> {
+        self.budget.as_ref()
+    }
+
+    /// Returns the current version. Joins over it keep reading it while
+    /// writers publish newer versions.
+    pub fn snapshot(&self) -> Arc<Snapshot<K>> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Calls `f` while building permutations.
+    pub fn on_progress(&self, f: impl FnMut(&BuildProgress<'_>) + Send + 'static) {
+        *self.writer.lock().unwrap() = Some(Box::new(f));
+    }
+
+    /// Adds or replaces the relation `name`, dropping the permutations of a
+    /// replaced relation. Fails if the relation exceeds the budget.
+    pub fn insert(&self, name: &str, relation: TrieRelation<K>) -> Result<(), DatabaseError> {
+        let reservation = reserve(&self.budget, Category::Tries, relation.heap_size())?;
+        self.write(|relations, _| {
+            let stored = Stored {
+                relation: Accounted::new(relation, reservation),
+                permutations: HashMap::new(),
+                metadata: Arc::default(),
+            };
+            relations.insert(name.to_string(), stored);
+            Ok(())
+        })
+    }
+
+    /// Declares the metadata of the relation `name`, replacing any declared
+    /// before. Fails if it does not hold for the relation.
+    pub fn set_metadata(&self, name: &str, metadata: Metadata) -> Result<(), DatabaseError> {
+        self.write(|relations, _| {
+            let stored = relations
+                .get_mut(name)
+                .ok_or_else(|| DatabaseError::UnknownRelation(name.to_string()))?;
+            metadata
+                .check_relation(&stored.relation.trie)
+                .map_err(|error| DatabaseError::Metadata {
+                    relation: name.to_string(),
+                    error,
+                })?;
+            stored.metadata = Arc::new(metadata);
+            Ok(())
+        })
+    }
+
+    /// Removes the relation `name`. The returned relation is no longer
+    /// accounted once the snapshots holding it are dropped.
+    pub fn remove(&self, name: &str) -> Option<Arc<TrieRelation<K>>> {
+        self.write(|relations, _| Ok(relations.remove(name).map(|stored| stored.relation.trie)))
+            .unwrap()
+    }
+
+    /// Builds the permutation of the relation `name` sorted by `order`,
+    /// unless it exists.
+    pub fn materialize(&self, name: &str, order: &[usize]) -> Result<(), DatabaseError> {
+        let stored = self.snapshot().relations.get(name).cloned();
+        let stored = stored.ok_or_else(|| DatabaseError::UnknownRelation(name.to_string()))?;
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        if !sorted.iter().copied().eq(0..stored.relation.trie.arity()) {
+            return Err(DatabaseError::InvalidOrder {
+                relation: name.to_string(),
+                order: order.to_vec(),
+            });
+        }
+        if order.is_sorted() || stored.permutations.contains_key(order) {
+            return Ok(());
+        }
+        self.write(|relations, progress| {
+            let stored = relations
+                .get_mut(name)
+                .ok_or_else(|| DatabaseError::UnknownRelation(name.to_string()))?;
+            // Another writer may have built it meanwhile.
+            if !stored.permutations.contains_key(order) {
+                let relation = &stored.relation.trie;
+                let total = relation.len();
+                let mut report = |done| {
+                    if let Some(progress) = progress {
+                        progress(&BuildProgress {
+                            relation: name,
+                            order,
+                            done,
+                            total,
+                        });
+                    }
+                    true
+                };
+                let permutation =
+                    build_accounted(&self.budget, relation, &stored.metadata, order, &mut report)?;
+                stored.permutations.insert(order.to_vec(), permutation);
+            }
+            Ok(())
+        })
+    }
+
+    /// Drops the permutation of the relation `name` sorted by `order`.
+    /// Snapshots using it keep it alive.
+    pub fn drop_permutation(&self, name: &str, order: &[usize]) -> bool {
+        self.write(|relations, _| {
+            let stored = relations.get_mut(name);
+            Ok(stored.is_some_and(|stored| stored.permutations.remove(order).is_some()))
+        })
+        .unwrap()
+    }
+
+    /// Materializes all permutations `query` needs.
+    pub fn prepare(&self, query: &DatabaseQuery) -> Result<(), DatabaseError> {
+        for route in self.snapshot().route(query)? {
+            self.materialize(&route.relation, &route.order)?;
+        }
+        Ok(())
+    }
+
+    /// Materializes the permutations `query` needs and runs it on the
+    /// version that has them.
+    pub fn run(&self, query: &DatabaseQuery) -> Result<Vec<Vec<K>>, DatabaseError> {
+        self.prepare(query)?;
+        Ok(self.snapshot().query(query)?.run()?)
+    }
+
+    /// Applies `f` to a copy of the relations of the current version and,
+    /// if it succeeds, publishes the result as the next version. Copying
+    /// only copies the maps; the relations are shared.
+    fn write<R>(
+        &self,
+        f: impl FnOnce(
+            &mut HashMap<String, Stored<K>>,
+            &mut Option<BuildProgressFn>,
+        ) -> Result<R, DatabaseError>,
+    ) -> Result<R, DatabaseError> {
+        let mut progress = self.writer.lock().unwrap();
+        let current = self.snapshot();
+        let mut relations = current.relations.clone();
+        let result = f(&mut relations, &mut progress)?;
+        let next = Snapshot {
+            version: current.version + 1,
+            relations,
+            budget: self.budget.clone(),
+        };
+        *self.current.write().unwrap() = Arc::new(next);
+        Ok(result)
+    }
+}
+
+#[cfg(feature = "threads")]
+impl<K: Ord + Copy + Send + Sync + 'static> Database<K> {
+    /// Builds the permutation of the relation `name` sorted by `order` on a
+    /// new thread, without blocking writers, and publishes it when done.
+    /// The database's progress callback is not called; the handle reports
+    /// progress instead.
+    pub fn build_in_background(
+        self: &Arc<Self>,
+        name: &str,
+        order: &[usize],
+    ) -> Result<BuildHandle, DatabaseError> {
+        let stored = self.snapshot().relations.get(name).cloned();
+        let stored = stored.ok_or_else(|| DatabaseError::UnknownRelation(name.to_string()))?;
+        let state = Arc::new(BuildState {
+            done: AtomicUsize::new(0),
+            total: stored.relation.trie.len(),
+            cancelled: AtomicBool::new(false),
+        });
+        let (db, thread_state) = (self.clone(), state.clone());
+        let (name, order) = (name.to_string(), order.to_vec());
+        let thread = thread::spawn(move || {
+            let relation = &stored.relation.trie;
+            let metadata = &stored.metadata;
+            let permutation =
+                build_accounted(&db.budget, relation, metadata, &order, &mut |done| {
+                    thread_state.done.store(done, Ordering::Relaxed);
+                    !thread_state.cancelled.load(Ordering::Relaxed)
+                })?;
+            db.write(|relations, _| {
+                let current = relations.get_mut(&name);
+                match current {
+                    Some(current) if Arc::ptr_eq(&current.relation.trie, relation) => {
+                        current.permutations.insert(order.clone(), permutation);
+                        Ok(())
+                    }
+                    _ => Err(DatabaseError::RelationChanged(name.clone())),
+                }
+            })
+        });
+        Ok(BuildHandle { state, thread })
+    }
+}
+
+/// Reserves `bytes` for `category` if there is a budget.
+fn reserve(
+    budget: &Option<Arc<MemoryBudget>>,
+    category: Category,
+    bytes: usize,
+) -> Result<Option<Reservation>, MemoryError> {
+    budget
+        .as_ref()
+        .map(|budget| budget.reserve(category, bytes))
+        .transpose()
+}
+
+/// Builds a permutation like build(), accounting the staged tuples as
+/// buffers while building, and the permutation as a trie. Fails with
+/// DatabaseError::Cancelled if `report` cancels the build.
+fn build_accounted<K: Ord + Copy>(
+    budget: &Option<Arc<MemoryBudget>>,
+    relation: &TrieRelation<K>,
+    metadata: &Metadata,
+    order: &[usize],
+    report: &mut dyn FnMut(usize) -> bool,
+) -> Result<Accounted<K>, DatabaseError> {
+    let staged = relation.len() * (size_of::<Vec<K>>() + order.len() * size_of::<K>());
+    let mut trie = reserve(budget, Category::Tries, relation.heap_size())?;
+    let buffers = reserve(budget, Category::Buffers, staged)?;
+    let permutation = build(relation, metadata, order, report).ok_or(DatabaseError::Cancelled)?;
+    drop(buffers);
+    if let Some(trie) = &mut trie {
+        trie.shrink(trie.bytes().saturating_sub(permutation.heap_size()));
+    }
+    Ok(Accounted::new(permutation, trie))
+}
+
+/// Builds the permutation of `relation` sorted by `order`, without sorting
+/// if the metadata says the rows are presorted. Calls `report` with the
+/// number of rows copied so far, and stops returning None once it returns
+/// false.
+fn build<K: Ord + Copy>(
+    relation: &TrieRelation<K>,
+    metadata: &Metadata,
+    order: &[usize],
+    report: &mut dyn FnMut(usize) -> bool,
+) -> Option<TrieRelation<K>> {
+    let total = relation.len();
+    let mut tuples = Vec::with_capacity(total);
+    for row in 0..total {
+        tuples.push(
+            order
+                .iter()
+                .map(|&a| relation.column(a)[row])
+                .collect::<Vec<_>>(),
+        );
+        if (row + 1) % PROGRESS_ROWS == 0 && row + 1 < total && !report(row + 1) {
+            return None;
+        }
+    }
+    let permutation = if metadata.presorted(order) {
+        Metadata::new()
+            .sorted_by(&(0..order.len()).collect::<Vec<_>>())
+            .build(order.len(), tuples)
+    } else {
+        TrieRelation::new(order.len(), tuples)
+    };
+    report(total);
+    Some(permutation)
+}
+
+/// The permutation of a relation an atom is evaluated on, and the
+/// variables of the atom in its attribute order.
+#[derive(Clone, Debug)]
+pub(crate) struct Route {
+    relation: String,
+    order: Vec<usize>,
+    variables: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AtomKind {
+    Join,
+    Exists,
+    Not,
+}
+
+#[derive(Clone, Debug)]
+struct NamedAtom {
+    relation: String,
+    variables: Vec<String>,
+    kind: AtomKind,
+}
+
+/// DatabaseQuery is a conjunctive query over the relations of a Database.
+/// Unlike in a Query, atoms list one variable per attribute of the relation
+/// in its original order, whatever the variable order.
+#[derive(Clone, Debug, Default)]
+pub struct DatabaseQuery {
+    atoms: Vec<NamedAtom>,
+    order: Option<Vec<String>>,
+}
+
+impl DatabaseQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the atom `relation(variables...)`.
+    pub fn atom(self, relation: &str, variables: &[&str]) -> Self {
+        self.push_atom(relation, variables, AtomKind::Join)
+    }
+
+    /// Adds the atom `EXISTS relation(variables...)`, see Query::exists().
+    pub fn exists(self, relation: &str, variables: &[&str]) -> Self {
+        self.push_atom(relation, variables, AtomKind::Exists)
+    }
+
+    /// Adds the atom `NOT relation(variables...)`, see Query::not().
+    pub fn not(self, relation: &str, variables: &[&str]) -> Self {
+        self.push_atom(relation, variables, AtomKind::Not)
+    }
+
+    /// Sets the variable order. By default, variables are ordered by first
+    /// appearance in the atoms that are neither EXISTS nor NOT.
+    pub fn order(mut self, variables: &[&str]) -> Self {
+        self.order = Some(variables.iter().map(|v| v.to_string()).collect());
+        self
+    }
+
+    fn push_atom(mut self, relation: &str, variables: &[&str], kind: AtomKind) -> Self {
+        self.atoms.push(NamedAtom {
+            relation: relation.to_string(),
+            variables: variables.iter().map(|v| v.to_string()).collect(),
+            kind,
+        });
+        self
+    }
+
+    /// Returns the query with its variable order spelled out, so that
+    /// queries differing only in whether they give the default order are
+    /// equal.
+    pub(crate) fn normalized(&self) -> Self {
+        Self {
+            atoms: self.atoms.clone(),
+            order: Some(self.variable_order()),
+        }
+    }
+
+    fn variable_order(&self) -> Vec<String> {
+        if let Some(order) = &self.order {
+            return order.clone();
+        }
+        let mut order: Vec<String> = Vec::new();
+        for atom in self.atoms.iter().filter(|a| a.kind == AtomKind::Join) {
+            for v in &atom.variables {
+                if !order.contains(v) {
+                    order.push(v.clone());
+                }
+            }
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::expr::var;
+
+    fn database() -> Database<u32> {
+        let db = Database::new();
+        let edges = [
+            [1, 2],
+            [1, 3],
+            [2, 3],
+            [2, 4],
+            [3, 4],
+            [3, 5],
+            [4, 5],
+            [1, 5],
+        ];
+        db.insert("edge", TrieRelation::new(2, edges)).unwrap();
+        db.insert("vertex", TrieRelation::new(1, (1..=6).map(|v| [v])))
+            .unwrap();
+        db
+    }
+
+    #[test]
+    fn test_routing() {
+        let db = database();
+        // Triangles need no permutation in the default variable order.
+        let query = DatabaseQuery::new()
+            .atom("edge", &["a", "b"])
+            .atom("edge", &["b", "c"])
+            .atom("edge", &["a", "c"]);
+        let result = db.run(&query).unwrap();
+        assert_eq!(
+            result,
+            vec![vec![1, 2, 3], vec![1, 3, 5], vec![2, 3, 4], vec![3, 4, 5]]
+        );
+        assert!(db.snapshot().materialized("edge").is_empty());
+
+        // Edges into b from vertices a with incoming edges need the
+        // reversed permutation.
+        let query = DatabaseQuery::new()
+            .atom("vertex", &["b"])
+            .atom("edge", &["a", "b"])
+            .exists("edge", &["x", "a"])
+            .order(&["b", "a"]);
+        assert_eq!(
+            db.snapshot().query(&query).err(),
+            Some(DatabaseError::NotMaterialized {
+                relation: "edge".to_string(),
+                order: vec![1, 0]
+            })
+        );
+        db.prepare(&query).unwrap();
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.materialized("edge"), vec![&[1, 0]]);
+        let result = snapshot
+            .query(&query)
+            .unwrap()
+            .filter(var("a").gt(1))
+            .run()
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![vec![3, 2], vec![4, 2], vec![4, 3], vec![5, 3], vec![5, 4]]
+        );
+        assert!(db.drop_permutation("edge", &[1, 0]));
+        assert!(db.snapshot().materialized("edge").is_empty());
+        assert_eq!(snapshot.materialized("edge"), vec![&[1, 0]]);
+    }
+
+    #[test]
+    fn test_build_progress() {
+        let db = Database::new();
+        let n = PROGRESS_ROWS as u32 * 2 + 5;
+        db.insert("r", TrieRelation::new(2, (0..n).map(|i| [i, n - i])))
+            .unwrap();
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        db.on_progress(move |p| {
+            sink.lock()
+                .unwrap()
+                .push((p.relation.to_string(), p.done, p.total))
+        });
+        db.materialize("r", &[1, 0]).unwrap();
+        db.materialize("r", &[1, 0]).unwrap();
+        let done: Vec<usize> = reports.lock().unwrap().iter().map(|r| r.1).collect();
+        assert_eq!(done, vec![PROGRESS_ROWS, PROGRESS_ROWS * 2, n as usize]);
+        assert_eq!(
+            db.snapshot().permutation("r", &[1, 0]).unwrap().tuple(0),
+            vec![1, n - 1]
+        );
+        assert_eq!(
+            db.materialize("r", &[0, 0]),
+            Err(DatabaseError::InvalidOrder {
+                relation: "r".to_string(),
+                order: vec![0, 0]
+            })
+        );
+    }
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let db = database();
+        let query = DatabaseQuery::new().atom("edge", &["a", "b"]);
+        let before = db.snapshot();
+        let join = before.query(&query).unwrap().execute().unwrap();
+        thread::scope(|scope| {
+            // A writer replaces the edges while the join is in flight.
+            scope
+                .spawn(|| db.insert("edge", TrieRelation::new(2, [[7, 8]])).unwrap())
+                .join()
+                .unwrap();
+            scope.spawn(|| db.materialize("edge", &[1, 0]).unwrap());
+        });
+        assert_eq!(join.count(), 8);
+        assert_eq!(before.version(), 2);
+        let after = db.snapshot();
+        assert_eq!(after.version(), 4);
+        assert_eq!(
+            after.query(&query).unwrap().run().unwrap(),
+            vec![vec![7, 8]]
+        );
+        assert_eq!(after.materialized("edge"), vec![&[1, 0]]);
+        assert_eq!(db.remove("edge").unwrap().len(), 1);
+        assert_eq!(
+            db.run(&query),
+            Err(DatabaseError::UnknownRelation("edge".to_string()))
+        );
+        assert_eq!(after.relation("edge").unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "threads")]
+    #[test]
+    fn test_background_build() {
+        let db = Arc::new(database());
+        let query = DatabaseQuery::new()
+            .atom("edge", &["a", "b"])
+            .order(&["b", "a"]);
+        let snapshot = db.snapshot();
+        let fallback = snapshot.fallback(&query).unwrap();
+        assert_eq!(fallback.built(), 1);
+        let expected = fallback.query(&query).unwrap().run().unwrap();
+        assert_eq!(expected[0], vec![2, 1]);
+
+        let handle = db.build_in_background("edge", &[1, 0]).unwrap();
+        handle.wait().unwrap();
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.fallback(&query).unwrap().built(), 0);
+        assert_eq!(snapshot.query(&query).unwrap().run().unwrap(), expected);
+
+        // Builds of replaced relations are not published.
+        let n = PROGRESS_ROWS as u32 * 4;
+        db.insert("big", TrieRelation::new(2, (0..n).map(|i| [i, n - i])))
+            .unwrap();
+        let handle = db.build_in_background("big", &[1, 0]).unwrap();
+        db.insert("big", TrieRelation::new(2, [[1, 2]])).unwrap();
+        assert_eq!(handle.progress().1, n as usize);
+        assert_eq!(
+            handle.wait(),
+            Err(DatabaseError::RelationChanged("big".to_string()))
+        );
+        assert!(db.snapshot().materialized("big").is_empty());
+
+        let mut calls = 0;
+        let relation = TrieRelation::new(2, (0..n).map(|i| [i, n - i]));
+        let cancelled = build(&relation, &Metadata::new(), &[1, 0], &mut |_| {
+            calls += 1;
+            false
+        });
+        assert!(cancelled.is_none());
+        assert_eq!(calls, 1);
+        let handle = db.build_in_background("big", &[1, 0]).unwrap();
+        handle.cancel();
+        match handle.wait() {
+            Ok(()) => assert_eq!(db.snapshot().materialized("big"), vec![&[1, 0]]),
+            Err(error) => assert_eq!(error, DatabaseError::Cancelled),
+        }
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let edges = TrieRelation::new(2, (0..100u32).map(|i| [i, i + 1]));
+        let size = edges.heap_size();
+        let staged = 100 * (size_of::<Vec<u32>>() + 8);
+        let budget = MemoryBudget::new(size * 2 + staged - 1);
+        let db = Database::with_budget(budget.clone());
+        db.insert("edge", edges.clone()).unwrap();
+        assert_eq!(budget.used_by(Category::Tries), size);
+        assert!(matches!(
+            db.materialize("edge", &[1, 0]),
+            Err(DatabaseError::Memory(MemoryError::Exceeded {
+                category: Category::Buffers,
+                ..
+            }))
+        ));
+        assert!(db.snapshot().materialized("edge").is_empty());
+        assert_eq!(budget.used(), size);
+
+        let snapshot = db.snapshot();
+        db.insert("edge", TrieRelation::new(2, [[1, 2]])).unwrap();
+        db.materialize("edge", &[1, 0]).unwrap();
+        assert_eq!(budget.used_by(Category::Buffers), 0);
+        // The old relation is accounted until the last snapshot drops.
+        assert!(budget.used() > size);
+        drop(snapshot);
+        assert!(budget.used() < size);
+        assert!(budget.peak() > size);
+        db.remove("edge");
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let db = database();
+        assert_eq!(db.snapshot().metadata("edge"), Some(&Metadata::new()));
+        let unique = Metadata::new().unique(&[0]);
+        assert!(matches!(
+            db.set_metadata("edge", unique),
+            Err(DatabaseError::Metadata {
+                error: MetadataError::Duplicate { .. },
+                ..
+            })
+        ));
+
+        // Ids are unique and the rank grows with the id.
+        let ranked = TrieRelation::new(3, (0..50u32).map(|i| [i, 100 - i, i / 2]));
+        db.insert("ranked", ranked.clone()).unwrap();
+        let metadata = Metadata::new().unique(&[0]).sorted_by(&[2, 0]);
+        db.set_metadata("ranked", metadata.clone()).unwrap();
+        assert_eq!(db.snapshot().metadata("ranked"), Some(&metadata));
+        for order in [[0, 2, 1], [2, 0, 1], [1, 0, 2]] {
+            db.materialize("ranked", &order).unwrap();
+            let snapshot = db.snapshot();
+            let permutation = snapshot.permutation("ranked", &order).unwrap();
+            assert_eq!(*permutation, ranked.permuted(&order));
+        }
+    }
+}
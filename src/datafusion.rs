@@ -0,0 +1,299 @@
+//! DataFusion integration: a physical-plan operator that intersects the
+//! sorted key columns of its inputs with the leapfrog join.
+
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+use ::datafusion::arrow::array::{Array, ArrayRef, Int64Array, UInt64Array};
+use ::datafusion::arrow::compute::{SortOptions, cast, max};
+use ::datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use ::datafusion::arrow::record_batch::RecordBatch;
+use ::datafusion::common::{DataFusionError, Result};
+use ::datafusion::execution::TaskContext;
+use ::datafusion::physical_expr::expressions::Column;
+use ::datafusion::physical_expr::{
+    EquivalenceProperties, LexRequirement, OrderingRequirements, PhysicalSortRequirement,
+};
+use ::datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use ::datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use ::datafusion::physical_plan::{
+    DisplayAs, DisplayFormatType, Distribution, ExecutionPlan, Partitioning, PlanProperties,
+    SendableRecordBatchStream, common, execute_stream,
+};
+use futures::stream;
+
+use crate::intersect;
+
+/// LeapfrogIntersectExec computes the multi-way intersection of one integer
+/// key column per input plan.
+///
+/// Every input must produce its key column sorted ascending, without
+/// duplicates and without nulls, in a single partition. The operator
+/// declares the partitioning and ordering as its input requirements, so the
+/// optimizer can arrange for them, and checks all of it while executing. Keys of any integer type are widened to Int64,
+/// which is also the type of the single output column; UInt64 keys above
+/// i64::MAX are an error.
+///
+/// The operator does not stream: it collects the key columns of all inputs
+/// in memory before intersecting them, and emits the result as one batch.
+#[derive(Debug)]
+pub struct LeapfrogIntersectExec {
+    inputs: Vec<Arc<dyn ExecutionPlan>>,
+    key_columns: Vec<usize>,
+    schema: SchemaRef,
+    properties: PlanProperties,
+}
+
+impl LeapfrogIntersectExec {
+    /// Creates the operator. `key_columns[i]` is the index of the key column
+    /// in the output of `inputs[i]`.
+    pub fn try_new(inputs: Vec<Arc<dyn ExecutionPlan>>, key_columns: Vec<usize>) -> Result<Self> {
+        if inputs.is_empty() || inputs.len() != key_columns.len() {
+            return Err(DataFusionError::Plan(
+                "leapfrog intersection needs one key column per input".to_string(),
+            ));
+        }
+        for (input, &column) in inputs.iter().zip(&key_columns) {
+            let input_schema = input.schema();
+            let field = input_schema.fields().get(column).ok_or_else(|| {
+                DataFusionError::Plan(format!("key column {column} is out of bounds"))
+            })?;
+            if !field.data_type().is_integer() {
+                return Err(DataFusionError::Plan(format!(
+                    "key column {} must be an integer column, got {}",
+                    field.name(),
+                    field.data_type()
+                )));
+            }
+        }
+
+        let name = inputs[0].schema().field(key_columns[0]).name().clone();
+        let schema = Arc::new(Schema::new(vec![Field::new(name, DataType::Int64, false)]));
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new(Arc::clone(&schema)),
+            Partitioning::UnknownPartitioning(1),
+            EmissionType::Final,
+            Boundedness::Bounded,
+        );
+        Ok(Self {
+            inputs,
+            key_columns,
+            schema,
+            properties,
+        })
+    }
+}
+
+impl DisplayAs for LeapfrogIntersectExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LeapfrogIntersectExec: inputs={}, key_columns={:?}",
+            self.inputs.len(),
+            self.key_columns
+        )
+    }
+}
+
+impl ExecutionPlan for LeapfrogIntersectExec {
+    fn name(&self) -> &str {
+        "LeapfrogIntersectExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        self.inputs.iter().collect()
+    }
+
+    fn required_input_distribution(&self) -> Vec<Distribution> {
+        vec![Distribution::SinglePartition; self.inputs.len()]
+    }
+
+    fn required_input_ordering(&self) -> Vec<Option<OrderingRequirements>> {
+        let ascending = SortOptions {
+            descending: false,
+            nulls_first: false,
+        };
+        self.inputs
+            .iter()
+            .zip(&self.key_columns)
+            .map(|(input, &column)| {
+                let name = input.schema().field(column).name().clone();
+                let key = PhysicalSortRequirement::new(
+                    Arc::new(Column::new(&name, column)),
+                    Some(ascending),
+                );
+                LexRequirement::new([key]).map(OrderingRequirements::new)
+            })
+            .collect()
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(Self::try_new(children, self.key_columns.clone())?))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        if partition != 0 {
+            return Err(DataFusionError::Internal(format!(
+                "LeapfrogIntersectExec has a single partition, got {partition}"
+            )));
+        }
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| execute_stream(Arc::clone(input), Arc::clone(&context)))
+            .collect::<Result<Vec<_>>>()?;
+        let key_columns = self.key_columns.clone();
+        let schema = Arc::clone(&self.schema);
+
+        let output_schema = Arc::clone(&schema);
+        let batch = async move {
+            let mut keys = Vec::with_capacity(inputs.len());
+            for (input, column) in inputs.into_iter().zip(key_columns) {
+                let batches = common::collect(input).await?;
+                keys.push(sorted_keys(&batches, column)?);
+            }
+            let result = intersect(keys.iter().map(Vec::as_slice).collect());
+            let column: ArrayRef = Arc::new(Int64Array::from(result));
+            Ok(RecordBatch::try_new(output_schema, vec![column])?)
+        };
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            schema,
+            stream::once(batch),
+        )))
+    }
+}
+
+/// Concatenates the key column of all batches into one vector, checking that
+/// it is free of nulls, fits into Int64 and is sorted strictly ascending.
+fn sorted_keys(batches: &[RecordBatch], column: usize) -> Result<Vec<i64>> {
+    let mut keys: Vec<i64> = Vec::new();
+    for batch in batches {
+        let array = batch.column(column);
+        if array.null_count() > 0 {
+            return Err(DataFusionError::Execution(
+                "leapfrog intersection key column contains nulls".to_string(),
+            ));
+        }
+        // Casting would turn these into nulls.
+        if let Some(array) = array.as_any().downcast_ref::<UInt64Array>()
+            && let Some(key) = max(array).filter(|&key| key > i64::MAX as u64)
+        {
+            return Err(DataFusionError::Execution(format!(
+                "leapfrog intersection key {key} does not fit into Int64"
+            )));
+        }
+        let array = cast(array, &DataType::Int64)?;
+        let array = array
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("cast to Int64 yields an Int64Array");
+        keys.extend(array.values().iter());
+    }
+    if !keys.windows(2).all(|w| w[0] < w[1]) {
+        return Err(DataFusionError::Execution(
+            "leapfrog intersection input is not sorted by its key column, or repeats a key"
+                .to_string(),
+        ));
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::datafusion::arrow::array::Int32Array;
+    use ::datafusion::datasource::memory::MemorySourceConfig;
+    use ::datafusion::physical_plan::collect;
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// A single-partition input whose column `key` holds `batches`.
+    fn input(batches: Vec<ArrayRef>) -> Arc<dyn ExecutionPlan> {
+        let field = Field::new("key", batches[0].data_type().clone(), true);
+        let schema = Arc::new(Schema::new(vec![field]));
+        let batches = batches
+            .into_iter()
+            .map(|array| RecordBatch::try_new(Arc::clone(&schema), vec![array]).unwrap())
+            .collect();
+        MemorySourceConfig::try_new_exec(&[batches], schema, None).unwrap()
+    }
+
+    fn run(inputs: Vec<Arc<dyn ExecutionPlan>>) -> Result<Vec<i64>> {
+        let key_columns = vec![0; inputs.len()];
+        let exec = Arc::new(LeapfrogIntersectExec::try_new(inputs, key_columns)?);
+        let batches = block_on(collect(exec, Arc::new(TaskContext::default())))?;
+        let keys = batches.iter().flat_map(|batch| {
+            let array = batch.column(0).as_any().downcast_ref::<Int64Array>();
+            array.unwrap().values().to_vec()
+        });
+        Ok(keys.collect())
+    }
+
+    #[test]
+    fn test_leapfrog_intersect_exec() {
+        let int32: ArrayRef = Arc::new(Int32Array::from(vec![1, 3, 4]));
+        let int64: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from(vec![0, 1, 2])),
+            Arc::new(Int64Array::from(vec![4, 5])),
+        ];
+        let uint64: ArrayRef = Arc::new(UInt64Array::from(vec![1, 4, 9]));
+        let inputs = vec![input(vec![int32]), input(int64), input(vec![uint64])];
+        assert_eq!(run(inputs).unwrap(), [1, 4]);
+    }
+
+    #[test]
+    fn test_leapfrog_intersect_exec_requirements() {
+        let keys: ArrayRef = Arc::new(Int64Array::from(vec![1, 2]));
+        let inputs = vec![input(vec![Arc::clone(&keys)]), input(vec![keys])];
+        let exec = LeapfrogIntersectExec::try_new(inputs, vec![0, 0]).unwrap();
+        let distribution = exec.required_input_distribution();
+        assert_eq!(distribution.len(), 2);
+        assert!(
+            distribution
+                .iter()
+                .all(|d| matches!(d, Distribution::SinglePartition))
+        );
+        let ordering = exec.required_input_ordering();
+        assert!(ordering.len() == 2 && ordering.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_leapfrog_intersect_exec_errors() {
+        let error = |keys: ArrayRef| {
+            let other: ArrayRef = Arc::new(Int64Array::from(vec![1, 2]));
+            run(vec![input(vec![keys]), input(vec![other])])
+                .unwrap_err()
+                .to_string()
+        };
+        let unsorted = error(Arc::new(Int64Array::from(vec![2, 1])));
+        assert!(
+            unsorted.contains("is not sorted by its key column"),
+            "{unsorted}"
+        );
+        let duplicates = error(Arc::new(Int64Array::from(vec![1, 1, 2])));
+        assert!(duplicates.contains("repeats a key"), "{duplicates}");
+        let nulls = error(Arc::new(Int64Array::from(vec![Some(1), None])));
+        assert!(nulls.contains("contains nulls"), "{nulls}");
+        let too_large = error(Arc::new(UInt64Array::from(vec![1, u64::MAX])));
+        assert!(
+            too_large.contains(&format!("key {} does not fit into Int64", u64::MAX)),
+            "{too_large}"
+        );
+    }
+}
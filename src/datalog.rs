@@ -0,0 +1,1625 @@
+//! Datalog programs over TrieRelations.
+//!
+//! A Program consists of base relations and rules like
+//! `path(a, c) :- path(a, b), edge(b, c)`. The relations in rule heads are
+//! derived by semi-naive evaluation: every iteration evaluates each rule once
+//! per derived body atom, with that atom restricted to the facts that were new
+//! in the previous iteration, until no rule derives new facts.
+//!
+//! Rule bodies are evaluated as queries by the leapfrog triejoin, with the
+//! variables ordered by first appearance in the body. Body atoms whose
+//! variables are out of that order are joined on permuted copies of their
+//! relations.
+//!
+//! Evaluation can be bounded by the number of iterations and of derived
+//! facts; the Model tells whether the fixpoint was reached. On request, it
+//! also records the provenance of derived facts: the base facts of the
+//! derivation that first produced them.
+//!
+//! Rule bodies may contain negated atoms, and aggregate rules
+//! (Program::aggregate()) derive a count, minimum or sum per group. Both
+//! need the relations they read to be complete, so programs are split into
+//! strata: a relation is evaluated in a later stratum than the relations it
+//! depends on through negation or aggregation. Programs where such a
+//! dependency is recursive are not stratifiable and are rejected.
+//!
+//! Program::dependency_graph_dot() draws which relations each relation is
+//! derived from, grouped by stratum, as a Graphviz DOT graph.
+//!
+//! Goals with bound arguments, like all `y` with `path(42, y)`, are
+//! evaluated goal-directed by the magic-set rewrite (Program::magic_sets()):
+//! every derived relation gets variants per pattern of bound arguments
+//! ("adornment"), and magic relations collect the bindings those variants
+//! are needed for, so only facts relevant to the goal are derived.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{self, Write};
+use std::ops::Deref;
+
+use crate::query::{Query, QueryError, TrieJoin};
+use crate::trie::TrieRelation;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Atom {
+    pub relation: String,
+    pub variables: Vec<String>,
+    pub negated: bool,
+}
+
+/// Creates the atom `relation(variables...)`.
+pub fn atom(relation: &str, variables: &[&str]) -> Atom {
+    Atom {
+        relation: relation.to_string(),
+        variables: variables.iter().map(|v| v.to_string()).collect(),
+        negated: false,
+    }
+}
+
+/// Creates the negated atom `not relation(variables...)`. All its variables
+/// must be bound by positive atoms of the same rule.
+pub fn not(relation: &str, variables: &[&str]) -> Atom {
+    Atom {
+        negated: true,
+        ..atom(relation, variables)
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "not ")?;
+        }
+        write!(f, "{}({})", self.relation, self.variables.join(", "))
+    }
+}
+
+/// Rule derives `head` for every binding of the variables satisfying all
+/// atoms of the body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub head: Atom,
+    pub body: Vec<Atom>,
+}
+
+impl Rule {
+    pub fn new(head: Atom, body: impl IntoIterator<Item = Atom>) -> Self {
+        Self {
+            head,
+            body: body.into_iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} :- ", self.head)?;
+        for (i, atom) in self.body.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{atom}")?;
+        }
+        write!(f, ".")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DatalogError {
+    /// A relation is neither a base relation nor derived by any rule.
+    UnknownRelation(String),
+    /// A relation is used with different arities.
+    Arity {
+        relation: String,
+        expected: usize,
+        found: usize,
+    },
+    /// A variable of the head of a rule does not occur in its body.
+    UnsafeRule { rule: usize, variable: String },
+    /// A base relation occurs in the head of a rule.
+    DerivedBaseRelation(String),
+    /// The body of a rule is not a valid query.
+    Query { rule: usize, error: QueryError },
+    /// A goal relation is not derived by any rule.
+    NotDerived(String),
+    /// The head of a rule is negated.
+    NegatedHead { rule: usize },
+    /// An aggregate rule is malformed.
+    InvalidAggregate { rule: usize, reason: String },
+    /// A rule makes `relation` depend on `depends_on` through negation or
+    /// aggregation, while `depends_on` depends on `relation`.
+    NotStratifiable {
+        rule: usize,
+        relation: String,
+        depends_on: String,
+    },
+    /// The operation does not support the program.
+    Unsupported(String),
+}
+
+impl fmt::Display for DatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatalogError::UnknownRelation(name) => write!(f, "unknown relation {name:?}"),
+            DatalogError::Arity {
+                relation,
+                expected,
+                found,
+            } => write!(
+                f,
+                "relation {relation:?} has arity {expected}, but is used with {found} variables"
+            ),
+            DatalogError::UnsafeRule { rule, variable } => write!(
+                f,
+                "head variable {variable:?} of rule {rule} does not occur in its body"
+            ),
+            DatalogError::DerivedBaseRelation(name) => {
+                write!(f, "base relation {name:?} occurs in a rule head")
+            }
+            DatalogError::Query { rule, error } => write!(f, "rule {rule}: {error}"),
+            DatalogError::NotDerived(name) => {
+                write!(f, "relation {name:?} is not derived by any rule")
+            }
+            DatalogError::NegatedHead { rule } => write!(f, "head of rule {rule} is negated"),
+            DatalogError::InvalidAggregate { rule, reason } => {
+                write!(f, "invalid aggregate in rule {rule}: {reason}")
+            }
+            DatalogError::NotStratifiable {
+                rule,
+                relation,
+                depends_on,
+            } => write!(
+                f,
+                "program is not stratifiable: rule {rule} makes {relation:?} depend on \
+                 {depends_on:?} through negation or aggregation, but {depends_on:?} \
+                 depends on {relation:?}"
+            ),
+            DatalogError::Unsupported(msg) => write!(f, "unsupported: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for DatalogError {}
+
+enum Base<'a, K> {
+    Borrowed(&'a TrieRelation<K>),
+    Owned(TrieRelation<K>),
+}
+
+impl<K> Deref for Base<'_, K> {
+    type Target = TrieRelation<K>;
+
+    fn deref(&self) -> &TrieRelation<K> {
+        match self {
+            Base::Borrowed(relation) => relation,
+            Base::Owned(relation) => relation,
+        }
+    }
+}
+
+/// Aggregate is computed over the bindings of the body of an aggregate
+/// rule, per binding of the other head variables.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Aggregate {
+    /// The number of bindings.
+    Count,
+    /// The least value of a variable.
+    Min(String),
+    /// The sum of a variable over all bindings.
+    Sum(String),
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregate::Count => write!(f, "count"),
+            Aggregate::Min(v) => write!(f, "min({v})"),
+            Aggregate::Sum(v) => write!(f, "sum({v})"),
+        }
+    }
+}
+
+/// AggregateKey is implemented by the key types aggregates can produce.
+pub trait AggregateKey: Ord + Copy {
+    fn from_count(count: usize) -> Option<Self>;
+    fn checked_add(self, other: Self) -> Option<Self>;
+}
+
+macro_rules! impl_aggregate_key {
+    ($($t:ty),*) => {$(
+        impl AggregateKey for $t {
+            fn from_count(count: usize) -> Option<Self> {
+                <$t>::try_from(count).ok()
+            }
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$t>::checked_add(self, other)
+            }
+        }
+    )*};
+}
+
+impl_aggregate_key!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// Folds the values of a group, or returns None on overflow.
+type FoldFn<K> = fn(&Aggregate, &[K]) -> Option<K>;
+
+fn fold<K: AggregateKey>(aggregate: &Aggregate, values: &[K]) -> Option<K> {
+    match aggregate {
+        Aggregate::Count => K::from_count(values.len()),
+        Aggregate::Min(_) => values.iter().min().copied(),
+        Aggregate::Sum(_) => values[1..]
+            .iter()
+            .try_fold(values[0], |sum, &v| sum.checked_add(v)),
+    }
+}
+
+/// Program holds base relations and the rules deriving new relations from
+/// them.
+pub struct Program<'a, K> {
+    relations: HashMap<String, Base<'a, K>>,
+    rules: Vec<Rule>,
+    /// Per rule, its aggregate if it is an aggregate rule.
+    aggregates: Vec<Option<(Aggregate, FoldFn<K>)>>,
+}
+
+impl<K> Default for Program<'_, K> {
+    fn default() -> Self {
+        Self {
+            relations: HashMap::new(),
+            rules: Vec::new(),
+            aggregates: Vec::new(),
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy> Program<'a, K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the base relation `name`.
+    pub fn relation(mut self, name: &str, relation: &'a TrieRelation<K>) -> Self {
+        self.relations
+            .insert(name.to_string(), Base::Borrowed(relation));
+        self
+    }
+
+    /// Adds the base relation `name`, owned by the program.
+    pub fn owned_relation(mut self, name: &str, relation: TrieRelation<K>) -> Self {
+        self.relations
+            .insert(name.to_string(), Base::Owned(relation));
+        self
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self.aggregates.push(None);
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Returns the aggregate of rule `rule`, if it is an aggregate rule.
+    pub fn aggregate_of(&self, rule: usize) -> Option<&Aggregate> {
+        self.aggregates[rule]
+            .as_ref()
+            .map(|(aggregate, _)| aggregate)
+    }
+
+    /// Evaluates the program to its fixpoint.
+    pub fn evaluate(&self) -> Result<Model<K>, DatalogError> {
+        self.fixpoint().run()
+    }
+
+    /// Returns a builder for evaluating the program with limits.
+    pub fn fixpoint(&self) -> Fixpoint<'_, 'a, K> {
+        Fixpoint {
+            program: self,
+            max_iterations: None,
+            max_facts: None,
+            progress: None,
+            provenance: false,
+        }
+    }
+
+    /// Evaluates the goal `relation(bound...)`, where None marks a free
+    /// argument, and returns the matching facts. Derived goals are evaluated
+    /// on the magic-set rewrite of the program.
+    pub fn evaluate_goal(
+        &self,
+        relation: &str,
+        bound: &[Option<K>],
+    ) -> Result<TrieRelation<K>, DatalogError> {
+        let matches = |tuple: &Vec<K>| {
+            tuple
+                .iter()
+                .zip(bound)
+                .all(|(k, b)| b.is_none_or(|b| *k == b))
+        };
+        let facts = match self.relations.get(relation) {
+            Some(base) => {
+                check_goal_arity(relation, base.arity(), bound)?;
+                base.tuples().filter(matches).collect::<Vec<_>>()
+            }
+            None if self.is_positive() => {
+                let model = self.magic_sets(relation, bound)?.evaluate()?;
+                model.relations[relation].tuples().filter(matches).collect()
+            }
+            None => {
+                let model = self.evaluate()?;
+                let facts = model
+                    .relations
+                    .get(relation)
+                    .ok_or_else(|| DatalogError::UnknownRelation(relation.to_string()))?;
+                check_goal_arity(relation, facts.arity(), bound)?;
+                facts.tuples().filter(matches).collect()
+            }
+        };
+        Ok(TrieRelation::new(bound.len(), facts))
+    }
+
+    /// Checks whether the program has neither negation nor aggregates.
+    fn is_positive(&self) -> bool {
+        let negation = self.rules.iter().flat_map(|r| &r.body).any(|a| a.negated);
+        !negation && self.aggregates.iter().all(Option::is_none)
+    }
+
+    /// Returns the magic-set rewrite of the program for the goal
+    /// `relation(bound...)`. In the rewritten program, `relation` holds all
+    /// answers to the goal, along with facts for other bindings that
+    /// deriving them required. Only programs without negation and
+    /// aggregates can be rewritten.
+    pub fn magic_sets(
+        &self,
+        relation: &str,
+        bound: &[Option<K>],
+    ) -> Result<Program<'_, K>, DatalogError> {
+        self.plan()?;
+        if !self.is_positive() {
+            return Err(DatalogError::Unsupported(
+                "magic sets for programs with negation or aggregates".to_string(),
+            ));
+        }
+        let head = self
+            .rules
+            .iter()
+            .map(|r| &r.head)
+            .find(|h| h.relation == relation);
+        let Some(head) = head else {
+            return Err(if self.relations.contains_key(relation) {
+                DatalogError::NotDerived(relation.to_string())
+            } else {
+                DatalogError::UnknownRelation(relation.to_string())
+            });
+        };
+        check_goal_arity(relation, head.variables.len(), bound)?;
+        let derived: HashSet<&str> = self
+            .rules
+            .iter()
+            .map(|r| r.head.relation.as_str())
+            .collect();
+        let goal: Vec<bool> = bound.iter().map(Option::is_some).collect();
+        let adorned = |name: &str, adornment: &[bool]| {
+            if name == relation && adornment == goal {
+                name.to_string()
+            } else {
+                format!("{name}^{}", adornment_string(adornment))
+            }
+        };
+
+        let mut rules = Vec::new();
+        let mut seen = HashSet::from([(relation.to_string(), goal.clone())]);
+        let mut pending = VecDeque::from([(relation.to_string(), goal.clone())]);
+        while let Some((name, adornment)) = pending.pop_front() {
+            for rule in self.rules.iter().filter(|r| r.head.relation == name) {
+                let mut known: HashSet<&str> = rule
+                    .head
+                    .variables
+                    .iter()
+                    .zip(&adornment)
+                    .filter(|(_, bound)| **bound)
+                    .map(|(v, _)| v.as_str())
+                    .collect();
+                // Bindings are passed sideways from left to right.
+                let mut body: Vec<Atom> = magic_atom(&rule.head, &adornment).into_iter().collect();
+                for atom in &rule.body {
+                    if !derived.contains(atom.relation.as_str()) {
+                        body.push(atom.clone());
+                    } else {
+                        let pattern: Vec<bool> = atom
+                            .variables
+                            .iter()
+                            .map(|v| known.contains(v.as_str()))
+                            .collect();
+                        if let Some(magic) = magic_atom(atom, &pattern)
+                            && (body.len() != 1 || body[0] != magic)
+                        {
+                            rules.push(Rule::new(magic, body.clone()));
+                        }
+                        if seen.insert((atom.relation.clone(), pattern.clone())) {
+                            pending.push_back((atom.relation.clone(), pattern.clone()));
+                        }
+                        body.push(Atom {
+                            relation: adorned(&atom.relation, &pattern),
+                            ..atom.clone()
+                        });
+                    }
+                    known.extend(atom.variables.iter().map(String::as_str));
+                }
+                let head = Atom {
+                    relation: adorned(&name, &adornment),
+                    ..rule.head.clone()
+                };
+                rules.push(Rule::new(head, body));
+            }
+        }
+
+        let relations = self
+            .relations
+            .iter()
+            .map(|(name, base)| (name.clone(), Base::Borrowed(&**base)));
+        let mut program = Program {
+            relations: relations.collect(),
+            aggregates: vec![None; rules.len()],
+            rules,
+        };
+        // The goal's bindings seed its magic relation. If rules derive more
+        // bindings for it, the seed becomes a base relation of its own.
+        let constants: Vec<K> = bound.iter().flatten().copied().collect();
+        if !constants.is_empty() {
+            let seed = TrieRelation::new(constants.len(), [constants]);
+            let adornment = adornment_string(&goal);
+            let magic = format!("magic^{relation}^{adornment}");
+            if program.rules.iter().any(|r| r.head.relation == magic) {
+                let name = format!("seed^{relation}^{adornment}");
+                let variables: Vec<String> = (0..seed.arity()).map(|i| format!("x{i}")).collect();
+                let variables: Vec<&str> = variables.iter().map(String::as_str).collect();
+                let seed_rule = Rule::new(atom(&magic, &variables), [atom(&name, &variables)]);
+                program.rules.insert(0, seed_rule);
+                program.aggregates.push(None);
+                program = program.owned_relation(&name, seed);
+            } else {
+                program = program.owned_relation(&magic, seed);
+            }
+        }
+        Ok(program)
+    }
+
+    /// Checks the rules, stratifies them and plans the evaluation of their
+    /// bodies.
+    fn plan(&self) -> Result<Vec<RulePlan>, DatalogError> {
+        let mut arities: HashMap<&str, usize> = self
+            .relations
+            .iter()
+            .map(|(name, rel)| (name.as_str(), rel.arity()))
+            .collect();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let head = &rule.head;
+            if head.negated {
+                return Err(DatalogError::NegatedHead { rule: i });
+            }
+            if self.relations.contains_key(&head.relation) {
+                return Err(DatalogError::DerivedBaseRelation(head.relation.clone()));
+            }
+            check_arity(&mut arities, head)?;
+        }
+        let strata = self.stratify()?;
+        let mut plans = Vec::with_capacity(self.rules.len());
+        for (i, rule) in self.rules.iter().enumerate() {
+            let mut variables: Vec<String> = Vec::new();
+            for atom in &rule.body {
+                if !arities.contains_key(atom.relation.as_str()) {
+                    return Err(DatalogError::UnknownRelation(atom.relation.clone()));
+                }
+                check_arity(&mut arities, atom)?;
+                for v in atom.variables.iter().filter(|_| !atom.negated) {
+                    if !variables.contains(v) {
+                        variables.push(v.clone());
+                    }
+                }
+            }
+            let index_of = |name: &str| variables.iter().position(|v| v == name);
+            let aggregate = self.aggregate_of(i);
+            let mut head = rule.head.variables.as_slice();
+            let mut aggregate_input = None;
+            if let Some(aggregate) = aggregate {
+                let invalid = |reason: String| DatalogError::InvalidAggregate { rule: i, reason };
+                let (output, group) = head
+                    .split_last()
+                    .ok_or_else(|| invalid("the head has no variables".to_string()))?;
+                if index_of(output).is_some() {
+                    return Err(invalid(format!("output {output:?} occurs in the body")));
+                }
+                if let Aggregate::Min(input) | Aggregate::Sum(input) = aggregate {
+                    let index = index_of(input);
+                    aggregate_input = Some(index.ok_or_else(|| {
+                        invalid(format!("input {input:?} does not occur in the body"))
+                    })?);
+                }
+                head = group;
+            }
+            let projection = head
+                .iter()
+                .map(|v| {
+                    index_of(v).ok_or_else(|| DatalogError::UnsafeRule {
+                        rule: i,
+                        variable: v.clone(),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let stratum = strata[rule.head.relation.as_str()];
+            let atoms = rule
+                .body
+                .iter()
+                .map(|atom| {
+                    let recursive = !atom.negated
+                        && strata.get(atom.relation.as_str()) == Some(&stratum)
+                        && aggregate.is_none();
+                    // Negated atoms are probed, so their order does not matter.
+                    let mut permutation: Vec<usize> = (0..atom.variables.len()).collect();
+                    if !atom.negated {
+                        permutation.sort_by_key(|&a| index_of(&atom.variables[a]));
+                    }
+                    let sorted = permutation.is_sorted();
+                    AtomPlan {
+                        relation: atom.relation.clone(),
+                        variables: permutation
+                            .iter()
+                            .map(|&a| atom.variables[a].clone())
+                            .collect(),
+                        permutation: (!sorted).then_some(permutation),
+                        negated: atom.negated,
+                        recursive,
+                    }
+                })
+                .collect();
+            plans.push(RulePlan {
+                head: rule.head.relation.clone(),
+                stratum,
+                projection,
+                aggregate_input,
+                variables,
+                atoms,
+            });
+        }
+        Ok(plans)
+    }
+
+    /// Renders the dependency graph of the relations as a Graphviz digraph.
+    /// Base relations are boxes, derived relations are grouped by stratum,
+    /// and dependencies through negation or aggregation are dashed. Programs
+    /// that are not stratifiable are drawn without strata.
+    pub fn dependency_graph_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph program {{").unwrap();
+        writeln!(dot, "  rankdir=BT;").unwrap();
+        let mut base: Vec<&String> = self.relations.keys().collect();
+        base.sort();
+        writeln!(dot, "  subgraph cluster_base {{").unwrap();
+        writeln!(dot, "    label=\"base\";").unwrap();
+        for name in base {
+            writeln!(dot, "    {name:?} [shape=box];").unwrap();
+        }
+        writeln!(dot, "  }}").unwrap();
+        let mut derived: Vec<&str> = Vec::new();
+        for rule in &self.rules {
+            if !derived.contains(&rule.head.relation.as_str()) {
+                derived.push(&rule.head.relation);
+            }
+        }
+        match self.stratify() {
+            Ok(strata) => {
+                let count = derived.iter().map(|r| strata[r] + 1).max().unwrap_or(0);
+                for stratum in 0..count {
+                    writeln!(dot, "  subgraph cluster_stratum_{stratum} {{").unwrap();
+                    writeln!(dot, "    label=\"stratum {stratum}\";").unwrap();
+                    for name in derived.iter().filter(|r| strata[*r] == stratum) {
+                        writeln!(dot, "    {name:?};").unwrap();
+                    }
+                    writeln!(dot, "  }}").unwrap();
+                }
+            }
+            Err(_) => {
+                for name in &derived {
+                    writeln!(dot, "  {name:?};").unwrap();
+                }
+            }
+        }
+        let mut edges: Vec<(&str, &str, String)> = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            for atom in &rule.body {
+                let label = match (&self.aggregates[i], atom.negated) {
+                    (_, true) => "not".to_string(),
+                    (Some((aggregate, _)), false) => aggregate.to_string(),
+                    (None, false) => String::new(),
+                };
+                let edge = (atom.relation.as_str(), rule.head.relation.as_str(), label);
+                if !edges.contains(&edge) {
+                    edges.push(edge);
+                }
+            }
+        }
+        for (from, to, label) in edges {
+            if label.is_empty() {
+                writeln!(dot, "  {from:?} -> {to:?};").unwrap();
+            } else {
+                writeln!(dot, "  {from:?} -> {to:?} [style=dashed, label={label:?}];").unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// Assigns each derived relation its stratum: the least number such that
+    /// every relation it depends on has a lower or equal stratum, and a
+    /// lower one for dependencies through negation or aggregation.
+    fn stratify(&self) -> Result<HashMap<&str, usize>, DatalogError> {
+        let mut strata: HashMap<&str, usize> = self
+            .rules
+            .iter()
+            .map(|r| (r.head.relation.as_str(), 0))
+            .collect();
+        let mut dependencies: HashMap<&str, Vec<&str>> = HashMap::new();
+        for rule in &self.rules {
+            let body = rule.body.iter().map(|a| a.relation.as_str());
+            dependencies
+                .entry(&rule.head.relation)
+                .or_default()
+                .extend(body.filter(|r| strata.contains_key(r)));
+        }
+        let depends = |from: &str, to: &str| {
+            let mut seen = HashSet::from([from]);
+            let mut pending = vec![from];
+            while let Some(relation) = pending.pop() {
+                for &next in dependencies.get(relation).into_iter().flatten() {
+                    if next == to {
+                        return true;
+                    }
+                    if seen.insert(next) {
+                        pending.push(next);
+                    }
+                }
+            }
+            from == to
+        };
+        for (i, rule) in self.rules.iter().enumerate() {
+            for atom in &rule.body {
+                let negative = atom.negated || self.aggregates[i].is_some();
+                if negative && depends(&atom.relation, &rule.head.relation) {
+                    return Err(DatalogError::NotStratifiable {
+                        rule: i,
+                        relation: rule.head.relation.clone(),
+                        depends_on: atom.relation.clone(),
+                    });
+                }
+            }
+        }
+        // Without negative cycles, raising strata terminates.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, rule) in self.rules.iter().enumerate() {
+                for atom in &rule.body {
+                    let Some(&stratum) = strata.get(atom.relation.as_str()) else {
+                        continue;
+                    };
+                    let negative = atom.negated || self.aggregates[i].is_some();
+                    let least = stratum + negative as usize;
+                    let head = strata.get_mut(rule.head.relation.as_str()).unwrap();
+                    if *head < least {
+                        *head = least;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        Ok(strata)
+    }
+}
+
+impl<'a, K: AggregateKey> Program<'a, K> {
+    /// Adds an aggregate rule. The last variable of its head receives the
+    /// aggregate over the bindings of the body, for every binding of the
+    /// other head variables; it must not occur in the body.
+    pub fn aggregate(mut self, rule: Rule, aggregate: Aggregate) -> Self {
+        self.rules.push(rule);
+        self.aggregates.push(Some((aggregate, fold::<K>)));
+        self
+    }
+}
+
+/// The magic atom holding the bindings of the bound variables of `atom`, or
+/// None if all are free.
+fn magic_atom(atom: &Atom, adornment: &[bool]) -> Option<Atom> {
+    let variables: Vec<String> = atom
+        .variables
+        .iter()
+        .zip(adornment)
+        .filter(|(_, bound)| **bound)
+        .map(|(v, _)| v.clone())
+        .collect();
+    (!variables.is_empty()).then(|| Atom {
+        relation: format!("magic^{}^{}", atom.relation, adornment_string(adornment)),
+        variables,
+        negated: false,
+    })
+}
+
+/// Writes an adornment like "bf" for a bound first and a free second argument.
+fn adornment_string(adornment: &[bool]) -> String {
+    adornment
+        .iter()
+        .map(|&b| if b { 'b' } else { 'f' })
+        .collect()
+}
+
+fn check_goal_arity<K>(
+    relation: &str,
+    arity: usize,
+    bound: &[Option<K>],
+) -> Result<(), DatalogError> {
+    if bound.len() != arity {
+        return Err(DatalogError::Arity {
+            relation: relation.to_string(),
+            expected: arity,
+            found: bound.len(),
+        });
+    }
+    Ok(())
+}
+
+fn check_arity<'r>(
+    arities: &mut HashMap<&'r str, usize>,
+    atom: &'r Atom,
+) -> Result<(), DatalogError> {
+    let expected = *arities
+        .entry(&atom.relation)
+        .or_insert(atom.variables.len());
+    if expected != atom.variables.len() {
+        return Err(DatalogError::Arity {
+            relation: atom.relation.clone(),
+            expected,
+            found: atom.variables.len(),
+        });
+    }
+    Ok(())
+}
+
+/// How a rule body is joined.
+struct RulePlan {
+    head: String,
+    stratum: usize,
+    /// Per head attribute, the index of its variable in `variables`. For
+    /// aggregate rules, the output is left out.
+    projection: Vec<usize>,
+    /// The index of the variable aggregated by Min and Sum.
+    aggregate_input: Option<usize>,
+    /// The variable order.
+    variables: Vec<String>,
+    atoms: Vec<AtomPlan>,
+}
+
+struct AtomPlan {
+    relation: String,
+    /// The variables in variable order.
+    variables: Vec<String>,
+    /// The attributes of the relation in variable order, if they differ.
+    permutation: Option<Vec<usize>>,
+    negated: bool,
+    /// Whether the relation is derived in the same stratum, so the atom
+    /// takes part in semi-naive evaluation.
+    recursive: bool,
+}
+
+/// Statistics passed to the progress callback after every iteration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of iterations done, starting at 1.
+    pub iteration: usize,
+    /// Facts derived in this iteration.
+    pub new_facts: usize,
+    /// All derived facts so far.
+    pub facts: usize,
+}
+
+/// Why evaluation stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// No rule derives new facts.
+    Fixpoint,
+    IterationLimit,
+    FactLimit,
+}
+
+type ProgressFn<'p> = Box<dyn FnMut(&Progress) + 'p>;
+
+/// Fixpoint evaluates a program, optionally with limits and a progress
+/// callback.
+pub struct Fixpoint<'p, 'a, K> {
+    program: &'p Program<'a, K>,
+    max_iterations: Option<usize>,
+    max_facts: Option<usize>,
+    progress: Option<ProgressFn<'p>>,
+    provenance: bool,
+}
+
+impl<'p, K: Ord + Copy> Fixpoint<'p, '_, K> {
+    /// Stops after `n` iterations.
+    pub fn max_iterations(mut self, n: usize) -> Self {
+        self.max_iterations = Some(n);
+        self
+    }
+
+    /// Stops after the iteration in which the number of derived facts reaches
+    /// `n`.
+    pub fn max_facts(mut self, n: usize) -> Self {
+        self.max_facts = Some(n);
+        self
+    }
+
+    /// Calls `f` after every iteration.
+    pub fn on_progress(mut self, f: impl FnMut(&Progress) + 'p) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Records the provenance of every derived fact, retrievable by
+    /// Model::provenance().
+    pub fn provenance(mut self) -> Self {
+        self.provenance = true;
+        self
+    }
+
+    pub fn run(mut self) -> Result<Model<K>, DatalogError> {
+        let program = self.program;
+        let plans = program.plan()?;
+        let mut provenance: Option<HashMap<String, Witnesses<K>>> =
+            self.provenance.then(HashMap::new);
+        let mut full: HashMap<String, TrieRelation<K>> = HashMap::new();
+        for rule in &program.rules {
+            full.entry(rule.head.relation.clone())
+                .or_insert_with(|| TrieRelation::empty(rule.head.variables.len()));
+        }
+        let strata = plans.iter().map(|p| p.stratum + 1).max().unwrap_or(0);
+        let mut iteration = 0;
+        for stratum in 0..strata {
+            let rules: Vec<usize> = (0..plans.len())
+                .filter(|&i| plans[i].stratum == stratum)
+                .collect();
+            let mut delta: HashMap<String, TrieRelation<K>> = rules
+                .iter()
+                .map(|&i| {
+                    (
+                        plans[i].head.clone(),
+                        TrieRelation::empty(full[&plans[i].head].arity()),
+                    )
+                })
+                .collect();
+            // Relations of other strata are complete and only need to be
+            // permuted once.
+            let mut permuted: HashMap<(usize, usize), TrieRelation<K>> = HashMap::new();
+            for &i in &rules {
+                for (j, atom) in plans[i].atoms.iter().enumerate() {
+                    if let (false, Some(permutation)) = (atom.recursive, &atom.permutation) {
+                        let relation = match program.relations.get(&atom.relation) {
+                            Some(base) => base.permuted(permutation),
+                            None => full[&atom.relation].permuted(permutation),
+                        };
+                        permuted.insert((i, j), relation);
+                    }
+                }
+            }
+
+            let mut first = true;
+            loop {
+                iteration += 1;
+                let mut derived: HashMap<&str, Vec<Vec<K>>> = HashMap::new();
+                for &i in &rules {
+                    let plan = &plans[i];
+                    let recursive: Vec<usize> = (0..plan.atoms.len())
+                        .filter(|&j| plan.atoms[j].recursive)
+                        .collect();
+                    // Rules without recursive atoms derive all their facts in
+                    // the first iteration; the others derive nothing then.
+                    let deltas: Vec<Option<usize>> = match (first, recursive.is_empty()) {
+                        (true, true) => vec![None],
+                        (false, true) | (true, false) => continue,
+                        (false, false) => recursive.into_iter().map(Some).collect(),
+                    };
+                    for delta_atom in deltas {
+                        let relations = plan.atoms.iter().enumerate().map(|(j, atom)| {
+                            if let Some(relation) = permuted.get(&(i, j)) {
+                                return Cow::Borrowed(relation);
+                            }
+                            if !atom.recursive {
+                                return match program.relations.get(&atom.relation) {
+                                    Some(base) => Cow::Borrowed(&**base),
+                                    None => Cow::Borrowed(&full[&atom.relation]),
+                                };
+                            }
+                            let source = if delta_atom == Some(j) { &delta } else { &full };
+                            let relation = &source[&atom.relation];
+                            match &atom.permutation {
+                                Some(permutation) => Cow::Owned(relation.permuted(permutation)),
+                                None => Cow::Borrowed(relation),
+                            }
+                        });
+                        let relations: Vec<Cow<'_, TrieRelation<K>>> = relations.collect();
+                        let mut join = join_body(plan, &relations)
+                            .map_err(|error| DatalogError::Query { rule: i, error })?;
+                        let mut bindings = Vec::new();
+                        let mut witnesses = Vec::new();
+                        while let Some(binding) = join.next() {
+                            if let Some(provenance) = &provenance {
+                                let sources = join.provenance();
+                                witnesses
+                                    .push(witness(program, plan, &relations, &sources, provenance));
+                            }
+                            bindings.push(binding);
+                        }
+                        let facts = match &program.aggregates[i] {
+                            None => bindings
+                                .iter()
+                                .enumerate()
+                                .map(|(b, binding)| {
+                                    let fact =
+                                        plan.projection.iter().map(|&v| binding[v]).collect();
+                                    (fact, vec![b])
+                                })
+                                .collect(),
+                            Some((aggregate, fold)) => aggregate_groups(
+                                plan, &bindings, aggregate, *fold,
+                            )
+                            .ok_or_else(|| DatalogError::InvalidAggregate {
+                                rule: i,
+                                reason: format!("{aggregate} overflows"),
+                            })?,
+                        };
+                        let head = &full[&plan.head];
+                        let derived = derived.entry(&plan.head).or_default();
+                        for (fact, sources) in facts {
+                            if head.contains(&fact) {
+                                continue;
+                            }
+                            if let Some(provenance) = &mut provenance {
+                                let facts = provenance.entry(plan.head.clone()).or_default();
+                                facts.entry(fact.clone()).or_insert_with(|| {
+                                    let mut facts: Vec<BaseFact> = sources
+                                        .iter()
+                                        .flat_map(|&b| witnesses[b].clone())
+                                        .collect();
+                                    facts.sort_unstable();
+                                    facts.dedup();
+                                    facts
+                                });
+                            }
+                            derived.push(fact);
+                        }
+                    }
+                }
+
+                let mut new_facts = 0;
+                for (name, relation) in delta.iter_mut() {
+                    let facts = derived.remove(name.as_str()).unwrap_or_default();
+                    *relation = TrieRelation::new(relation.arity(), facts);
+                    new_facts += relation.len();
+                    if !relation.is_empty() {
+                        let old = &full[name];
+                        let merged =
+                            TrieRelation::new(old.arity(), old.tuples().chain(relation.tuples()));
+                        full.insert(name.clone(), merged);
+                    }
+                }
+                let facts = full.values().map(TrieRelation::len).sum();
+                if let Some(progress) = &mut self.progress {
+                    progress(&Progress {
+                        iteration,
+                        new_facts,
+                        facts,
+                    });
+                }
+
+                let termination = if new_facts == 0 {
+                    // The stratum is complete.
+                    break;
+                } else if self.max_facts.is_some_and(|n| facts >= n) {
+                    Termination::FactLimit
+                } else if self.max_iterations.is_some_and(|n| iteration >= n) {
+                    Termination::IterationLimit
+                } else {
+                    first = false;
+                    continue;
+                };
+                return Ok(Model {
+                    relations: full,
+                    iterations: iteration,
+                    termination,
+                    provenance,
+                });
+            }
+        }
+        Ok(Model {
+            relations: full,
+            iterations: iteration,
+            termination: Termination::Fixpoint,
+            provenance,
+        })
+    }
+}
+
+/// Joins the body of a rule, returning bindings of its variables.
+fn join_body<'r, K: Ord + Copy>(
+    plan: &RulePlan,
+    relations: &'r [Cow<'_, TrieRelation<K>>],
+) -> Result<TrieJoin<'r, K>, QueryError> {
+    let mut query = Query::new();
+    for (atom, relation) in plan.atoms.iter().zip(relations) {
+        let variables: Vec<&str> = atom.variables.iter().map(String::as_str).collect();
+        query = if atom.negated {
+            query.not(relation, &variables)
+        } else {
+            query.atom(relation, &variables)
+        };
+    }
+    let order: Vec<&str> = plan.variables.iter().map(String::as_str).collect();
+    query.order(&order).execute()
+}
+
+/// The base facts of the derivation of the current result of a rule body,
+/// given the rows of its joined atoms.
+fn witness<K: Ord + Copy>(
+    program: &Program<'_, K>,
+    plan: &RulePlan,
+    relations: &[Cow<'_, TrieRelation<K>>],
+    sources: &[(usize, usize)],
+    provenance: &HashMap<String, Witnesses<K>>,
+) -> Vec<BaseFact> {
+    let mut facts = Vec::new();
+    for &(j, row) in sources {
+        let atom = &plan.atoms[j];
+        let mut tuple = relations[j].tuple(row);
+        if let Some(permutation) = &atom.permutation {
+            let permuted = tuple.clone();
+            for (&a, &key) in permutation.iter().zip(&permuted) {
+                tuple[a] = key;
+            }
+        }
+        match program.relations.get(&atom.relation) {
+            Some(base) => facts.push(BaseFact {
+                relation: atom.relation.clone(),
+                row: base
+                    .position(&tuple)
+                    .expect("Joined tuple is in its relation"),
+            }),
+            None => facts.extend_from_slice(&provenance[&atom.relation][&tuple]),
+        }
+    }
+    facts
+}
+
+/// Groups bindings by the head variables of an aggregate rule and appends
+/// the aggregate of each group. Returns the facts with the indices of the
+/// bindings of their group, or None on overflow.
+fn aggregate_groups<K: Ord + Copy>(
+    plan: &RulePlan,
+    bindings: &[Vec<K>],
+    aggregate: &Aggregate,
+    fold: FoldFn<K>,
+) -> Option<Vec<(Vec<K>, Vec<usize>)>> {
+    let mut values: Vec<(Vec<K>, K, usize)> = bindings
+        .iter()
+        .enumerate()
+        .map(|(b, binding)| {
+            let group = plan.projection.iter().map(|&v| binding[v]).collect();
+            // Count ignores the value.
+            (group, binding[plan.aggregate_input.unwrap_or(0)], b)
+        })
+        .collect();
+    values.sort_unstable();
+    values
+        .chunk_by(|a, b| a.0 == b.0)
+        .map(|group| {
+            let folded: Vec<K> = group.iter().map(|(_, v, _)| *v).collect();
+            let mut fact = group[0].0.clone();
+            fact.push(fold(aggregate, &folded)?);
+            Some((fact, group.iter().map(|(_, _, b)| *b).collect()))
+        })
+        .collect()
+}
+
+/// BaseFact identifies a fact of a base relation by its row, see
+/// TrieRelation::tuple().
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BaseFact {
+    pub relation: String,
+    pub row: usize,
+}
+
+/// Per derived fact, the base facts it was derived from.
+type Witnesses<K> = BTreeMap<Vec<K>, Vec<BaseFact>>;
+
+/// Model holds the derived relations of a program.
+#[derive(Clone, Debug)]
+pub struct Model<K> {
+    relations: HashMap<String, TrieRelation<K>>,
+    iterations: usize,
+    termination: Termination,
+    provenance: Option<HashMap<String, Witnesses<K>>>,
+}
+
+impl<K> Model<K> {
+    pub fn relation(&self, name: &str) -> Option<&TrieRelation<K>> {
+        self.relations.get(name)
+    }
+
+    pub fn into_relations(self) -> HashMap<String, TrieRelation<K>> {
+        self.relations
+    }
+
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    pub fn termination(&self) -> Termination {
+        self.termination
+    }
+}
+
+impl<K: Ord> Model<K> {
+    /// Returns the base facts `fact` of `relation` was first derived from,
+    /// sorted and without duplicates. Returns None if the fact was not
+    /// derived or provenance was not recorded.
+    pub fn provenance(&self, relation: &str, fact: &[K]) -> Option<&[BaseFact]> {
+        let facts = self.provenance.as_ref()?.get(relation)?.get(fact)?;
+        Some(facts)
+    }
+}
+
+/// Computes the transitive closure of a binary relation.
+pub fn transitive_closure<K: Ord + Copy>(edges: &TrieRelation<K>) -> TrieRelation<K> {
+    assert_eq!(edges.arity(), 2, "Edges must have arity 2");
+    let program = Program::new()
+        .relation("edge", edges)
+        .rule(Rule::new(
+            atom("path", &["a", "b"]),
+            [atom("edge", &["a", "b"])],
+        ))
+        .rule(Rule::new(
+            atom("path", &["a", "c"]),
+            [atom("path", &["a", "b"]), atom("edge", &["b", "c"])],
+        ));
+    let model = program
+        .evaluate()
+        .expect("Transitive closure program is valid");
+    model.into_relations().remove("path").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cycle 0 -> 1 -> 2 -> 0 with a tail 2 -> 3 -> 4.
+    fn graph() -> TrieRelation<u32> {
+        TrieRelation::new(2, [[0, 1], [1, 2], [2, 0], [2, 3], [3, 4]])
+    }
+
+    fn closure_by_brute_force(edges: &TrieRelation<u32>) -> Vec<Vec<u32>> {
+        let mut paths: Vec<Vec<u32>> = edges.tuples().collect();
+        loop {
+            let mut extended = paths.clone();
+            for p in &paths {
+                for e in edges.tuples() {
+                    if p[1] == e[0] && !extended.contains(&vec![p[0], e[1]]) {
+                        extended.push(vec![p[0], e[1]]);
+                    }
+                }
+            }
+            if extended.len() == paths.len() {
+                paths.sort();
+                return paths;
+            }
+            paths = extended;
+        }
+    }
+
+    #[test]
+    fn test_transitive_closure() {
+        let edges = graph();
+        let closure = transitive_closure(&edges);
+        assert_eq!(
+            closure.tuples().collect::<Vec<_>>(),
+            closure_by_brute_force(&edges)
+        );
+    }
+
+    #[test]
+    fn test_permuted_body_atoms() {
+        let edges = TrieRelation::new(2, [[0, 2], [1, 2], [1, 3], [4, 3]]);
+        // Vertices with a common successor.
+        let program = Program::new().relation("edge", &edges).rule(Rule::new(
+            atom("sibling", &["a", "c"]),
+            [atom("edge", &["a", "b"]), atom("edge", &["c", "b"])],
+        ));
+        let model = program.evaluate().unwrap();
+        assert_eq!(
+            model
+                .relation("sibling")
+                .unwrap()
+                .tuples()
+                .collect::<Vec<_>>(),
+            vec![
+                vec![0, 0],
+                vec![0, 1],
+                vec![1, 0],
+                vec![1, 1],
+                vec![1, 4],
+                vec![4, 1],
+                vec![4, 4]
+            ]
+        );
+        assert_eq!(model.iterations(), 2);
+    }
+
+    #[test]
+    fn test_fixpoint_limits() {
+        let chain = TrieRelation::new(2, (0..10u32).map(|i| [i, i + 1]));
+        let program = Program::new()
+            .relation("edge", &chain)
+            .rule(Rule::new(
+                atom("path", &["a", "b"]),
+                [atom("edge", &["a", "b"])],
+            ))
+            .rule(Rule::new(
+                atom("path", &["a", "c"]),
+                [atom("path", &["a", "b"]), atom("edge", &["b", "c"])],
+            ));
+
+        let mut progress = vec![];
+        let model = program
+            .fixpoint()
+            .max_iterations(3)
+            .on_progress(|p| progress.push(*p))
+            .run()
+            .unwrap();
+        assert_eq!(model.termination(), Termination::IterationLimit);
+        // Paths of length 1 to 3.
+        assert_eq!(model.relation("path").unwrap().len(), 10 + 9 + 8);
+        assert_eq!(
+            progress.last(),
+            Some(&Progress {
+                iteration: 3,
+                new_facts: 8,
+                facts: 27
+            })
+        );
+
+        let model = program.fixpoint().max_facts(20).run().unwrap();
+        assert_eq!(model.termination(), Termination::FactLimit);
+        assert_eq!(model.iterations(), 3);
+
+        let model = program.fixpoint().max_iterations(100).run().unwrap();
+        assert_eq!(model.termination(), Termination::Fixpoint);
+        assert_eq!(model.relation("path").unwrap().len(), 55);
+        assert_eq!(model.iterations(), 11);
+    }
+
+    #[test]
+    fn test_magic_sets() {
+        let chain = TrieRelation::new(2, (0..200u32).map(|i| [i, i + 1]));
+        let left = Rule::new(
+            atom("path", &["a", "c"]),
+            [atom("path", &["a", "b"]), atom("edge", &["b", "c"])],
+        );
+        let right = Rule::new(
+            atom("path", &["a", "c"]),
+            [atom("edge", &["a", "b"]), atom("path", &["b", "c"])],
+        );
+        let program = |recursive: &Rule| {
+            Program::new()
+                .relation("edge", &chain)
+                .rule(Rule::new(
+                    atom("path", &["a", "b"]),
+                    [atom("edge", &["a", "b"])],
+                ))
+                .rule(recursive.clone())
+        };
+        let rules = |program: &Program<'_, u32>| {
+            program
+                .rules()
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let full = program(&left);
+        let magic = full.magic_sets("path", &[Some(190), None]).unwrap();
+        assert_eq!(
+            rules(&magic),
+            vec![
+                "path(a, b) :- magic^path^bf(a), edge(a, b).",
+                "path(a, c) :- magic^path^bf(a), path(a, b), edge(b, c).",
+            ]
+        );
+        let model = magic.evaluate().unwrap();
+        assert_eq!(model.relation("path").unwrap().len(), 10);
+
+        let full = program(&right);
+        let magic = full.magic_sets("path", &[Some(190), None]).unwrap();
+        assert_eq!(
+            rules(&magic),
+            vec![
+                "magic^path^bf(x0) :- seed^path^bf(x0).",
+                "path(a, b) :- magic^path^bf(a), edge(a, b).",
+                "magic^path^bf(b) :- magic^path^bf(a), edge(a, b).",
+                "path(a, c) :- magic^path^bf(a), edge(a, b), path(b, c).",
+            ]
+        );
+        // Paths from the vertices reachable from 190, instead of all 20100.
+        let model = magic.evaluate().unwrap();
+        assert_eq!(model.relation("path").unwrap().len(), 55);
+
+        let answers = program(&right)
+            .evaluate_goal("path", &[Some(190), None])
+            .unwrap();
+        let expected: Vec<Vec<u32>> = (191..=200).map(|c| vec![190, c]).collect();
+        assert_eq!(answers.tuples().collect::<Vec<_>>(), expected);
+
+        // Goals bound on other arguments use other adornments.
+        let edges = graph();
+        let program = Program::new()
+            .relation("edge", &edges)
+            .rule(Rule::new(
+                atom("path", &["a", "b"]),
+                [atom("edge", &["a", "b"])],
+            ))
+            .rule(right.clone());
+        let closure = transitive_closure(&edges);
+        for goal in [
+            [None, Some(0)],
+            [Some(2), Some(4)],
+            [Some(4), None],
+            [None, None],
+        ] {
+            let expected: Vec<Vec<u32>> = closure
+                .tuples()
+                .filter(|t| t.iter().zip(&goal).all(|(k, b)| b.is_none_or(|b| *k == b)))
+                .collect();
+            let answers = program.evaluate_goal("path", &goal).unwrap();
+            assert_eq!(answers.tuples().collect::<Vec<_>>(), expected, "{goal:?}");
+        }
+        assert_eq!(
+            program
+                .evaluate_goal("edge", &[Some(2), None])
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            program.magic_sets("edge", &[None, None]).err(),
+            Some(DatalogError::NotDerived("edge".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_stratified_negation() {
+        let edges = graph();
+        let vertices = TrieRelation::new(1, (0..6u32).map(|v| [v]));
+        // Pairs of vertices that are not connected by a path.
+        let program = Program::new()
+            .relation("edge", &edges)
+            .relation("vertex", &vertices)
+            .rule(Rule::new(
+                atom("path", &["a", "b"]),
+                [atom("edge", &["a", "b"])],
+            ))
+            .rule(Rule::new(
+                atom("path", &["a", "c"]),
+                [atom("path", &["a", "b"]), atom("edge", &["b", "c"])],
+            ))
+            .rule(Rule::new(
+                atom("unreachable", &["a", "b"]),
+                [
+                    atom("vertex", &["a"]),
+                    atom("vertex", &["b"]),
+                    not("path", &["a", "b"]),
+                ],
+            ));
+        let model = program.evaluate().unwrap();
+        let closure = transitive_closure(&edges);
+        let unreachable = model.relation("unreachable").unwrap();
+        assert_eq!(unreachable.len(), 36 - closure.len());
+        assert!(unreachable.contains(&[4, 0]));
+        assert!(unreachable.contains(&[5, 5]));
+        assert!(!unreachable.contains(&[0, 4]));
+        assert_eq!(
+            program.rules()[2].to_string(),
+            "unreachable(a, b) :- vertex(a), vertex(b), not path(a, b)."
+        );
+
+        // win(x) :- move(x, y), not win(y) negates itself recursively.
+        let error = Program::new()
+            .relation("move", &edges)
+            .rule(Rule::new(
+                atom("win", &["x"]),
+                [atom("move", &["x", "y"]), not("win", &["y"])],
+            ))
+            .evaluate()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            DatalogError::NotStratifiable {
+                rule: 0,
+                relation: "win".to_string(),
+                depends_on: "win".to_string()
+            }
+        );
+        assert!(error.to_string().starts_with("program is not stratifiable"));
+    }
+
+    #[test]
+    fn test_aggregates() {
+        // Weighted edges (from, to, weight).
+        let weighted =
+            TrieRelation::new(3, [[0, 1, 5], [0, 2, 3], [1, 2, 4], [2, 0, 3], [2, 3, 7]]);
+        let edges = graph();
+        let program = Program::new()
+            .relation("weighted", &weighted)
+            .relation("edge", &edges)
+            .rule(Rule::new(
+                atom("path", &["a", "b"]),
+                [atom("edge", &["a", "b"])],
+            ))
+            .rule(Rule::new(
+                atom("path", &["a", "c"]),
+                [atom("path", &["a", "b"]), atom("edge", &["b", "c"])],
+            ))
+            .aggregate(
+                Rule::new(atom("reach", &["a", "n"]), [atom("path", &["a", "b"])]),
+                Aggregate::Count,
+            )
+            .aggregate(
+                Rule::new(
+                    atom("lightest", &["a", "m"]),
+                    [atom("weighted", &["a", "b", "w"])],
+                ),
+                Aggregate::Min("w".to_string()),
+            )
+            .aggregate(
+                Rule::new(atom("total", &["w"]), [atom("weighted", &["a", "b", "x"])]),
+                Aggregate::Sum("x".to_string()),
+            );
+        let model = program.evaluate().unwrap();
+        let facts = |name| model.relation(name).unwrap().tuples().collect::<Vec<_>>();
+        assert_eq!(
+            facts("reach"),
+            vec![vec![0, 5], vec![1, 5], vec![2, 5], vec![3, 1]]
+        );
+        assert_eq!(facts("lightest"), vec![vec![0, 3], vec![1, 4], vec![2, 3]]);
+        assert_eq!(facts("total"), vec![vec![22]]);
+
+        let dot = program.dependency_graph_dot();
+        assert!(dot.contains(
+            "  subgraph cluster_stratum_1 {\n    label=\"stratum 1\";\n    \"reach\";\n"
+        ));
+        assert!(dot.contains("\"path\" -> \"path\";"));
+        assert!(dot.contains("\"path\" -> \"reach\" [style=dashed, label=\"count\"];"));
+        assert!(dot.contains("\"weighted\" [shape=box];"));
+
+        let error = |aggregate| {
+            Program::new()
+                .relation("weighted", &weighted)
+                .aggregate(
+                    Rule::new(atom("p", &["a", "b"]), [atom("weighted", &["a", "b", "w"])]),
+                    aggregate,
+                )
+                .evaluate()
+                .unwrap_err()
+        };
+        assert!(matches!(
+            error(Aggregate::Count),
+            DatalogError::InvalidAggregate { rule: 0, .. }
+        ));
+        let overflow = TrieRelation::new(2, [[0u8, 200], [1, 100]]);
+        let error = Program::new()
+            .relation("r", &overflow)
+            .aggregate(
+                Rule::new(atom("s", &["t"]), [atom("r", &["a", "x"])]),
+                Aggregate::Sum("x".to_string()),
+            )
+            .evaluate()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid aggregate in rule 0: sum(x) overflows"
+        );
+    }
+
+    #[test]
+    fn test_provenance() {
+        let edges = graph();
+        let vertices = TrieRelation::new(1, (0..6u32).map(|v| [v]));
+        let program = Program::new()
+            .relation("edge", &edges)
+            .relation("vertex", &vertices)
+            .rule(Rule::new(
+                atom("path", &["a", "b"]),
+                [atom("edge", &["a", "b"])],
+            ))
+            .rule(Rule::new(
+                atom("path", &["a", "c"]),
+                [atom("path", &["a", "b"]), atom("edge", &["b", "c"])],
+            ))
+            .rule(Rule::new(
+                atom("back", &["b", "a"]),
+                [atom("edge", &["a", "b"]), not("vertex", &["b"])],
+            ))
+            .aggregate(
+                Rule::new(atom("reach", &["a", "n"]), [atom("path", &["a", "b"])]),
+                Aggregate::Count,
+            );
+        let model = program.fixpoint().provenance().run().unwrap();
+        let rows = |name: &str, fact: &[u32]| {
+            let facts = model.provenance(name, fact).unwrap();
+            assert!(facts.iter().all(|f| f.relation == "edge"));
+            facts.iter().map(|f| edges.tuple(f.row)).collect::<Vec<_>>()
+        };
+        assert_eq!(rows("path", &[0, 1]), vec![vec![0, 1]]);
+        assert_eq!(
+            rows("path", &[0, 4]),
+            vec![vec![0, 1], vec![1, 2], vec![2, 3], vec![3, 4]]
+        );
+        assert_eq!(
+            rows("path", &[0, 0]),
+            vec![vec![0, 1], vec![1, 2], vec![2, 0]]
+        );
+        // Aggregates are derived from all facts of their group.
+        assert_eq!(rows("reach", &[3, 1]), vec![vec![3, 4]]);
+        assert_eq!(rows("reach", &[0, 5]).len(), 5);
+        assert_eq!(model.provenance("path", &[4, 0]), None);
+        assert_eq!(model.relation("back").unwrap().len(), 0);
+        assert_eq!(
+            program.evaluate().unwrap().provenance("path", &[0, 1]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_program_errors() {
+        let edges = graph();
+        let base = || Program::new().relation("edge", &edges);
+        let error = |program: Program<'_, u32>| program.evaluate().err();
+        assert_eq!(
+            error(base().rule(Rule::new(atom("p", &["a"]), [atom("q", &["a"])]))),
+            Some(DatalogError::UnknownRelation("q".to_string()))
+        );
+        assert_eq!(
+            error(base().rule(Rule::new(atom("p", &["a"]), [atom("edge", &["a"])]))),
+            Some(DatalogError::Arity {
+                relation: "edge".to_string(),
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            error(base().rule(Rule::new(atom("p", &["c"]), [atom("edge", &["a", "b"])]))),
+            Some(DatalogError::UnsafeRule {
+                rule: 0,
+                variable: "c".to_string()
+            })
+        );
+        assert_eq!(
+            error(base().rule(Rule::new(
+                atom("edge", &["a", "b"]),
+                [atom("edge", &["b", "a"])]
+            ))),
+            Some(DatalogError::DerivedBaseRelation("edge".to_string()))
+        );
+        assert!(matches!(
+            error(base().rule(Rule::new(atom("p", &["a"]), [atom("edge", &["a", "a"])]))),
+            Some(DatalogError::Query { rule: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! Set semantics over sources with duplicate keys.
+
+use crate::Seekable;
+use crate::zonemap::Zone;
+
+/// DedupIterator collapses runs of equal keys of the wrapped iterator into a
+/// single key, so that sources with duplicates can be joined as sets.
+///
+/// seek() needs no special handling: it lands on the first key of a run,
+/// which is exactly where a run starts for the deduplicated iterator, too.
+pub struct DedupIterator<I> {
+    iter: I,
+}
+
+impl<I> DedupIterator<I> {
+    pub fn new(iter: I) -> Self {
+        Self { iter }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I: Seekable> Seekable for DedupIterator<I> {
+    type Key = I::Key;
+
+    fn key(&self) -> I::Key {
+        self.iter.key()
+    }
+
+    fn next(&mut self) {
+        let key = self.iter.key();
+        self.iter.next();
+        while !self.iter.at_end() && self.iter.key() == key {
+            self.iter.next();
+        }
+    }
+
+    fn seek(&mut self, seek_key: I::Key) {
+        self.iter.seek(seek_key);
+    }
+
+    fn at_end(&self) -> bool {
+        self.iter.at_end()
+    }
+
+    fn zone_map(&self) -> Option<Vec<Zone<I::Key>>> {
+        self.iter.zone_map()
+    }
+
+    /// Collapsing duplicates keeps the bound, but not the exact count.
+    fn estimate(&self) -> Option<usize> {
+        self.iter.estimate()
+    }
+
+    fn will_need(&mut self, key: I::Key) {
+        self.iter.will_need(key)
+    }
+
+    fn upper_bound(&self) -> Option<I::Key> {
+        self.iter.upper_bound()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LinearIterator;
+    #[cfg(feature = "time")]
+    use crate::{LeapFrogJoin, trace::Tracer};
+
+    fn collect<I: Seekable>(mut iter: I) -> Vec<I::Key> {
+        let mut keys = vec![];
+        while !iter.at_end() {
+            keys.push(iter.key());
+            iter.next();
+        }
+        keys
+    }
+
+    #[test]
+    fn test_dedup_next_and_seek() {
+        let keys = [1, 1, 2, 3, 3, 3, 7, 7];
+        assert_eq!(
+            collect(DedupIterator::new(LinearIterator::new(&keys))),
+            vec![1, 2, 3, 7]
+        );
+        let mut iter = DedupIterator::new(LinearIterator::new(&keys));
+        iter.seek(3);
+        assert_eq!(iter.key(), 3);
+        iter.next();
+        assert_eq!(iter.key(), 7);
+        iter.next();
+        assert!(iter.at_end());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_dedup_join() {
+        let tab1 = [0, 0, 2, 2, 2, 4, 5, 5];
+        let tab2 = [0, 1, 2, 2, 5, 5, 5];
+        let tracer = Tracer::new();
+        // Composes with other adapters in any order.
+        let mut join = LeapFrogJoin::from_iters(vec![
+            DedupIterator::new(tracer.wrap("tab1", LinearIterator::new(&tab1))),
+            DedupIterator::new(tracer.wrap("tab2", LinearIterator::new(&tab2))),
+        ]);
+        let mut result = vec![];
+        while !join.at_end() {
+            result.push(join.key());
+            join.next();
+        }
+        assert_eq!(result, vec![0, 2, 5]);
+        assert!(!tracer.is_empty());
+    }
+}
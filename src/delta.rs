@@ -0,0 +1,182 @@
+//! Point updates to relations.
+//!
+//! A TrieRelation is immutable, and building one sorts all of its tuples.
+//! DeltaRelation puts a small buffer of inserted and deleted tuples in front
+//! of it, so single tuples are inserted and deleted in O(log m) for m
+//! buffered changes. DeltaRelation::relation() applies the buffer before the
+//! next join by merging it into the base relation, which costs one pass over
+//! the base and no sorting; reads between writes find nothing to merge.
+
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use crate::trie::TrieRelation;
+
+/// DeltaRelation is a TrieRelation with buffered inserts and deletes.
+#[derive(Clone, Debug)]
+pub struct DeltaRelation<K> {
+    base: TrieRelation<K>,
+    /// Tuples missing from the base.
+    inserted: BTreeSet<Vec<K>>,
+    /// Tuples of the base.
+    deleted: BTreeSet<Vec<K>>,
+}
+
+impl<K: Ord + Copy> DeltaRelation<K> {
+    pub fn new(base: TrieRelation<K>) -> Self {
+        Self {
+            base,
+            inserted: BTreeSet::new(),
+            deleted: BTreeSet::new(),
+        }
+    }
+
+    pub fn empty(arity: usize) -> Self {
+        Self::new(TrieRelation::empty(arity))
+    }
+
+    pub fn arity(&self) -> usize {
+        self.base.arity()
+    }
+
+    /// Number of tuples, including buffered changes.
+    pub fn len(&self) -> usize {
+        self.base.len() + self.inserted.len() - self.deleted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of buffered changes.
+    pub fn pending(&self) -> usize {
+        self.inserted.len() + self.deleted.len()
+    }
+
+    pub fn contains(&self, tuple: &[K]) -> bool {
+        if self.base.contains(tuple) {
+            !self.deleted.contains(tuple)
+        } else {
+            self.inserted.contains(tuple)
+        }
+    }
+
+    /// Inserts `tuple`, returning whether it was missing.
+    pub fn insert(&mut self, tuple: &[K]) -> bool {
+        if self.base.contains(tuple) {
+            self.deleted.remove(tuple)
+        } else {
+            self.inserted.insert(tuple.to_vec())
+        }
+    }
+
+    /// Deletes `tuple`, returning whether it was present.
+    pub fn remove(&mut self, tuple: &[K]) -> bool {
+        if self.base.contains(tuple) {
+            self.deleted.insert(tuple.to_vec())
+        } else {
+            self.inserted.remove(tuple)
+        }
+    }
+
+    /// Returns the relation with all changes applied, merging buffered
+    /// changes into it first.
+    pub fn relation(&mut self) -> &TrieRelation<K> {
+        if self.pending() > 0 {
+            self.base = self.merged();
+            self.inserted.clear();
+            self.deleted.clear();
+        }
+        &self.base
+    }
+
+    pub fn into_relation(mut self) -> TrieRelation<K> {
+        self.relation();
+        self.base
+    }
+
+    /// Merges the base rows that were not deleted with the inserted tuples.
+    fn merged(&self) -> TrieRelation<K> {
+        let base = &self.base;
+        let arity = base.arity();
+        let compare = |row: usize, tuple: &[K]| {
+            (0..arity)
+                .map(|a| base.column(a)[row].cmp(&tuple[a]))
+                .find(|o| o.is_ne())
+                .unwrap_or(Ordering::Equal)
+        };
+        // The rows of the result, from the base or inserted.
+        let mut rows: Vec<Result<usize, &[K]>> = Vec::with_capacity(self.len());
+        let mut deleted = self.deleted.iter().peekable();
+        let mut inserted = self.inserted.iter().peekable();
+        for row in 0..base.len() {
+            while let Some(tuple) = inserted.next_if(|t| compare(row, t).is_gt()) {
+                rows.push(Err(tuple));
+            }
+            match deleted.next_if(|t| compare(row, t).is_eq()) {
+                Some(_) => continue,
+                None => rows.push(Ok(row)),
+            }
+        }
+        rows.extend(inserted.map(|t| Err(t.as_slice())));
+
+        let mut keys = Vec::with_capacity(arity * rows.len());
+        for a in 0..arity {
+            keys.extend(rows.iter().map(|row| match row {
+                Ok(row) => base.column(a)[*row],
+                Err(tuple) => tuple[a],
+            }));
+        }
+        TrieRelation::from_arena(arity, keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+
+    #[test]
+    fn test_point_updates() {
+        let mut rel = DeltaRelation::new(TrieRelation::new(2, [[1, 2], [2, 3], [4, 5]]));
+        assert!(rel.insert(&[3, 4]));
+        assert!(!rel.insert(&[2, 3]));
+        assert!(rel.remove(&[2, 3]));
+        assert!(!rel.remove(&[2, 3]));
+        assert!(rel.insert(&[0, 9]));
+        assert!(rel.insert(&[9, 0]));
+        assert!(rel.remove(&[9, 0]));
+        assert!(rel.contains(&[3, 4]));
+        assert!(!rel.contains(&[2, 3]));
+        assert_eq!(rel.len(), 4);
+        assert_eq!(rel.pending(), 3);
+
+        let expected = TrieRelation::new(2, [[0, 9], [1, 2], [3, 4], [4, 5]]);
+        assert_eq!(rel.relation(), &expected);
+        assert_eq!(rel.pending(), 0);
+        assert!(rel.insert(&[2, 3]));
+        assert!(rel.remove(&[4, 5]));
+        assert!(rel.remove(&[0, 9]));
+        let expected = TrieRelation::new(2, [[1, 2], [2, 3], [3, 4]]);
+        assert_eq!(rel.clone().into_relation(), expected);
+    }
+
+    #[test]
+    fn test_join_after_updates() {
+        let mut edges = DeltaRelation::empty(2);
+        for [a, b] in [[1, 2], [2, 3], [1, 4]] {
+            edges.insert(&[a, b]);
+        }
+        edges.relation();
+        edges.insert(&[1, 3]);
+        edges.remove(&[1, 4]);
+        let edges = edges.relation();
+        let paths = Query::new()
+            .atom(edges, &["a", "b"])
+            .atom(edges, &["b", "c"])
+            .atom(edges, &["a", "c"])
+            .run()
+            .unwrap();
+        assert_eq!(paths, vec![vec![1, 2, 3]]);
+    }
+}
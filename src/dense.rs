@@ -0,0 +1,204 @@
+//! Dense renumbering of keys.
+//!
+//! A KeyDomain maps the distinct keys of a set of sources to the codes
+//! 0..n, in key order, and back. Joins over the codes find the codes of the
+//! keys a join over the keys finds, in the same order, while the codes fit
+//! into bitsets and compress well.
+//!
+//! intersect() applies this automatically to integer keys: if the keys of
+//! all sources lie within a span no more than 64 times the length of the
+//! smallest source, key - min is already a dense code, and a bitset per
+//! source intersects them with one AND per 64 codes. Otherwise it falls back
+//! to the leapfrog join. With the `gpu` feature, gpu::intersect() puts a
+//! GPU in front of it for two large u32 sources.
+
+/// KeyDomain is the sorted, distinct keys of some sources.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyDomain<K> {
+    keys: Vec<K>,
+}
+
+impl<K: Ord + Copy> KeyDomain<K> {
+    /// Collects the keys of sorted sources.
+    pub fn new(sources: &[&[K]]) -> Self {
+        let mut keys: Vec<K> = sources.iter().flat_map(|s| s.iter().copied()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        assert!(keys.len() <= u32::MAX as usize, "Key domain is too large");
+        Self { keys }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the code of `key`, or None if it is not in the domain.
+    pub fn code(&self, key: K) -> Option<u32> {
+        self.keys.binary_search(&key).ok().map(|code| code as u32)
+    }
+
+    /// Returns the key of `code`, which must be < len().
+    pub fn key(&self, code: u32) -> K {
+        self.keys[code as usize]
+    }
+
+    /// Returns the codes of a source whose keys are all in the domain.
+    pub fn encode(&self, source: &[K]) -> Vec<u32> {
+        let mut codes = Vec::with_capacity(source.len());
+        // The source is sorted, so every key is searched after the last.
+        let mut pos = 0;
+        for &key in source {
+            pos += self.keys[pos..].partition_point(|&k| k < key);
+            assert!(self.keys.get(pos) == Some(&key), "Key is not in the domain");
+            codes.push(pos as u32);
+        }
+        codes
+    }
+
+    pub fn decode(&self, codes: &[u32]) -> Vec<K> {
+        codes.iter().map(|&code| self.key(code)).collect()
+    }
+}
+
+/// Intersects sources of codes below `len` with one bitset per source. The
+/// result is in ascending order.
+pub fn intersect_codes(sources: &[&[u32]], len: usize) -> Vec<u32> {
+    let codes = intersect_bits(sources, len, |c| c as usize);
+    codes.into_iter().map(|code| code as u32).collect()
+}
+
+/// Intersects sources with one bitset each, given the code below `len` of
+/// every key, and returns the common codes in ascending order.
+fn intersect_bits<K: Copy>(sources: &[&[K]], len: usize, code: impl Fn(K) -> usize) -> Vec<usize> {
+    let bitset = |source: &[K]| {
+        let mut bits = vec![0u64; len.div_ceil(64)];
+        for &key in source {
+            let code = code(key);
+            bits[code / 64] |= 1 << (code % 64);
+        }
+        bits
+    };
+    let Some((first, rest)) = sources.split_first() else {
+        return Vec::new();
+    };
+    let mut bits = bitset(first);
+    for source in rest {
+        for (word, other) in bits.iter_mut().zip(bitset(source)) {
+            *word &= other;
+        }
+    }
+    set_bits(&bits).collect()
+}
+
+fn set_bits(bits: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    bits.iter().enumerate().flat_map(|(i, &word)| {
+        let mut word = word;
+        std::iter::from_fn(move || {
+            (word != 0).then(|| {
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                i * 64 + bit
+            })
+        })
+    })
+}
+
+/// DenseKey is an integer key whose distance from a smaller key is a code.
+pub trait DenseKey: Ord + Copy {
+    /// Returns `self - min`, which is >= 0.
+    fn offset_from(self, min: Self) -> u64;
+
+    /// Returns `min + offset`.
+    fn from_offset(min: Self, offset: u64) -> Self;
+}
+
+macro_rules! impl_dense_key {
+    ($($t:ty),*) => {$(
+        impl DenseKey for $t {
+            fn offset_from(self, min: Self) -> u64 {
+                (self as i128 - min as i128) as u64
+            }
+
+            fn from_offset(min: Self, offset: u64) -> Self {
+                (min as i128 + offset as i128) as $t
+            }
+        }
+    )*};
+}
+
+impl_dense_key!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+/// Returns the smallest key of all sorted sources and the span of all keys,
+/// if no source is empty.
+fn span<K: DenseKey>(sources: &[&[K]]) -> Option<(K, u64)> {
+    let min = sources.iter().map(|s| s.first().copied()).min()??;
+    let max = sources.iter().map(|s| s.last().copied()).max()??;
+    Some((min, max.offset_from(min)))
+}
+
+/// Checks whether intersect() uses bitsets for the sources.
+pub fn prefers_bitsets<K: DenseKey>(sources: &[&[K]]) -> bool {
+    let smallest = sources.iter().map(|s| s.len()).min().unwrap_or(0) as u64;
+    span(sources).is_some_and(|(_, span)| span / 64 <= smallest)
+}
+
+/// Returns the keys common to all sorted sources, in ascending order, with
+/// bitsets if the keys are dense enough and the leapfrog join otherwise.
+pub fn intersect<K: DenseKey>(sources: Vec<&[K]>) -> Vec<K> {
+    if !prefers_bitsets(&sources) {
+        return crate::intersect(sources);
+    }
+    let Some((min, span)) = span(&sources) else {
+        return Vec::new();
+    };
+    let offsets = intersect_bits(&sources, span as usize + 1, |k| k.offset_from(min) as usize);
+    offsets
+        .into_iter()
+        .map(|offset| K::from_offset(min, offset as u64))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LeapFrogJoin;
+
+    #[test]
+    fn test_key_domain() {
+        let a = [-1_000_000i64, 5, 70, 9_000_000];
+        let b = [5, 6, 70, 80, 9_000_000];
+        let domain = KeyDomain::new(&[&a, &b]);
+        assert_eq!(domain.len(), 6);
+        assert_eq!(domain.code(70), Some(3));
+        assert_eq!(domain.code(71), None);
+        let (ca, cb) = (domain.encode(&a), domain.encode(&b));
+        assert_eq!(ca, [0, 1, 3, 5]);
+        assert_eq!(domain.decode(&cb), b);
+
+        let joined = LeapFrogJoin::new(vec![&ca, &cb])
+            .into_keys()
+            .collect::<Vec<_>>();
+        assert_eq!(intersect_codes(&[&ca, &cb], domain.len()), joined);
+        assert_eq!(domain.decode(&joined), crate::intersect(vec![&a, &b]));
+    }
+
+    #[test]
+    fn test_intersect() {
+        let a: Vec<i32> = (-500..500).collect();
+        let b: Vec<i32> = (-500..500).step_by(3).collect();
+        let c: Vec<i32> = vec![-499, -497, 1, 499];
+        assert!(prefers_bitsets(&[&a, &b]));
+        assert!(!prefers_bitsets(&[&a, &b, &c]));
+        for sources in [vec![&a[..], &b], vec![&a, &b, &c], vec![&a, &[]]] {
+            assert_eq!(intersect(sources.clone()), crate::intersect(sources));
+        }
+        assert_eq!(
+            intersect(vec![&[u64::MAX - 1, u64::MAX][..], &[u64::MAX]]),
+            [u64::MAX]
+        );
+    }
+}
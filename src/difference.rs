@@ -0,0 +1,174 @@
+//! Set difference of sorted sources.
+//!
+//! Subtracting keys is also how soft deletes and per-query exclusion lists
+//! (blocked users, retracted documents) are applied without rebuilding an
+//! index: a DifferenceIterator with a sorted mask as its right input
+//! presents a source minus the keys of the mask. The mask is only sought to
+//! keys the source lands on, so a small mask costs a few seeks, and a large
+//! one is skipped through by seeking rather than scanned.
+
+use crate::Seekable;
+use crate::zonemap::Zone;
+
+/// DifferenceIterator returns the keys of one sorted iterator that another
+/// one lacks, the anti-join of two single-attribute inputs. It is sorted
+/// itself, so it can feed a LeapFrogJoin like any other source.
+///
+/// The subtracted iterator is only ever sought to the current key, so it
+/// advances at the pace of the result and never further.
+pub struct DifferenceIterator<I, J> {
+    left: I,
+    right: J,
+}
+
+impl<I, J> DifferenceIterator<I, J>
+where
+    I: Seekable,
+    J: Seekable<Key = I::Key>,
+{
+    /// Creates an iterator over the keys of `left` missing from `right`.
+    pub fn new(left: I, right: J) -> Self {
+        let mut difference = Self { left, right };
+        difference.skip();
+        difference
+    }
+
+    pub fn into_inner(self) -> (I, J) {
+        (self.left, self.right)
+    }
+
+    /// Advances the left iterator past all keys the right one contains.
+    fn skip(&mut self) {
+        while !self.left.at_end() && !self.right.at_end() {
+            let key = self.left.key();
+            // Past the bound of the right iterator, nothing is subtracted.
+            if self.right.upper_bound().is_some_and(|bound| key > bound) {
+                break;
+            }
+            if self.right.key() < key {
+                self.right.seek(key);
+            }
+            if self.right.at_end() || self.right.key() > key {
+                break;
+            }
+            self.left.next();
+        }
+    }
+}
+
+impl<I, J> Seekable for DifferenceIterator<I, J>
+where
+    I: Seekable,
+    J: Seekable<Key = I::Key>,
+{
+    type Key = I::Key;
+
+    fn key(&self) -> I::Key {
+        self.left.key()
+    }
+
+    fn next(&mut self) {
+        self.left.next();
+        self.skip();
+    }
+
+    fn seek(&mut self, seek_key: I::Key) {
+        self.left.seek(seek_key);
+        self.skip();
+    }
+
+    fn at_end(&self) -> bool {
+        self.left.at_end()
+    }
+
+    /// The zones of the left iterator, which bound the difference, too.
+    fn zone_map(&self) -> Option<Vec<Zone<I::Key>>> {
+        self.left.zone_map()
+    }
+
+    fn estimate(&self) -> Option<usize> {
+        self.left.estimate()
+    }
+
+    fn will_need(&mut self, key: I::Key) {
+        self.left.will_need(key);
+        self.right.will_need(key);
+    }
+
+    fn upper_bound(&self) -> Option<I::Key> {
+        self.left.upper_bound()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::union::UnionIterator;
+    use crate::{LeapFrogJoin, LinearIterator};
+
+    fn keys(mut iter: impl Seekable<Key = i32>) -> Vec<i32> {
+        let mut keys = vec![];
+        while !iter.at_end() {
+            keys.push(iter.key());
+            iter.next();
+        }
+        keys
+    }
+
+    #[test]
+    fn test_difference_iterator() {
+        let left = [1, 2, 4, 5, 7, 9];
+        let right = [2, 3, 5, 9, 10];
+        let difference =
+            || DifferenceIterator::new(LinearIterator::new(&left), LinearIterator::new(&right));
+        assert_eq!(keys(difference()), [1, 4, 7]);
+
+        let mut iter = difference();
+        iter.seek(5);
+        assert_eq!(iter.key(), 7);
+        iter.seek(8);
+        assert!(iter.at_end());
+    }
+
+    #[test]
+    fn test_masked_source() {
+        let followers = [1, 2, 3, 5, 8, 13, 21];
+        let active = [2, 3, 5, 7, 11, 13, 17, 21];
+        let blocked = [0, 3, 4, 13, 14, 15, 16, 17, 18, 19, 20];
+        let join = LeapFrogJoin::from_iters(vec![
+            DifferenceIterator::new(
+                LinearIterator::new(&followers),
+                LinearIterator::new(&blocked),
+            ),
+            DifferenceIterator::new(LinearIterator::new(&active), LinearIterator::new(&[])),
+        ]);
+        assert_eq!(join.into_keys().collect::<Vec<_>>(), [2, 5, 21]);
+
+        let mut masked =
+            DifferenceIterator::new(LinearIterator::new(&active), LinearIterator::new(&blocked));
+        masked.seek(12);
+        assert_eq!(masked.key(), 21);
+    }
+
+    #[test]
+    fn test_composed_sources() {
+        let evens: Vec<i32> = (0..60).filter(|x| x % 2 == 0).collect();
+        let threes: Vec<i32> = (0..60).filter(|x| x % 3 == 0).collect();
+        let fives: Vec<i32> = (0..60).filter(|x| x % 5 == 0).collect();
+        let sevens: Vec<i32> = (0..60).filter(|x| x % 7 == 0).collect();
+
+        // Even numbers divisible by 3 or 7, but not by 5.
+        let join = LeapFrogJoin::from_iters(vec![
+            Box::new(LinearIterator::new(&evens)) as Box<dyn Seekable<Key = i32>>,
+            Box::new(UnionIterator::new(vec![
+                LinearIterator::new(&threes),
+                LinearIterator::new(&sevens),
+            ])),
+        ]);
+        let result = keys(DifferenceIterator::new(join, LinearIterator::new(&fives)));
+        let expected: Vec<i32> = (0..60)
+            .filter(|x| x % 2 == 0 && (x % 3 == 0 || x % 7 == 0) && x % 5 != 0)
+            .collect();
+        assert_eq!(result, expected);
+    }
+}
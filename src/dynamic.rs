@@ -0,0 +1,431 @@
+//! Relations whose schema is only known at runtime.
+//!
+//! An AnyRelation is a set of typed columns plus a schema naming them. This
+//! is meant for callers like query frontends that don't know column types at
+//! compile time. join() dispatches on the type of the key columns to the
+//! statically typed LeapFrogJoin, which does the actual work, and assembles
+//! the matching rows as boxed Values.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::dedup::DedupIterator;
+use crate::{LeapFrogJoin, LinearIterator};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataType {
+    Int64,
+    UInt64,
+    Utf8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub data_type: DataType,
+}
+
+impl Field {
+    pub fn new(name: &str, data_type: DataType) -> Self {
+        Self {
+            name: name.to_string(),
+            data_type,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schema {
+    pub fields: Vec<Field>,
+}
+
+impl Schema {
+    pub fn new(fields: Vec<Field>) -> Self {
+        Self { fields }
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Int64(Vec<i64>),
+    UInt64(Vec<u64>),
+    Utf8(Vec<String>),
+}
+
+impl Column {
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Column::Int64(_) => DataType::Int64,
+            Column::UInt64(_) => DataType::UInt64,
+            Column::Utf8(_) => DataType::Utf8,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Int64(v) => v.len(),
+            Column::UInt64(v) => v.len(),
+            Column::Utf8(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn value(&self, row: usize) -> Value {
+        match self {
+            Column::Int64(v) => Value::Int64(v[row]),
+            Column::UInt64(v) => Value::UInt64(v[row]),
+            Column::Utf8(v) => Value::Utf8(v[row].clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Value {
+    Int64(i64),
+    UInt64(u64),
+    Utf8(String),
+}
+
+pub type Row = Box<[Value]>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DynamicError {
+    /// The number of columns or their types do not match the schema.
+    SchemaMismatch(String),
+    /// Columns of one relation differ in length.
+    LengthMismatch,
+    UnknownColumn(String),
+    /// The key columns of the join have different types.
+    KeyTypeMismatch {
+        expected: DataType,
+        found: DataType,
+    },
+}
+
+impl fmt::Display for DynamicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DynamicError::SchemaMismatch(msg) => write!(f, "columns do not match schema: {msg}"),
+            DynamicError::LengthMismatch => write!(f, "columns differ in length"),
+            DynamicError::UnknownColumn(name) => write!(f, "unknown column {name:?}"),
+            DynamicError::KeyTypeMismatch { expected, found } => {
+                write!(f, "key column has type {found:?}, expected {expected:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DynamicError {}
+
+/// AnyRelation is a relation with a runtime schema. Rows need not be sorted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnyRelation {
+    schema: Schema,
+    columns: Vec<Column>,
+}
+
+impl AnyRelation {
+    pub fn new(schema: Schema, columns: Vec<Column>) -> Result<Self, DynamicError> {
+        if schema.fields.len() != columns.len() {
+            return Err(DynamicError::SchemaMismatch(format!(
+                "{} fields, {} columns",
+                schema.fields.len(),
+                columns.len()
+            )));
+        }
+        for (field, column) in schema.fields.iter().zip(&columns) {
+            if field.data_type != column.data_type() {
+                return Err(DynamicError::SchemaMismatch(format!(
+                    "column {:?} has type {:?}",
+                    field.name,
+                    column.data_type()
+                )));
+            }
+        }
+        if columns.windows(2).any(|w| w[0].len() != w[1].len()) {
+            return Err(DynamicError::LengthMismatch);
+        }
+        Ok(Self { schema, columns })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.first().map_or(0, Column::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn row(&self, row: usize) -> Row {
+        self.columns.iter().map(|c| c.value(row)).collect()
+    }
+}
+
+/// The result of join(): the output schema and one row per match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JoinOutput {
+    pub schema: Schema,
+    pub rows: Vec<Row>,
+}
+
+/// Joins relations on the named key column of each. The output has the key
+/// column first, followed by the non-key columns of every relation in order,
+/// and contains every combination of matching rows.
+pub fn join(inputs: &[(&AnyRelation, &str)]) -> Result<JoinOutput, DynamicError> {
+    let mut key_columns = Vec::with_capacity(inputs.len());
+    for (relation, name) in inputs {
+        let index = relation
+            .schema
+            .index_of(name)
+            .ok_or_else(|| DynamicError::UnknownColumn(name.to_string()))?;
+        key_columns.push(index);
+    }
+    let Some(&(first, _)) = inputs.first() else {
+        return Ok(JoinOutput {
+            schema: Schema::default(),
+            rows: Vec::new(),
+        });
+    };
+    let key_type = first.schema.fields[key_columns[0]].data_type;
+    for ((relation, _), &index) in inputs.iter().zip(&key_columns) {
+        let found = relation.schema.fields[index].data_type;
+        if found != key_type {
+            return Err(DynamicError::KeyTypeMismatch {
+                expected: key_type,
+                found,
+            });
+        }
+    }
+
+    let columns: Vec<&Column> = inputs
+        .iter()
+        .zip(&key_columns)
+        .map(|((relation, _), &index)| &relation.columns[index])
+        .collect();
+    // Per relation, the row numbers sorted by key, and per match, the range
+    // of that order holding the key.
+    let (orders, matches) = match key_type {
+        DataType::Int64 => join_typed(&columns, |c| match c {
+            Column::Int64(v) => v.as_slice(),
+            _ => unreachable!(),
+        }),
+        DataType::UInt64 => join_typed(&columns, |c| match c {
+            Column::UInt64(v) => v.as_slice(),
+            _ => unreachable!(),
+        }),
+        DataType::Utf8 => {
+            let strs: Vec<Vec<&str>> = columns
+                .iter()
+                .map(|c| match c {
+                    Column::Utf8(v) => v.iter().map(String::as_str).collect(),
+                    _ => unreachable!(),
+                })
+                .collect();
+            join_sorted(strs.iter().map(Vec::as_slice).collect())
+        }
+    };
+
+    let mut fields = vec![first.schema.fields[key_columns[0]].clone()];
+    for ((relation, _), &key) in inputs.iter().zip(&key_columns) {
+        fields.extend(
+            relation
+                .schema
+                .fields
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != key)
+                .map(|(_, f)| f.clone()),
+        );
+    }
+
+    let mut rows = Vec::new();
+    for ranges in matches {
+        let mut combination = vec![0; inputs.len()];
+        'combinations: loop {
+            let mut row = Vec::with_capacity(fields.len());
+            let first_row = orders[0][ranges[0].start + combination[0]];
+            row.push(first.columns[key_columns[0]].value(first_row));
+            for (r, ((relation, _), &key)) in inputs.iter().zip(&key_columns).enumerate() {
+                let source_row = orders[r][ranges[r].start + combination[r]];
+                for (i, column) in relation.columns.iter().enumerate() {
+                    if i != key {
+                        row.push(column.value(source_row));
+                    }
+                }
+            }
+            rows.push(row.into_boxed_slice());
+
+            // Advance the combination like an odometer.
+            for r in (0..inputs.len()).rev() {
+                combination[r] += 1;
+                if combination[r] < ranges[r].len() {
+                    continue 'combinations;
+                }
+                combination[r] = 0;
+            }
+            break;
+        }
+    }
+    Ok(JoinOutput {
+        schema: Schema::new(fields),
+        rows,
+    })
+}
+
+type Matches = (Vec<Vec<usize>>, Vec<Vec<Range<usize>>>);
+
+fn join_typed<K: Ord + Copy>(columns: &[&Column], keys: impl Fn(&Column) -> &[K]) -> Matches {
+    join_sorted(columns.iter().map(|&c| keys(c)).collect())
+}
+
+/// Sorts the key columns and joins them with the statically typed join.
+fn join_sorted<K: Ord + Copy>(columns: Vec<&[K]>) -> Matches {
+    let orders: Vec<Vec<usize>> = columns
+        .iter()
+        .map(|keys| {
+            let mut order: Vec<usize> = (0..keys.len()).collect();
+            order.sort_by_key(|&row| keys[row]);
+            order
+        })
+        .collect();
+    let sorted: Vec<Vec<K>> = columns
+        .iter()
+        .zip(&orders)
+        .map(|(keys, order)| order.iter().map(|&row| keys[row]).collect())
+        .collect();
+
+    let mut join = LeapFrogJoin::from_iters(
+        sorted
+            .iter()
+            .map(|keys| DedupIterator::new(LinearIterator::new(keys)))
+            .collect(),
+    );
+    let mut matches = Vec::new();
+    while !join.at_end() {
+        let key = join.key();
+        matches.push(
+            sorted
+                .iter()
+                .map(|keys| keys.partition_point(|&k| k < key)..keys.partition_point(|&k| k <= key))
+                .collect(),
+        );
+        join.next();
+    }
+    (orders, matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users() -> AnyRelation {
+        AnyRelation::new(
+            Schema::new(vec![
+                Field::new("id", DataType::Int64),
+                Field::new("name", DataType::Utf8),
+            ]),
+            vec![
+                Column::Int64(vec![3, 1, 2]),
+                Column::Utf8(vec!["carol".into(), "alice".into(), "bob".into()]),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn orders() -> AnyRelation {
+        AnyRelation::new(
+            Schema::new(vec![
+                Field::new("user", DataType::Int64),
+                Field::new("amount", DataType::UInt64),
+            ]),
+            vec![
+                Column::Int64(vec![1, 3, 1, 4]),
+                Column::UInt64(vec![10, 30, 11, 40]),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dynamic_join() {
+        let users = users();
+        let orders = orders();
+        let output = join(&[(&users, "id"), (&orders, "user")]).unwrap();
+        let names: Vec<&str> = output
+            .schema
+            .fields
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["id", "name", "amount"]);
+        let row = |id, name: &str, amount| {
+            vec![
+                Value::Int64(id),
+                Value::Utf8(name.into()),
+                Value::UInt64(amount),
+            ]
+            .into_boxed_slice()
+        };
+        assert_eq!(
+            output.rows,
+            vec![
+                row(1, "alice", 10),
+                row(1, "alice", 11),
+                row(3, "carol", 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dynamic_join_on_strings() {
+        let users = users();
+        let nicknames = AnyRelation::new(
+            Schema::new(vec![Field::new("name", DataType::Utf8)]),
+            vec![Column::Utf8(vec!["bob".into(), "dave".into()])],
+        )
+        .unwrap();
+        let output = join(&[(&users, "name"), (&nicknames, "name")]).unwrap();
+        assert_eq!(
+            output.rows,
+            vec![vec![Value::Utf8("bob".into()), Value::Int64(2)].into_boxed_slice()]
+        );
+    }
+
+    #[test]
+    fn test_dynamic_errors() {
+        let users = users();
+        let orders = orders();
+        assert_eq!(
+            join(&[(&users, "id"), (&orders, "amount")]),
+            Err(DynamicError::KeyTypeMismatch {
+                expected: DataType::Int64,
+                found: DataType::UInt64
+            })
+        );
+        assert_eq!(
+            join(&[(&users, "nope")]),
+            Err(DynamicError::UnknownColumn("nope".to_string()))
+        );
+        assert_eq!(
+            AnyRelation::new(
+                Schema::new(vec![
+                    Field::new("a", DataType::Int64),
+                    Field::new("b", DataType::Int64)
+                ]),
+                vec![Column::Int64(vec![1]), Column::Int64(vec![])],
+            ),
+            Err(DynamicError::LengthMismatch)
+        );
+    }
+}
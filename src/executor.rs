@@ -0,0 +1,236 @@
+//! Executors that run the parallel work of the crate.
+//!
+//! Parallel operations like TrieRelation::merge_shards_on() hand their
+//! tasks to an Executor instead of a thread pool of their own, so that a
+//! service that already runs a pool, e.g. tokio's blocking pool, does not
+//! end up with two pools competing for the cores.
+//!
+//! An executor only needs to implement Executor::spawn(), which runs a task
+//! at some point on some thread. Executor::scope() builds on it to run
+//! tasks that borrow from the caller, and waits for them to finish. The
+//! crate comes with:
+//!
+//! - CurrentThread, which runs every task in place,
+//! - ThreadExecutor, which starts a thread per task, with the `threads`
+//!   feature, and
+//! - RayonExecutor, which runs tasks on the rayon pool, with the `rayon`
+//!   feature.
+//!
+//! A tokio runtime can serve as an executor by spawning tasks with
+//! `Handle::spawn_blocking()` from Executor::spawn().
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A task of an executor, which may borrow for `'s`.
+pub type Task<'s> = Box<dyn FnOnce() + Send + 's>;
+
+pub trait Executor: Sync {
+    /// The number of tasks the executor runs at once, which parallel
+    /// operations split their work into by default.
+    fn threads(&self) -> usize;
+
+    /// Runs `task`, now or later, on any thread.
+    fn spawn(&self, task: Task<'static>);
+
+    /// Runs all tasks through spawn() and returns once they all finished.
+    /// If a task panics, the panic is resumed here after the others
+    /// finished.
+    fn scope<'s>(&self, tasks: Vec<Task<'s>>) {
+        let latch = Arc::new(Latch::default());
+        let wait = Wait(latch.clone());
+        for task in tasks {
+            latch.state.lock().unwrap().pending += 1;
+            let done = Done(latch.clone());
+            let job: Task<'s> = Box::new(move || {
+                if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(task)) {
+                    done.0.state.lock().unwrap().panic.get_or_insert(panic);
+                }
+            });
+            // SAFETY: `wait` blocks until every job has finished or been
+            // dropped unrun, also when spawn() panics, so the job does not
+            // outlive what it borrows.
+            let job: Task<'static> = unsafe { std::mem::transmute::<Task<'s>, Task<'static>>(job) };
+            self.spawn(job);
+        }
+        drop(wait);
+        if let Some(panic) = latch.state.lock().unwrap().panic.take() {
+            panic::resume_unwind(panic);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Latch {
+    state: Mutex<LatchState>,
+    done: Condvar,
+}
+
+#[derive(Default)]
+struct LatchState {
+    pending: usize,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// Counts a job as finished when dropped, whether it ran or not.
+struct Done(Arc<Latch>);
+
+impl Drop for Done {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.pending -= 1;
+        self.0.done.notify_all();
+    }
+}
+
+/// Waits for all jobs of a scope when dropped.
+struct Wait(Arc<Latch>);
+
+impl Drop for Wait {
+    fn drop(&mut self) {
+        let state = self.0.state.lock().unwrap_or_else(|e| e.into_inner());
+        let _state = self.0.done.wait_while(state, |s| s.pending > 0);
+    }
+}
+
+/// CurrentThread runs every task in place, one after the other.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CurrentThread;
+
+impl Executor for CurrentThread {
+    fn threads(&self) -> usize {
+        1
+    }
+
+    fn spawn(&self, task: Task<'static>) {
+        task()
+    }
+
+    fn scope<'s>(&self, tasks: Vec<Task<'s>>) {
+        tasks.into_iter().for_each(|task| task());
+    }
+}
+
+/// ThreadExecutor starts a thread per task.
+#[cfg(feature = "threads")]
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadExecutor {
+    threads: usize,
+}
+
+#[cfg(feature = "threads")]
+impl ThreadExecutor {
+    /// Creates an executor that splits work into `threads` tasks.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "Executor needs a thread");
+        Self { threads }
+    }
+}
+
+#[cfg(feature = "threads")]
+impl Default for ThreadExecutor {
+    /// An executor with a task per hardware thread.
+    fn default() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+#[cfg(feature = "threads")]
+impl Executor for ThreadExecutor {
+    fn threads(&self) -> usize {
+        self.threads
+    }
+
+    fn spawn(&self, task: Task<'static>) {
+        std::thread::spawn(task);
+    }
+
+    fn scope<'s>(&self, tasks: Vec<Task<'s>>) {
+        std::thread::scope(|scope| {
+            for task in tasks {
+                scope.spawn(task);
+            }
+        });
+    }
+}
+
+/// RayonExecutor runs tasks on the global rayon pool.
+#[cfg(feature = "rayon")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RayonExecutor;
+
+#[cfg(feature = "rayon")]
+impl Executor for RayonExecutor {
+    fn threads(&self) -> usize {
+        rayon::current_num_threads()
+    }
+
+    fn spawn(&self, task: Task<'static>) {
+        rayon::spawn(task);
+    }
+
+    fn scope<'s>(&self, tasks: Vec<Task<'s>>) {
+        rayon::scope(|scope| {
+            for task in tasks {
+                scope.spawn(|_| task());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Implements spawn() only, like a user-provided pool.
+    struct Spawner;
+
+    impl Executor for Spawner {
+        fn threads(&self) -> usize {
+            2
+        }
+
+        fn spawn(&self, task: Task<'static>) {
+            std::thread::spawn(task);
+        }
+    }
+
+    fn squares(executor: &dyn Executor) -> Vec<usize> {
+        let mut slots = vec![0; 10];
+        let tasks = slots.iter_mut().enumerate().map(|(i, slot)| {
+            let task: Task<'_> = Box::new(move || *slot = i * i);
+            task
+        });
+        executor.scope(tasks.collect());
+        slots
+    }
+
+    #[test]
+    fn test_executors() {
+        let expected: Vec<usize> = (0..10).map(|i| i * i).collect();
+        assert_eq!(squares(&CurrentThread), expected);
+        #[cfg(feature = "threads")]
+        assert_eq!(squares(&ThreadExecutor::new(3)), expected);
+        assert_eq!(squares(&Spawner), expected);
+        #[cfg(feature = "rayon")]
+        assert_eq!(squares(&RayonExecutor), expected);
+
+        let finished = Mutex::new(0);
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            let tasks: Vec<Task<'_>> = (0..4)
+                .map(|i| {
+                    let finished = &finished;
+                    let task: Task<'_> = Box::new(move || {
+                        assert_ne!(i, 2, "Task fails");
+                        *finished.lock().unwrap() += 1;
+                    });
+                    task
+                })
+                .collect();
+            Spawner.scope(tasks);
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(*finished.lock().unwrap(), 3);
+    }
+}
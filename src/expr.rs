@@ -0,0 +1,327 @@
+//! Expressions over the variables of a query.
+//!
+//! Expressions are built from variables, integer constants, arithmetic,
+//! comparisons and boolean operators, e.g. `var("a").lt(var("c"))` or
+//! `(var("b") + 1).eq(var("c"))`. Integer values are evaluated as i128, so
+//! that all integer key types fit; an arithmetic overflow makes the whole
+//! expression false.
+//!
+//! Queries take boolean expressions as filters (Query::filter()) and evaluate
+//! each of them as soon as all of its variables are bound, so bindings that
+//! fail a filter are pruned right inside the triejoin.
+
+use std::fmt;
+use std::ops;
+
+/// ExprKey is implemented by the key types expressions can be evaluated on.
+pub trait ExprKey: Copy {
+    fn to_i128(self) -> i128;
+
+    /// Returns the key of value `value`, if there is one. Keys without
+    /// cannot be pinned by filters, see Expr::pinned().
+    fn from_i128(value: i128) -> Option<Self> {
+        let _ = value;
+        None
+    }
+}
+
+macro_rules! impl_expr_key {
+    ($($t:ty),*) => {$(
+        impl ExprKey for $t {
+            fn to_i128(self) -> i128 {
+                self as i128
+            }
+
+            fn from_i128(value: i128) -> Option<Self> {
+                <$t>::try_from(value).ok()
+            }
+        }
+    )*};
+}
+
+impl_expr_key!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Var(String),
+    Const(i128),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+/// Refers to the query variable `name`.
+pub fn var(name: &str) -> Expr {
+    Expr::Var(name.to_string())
+}
+
+macro_rules! impl_from_int {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Expr {
+            fn from(value: $t) -> Self {
+                Expr::Const(value as i128)
+            }
+        }
+    )*};
+}
+
+impl_from_int!(i32, i64, u32, u64);
+
+macro_rules! comparison {
+    ($($name:ident => $op:ident),*) => {$(
+        pub fn $name(self, other: impl Into<Expr>) -> Expr {
+            Expr::Binary(BinaryOp::$op, Box::new(self), Box::new(other.into()))
+        }
+    )*};
+}
+
+impl Expr {
+    comparison!(lt => Lt, le => Le, eq => Eq, ne => Ne, gt => Gt, ge => Ge, and => And, or => Or);
+
+    /// Returns the variable and the constant of an expression `var == c`
+    /// or `c == var`, which pins the variable to a single value.
+    pub fn pinned(&self) -> Option<(&str, i128)> {
+        match self {
+            Expr::Binary(BinaryOp::Eq, a, b) => match (&**a, &**b) {
+                (Expr::Var(name), Expr::Const(c)) | (Expr::Const(c), Expr::Var(name)) => {
+                    Some((name, *c))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Names of the variables referenced, in order of first appearance.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut vars = Vec::new();
+        self.collect_variables(&mut vars);
+        vars
+    }
+
+    fn collect_variables<'a>(&'a self, vars: &mut Vec<&'a str>) {
+        match self {
+            Expr::Var(name) => {
+                if !vars.contains(&name.as_str()) {
+                    vars.push(name);
+                }
+            }
+            Expr::Const(_) => {}
+            Expr::Binary(_, a, b) => {
+                a.collect_variables(vars);
+                b.collect_variables(vars);
+            }
+            Expr::Not(a) => a.collect_variables(vars),
+        }
+    }
+
+    /// Resolves variables to binding indices and checks types.
+    pub(crate) fn compile(
+        &self,
+        index_of: &impl Fn(&str) -> Option<usize>,
+    ) -> Result<(Compiled, Type), ExprError> {
+        Ok(match self {
+            Expr::Var(name) => {
+                let index =
+                    index_of(name).ok_or_else(|| ExprError::UnknownVariable(name.clone()))?;
+                (Compiled::Var(index), Type::Int)
+            }
+            Expr::Const(value) => (Compiled::Const(*value), Type::Int),
+            Expr::Binary(op, a, b) => {
+                let (a, ta) = a.compile(index_of)?;
+                let (b, tb) = b.compile(index_of)?;
+                let (operands, result) = match op {
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => (Type::Int, Type::Int),
+                    BinaryOp::And | BinaryOp::Or => (Type::Bool, Type::Bool),
+                    _ => (Type::Int, Type::Bool),
+                };
+                if ta != operands || tb != operands {
+                    return Err(ExprError::Type(format!(
+                        "{op:?} expects {operands:?} operands, got {ta:?} and {tb:?}"
+                    )));
+                }
+                (Compiled::Binary(*op, Box::new(a), Box::new(b)), result)
+            }
+            Expr::Not(a) => {
+                let (a, ta) = a.compile(index_of)?;
+                if ta != Type::Bool {
+                    return Err(ExprError::Type(format!("Not expects Bool, got {ta:?}")));
+                }
+                (Compiled::Not(Box::new(a)), Type::Bool)
+            }
+        })
+    }
+}
+
+impl<T: Into<Expr>> ops::Add<T> for Expr {
+    type Output = Expr;
+
+    fn add(self, other: T) -> Expr {
+        Expr::Binary(BinaryOp::Add, Box::new(self), Box::new(other.into()))
+    }
+}
+
+impl<T: Into<Expr>> ops::Sub<T> for Expr {
+    type Output = Expr;
+
+    fn sub(self, other: T) -> Expr {
+        Expr::Binary(BinaryOp::Sub, Box::new(self), Box::new(other.into()))
+    }
+}
+
+impl<T: Into<Expr>> ops::Mul<T> for Expr {
+    type Output = Expr;
+
+    fn mul(self, other: T) -> Expr {
+        Expr::Binary(BinaryOp::Mul, Box::new(self), Box::new(other.into()))
+    }
+}
+
+impl ops::Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr::Not(Box::new(self))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExprError {
+    UnknownVariable(String),
+    /// Operands of the wrong type, e.g. `a + (b < c)`, or a filter that is
+    /// not boolean.
+    Type(String),
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprError::UnknownVariable(name) => write!(f, "unknown variable {name:?}"),
+            ExprError::Type(msg) => write!(f, "type error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Type {
+    Int,
+    Bool,
+}
+
+/// An expression with variables resolved to indices into the binding.
+#[derive(Clone, Debug)]
+pub(crate) enum Compiled {
+    Var(usize),
+    Const(i128),
+    Binary(BinaryOp, Box<Compiled>, Box<Compiled>),
+    Not(Box<Compiled>),
+}
+
+impl Compiled {
+    /// Highest binding index referenced, i.e. the variable after whose
+    /// binding the expression can be evaluated.
+    pub(crate) fn max_var(&self) -> Option<usize> {
+        match self {
+            Compiled::Var(index) => Some(*index),
+            Compiled::Const(_) => None,
+            Compiled::Binary(_, a, b) => a.max_var().max(b.max_var()),
+            Compiled::Not(a) => a.max_var(),
+        }
+    }
+
+    /// Evaluates a boolean expression. Overflows evaluate to false.
+    pub(crate) fn eval_bool<K: ExprKey>(&self, binding: &[K]) -> bool {
+        self.eval(binding) == Some(1)
+    }
+
+    /// Evaluates to an integer, with booleans as 0 and 1, or None on overflow.
+    pub(crate) fn eval<K: ExprKey>(&self, binding: &[K]) -> Option<i128> {
+        Some(match self {
+            Compiled::Var(index) => binding[*index].to_i128(),
+            Compiled::Const(value) => *value,
+            Compiled::Not(a) => (a.eval(binding)? == 0) as i128,
+            Compiled::Binary(op, a, b) => {
+                let a = a.eval(binding)?;
+                // Short-circuit boolean operators.
+                match (op, a) {
+                    (BinaryOp::And, 0) => return Some(0),
+                    (BinaryOp::Or, 1) => return Some(1),
+                    _ => {}
+                }
+                let b = b.eval(binding)?;
+                match op {
+                    BinaryOp::Add => a.checked_add(b)?,
+                    BinaryOp::Sub => a.checked_sub(b)?,
+                    BinaryOp::Mul => a.checked_mul(b)?,
+                    BinaryOp::Lt => (a < b) as i128,
+                    BinaryOp::Le => (a <= b) as i128,
+                    BinaryOp::Eq => (a == b) as i128,
+                    BinaryOp::Ne => (a != b) as i128,
+                    BinaryOp::Gt => (a > b) as i128,
+                    BinaryOp::Ge => (a >= b) as i128,
+                    BinaryOp::And | BinaryOp::Or => b,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(expr: &Expr) -> Result<Compiled, ExprError> {
+        let vars = ["a", "b", "c"];
+        expr.compile(&|name| vars.iter().position(|&v| v == name))
+            .map(|(c, _)| c)
+    }
+
+    #[test]
+    fn test_expr_eval() {
+        let expr = (var("b") + 1).eq(var("c")).and(var("a").lt(var("c")));
+        assert_eq!(expr.variables(), vec!["b", "c", "a"]);
+        let compiled = compile(&expr).unwrap();
+        assert_eq!(compiled.max_var(), Some(2));
+        assert!(compiled.eval_bool(&[1, 2, 3]));
+        assert!(!compiled.eval_bool(&[4, 2, 3]));
+        assert!(!compiled.eval_bool(&[1, 2, 4]));
+
+        let negated = compile(&!(var("a") * 2).ge(var("b") - 1)).unwrap();
+        assert!(negated.eval_bool(&[1u64, 10, 0]));
+        assert!(!negated.eval_bool(&[5u64, 10, 0]));
+
+        // u64::MAX squared overflows even i128.
+        let square = compile(&(var("a") * var("a")).gt(0)).unwrap();
+        assert!(square.eval_bool(&[1u64 << 32, 0, 0]));
+        assert!(!square.eval_bool(&[u64::MAX, 0, 0]));
+    }
+
+    #[test]
+    fn test_expr_errors() {
+        assert_eq!(
+            compile(&var("d").lt(1)).unwrap_err(),
+            ExprError::UnknownVariable("d".to_string())
+        );
+        assert!(matches!(
+            compile(&(var("a").lt(1) + 1)),
+            Err(ExprError::Type(_))
+        ));
+        assert!(matches!(compile(&!var("a")), Err(ExprError::Type(_))));
+    }
+}
@@ -0,0 +1,300 @@
+//! C interface to the leapfrog join over `int64_t` keys.
+//!
+//! The matching declarations live in `include/leapfrog.h`, which can be
+//! regenerated with `cbindgen --config cbindgen.toml --output include/leapfrog.h`.
+//!
+//! # Ownership
+//!
+//! leapfrog_join_create() copies the input arrays, so the caller may free them
+//! as soon as it returns. The returned handle is owned by the caller and must
+//! be released with leapfrog_join_destroy() exactly once.
+//!
+//! # Thread safety
+//!
+//! A handle may be moved to and used from any thread, but not from several
+//! threads at the same time. Distinct handles are fully independent.
+//!
+//! # Errors
+//!
+//! No function panics across the boundary. Every fallible function returns a
+//! LeapfrogStatus and leaves the handle unchanged on error, except for
+//! Internal: a panic inside the join is caught and reported as Internal, after
+//! which the handle may only be destroyed.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use crate::{LeapFrogJoin, Seekable};
+
+/// Result codes of the C interface. Negative values are errors.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeapfrogStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// The join is exhausted, there is no current key.
+    End = 1,
+    /// A required pointer argument was NULL.
+    NullArgument = -1,
+    /// An input array is not sorted in strictly ascending order.
+    UnsortedInput = -2,
+    /// The seek key is smaller than the current key.
+    InvalidSeek = -3,
+    /// The join failed unexpectedly; the handle must not be used other than
+    /// to destroy it.
+    Internal = -4,
+}
+
+/// Opaque join handle.
+pub struct LeapfrogJoinHandle {
+    join: LeapFrogJoin<OwnedIterator>,
+}
+
+/// Iterator over a copy of one input array.
+struct OwnedIterator {
+    keys: Vec<i64>,
+    pos: usize,
+}
+
+impl Seekable for OwnedIterator {
+    type Key = i64;
+
+    fn key(&self) -> i64 {
+        self.keys[self.pos]
+    }
+
+    fn next(&mut self) {
+        self.pos += 1;
+    }
+
+    fn seek(&mut self, seek_key: i64) {
+        self.pos += self.keys[self.pos..].partition_point(|&k| k < seek_key);
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.keys.len()
+    }
+}
+
+/// Creates a join over `num_sources` strictly ascending arrays, i.e. sorted
+/// and without duplicates. `sources[i]` points to `lengths[i]` keys; it may
+/// be NULL if the length is 0. On success the new handle is stored in `*out`.
+///
+/// # Safety
+///
+/// `sources` and `lengths` must point to `num_sources` elements each, every
+/// non-NULL `sources[i]` to `lengths[i]` readable keys, and `out` must be
+/// writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn leapfrog_join_create(
+    sources: *const *const i64,
+    lengths: *const usize,
+    num_sources: usize,
+    out: *mut *mut LeapfrogJoinHandle,
+) -> LeapfrogStatus {
+    if out.is_null() || (num_sources > 0 && (sources.is_null() || lengths.is_null())) {
+        return LeapfrogStatus::NullArgument;
+    }
+    let mut iters = Vec::with_capacity(num_sources);
+    for i in 0..num_sources {
+        let (source, len) = unsafe { (*sources.add(i), *lengths.add(i)) };
+        let keys = if len == 0 {
+            Vec::new()
+        } else if source.is_null() {
+            return LeapfrogStatus::NullArgument;
+        } else {
+            unsafe { slice::from_raw_parts(source, len) }.to_vec()
+        };
+        if !keys.windows(2).all(|w| w[0] < w[1]) {
+            return LeapfrogStatus::UnsortedInput;
+        }
+        iters.push(OwnedIterator { keys, pos: 0 });
+    }
+    let Ok(join) = panic::catch_unwind(|| LeapFrogJoin::from_iters(iters)) else {
+        return LeapfrogStatus::Internal;
+    };
+    let handle = Box::new(LeapfrogJoinHandle { join });
+    unsafe { *out = Box::into_raw(handle) };
+    LeapfrogStatus::Ok
+}
+
+/// Stores the current key in `*out`, or returns End if the join is exhausted.
+///
+/// # Safety
+///
+/// `join` must be a live handle and `out` must be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn leapfrog_join_key(
+    join: *const LeapfrogJoinHandle,
+    out: *mut i64,
+) -> LeapfrogStatus {
+    let Some(handle) = (unsafe { join.as_ref() }) else {
+        return LeapfrogStatus::NullArgument;
+    };
+    if out.is_null() {
+        return LeapfrogStatus::NullArgument;
+    }
+    if handle.join.at_end() {
+        return LeapfrogStatus::End;
+    }
+    unsafe { *out = handle.join.key() };
+    LeapfrogStatus::Ok
+}
+
+/// Advances to the next common key. Returns End if the join is (or becomes)
+/// exhausted.
+///
+/// # Safety
+///
+/// `join` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn leapfrog_join_next(join: *mut LeapfrogJoinHandle) -> LeapfrogStatus {
+    let Some(handle) = (unsafe { join.as_mut() }) else {
+        return LeapfrogStatus::NullArgument;
+    };
+    if handle.join.at_end() {
+        return LeapfrogStatus::End;
+    }
+    guarded(handle, |join| join.next())
+}
+
+/// Fast-forwards to the first common key >= `key`. The key must not be
+/// smaller than the current key. Returns End if no such key exists.
+///
+/// # Safety
+///
+/// `join` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn leapfrog_join_seek(
+    join: *mut LeapfrogJoinHandle,
+    key: i64,
+) -> LeapfrogStatus {
+    let Some(handle) = (unsafe { join.as_mut() }) else {
+        return LeapfrogStatus::NullArgument;
+    };
+    if handle.join.at_end() {
+        return LeapfrogStatus::End;
+    }
+    if key < handle.join.key() {
+        return LeapfrogStatus::InvalidSeek;
+    }
+    guarded(handle, |join| join.seek(key))
+}
+
+/// Returns true if the join is exhausted or `join` is NULL.
+///
+/// # Safety
+///
+/// `join` must be NULL or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn leapfrog_join_at_end(join: *const LeapfrogJoinHandle) -> bool {
+    unsafe { join.as_ref() }.is_none_or(|handle| handle.join.at_end())
+}
+
+/// Releases a handle. Passing NULL is a no-op.
+///
+/// # Safety
+///
+/// `join` must be NULL or a live handle, which must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn leapfrog_join_destroy(join: *mut LeapfrogJoinHandle) {
+    if !join.is_null() {
+        drop(unsafe { Box::from_raw(join) });
+    }
+}
+
+/// Runs `f` on the join of `handle`, turning a panic into Internal.
+fn guarded(
+    handle: &mut LeapfrogJoinHandle,
+    f: impl FnOnce(&mut LeapFrogJoin<OwnedIterator>),
+) -> LeapfrogStatus {
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut handle.join))) {
+        Ok(()) => status(handle),
+        Err(_) => LeapfrogStatus::Internal,
+    }
+}
+
+fn status(handle: &LeapfrogJoinHandle) -> LeapfrogStatus {
+    if handle.join.at_end() {
+        LeapfrogStatus::End
+    } else {
+        LeapfrogStatus::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    fn create(sources: &[&[i64]]) -> Result<*mut LeapfrogJoinHandle, LeapfrogStatus> {
+        let ptrs: Vec<*const i64> = sources.iter().map(|s| s.as_ptr()).collect();
+        let lengths: Vec<usize> = sources.iter().map(|s| s.len()).collect();
+        let mut join = ptr::null_mut();
+        match unsafe {
+            leapfrog_join_create(ptrs.as_ptr(), lengths.as_ptr(), sources.len(), &mut join)
+        } {
+            LeapfrogStatus::Ok => Ok(join),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn test_ffi_join() {
+        let join = create(&[&[0, 1, 3, 4, 5, 6, 7, 8, 9, 11], &[0, 2, 6, 7, 8, 9, 11]]).unwrap();
+        let mut keys = vec![];
+        let mut key = 0;
+        while unsafe { leapfrog_join_key(join, &mut key) } == LeapfrogStatus::Ok {
+            keys.push(key);
+            unsafe { leapfrog_join_next(join) };
+        }
+        assert_eq!(keys, vec![0, 6, 7, 8, 9, 11]);
+        assert!(unsafe { leapfrog_join_at_end(join) });
+        assert_eq!(unsafe { leapfrog_join_next(join) }, LeapfrogStatus::End);
+        unsafe { leapfrog_join_destroy(join) };
+    }
+
+    #[test]
+    fn test_ffi_seek() {
+        let join = create(&[&[0, 1, 3, 4, 5, 6, 7, 8, 9, 11], &[0, 2, 6, 7, 8, 9, 11]]).unwrap();
+        let mut key = 0;
+        assert_eq!(unsafe { leapfrog_join_seek(join, 7) }, LeapfrogStatus::Ok);
+        unsafe { leapfrog_join_key(join, &mut key) };
+        assert_eq!(key, 7);
+        assert_eq!(
+            unsafe { leapfrog_join_seek(join, 3) },
+            LeapfrogStatus::InvalidSeek
+        );
+        assert_eq!(unsafe { leapfrog_join_seek(join, 12) }, LeapfrogStatus::End);
+        unsafe { leapfrog_join_destroy(join) };
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        assert_eq!(
+            create(&[&[3, 1], &[1]]).unwrap_err(),
+            LeapfrogStatus::UnsortedInput
+        );
+        assert_eq!(
+            create(&[&[1, 1, 2], &[1, 2]]).unwrap_err(),
+            LeapfrogStatus::UnsortedInput
+        );
+        assert_eq!(
+            unsafe { leapfrog_join_create(ptr::null(), ptr::null(), 1, ptr::null_mut()) },
+            LeapfrogStatus::NullArgument
+        );
+        assert_eq!(
+            unsafe { leapfrog_join_next(ptr::null_mut()) },
+            LeapfrogStatus::NullArgument
+        );
+        unsafe { leapfrog_join_destroy(ptr::null_mut()) };
+    }
+
+    #[test]
+    fn test_ffi_empty_source() {
+        let join = create(&[&[], &[1, 2]]).unwrap();
+        assert!(unsafe { leapfrog_join_at_end(join) });
+        unsafe { leapfrog_join_destroy(join) };
+    }
+}
@@ -0,0 +1,208 @@
+//! Golden-file testing of joins.
+//!
+//! Applications embedding the crate can pin the results and execution traces
+//! of their queries in golden files: results_text() and keys_text() format
+//! results canonically, i.e. sorted and with keys formatted by Debug, and
+//! trace_text() formats the operations a Tracer recorded, without timings.
+//! check() compares such a text with a golden file and reports a line diff
+//! if they differ.
+//!
+//! With the environment variable `LEAPFROG_BLESS` set to anything but `0`,
+//! check() writes the text to the golden file instead, which creates missing
+//! files and accepts intended changes.
+
+use std::fmt::{self, Debug, Write};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::trace::Tracer;
+
+/// The environment variable that makes check() write golden files.
+pub const BLESS_VAR: &str = "LEAPFROG_BLESS";
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// The text differs from the golden file. The diff lists the lines of
+    /// the file missing from the text with `-`, the extra lines of the text
+    /// with `+`, and common lines with a space.
+    Mismatch {
+        path: PathBuf,
+        diff: String,
+    },
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            GoldenError::Mismatch { path, diff } => write!(
+                f,
+                "{} differs (set {BLESS_VAR}=1 to update it):\n{diff}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+/// Formats results as a header line with the variables, then one line per
+/// result, sorted, with tab-separated keys.
+pub fn results_text<K: Ord + Debug>(variables: &[String], results: &[Vec<K>]) -> String {
+    let mut sorted: Vec<&Vec<K>> = results.iter().collect();
+    sorted.sort();
+    let mut text = variables.join("\t");
+    text.push('\n');
+    for result in sorted {
+        let keys: Vec<String> = result.iter().map(|k| format!("{k:?}")).collect();
+        text.push_str(&keys.join("\t"));
+        text.push('\n');
+    }
+    text
+}
+
+/// Formats the keys of a single-variable join, sorted, one per line.
+pub fn keys_text<K: Ord + Debug>(keys: &[K]) -> String {
+    let mut sorted: Vec<&K> = keys.iter().collect();
+    sorted.sort();
+    let mut text = String::new();
+    for key in sorted {
+        writeln!(text, "{key:?}").unwrap();
+    }
+    text
+}
+
+/// Formats the operations recorded by `tracer`, see Tracer::write_text().
+pub fn trace_text(tracer: &Tracer) -> String {
+    let mut text = Vec::new();
+    tracer.write_text(&mut text).unwrap();
+    String::from_utf8(text).expect("Trace is valid UTF-8")
+}
+
+/// Compares `actual` with the golden file at `path`, or writes it there if
+/// `LEAPFROG_BLESS` is set.
+pub fn check(path: impl AsRef<Path>, actual: &str) -> Result<(), GoldenError> {
+    let bless = std::env::var_os(BLESS_VAR).is_some_and(|v| v != "0");
+    check_with(path.as_ref(), actual, bless)
+}
+
+/// Like check(), with blessing given explicitly.
+pub fn check_with(path: impl AsRef<Path>, actual: &str, bless: bool) -> Result<(), GoldenError> {
+    let path = path.as_ref();
+    let io_error = |error| GoldenError::Io {
+        path: path.to_path_buf(),
+        error,
+    };
+    if bless {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        return fs::write(path, actual).map_err(io_error);
+    }
+    let expected = fs::read_to_string(path).map_err(io_error)?;
+    if expected == actual {
+        return Ok(());
+    }
+    Err(GoldenError::Mismatch {
+        path: path.to_path_buf(),
+        diff: diff(&expected, actual),
+    })
+}
+
+/// Panics with the diff unless check() succeeds.
+#[track_caller]
+pub fn assert_golden(path: impl AsRef<Path>, actual: &str) {
+    if let Err(e) = check(path, actual) {
+        panic!("{e}");
+    }
+}
+
+/// Returns a line diff of two texts, based on their longest common
+/// subsequence of lines.
+fn diff(expected: &str, actual: &str) -> String {
+    let a: Vec<&str> = expected.lines().collect();
+    let b: Vec<&str> = actual.lines().collect();
+    // lcs[i][j] is the LCS length of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            writeln!(out, " {}", a[i]).unwrap();
+            (i, j) = (i + 1, j + 1);
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            writeln!(out, "-{}", a[i]).unwrap();
+            i += 1;
+        } else {
+            writeln!(out, "+{}", b[j]).unwrap();
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+    use crate::trie::TrieRelation;
+    use crate::{LeapFrogJoin, LinearIterator};
+
+    #[test]
+    fn test_golden_texts() {
+        let e = TrieRelation::new(2, [[1, 2], [2, 3], [1, 3], [3, 4]]);
+        let join = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .execute()
+            .unwrap();
+        let variables = join.variables().to_vec();
+        let mut results: Vec<Vec<u32>> = join.collect();
+        results.reverse();
+        assert_eq!(
+            results_text(&variables, &results),
+            "a\tb\tc\n1\t2\t3\n1\t3\t4\n2\t3\t4\n"
+        );
+
+        let (tab1, tab2) = ([1, 3, 5, 7], [3, 4, 7]);
+        let tracer = Tracer::new();
+        let join = LeapFrogJoin::from_iters(vec![
+            tracer.wrap("tab1", LinearIterator::new(&tab1)),
+            tracer.wrap("tab2", LinearIterator::new(&tab2)),
+        ]);
+        assert_eq!(keys_text(&join.into_keys().collect::<Vec<_>>()), "3\n7\n");
+        assert!(trace_text(&tracer).starts_with("tab1 seek 3 -> 3\n"));
+    }
+
+    #[test]
+    fn test_check() {
+        let path = std::env::temp_dir()
+            .join(format!("leapfrog-golden-{}", std::process::id()))
+            .join("keys.txt");
+        assert!(matches!(
+            check_with(&path, "1\n", false),
+            Err(GoldenError::Io { .. })
+        ));
+        check_with(&path, "1\n2\n3\n", true).unwrap();
+        check_with(&path, "1\n2\n3\n", false).unwrap();
+        let Err(GoldenError::Mismatch { diff, .. }) = check_with(&path, "1\n3\n4\n", false) else {
+            panic!("expected a mismatch");
+        };
+        assert_eq!(diff, " 1\n-2\n 3\n+4\n");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
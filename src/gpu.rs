@@ -0,0 +1,283 @@
+//! Experimental GPU offload of large two-way intersections.
+//!
+//! With the `gpu` feature, intersect() hands two sorted u32 sources to the
+//! GPU once the smaller one has GPU_MIN_LEN keys. A compute shader binary
+//! searches every key of the larger source in the smaller one, and the keys
+//! found are compacted on the CPU, so the result keeps the order of the
+//! sources. Smaller inputs, hosts without an adapter and any failure on the
+//! GPU fall back to dense::intersect(), which picks bitsets or the leapfrog
+//! join as usual.
+//!
+//! This is a prototype to evaluate feasibility: the sources are copied to
+//! the GPU for every call, and the smaller one must fit into a single
+//! storage buffer. GpuIntersector::intersect() runs on the GPU regardless of
+//! the input sizes, e.g. to compare against the CPU strategies.
+
+use std::fmt;
+use std::sync::{OnceLock, mpsc};
+
+use wgpu::util::DeviceExt;
+
+/// The length of the smaller source from which intersect() uses the GPU.
+pub const GPU_MIN_LEN: usize = 1 << 20;
+
+const WORKGROUP_SIZE: u32 = 256;
+
+/// The most workgroups per dimension of a dispatch that every device allows.
+const MAX_GROUPS: u32 = 65535;
+
+const SHADER: &str = "
+@group(0) @binding(0) var<storage, read> probe: array<u32>;
+@group(0) @binding(1) var<storage, read> build: array<u32>;
+@group(0) @binding(2) var<storage, read_write> found: array<u32>;
+
+@compute @workgroup_size(256)
+fn main(@builtin(global_invocation_id) id: vec3<u32>,
+        @builtin(num_workgroups) groups: vec3<u32>) {
+    let i = id.x + id.y * groups.x * 256u;
+    if (i >= arrayLength(&probe)) {
+        return;
+    }
+    let key = probe[i];
+    var lo = 0u;
+    var hi = arrayLength(&build);
+    while (lo < hi) {
+        let mid = lo + (hi - lo) / 2u;
+        if (build[mid] < key) {
+            lo = mid + 1u;
+        } else {
+            hi = mid;
+        }
+    }
+    found[i] = select(0u, 1u, lo < arrayLength(&build) && build[lo] == key);
+}
+";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuError {
+    /// The smaller source does not fit into a storage buffer of the device.
+    TooLarge { len: usize, max: usize },
+    /// The device rejected or lost the work, or the results could not be
+    /// read back.
+    Device(String),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuError::TooLarge { len, max } => {
+                write!(f, "{len} keys exceed the {max} keys of a GPU buffer")
+            }
+            GpuError::Device(message) => write!(f, "GPU failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+/// GpuIntersector owns a device and the compiled intersection shader.
+pub struct GpuIntersector {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    /// Keys per storage buffer.
+    max_keys: usize,
+}
+
+impl GpuIntersector {
+    /// Opens the default adapter, or returns None if there is none.
+    pub fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        };
+        let adapter = pollster::block_on(instance.request_adapter(&options))?;
+        let limits = adapter.limits();
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("leapfrog"),
+            required_features: wgpu::Features::empty(),
+            required_limits: limits.clone(),
+            memory_hints: wgpu::MemoryHints::Performance,
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None)).ok()?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("intersect"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("intersect"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let max_bytes = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+        Some(Self {
+            device,
+            queue,
+            pipeline,
+            max_keys: (max_bytes / 4) as usize,
+        })
+    }
+
+    /// Returns an intersector opened on first use and kept for the lifetime
+    /// of the process, or None if the host has no adapter.
+    pub fn shared() -> Option<&'static GpuIntersector> {
+        static SHARED: OnceLock<Option<GpuIntersector>> = OnceLock::new();
+        SHARED.get_or_init(Self::new).as_ref()
+    }
+
+    /// Returns the keys common to both sorted sources, in ascending order.
+    pub fn intersect(&self, a: &[u32], b: &[u32]) -> Result<Vec<u32>, GpuError> {
+        let (probe, build) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+        if build.is_empty() {
+            return Ok(Vec::new());
+        }
+        if build.len() > self.max_keys {
+            return Err(GpuError::TooLarge {
+                len: build.len(),
+                max: self.max_keys,
+            });
+        }
+        self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let build_buffer = self.storage("build", build);
+        let mut result = Vec::new();
+        let mut failure = None;
+        for chunk in probe.chunks(self.max_keys) {
+            match self.probe(chunk, &build_buffer) {
+                Ok(found) => {
+                    let kept = chunk.iter().zip(found).filter(|&(_, f)| f != 0);
+                    result.extend(kept.map(|(&k, _)| k));
+                }
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+        for _ in 0..2 {
+            if let Some(e) = pollster::block_on(self.device.pop_error_scope()) {
+                failure.get_or_insert(GpuError::Device(e.to_string()));
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
+    }
+
+    fn storage(&self, label: &str, keys: &[u32]) -> wgpu::Buffer {
+        let bytes: Vec<u8> = keys.iter().flat_map(|k| k.to_ne_bytes()).collect();
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: &bytes,
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+    }
+
+    /// Runs the shader over `probe` and returns a flag per key whether it
+    /// was found.
+    fn probe(&self, probe: &[u32], build: &wgpu::Buffer) -> Result<Vec<u32>, GpuError> {
+        let size = (probe.len() * 4) as u64;
+        let probe_buffer = self.storage("probe", probe);
+        let found = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("found"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout = self.pipeline.get_bind_group_layout(0);
+        let buffers = [&probe_buffer, build, &found];
+        let entries: Vec<wgpu::BindGroupEntry> = (0..)
+            .zip(buffers)
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layout,
+            entries: &entries,
+        });
+
+        let groups = (probe.len() as u32).div_ceil(WORKGROUP_SIZE);
+        let (x, y) = (groups.min(MAX_GROUPS), groups.div_ceil(MAX_GROUPS));
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(x, y, 1);
+        }
+        encoder.copy_buffer_to_buffer(&found, 0, &staging, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |mapped| {
+            let _ = sender.send(mapped);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        match receiver.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Err(GpuError::Device(e.to_string())),
+            Err(e) => return Err(GpuError::Device(e.to_string())),
+        }
+        let flags = slice
+            .get_mapped_range()
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+            .collect();
+        staging.unmap();
+        Ok(flags)
+    }
+}
+
+/// Checks whether intersect() tries the GPU for the sources.
+pub fn prefers_gpu(sources: &[&[u32]]) -> bool {
+    sources.len() == 2 && sources.iter().all(|s| s.len() >= GPU_MIN_LEN)
+}
+
+/// Returns the keys common to all sorted sources, in ascending order, on
+/// the GPU for two large sources and with dense::intersect() otherwise.
+pub fn intersect(sources: Vec<&[u32]>) -> Vec<u32> {
+    if prefers_gpu(&sources)
+        && let Some(gpu) = GpuIntersector::shared()
+        && let Ok(keys) = gpu.intersect(sources[0], sources[1])
+    {
+        return keys;
+    }
+    crate::dense::intersect(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gpu_intersect() {
+        let a: Vec<u32> = (0..GPU_MIN_LEN as u32 * 2).step_by(2).collect();
+        let b: Vec<u32> = (0..GPU_MIN_LEN as u32 * 3).step_by(3).collect();
+        let expected = crate::intersect(vec![&a, &b]);
+        assert!(prefers_gpu(&[&a, &b]));
+        assert!(!prefers_gpu(&[&a, &b[..10]]));
+        // Falls back to the CPU on hosts without an adapter.
+        assert_eq!(intersect(vec![&a, &b]), expected);
+        if let Some(gpu) = GpuIntersector::shared() {
+            let few = crate::intersect(vec![&a[..100], &b]);
+            assert_eq!(gpu.intersect(&a[..100], &b).unwrap(), few);
+            assert!(gpu.intersect(&a, &[]).unwrap().is_empty());
+        }
+    }
+}
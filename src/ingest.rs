@@ -0,0 +1,468 @@
+//! Parsing text columns into keys.
+//!
+//! Every reader of text formats parses its fields with ParseKey, so all of
+//! them accept the same numbers: ASCII digits with an optional leading `-`
+//! for signed types, and nothing else. There is no whitespace trimming, no
+//! `+`, no digit grouping, no exponents and no other digit scripts, no
+//! matter the locale; `1,000`, ` 7` and `1e3` are errors, not 1000, 7 and
+//! 1000. Errors name the line and column of the field, counted from 1.
+//!
+//! TextReader reads delimited text, one tuple per line, from the columns it
+//! is told to extract. KeySource turns a file of keys into a join source,
+//! given a KeyReader for its format.
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::marker::PhantomData;
+
+use crate::Seekable;
+use crate::trie::TrieRelation;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    Empty,
+    /// A character other than a digit, at a byte offset into the field.
+    InvalidChar {
+        offset: usize,
+        ch: char,
+    },
+    /// A negative number for an unsigned type.
+    Negative,
+    /// The number does not fit into the key type.
+    Overflow,
+    /// The line has fewer columns than selected.
+    MissingColumn,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Empty => write!(f, "no digits"),
+            ParseErrorKind::InvalidChar { offset, ch } => {
+                write!(f, "invalid character {ch:?} at offset {offset}")
+            }
+            ParseErrorKind::Negative => write!(f, "negative number for an unsigned key"),
+            ParseErrorKind::Overflow => write!(f, "number out of range"),
+            ParseErrorKind::MissingColumn => write!(f, "missing column"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    /// The field, empty for a missing column.
+    pub text: String,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: cannot parse {:?}: {}",
+            self.line, self.column, self.text, self.kind
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub enum IngestError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestError::Io(e) => write!(f, "I/O error: {e}"),
+            IngestError::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+impl From<io::Error> for IngestError {
+    fn from(e: io::Error) -> Self {
+        IngestError::Io(e)
+    }
+}
+
+impl From<ParseError> for IngestError {
+    fn from(e: ParseError) -> Self {
+        IngestError::Parse(e)
+    }
+}
+
+/// How a source reading keys from a file gets them in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// The keys are sorted; a key out of order is not noticed.
+    Trust,
+    /// The keys must be sorted; a key out of order is an error.
+    Verify,
+    /// Read and sort all keys before the first one.
+    Sort,
+}
+
+/// KeyReader reads the keys of a file one at a time, in file order.
+pub trait KeyReader {
+    type Key: Ord + Copy;
+    type Error;
+
+    /// Reads the next key, or None at the end of the file.
+    fn read_key(&mut self) -> Result<Option<Self::Key>, Self::Error>;
+
+    /// Returns the error for the key read last being out of order.
+    fn unsorted(&self) -> Self::Error;
+}
+
+/// KeySource is the Seekable over the keys of a KeyReader.
+///
+/// Streamed keys must be sorted, since seeks only ever read forward. Order
+/// says whether that is trusted, verified as keys are read, or established
+/// by reading and sorting all keys in memory first. Equal adjacent keys are
+/// returned once. Like a CastIterator, a KeySource ends at the first error,
+/// which is then available from KeySource::error().
+pub struct KeySource<R: KeyReader> {
+    reader: R,
+    verify: bool,
+    /// With Order::Sort, the keys left, in descending order.
+    sorted: Option<Vec<R::Key>>,
+    key: Option<R::Key>,
+    error: Option<R::Error>,
+}
+
+impl<R: KeyReader> KeySource<R> {
+    /// Creates a source positioned at the first key. With Order::Sort, this
+    /// reads all keys.
+    pub fn new(mut reader: R, order: Order) -> Result<Self, R::Error> {
+        let sorted = match order {
+            Order::Sort => {
+                let mut keys = Vec::new();
+                while let Some(key) = reader.read_key()? {
+                    keys.push(key);
+                }
+                keys.sort_unstable();
+                keys.dedup();
+                keys.reverse();
+                Some(keys)
+            }
+            Order::Trust | Order::Verify => None,
+        };
+        let mut source = Self {
+            reader,
+            verify: order == Order::Verify,
+            sorted,
+            key: None,
+            error: None,
+        };
+        source.advance();
+        match source.error.take() {
+            Some(e) => Err(e),
+            None => Ok(source),
+        }
+    }
+
+    /// The error that ended the source early, if any.
+    pub fn error(&self) -> Option<&R::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn into_reader(self) -> R {
+        self.reader
+    }
+
+    /// Moves to the next key greater than the current one.
+    fn advance(&mut self) {
+        let previous = self.key.take();
+        loop {
+            let key = match &mut self.sorted {
+                Some(keys) => keys.pop(),
+                None => match self.reader.read_key() {
+                    Ok(key) => key,
+                    Err(e) => {
+                        self.error = Some(e);
+                        return;
+                    }
+                },
+            };
+            match (previous, key) {
+                (Some(previous), Some(key)) if key == previous => continue,
+                (Some(previous), Some(key)) if key < previous && self.verify => {
+                    self.error = Some(self.reader.unsorted());
+                    return;
+                }
+                _ => {
+                    self.key = key;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<R: KeyReader> Seekable for KeySource<R> {
+    type Key = R::Key;
+
+    fn key(&self) -> R::Key {
+        self.key.expect("Iterator is at end")
+    }
+
+    fn next(&mut self) {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.advance();
+    }
+
+    fn seek(&mut self, seek_key: R::Key) {
+        assert!(!self.at_end(), "Iterator is at end");
+        while self.key.is_some_and(|key| key < seek_key) {
+            self.advance();
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.key.is_none()
+    }
+
+    /// Known once the keys are sorted in memory, from the last of them.
+    fn upper_bound(&self) -> Option<R::Key> {
+        self.sorted.as_ref()?.first().copied().or(self.key)
+    }
+
+    /// Known once the keys are sorted in memory.
+    fn exact_len_hint(&self) -> Option<usize> {
+        let keys = self.sorted.as_ref()?;
+        Some(keys.len() + self.key.is_some() as usize)
+    }
+}
+
+/// ParseKey is implemented by the key types text can be parsed into.
+pub trait ParseKey: Sized {
+    fn parse_key(text: &str) -> Result<Self, ParseErrorKind>;
+}
+
+/// Checks that `text` is an optional `-` followed by ASCII digits, and
+/// returns whether it is negative.
+fn check_digits(text: &str) -> Result<bool, ParseErrorKind> {
+    let negative = text.starts_with('-');
+    let digits = &text[negative as usize..];
+    if let Some((offset, ch)) = digits.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        let offset = offset + negative as usize;
+        return Err(ParseErrorKind::InvalidChar { offset, ch });
+    }
+    if digits.is_empty() {
+        Err(ParseErrorKind::Empty)
+    } else {
+        Ok(negative)
+    }
+}
+
+macro_rules! impl_parse_key {
+    ($signed:expr; $($t:ty),*) => {$(
+        impl ParseKey for $t {
+            fn parse_key(text: &str) -> Result<Self, ParseErrorKind> {
+                if check_digits(text)? && !$signed {
+                    return Err(ParseErrorKind::Negative);
+                }
+                // Only digits are left, so parsing fails on overflow only.
+                text.parse().map_err(|_| ParseErrorKind::Overflow)
+            }
+        }
+    )*};
+}
+
+impl_parse_key!(false; u8, u16, u32, u64, usize);
+impl_parse_key!(true; i8, i16, i32, i64, isize);
+
+/// Parses the field at `line` and `column`.
+pub fn parse_field<K: ParseKey>(text: &str, line: usize, column: usize) -> Result<K, ParseError> {
+    K::parse_key(text).map_err(|kind| ParseError {
+        line,
+        column,
+        text: text.to_string(),
+        kind,
+    })
+}
+
+/// TextReader reads tuples from delimited text, one per line.
+pub struct TextReader<R> {
+    reader: R,
+    delimiter: char,
+    header: bool,
+    columns: Option<Vec<usize>>,
+}
+
+impl<R: BufRead> TextReader<R> {
+    /// Creates a reader of tab-separated columns, all of which are read.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            delimiter: '\t',
+            header: false,
+            columns: None,
+        }
+    }
+
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Skips the first line.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Reads only the given columns, counted from 0, in the order given.
+    pub fn columns(mut self, columns: &[usize]) -> Self {
+        self.columns = Some(columns.to_vec());
+        self
+    }
+
+    /// Returns an iterator over the tuples. Empty lines are skipped.
+    pub fn rows<K: ParseKey>(self) -> Rows<R, K> {
+        Rows {
+            lines: self.reader.lines(),
+            line: 0,
+            skip: self.header as usize,
+            delimiter: self.delimiter,
+            columns: self.columns,
+            _key: PhantomData,
+        }
+    }
+
+    /// Reads all tuples into a relation of `arity` attributes.
+    pub fn relation<K: ParseKey + Ord + Copy>(
+        self,
+        arity: usize,
+    ) -> Result<TrieRelation<K>, IngestError> {
+        let rows = self.rows().collect::<Result<Vec<Vec<K>>, _>>()?;
+        Ok(TrieRelation::new(arity, rows))
+    }
+}
+
+/// The tuples of a TextReader.
+pub struct Rows<R, K> {
+    lines: io::Lines<R>,
+    line: usize,
+    skip: usize,
+    delimiter: char,
+    columns: Option<Vec<usize>>,
+    _key: PhantomData<K>,
+}
+
+impl<R: BufRead, K: ParseKey> Rows<R, K> {
+    fn parse(&self, text: &str) -> Result<Vec<K>, ParseError> {
+        let fields: Vec<&str> = text.split(self.delimiter).collect();
+        let Some(columns) = &self.columns else {
+            return (fields.iter().enumerate())
+                .map(|(c, field)| parse_field(field, self.line, c + 1))
+                .collect();
+        };
+        columns
+            .iter()
+            .map(|&c| match fields.get(c) {
+                Some(field) => parse_field(field, self.line, c + 1),
+                None => Err(ParseError {
+                    line: self.line,
+                    column: c + 1,
+                    text: String::new(),
+                    kind: ParseErrorKind::MissingColumn,
+                }),
+            })
+            .collect()
+    }
+}
+
+impl<R: BufRead, K: ParseKey> Iterator for Rows<R, K> {
+    type Item = Result<Vec<K>, IngestError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let text = match self.lines.next()? {
+                Ok(text) => text,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+            if self.line <= self.skip || text.is_empty() {
+                continue;
+            }
+            let text = text.strip_suffix('\r').unwrap_or(&text);
+            return Some(self.parse(text).map_err(IngestError::from));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(i32::parse_key("-42"), Ok(-42));
+        assert_eq!(u8::parse_key("007"), Ok(7));
+        assert_eq!(i8::parse_key("-128"), Ok(-128));
+        assert_eq!(u8::parse_key("256"), Err(ParseErrorKind::Overflow));
+        assert_eq!(u32::parse_key("-1"), Err(ParseErrorKind::Negative));
+        assert_eq!(i32::parse_key("-"), Err(ParseErrorKind::Empty));
+        assert_eq!(i32::parse_key(""), Err(ParseErrorKind::Empty));
+        for (text, offset, ch) in [
+            ("+1", 0, '+'),
+            (" 7", 0, ' '),
+            ("7 ", 1, ' '),
+            ("1,000", 1, ','),
+            ("1e3", 1, 'e'),
+            ("-0x10", 2, 'x'),
+            ("1٢", 1, '٢'),
+        ] {
+            let kind = ParseErrorKind::InvalidChar { offset, ch };
+            assert_eq!(i64::parse_key(text), Err(kind), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn test_text_reader() {
+        let text = "a,b,c\n1,2,3\r\n\n4,5,6\n";
+        let rows: Vec<Vec<u32>> = TextReader::new(text.as_bytes())
+            .delimiter(',')
+            .header(true)
+            .columns(&[2, 0])
+            .rows()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows, [[3, 1], [6, 4]]);
+
+        let relation = TextReader::new("2\t1\n1\t2\n".as_bytes()).relation::<u8>(2);
+        assert_eq!(relation.unwrap(), TrieRelation::new(2, [[1, 2], [2, 1]]));
+
+        let text = "1,2\n3,x4\n5\n";
+        let mut rows = TextReader::new(text.as_bytes())
+            .delimiter(',')
+            .rows::<u32>();
+        assert!(rows.next().unwrap().is_ok());
+        let Some(Err(IngestError::Parse(e))) = rows.next() else {
+            panic!("expected a parse error");
+        };
+        assert_eq!((e.line, e.column), (2, 2));
+        assert_eq!(
+            e.to_string(),
+            "line 2, column 2: cannot parse \"x4\": invalid character 'x' at offset 0"
+        );
+        let rows = TextReader::new(text.as_bytes())
+            .delimiter(',')
+            .columns(&[1]);
+        let Some(Err(IngestError::Parse(e))) = rows.rows::<u32>().nth(2) else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(
+            (e.line, e.column, e.kind),
+            (3, 2, ParseErrorKind::MissingColumn)
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Production metrics, emitted through the `metrics` facade when the `metrics`
+//! feature is enabled. Without the feature all hooks compile to nothing.
+//!
+//! Install any `metrics` recorder (e.g. a Prometheus exporter) to collect:
+//!
+//! - JOINS_STARTED: counter, incremented per constructed LeapFrogJoin.
+//! - ROWS_EMITTED: counter, incremented per key a join produces.
+//! - SEEK_SECONDS: histogram of the latency of iterator seeks inside joins.
+//! - BYTES_DECODED: counter of key bytes received by remote sources.
+
+pub const JOINS_STARTED: &str = "leapfrog_joins_started";
+pub const ROWS_EMITTED: &str = "leapfrog_rows_emitted";
+pub const SEEK_SECONDS: &str = "leapfrog_seek_seconds";
+pub const BYTES_DECODED: &str = "leapfrog_bytes_decoded";
+
+#[cfg(feature = "metrics")]
+mod hooks {
+    use std::time::Instant;
+
+    pub(crate) fn join_started() {
+        metrics::counter!(super::JOINS_STARTED).increment(1);
+    }
+
+    pub(crate) fn row_emitted() {
+        metrics::counter!(super::ROWS_EMITTED).increment(1);
+    }
+
+    pub(crate) fn timed_seek<R>(seek: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = seek();
+        metrics::histogram!(super::SEEK_SECONDS).record(start.elapsed().as_secs_f64());
+        result
+    }
+
+    pub(crate) fn bytes_decoded(bytes: usize) {
+        metrics::counter!(super::BYTES_DECODED).increment(bytes as u64);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod hooks {
+    pub(crate) fn join_started() {}
+
+    pub(crate) fn row_emitted() {}
+
+    pub(crate) fn timed_seek<R>(seek: impl FnOnce() -> R) -> R {
+        seek()
+    }
+
+    pub(crate) fn bytes_decoded(_bytes: usize) {}
+}
+
+pub(crate) use hooks::*;
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use metrics::{
+        Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+
+    use super::*;
+    use crate::LeapFrogJoin;
+
+    #[derive(Default)]
+    struct CountingRecorder {
+        counters: Mutex<HashMap<String, Arc<AtomicU64>>>,
+    }
+
+    impl CountingRecorder {
+        fn get(&self, name: &str) -> u64 {
+            let counters = self.counters.lock().unwrap();
+            counters.get(name).map_or(0, |c| c.load(Ordering::Relaxed))
+        }
+    }
+
+    impl Recorder for CountingRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let mut counters = self.counters.lock().unwrap();
+            let counter = counters.entry(key.name().to_string()).or_default();
+            Counter::from_arc(Arc::clone(counter))
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn test_join_metrics() {
+        let recorder = CountingRecorder::default();
+        metrics::with_local_recorder(&recorder, || {
+            let tab1 = [0, 1, 3, 4, 5, 6, 7, 8, 9, 11];
+            let tab2 = [0, 2, 6, 7, 8, 9, 11];
+            let mut join = LeapFrogJoin::new(vec![&tab1[..], &tab2[..]]);
+            while !join.at_end() {
+                join.next();
+            }
+        });
+        assert_eq!(recorder.get(JOINS_STARTED), 1);
+        assert_eq!(recorder.get(ROWS_EMITTED), 6);
+    }
+}
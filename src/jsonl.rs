@@ -0,0 +1,232 @@
+//! JSON Lines files as join sources.
+//!
+//! JsonlReader extracts one key per line with a JSON pointer (RFC 6901),
+//! e.g. `/user/id`, so that log files can be joined on the IDs they
+//! mention. A key may be a JSON number or a string holding one; both are
+//! parsed with the strict rules of the ingest module, so `1.5`, `1e3` and
+//! `" 7"` are errors. Empty lines are skipped.
+//!
+//! The keys are read as a KeySource, which gets them in order and handles
+//! errors.
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::marker::PhantomData;
+
+use serde_json::Value;
+
+use crate::ingest::{KeyReader, KeySource, Order, ParseErrorKind, ParseKey};
+
+#[derive(Debug)]
+pub enum JsonlError {
+    Io(io::Error),
+    /// The line is not valid JSON.
+    Json {
+        line: usize,
+        error: serde_json::Error,
+    },
+    /// The pointer does not resolve, or resolves to null.
+    Missing {
+        line: usize,
+    },
+    /// The value is not a valid key.
+    Key {
+        line: usize,
+        value: String,
+        kind: ParseErrorKind,
+    },
+    /// The key at `line` is smaller than the one before it.
+    Unsorted {
+        line: usize,
+    },
+}
+
+impl fmt::Display for JsonlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonlError::Io(e) => write!(f, "I/O error: {e}"),
+            JsonlError::Json { line, error } => write!(f, "line {line}: invalid JSON: {error}"),
+            JsonlError::Missing { line } => write!(f, "line {line}: no key"),
+            JsonlError::Key { line, value, kind } => {
+                write!(f, "line {line}: cannot parse key {value}: {kind}")
+            }
+            JsonlError::Unsorted { line } => {
+                write!(f, "line {line}: key is smaller than the one before")
+            }
+        }
+    }
+}
+
+impl std::error::Error for JsonlError {}
+
+impl From<io::Error> for JsonlError {
+    fn from(e: io::Error) -> Self {
+        JsonlError::Io(e)
+    }
+}
+
+/// JsonlReader configures how keys are extracted.
+pub struct JsonlReader<R> {
+    reader: R,
+    pointer: String,
+    skip_missing: bool,
+    order: Order,
+}
+
+impl<R: BufRead> JsonlReader<R> {
+    /// Creates a reader of the keys `pointer` points to, verifying that they
+    /// are sorted.
+    pub fn new(reader: R, pointer: &str) -> Self {
+        Self {
+            reader,
+            pointer: pointer.to_string(),
+            skip_missing: false,
+            order: Order::Verify,
+        }
+    }
+
+    /// Skips lines without a key instead of failing.
+    pub fn skip_missing(mut self, skip_missing: bool) -> Self {
+        self.skip_missing = skip_missing;
+        self
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Opens the keys as a source, positioned at the first one. With
+    /// Order::Sort, this reads the whole file.
+    pub fn source<K: ParseKey + Ord + Copy>(self) -> Result<JsonlSource<R, K>, JsonlError> {
+        let keys = JsonlKeys {
+            lines: self.reader.lines(),
+            line: 0,
+            pointer: self.pointer,
+            skip_missing: self.skip_missing,
+            _key: PhantomData,
+        };
+        KeySource::new(keys, self.order)
+    }
+}
+
+/// JsonlSource is the sorted keys of a JSON Lines file, see JsonlReader.
+pub type JsonlSource<R, K> = KeySource<JsonlKeys<R, K>>;
+
+/// The keys of a JSON Lines file, in file order.
+pub struct JsonlKeys<R, K> {
+    lines: io::Lines<R>,
+    line: usize,
+    pointer: String,
+    skip_missing: bool,
+    _key: PhantomData<K>,
+}
+
+impl<R: BufRead, K: ParseKey + Ord + Copy> KeyReader for JsonlKeys<R, K> {
+    type Key = K;
+    type Error = JsonlError;
+
+    fn read_key(&mut self) -> Result<Option<K>, JsonlError> {
+        loop {
+            let Some(text) = self.lines.next() else {
+                return Ok(None);
+            };
+            let text = text?;
+            self.line += 1;
+            let line = self.line;
+            if text.trim().is_empty() {
+                continue;
+            }
+            let value: Value =
+                serde_json::from_str(&text).map_err(|error| JsonlError::Json { line, error })?;
+            let Some(found) = value.pointer(&self.pointer).filter(|v| !v.is_null()) else {
+                if self.skip_missing {
+                    continue;
+                }
+                return Err(JsonlError::Missing { line });
+            };
+            let key = match found {
+                Value::Number(n) => K::parse_key(&n.to_string()),
+                Value::String(s) => K::parse_key(s),
+                _ => Err(ParseErrorKind::Empty),
+            };
+            return key.map(Some).map_err(|kind| JsonlError::Key {
+                line,
+                value: found.to_string(),
+                kind,
+            });
+        }
+    }
+
+    fn unsorted(&self) -> JsonlError {
+        JsonlError::Unsorted { line: self.line }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LeapFrogJoin, LinearIterator, Seekable};
+
+    const LOG: &str = r#"{"user": {"id": 3}, "event": "login"}
+{"user": {"id": "5"}, "event": "view"}
+
+{"user": {"id": 5}, "event": "view"}
+{"event": "ping"}
+{"user": {"id": 8, "name": "x"}, "event": "logout"}
+"#;
+
+    #[test]
+    fn test_jsonl_source() {
+        let source = JsonlReader::new(LOG.as_bytes(), "/user/id")
+            .skip_missing(true)
+            .source::<u64>()
+            .unwrap();
+        let others = [1, 5, 8, 9];
+        let join = LeapFrogJoin::from_iters(vec![
+            Box::new(source) as Box<dyn Seekable<Key = u64>>,
+            Box::new(LinearIterator::new(&others)),
+        ]);
+        assert_eq!(join.into_keys().collect::<Vec<_>>(), [5, 8]);
+
+        let unsorted = "{\"id\": 2}\n{\"id\": 1}\n";
+        let source = JsonlReader::new(unsorted.as_bytes(), "/id").order(Order::Sort);
+        assert_eq!(source.source::<i32>().unwrap().exact_len_hint(), Some(2));
+    }
+
+    #[test]
+    fn test_jsonl_errors() {
+        let mut source = JsonlReader::new(LOG.as_bytes(), "/user/id")
+            .source::<u64>()
+            .unwrap();
+        source.next();
+        source.next();
+        assert!(source.at_end());
+        assert!(matches!(
+            source.error(),
+            Some(JsonlError::Missing { line: 5 })
+        ));
+
+        for (text, expected) in [
+            (
+                "{\"id\": 1.5}",
+                "line 1: cannot parse key 1.5: invalid character '.' at offset 1",
+            ),
+            ("{\"id\": [1]}", "line 1: cannot parse key [1]: no digits"),
+            (
+                "{\"id\": \"-1\"}",
+                "line 1: cannot parse key \"-1\": negative number for an unsigned key",
+            ),
+        ] {
+            let error = JsonlReader::new(text.as_bytes(), "/id")
+                .source::<u32>()
+                .err();
+            assert_eq!(error.unwrap().to_string(), expected);
+        }
+        let error = JsonlReader::new("{\"id\": 1\n".as_bytes(), "/id").source::<u32>();
+        assert!(matches!(
+            error.err(),
+            Some(JsonlError::Json { line: 1, .. })
+        ));
+    }
+}
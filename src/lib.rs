@@ -1,4 +1,198 @@
 use std::cmp::Ordering;
+use std::fmt;
+
+// Lets the code generated by leapfrog-derive refer to `::leapfrog` from
+// within this crate, too.
+extern crate self as leapfrog;
+
+pub mod advisor;
+#[cfg(feature = "baselines")]
+pub mod baselines;
+#[cfg(feature = "time")]
+pub mod bench;
+pub mod boolean;
+pub mod cache;
+pub mod cast;
+pub mod catalog;
+pub mod chain;
+pub mod cost;
+#[cfg(feature = "csv")]
+pub mod csv;
+pub mod database;
+#[cfg(feature = "datafusion")]
+pub mod datafusion;
+pub mod datalog;
+pub mod dedup;
+pub mod delta;
+pub mod dense;
+pub mod difference;
+pub mod dynamic;
+pub mod executor;
+pub mod expr;
+pub mod ffi;
+#[cfg(all(feature = "fs", feature = "time"))]
+pub mod golden;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod histogram;
+pub mod ingest;
+pub mod instrument;
+#[cfg(feature = "jsonl")]
+pub mod jsonl;
+pub mod memcomparable;
+pub mod memory;
+pub mod metadata;
+#[cfg(feature = "node")]
+pub mod node;
+#[cfg(feature = "numa")]
+pub mod numa;
+pub mod persist;
+pub mod pinned;
+pub mod pipeline;
+pub mod plancache;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod prepared;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod query;
+pub mod random;
+pub mod ranged;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod relation;
+pub mod remote;
+pub mod replay;
+pub mod rows;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stepper;
+#[cfg(feature = "time")]
+pub mod trace;
+pub mod trie;
+pub mod tuning;
+pub mod union;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub mod uring;
+pub mod validate;
+pub mod visualize;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod wide;
+pub mod zonemap;
+
+#[cfg(feature = "derive")]
+pub use leapfrog_derive::Relation;
+use tuning::Tuning;
+use zonemap::Zone;
+
+/// Seekable is the linear iterator interface from the leapfrog join paper:
+/// a cursor over a sorted sequence of keys that can be advanced by one
+/// (next()) or fast-forwarded to the first key >= some seek key (seek()).
+///
+/// Any sorted source that implements this trait can take part in a
+/// LeapFrogJoin.
+pub trait Seekable {
+    type Key: Ord + Copy;
+
+    /// Returns the key at the current position. Must not be called at end.
+    fn key(&self) -> Self::Key;
+
+    /// Advances to the next key. Must not be called at end.
+    fn next(&mut self);
+
+    /// Positions the iterator at the least key >= seek_key, or at end if no
+    /// such key exists. The seek key must be >= the current key.
+    fn seek(&mut self, seek_key: Self::Key);
+
+    fn at_end(&self) -> bool;
+
+    /// Returns the key range of every block of the source, in ascending
+    /// order, if the source keeps a zone map. Keys outside of all zones must
+    /// not occur in the source.
+    fn zone_map(&self) -> Option<Vec<Zone<Self::Key>>> {
+        None
+    }
+
+    /// Returns an upper bound on the number of keys from the current one
+    /// on, if the source can tell cheaply, e.g. from its position or from
+    /// counts a backend keeps. Defaults to exact_len_hint().
+    fn estimate(&self) -> Option<usize> {
+        self.exact_len_hint()
+    }
+
+    /// Returns the exact number of keys from the current one on, if the
+    /// source can tell cheaply.
+    fn exact_len_hint(&self) -> Option<usize> {
+        None
+    }
+
+    /// Tells the source that it will likely be sought to `key` or beyond
+    /// soon, so that it can fetch the data in the background. Joins call
+    /// this on their other inputs whenever a seek overshoots, as those will
+    /// have to catch up. Must not change the position.
+    fn will_need(&mut self, _key: Self::Key) {}
+
+    /// Returns a key >= all keys from the current one on, e.g. the largest
+    /// key of the source, if the source can tell cheaply. A join ends as
+    /// soon as it would have to seek an input past its bound, without
+    /// seeking. Must not be called at end.
+    fn upper_bound(&self) -> Option<Self::Key> {
+        None
+    }
+}
+
+/// Boxed iterators are iterators, too, which allows joining iterators of
+/// different types through `Box<dyn Seekable<Key = K>>`.
+impl<S: Seekable + ?Sized> Seekable for Box<S> {
+    type Key = S::Key;
+
+    fn key(&self) -> S::Key {
+        (**self).key()
+    }
+
+    fn next(&mut self) {
+        (**self).next()
+    }
+
+    fn seek(&mut self, seek_key: S::Key) {
+        (**self).seek(seek_key)
+    }
+
+    fn at_end(&self) -> bool {
+        (**self).at_end()
+    }
+
+    fn zone_map(&self) -> Option<Vec<Zone<S::Key>>> {
+        (**self).zone_map()
+    }
+
+    fn estimate(&self) -> Option<usize> {
+        (**self).estimate()
+    }
+
+    fn exact_len_hint(&self) -> Option<usize> {
+        (**self).exact_len_hint()
+    }
+
+    fn will_need(&mut self, key: S::Key) {
+        (**self).will_need(key)
+    }
+
+    fn upper_bound(&self) -> Option<S::Key> {
+        (**self).upper_bound()
+    }
+}
+
+/// Orders iterators by their current key, with iterators at end last.
+fn cmp_seekable<I: Seekable>(a: &I, b: &I) -> Ordering {
+    match (a.at_end(), b.at_end()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.key().cmp(&b.key()),
+    }
+}
 
 /// LinearIterator provides iteration over a vector with specific operations
 /// required by the leapfrog join algorithm.
@@ -8,16 +202,46 @@ use std::cmp::Ordering;
 /// then, this is just a toy implementation using vectors.
 ///
 /// As a result, key() and at_end() have the desired time complexity O(1), while,
-/// next() and seek() have time complexity O(N) instead of O(log N).
+/// next() and seek() have time complexity O(N) instead of O(log N). Iterators
+/// created with with_tuning() gallop on long seeks, which brings seek() down
+/// to O(log N).
 #[derive(Clone)]
 pub struct LinearIterator<'a, T> {
     source: &'a [T],
     pos: usize,
+    zone_block_size: Option<usize>,
+    gallop_after: usize,
 }
 
 impl<'a, T> LinearIterator<'a, T> {
     pub fn new(source: &'a [T]) -> Self {
-        Self { source, pos: 0 }
+        Self {
+            source,
+            pos: 0,
+            zone_block_size: None,
+            gallop_after: usize::MAX,
+        }
+    }
+
+    /// Creates an iterator whose seeks gallop after scanning
+    /// `tuning.galloping_threshold` keys.
+    pub fn with_tuning(source: &'a [T], tuning: &Tuning) -> Self {
+        Self {
+            gallop_after: tuning.galloping_threshold,
+            ..Self::new(source)
+        }
+    }
+
+    /// Creates an iterator that publishes a zone map with one zone per
+    /// `block_size` keys.
+    pub fn with_zone_map(source: &'a [T], block_size: usize) -> Self {
+        assert!(block_size > 0, "Block size must be > 0");
+        Self {
+            source,
+            pos: 0,
+            zone_block_size: Some(block_size),
+            gallop_after: usize::MAX,
+        }
     }
 
     pub fn at_end(&self) -> bool {
@@ -45,9 +269,58 @@ where
             seek_key >= self.source[self.pos],
             "Seek key must be >= current key"
         );
-        while !self.at_end() && self.source[self.pos] < seek_key {
+        let end = self
+            .source
+            .len()
+            .min(self.pos.saturating_add(self.gallop_after));
+        while self.pos < end && self.source[self.pos] < seek_key {
             self.next();
         }
+        if self.pos < end || self.at_end() {
+            return;
+        }
+        // Find a key >= seek_key at exponentially growing distances, then
+        // binary search the last of them.
+        let (mut lo, mut step) = (self.pos, 1);
+        while lo + step < self.source.len() && self.source[lo + step] < seek_key {
+            lo += step;
+            step *= 2;
+        }
+        let hi = (lo + step).min(self.source.len());
+        self.pos = lo + self.source[lo..hi].partition_point(|&k| k < seek_key);
+    }
+}
+
+impl<'a, T: Ord + Copy> Seekable for LinearIterator<'a, T> {
+    type Key = T;
+
+    fn key(&self) -> T {
+        LinearIterator::key(self)
+    }
+
+    fn next(&mut self) {
+        LinearIterator::next(self)
+    }
+
+    fn seek(&mut self, seek_key: T) {
+        LinearIterator::seek(self, seek_key)
+    }
+
+    fn at_end(&self) -> bool {
+        LinearIterator::at_end(self)
+    }
+
+    fn zone_map(&self) -> Option<Vec<Zone<T>>> {
+        self.zone_block_size
+            .map(|block_size| zonemap::zones_of_sorted(self.source, block_size))
+    }
+
+    fn exact_len_hint(&self) -> Option<usize> {
+        Some(self.source.len().saturating_sub(self.pos))
+    }
+
+    fn upper_bound(&self) -> Option<T> {
+        self.source.last().copied()
     }
 }
 
@@ -73,51 +346,162 @@ impl<'a, T: Ord + Copy> PartialOrd for LinearIterator<'a, T> {
 
 impl<'a, T: Ord + Copy> Ord for LinearIterator<'a, T> {
     fn cmp(&self, other: &Self) -> Ordering {
-        if self.at_end() && other.at_end() {
-            Ordering::Equal
-        } else if self.at_end() {
-            Ordering::Greater
-        } else if other.at_end() {
-            Ordering::Less
-        } else {
-            self.key().cmp(&other.key())
-        }
+        cmp_seekable(self, other)
+    }
+}
+
+/// Stalled is why a join ended early: its search took more steps than its
+/// inputs have keys, which only an input breaking the Seekable contract
+/// can cause, see LeapFrogJoin::stalled().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stalled {
+    pub steps: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for Stalled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "join stopped after {} search steps, more than the {} its inputs allow; \
+             an input breaks the Seekable contract",
+            self.steps, self.limit
+        )
     }
 }
 
+impl std::error::Error for Stalled {}
+
 /// LeapFrogJoin implements the leapfrog join algorithm for finding
-/// common elements across multiple sorted vectors.
-pub struct LeapFrogJoin<'a, T> {
-    iters: Vec<LinearIterator<'a, T>>,
+/// common elements across multiple sorted inputs.
+///
+/// If some inputs publish zone maps, the join only searches the key ranges
+/// covered by a zone of each of them, and skips every gap with a single seek.
+///
+/// Every search step moves an input to a greater key, so a join takes at
+/// most as many steps as its inputs have keys. If all inputs estimate their
+/// size, the join stops with an error past that many steps instead of
+/// looping forever on a broken input.
+pub struct LeapFrogJoin<I: Seekable> {
+    iters: Vec<I>,
     iters_indices: Vec<usize>,
     at_end: bool,
     pos: usize,
+    live: Option<Vec<Zone<I::Key>>>,
+    /// The least upper bound of the inputs, past which no key matches.
+    bound: Option<I::Key>,
+    reorder: Option<Reorder>,
+    /// The number of matches from the current one on, if known.
+    remaining: Option<usize>,
+    steps: usize,
+    step_limit: Option<usize>,
+    stalled: Option<Stalled>,
 }
 
-impl<'a, T> LeapFrogJoin<'a, T>
+/// State of the optional runtime re-ordering, see LeapFrogJoin::with_reordering().
+struct Reorder {
+    interval: usize,
+    matches: usize,
+    /// Per iterator: how often one of its seeks overshot the seek key, i.e.
+    /// raised the key all other iterators have to catch up to.
+    leads: Vec<usize>,
+}
+
+impl<'a, T> LeapFrogJoin<LinearIterator<'a, T>>
 where
     T: Ord + Copy,
 {
     pub fn new(sources: Vec<&'a [T]>) -> Self {
-        let iters: Vec<LinearIterator<'a, T>> =
-            sources.iter().map(|&s| LinearIterator::new(s)).collect();
+        Self::from_iters(sources.iter().map(|&s| LinearIterator::new(s)).collect())
+    }
 
-        let mut at_end = true;
-        for iter in &iters {
-            at_end &= iter.at_end();
-        }
+    /// Creates a join whose iterators gallop, see LinearIterator::with_tuning().
+    pub fn tuned(sources: Vec<&'a [T]>, tuning: &Tuning) -> Self {
+        let iters = sources
+            .iter()
+            .map(|&s| LinearIterator::with_tuning(s, tuning))
+            .collect();
+        Self::from_iters(iters)
+    }
+}
 
+impl<I> LeapFrogJoin<I>
+where
+    I: Seekable,
+{
+    /// Creates a join over arbitrary seekable iterators, all positioned at
+    /// their first key.
+    pub fn from_iters(iters: Vec<I>) -> Self {
         let mut iters_indices: Vec<usize> = (0..iters.len()).collect();
+        if !iters.iter().any(|iter| iter.at_end()) {
+            // Sort iterators by their current key
+            iters_indices.sort_by(|&a, &b| cmp_seekable(&iters[a], &iters[b]));
+        }
+        Self::with_indices(iters, iters_indices)
+    }
+
+    /// Creates a join that visits the iterators in `order`, a permutation of
+    /// their indices, e.g. as chosen by a planner. The first of them leads:
+    /// every other iterator is sought to its key in turn before the search
+    /// starts, which leaves them sorted by key in `order`.
+    pub fn from_iters_ordered(mut iters: Vec<I>, order: &[usize]) -> Self {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        assert!(
+            sorted.iter().copied().eq(0..iters.len()),
+            "Order is not a permutation of the iterators"
+        );
+        let mut max_key = None;
+        for &i in order {
+            let iter = &mut iters[i];
+            if iter.at_end() {
+                break;
+            }
+            if let Some(key) = max_key
+                && iter.key() < key
+            {
+                instrument::timed_seek(|| iter.seek(key));
+                #[cfg(debug_assertions)]
+                if let Err(violation) = validate::check_seek(iter, key) {
+                    panic!("Input {i}: {}", violation.description());
+                }
+                if iter.at_end() {
+                    break;
+                }
+            }
+            max_key = Some(iter.key());
+        }
+        Self::with_indices(iters, order.to_vec())
+    }
+
+    /// Creates a join visiting the iterators in the cyclic order of
+    /// `iters_indices`, which must be sorted by their current keys.
+    fn with_indices(iters: Vec<I>, iters_indices: Vec<usize>) -> Self {
+        instrument::join_started();
+
+        // The intersection is empty as soon as any single input is empty.
+        let at_end = iters.is_empty() || iters.iter().any(|iter| iter.at_end());
 
         if !at_end {
-            // Sort iterators by their current key
-            iters_indices.sort_by(|&a, &b| iters[a].cmp(&iters[b]));
+            let zone_maps: Vec<_> = iters.iter().filter_map(|iter| iter.zone_map()).collect();
+            let live = (!zone_maps.is_empty()).then(|| zonemap::live_ranges(&zone_maps));
+            let bound = iters.iter().filter_map(|iter| iter.upper_bound()).min();
+            let step_limit = iters.iter().try_fold(iters.len(), |sum, iter| {
+                Some(sum.saturating_add(iter.estimate()?))
+            });
 
             let mut join = Self {
                 iters,
                 iters_indices,
                 at_end,
                 pos: 0,
+                live,
+                bound,
+                reorder: None,
+                remaining: None,
+                steps: 0,
+                step_limit,
+                stalled: None,
             };
 
             join.search();
@@ -128,19 +512,102 @@ where
                 iters_indices,
                 at_end,
                 pos: 0,
+                live: None,
+                bound: None,
+                reorder: None,
+                remaining: None,
+                steps: 0,
+                step_limit: None,
+                stalled: None,
             }
         }
     }
 
-    pub fn key(&self) -> T {
+    /// Re-orders the iterators after every `interval` matches, so that the
+    /// most selective iterator, the one whose seeks overshoot most often,
+    /// leads the round-robin sequence. This usually saves seeks, but changes
+    /// the order in which the iterators are visited.
+    pub fn with_reordering(mut self, interval: usize) -> Self {
+        assert!(interval > 0, "Reorder interval must be > 0");
+        self.reorder = Some(Reorder {
+            interval,
+            matches: 0,
+            leads: vec![0; self.iters.len()],
+        });
+        self
+    }
+
+    /// Sets the number of matches from the current one on, e.g. counted up
+    /// front by a backend that intersects bitmaps cheaply. The join keeps
+    /// it up to date while advancing, and reports it as exact_len_hint().
+    pub fn with_len(mut self, len: usize) -> Self {
+        self.remaining = Some(len);
+        self
+    }
+
+    /// Stops the join after `limit` search steps in total, e.g. for inputs
+    /// that do not estimate their size.
+    pub fn with_step_limit(mut self, limit: usize) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    /// Returns why the join ended early, if it did. Its results are
+    /// incomplete then.
+    pub fn stalled(&self) -> Option<Stalled> {
+        self.stalled
+    }
+
+    /// The number of matches from the current one on: the count set by
+    /// with_len(), or the count of the only input.
+    pub fn exact_len_hint(&self) -> Option<usize> {
+        if self.at_end {
+            return Some(0);
+        }
+        match self.iters.as_slice() {
+            [iter] => self.remaining.or_else(|| iter.exact_len_hint()),
+            _ => self.remaining,
+        }
+    }
+
+    /// An upper bound on the matches from the current one on: the exact
+    /// count if known, and the least bound of any input otherwise.
+    pub fn estimate(&self) -> Option<usize> {
+        self.exact_len_hint()
+            .or_else(|| self.iters.iter().filter_map(|iter| iter.estimate()).min())
+    }
+
+    /// Returns an iterator over the keys of the join.
+    pub fn into_keys(self) -> Keys<I> {
+        Keys { join: self }
+    }
+
+    /// Returns an ExactSizeIterator over the keys of the join, or None unless
+    /// the join knows its exact size (see exact_len_hint()).
+    pub fn into_exact_keys(self) -> Option<ExactKeys<I>> {
+        self.exact_len_hint()?;
+        Some(ExactKeys {
+            keys: self.into_keys(),
+        })
+    }
+
+    pub fn key(&self) -> I::Key {
         assert!(!self.at_end, "Join is at end");
         self.iters[self.iters_indices[0]].key()
     }
 
     pub fn next(&mut self) {
         assert!(!self.at_end, "Join is at end");
+        self.remaining = self.remaining.map(|n| n.saturating_sub(1));
+        self.maybe_reorder();
         let cur_idx = self.iters_indices[self.pos];
+        #[cfg(debug_assertions)]
+        let key = self.iters[cur_idx].key();
         self.iters[cur_idx].next();
+        #[cfg(debug_assertions)]
+        if let Err(violation) = validate::check_next(&self.iters[cur_idx], key) {
+            panic!("Input {cur_idx}: {}", violation.description());
+        }
 
         if self.iters[cur_idx].at_end() {
             self.at_end = true;
@@ -150,47 +617,159 @@ where
         }
     }
 
-    pub fn seek(&mut self, seek_key: T) {
+    /// Seekable::seek(): like skip_to(), but must not be called at end.
+    pub fn seek(&mut self, seek_key: I::Key) {
         assert!(!self.at_end, "Join is at end");
-        let cur_idx = self.iters_indices[self.pos];
-        self.iters[cur_idx].seek(seek_key);
+        self.skip_to(seek_key);
+    }
 
-        if self.iters[cur_idx].at_end() {
+    /// Fast-forwards the join to the first match >= `key`, or to the end if
+    /// there is none. Keys at or before the current match leave the join
+    /// where it is, and so does calling it at end.
+    ///
+    /// Every iterator behind `key` is sought to it right away, instead of
+    /// one after the other while searching, and the search then resumes
+    /// from the largest of their keys.
+    pub fn skip_to(&mut self, key: I::Key) {
+        if self.at_end || self.key() >= key {
+            return;
+        }
+        if self.bound.is_some_and(|bound| key > bound) {
             self.at_end = true;
-        } else {
-            self.pos = self.next_pos();
-            self.search();
+            return;
+        }
+        self.remaining = None;
+        self.maybe_reorder();
+        for i in 0..self.iters.len() {
+            let iter = &mut self.iters[i];
+            if iter.key() < key {
+                instrument::timed_seek(|| iter.seek(key));
+                #[cfg(debug_assertions)]
+                if let Err(violation) = validate::check_seek(iter, key) {
+                    panic!("Input {i}: {}", violation.description());
+                }
+                if iter.at_end() {
+                    self.at_end = true;
+                    return;
+                }
+            }
         }
+        // Restore the leapfrog invariant: the iterators in cyclic order from
+        // the smallest key to the largest, which precedes the current one.
+        // The sort is stable, so ties keep the order re-ordering chose.
+        let iters = &self.iters;
+        self.iters_indices
+            .sort_by(|&a, &b| iters[a].key().cmp(&iters[b].key()));
+        self.pos = 0;
+        self.search();
     }
 
     pub fn at_end(&self) -> bool {
         self.at_end
     }
 
+    /// The iterators in their current state. At a match, all of them are
+    /// positioned at the current key.
+    pub fn iters(&self) -> &[I] {
+        &self.iters
+    }
+
+    /// Consumes the join and returns its iterators, e.g. to inspect
+    /// per-source statistics after the join has run.
+    pub fn into_iters(self) -> Vec<I> {
+        self.iters
+    }
+
     fn search(&mut self) {
         assert!(!self.at_end, "Join is at end");
         let prev_idx = self.iters_indices[self.prev_pos()];
         let mut max_key = self.iters[prev_idx].key();
 
         loop {
+            match self.prune(max_key) {
+                Some(key) => max_key = key,
+                None => {
+                    self.at_end = true;
+                    break;
+                }
+            }
             let cur_idx = self.iters_indices[self.pos];
             let cur_key = self.iters[cur_idx].key();
 
             if cur_key == max_key {
+                instrument::row_emitted();
                 break;
             } else {
-                self.iters[cur_idx].seek(max_key);
+                self.steps += 1;
+                if let Some(limit) = self.step_limit
+                    && self.steps > limit
+                {
+                    self.stalled = Some(Stalled {
+                        steps: self.steps,
+                        limit,
+                    });
+                    self.at_end = true;
+                    break;
+                }
+                instrument::timed_seek(|| self.iters[cur_idx].seek(max_key));
+                #[cfg(debug_assertions)]
+                if let Err(violation) = validate::check_seek(&self.iters[cur_idx], max_key) {
+                    panic!("Input {cur_idx}: {}", violation.description());
+                }
                 if self.iters[cur_idx].at_end() {
                     self.at_end = true;
                     break;
                 } else {
-                    max_key = self.iters[cur_idx].key();
+                    let key = self.iters[cur_idx].key();
+                    if key > max_key {
+                        if let Some(reorder) = &mut self.reorder {
+                            reorder.leads[cur_idx] += 1;
+                        }
+                        for (i, iter) in self.iters.iter_mut().enumerate() {
+                            if i != cur_idx {
+                                iter.will_need(key);
+                            }
+                        }
+                    }
+                    max_key = key;
                     self.pos = (self.pos + 1) % self.iters.len();
                 }
             }
         }
     }
 
+    /// Called at a match. All iterators are at the same key there, so any
+    /// cyclic order is a valid one to continue with.
+    fn maybe_reorder(&mut self) {
+        let Some(reorder) = &mut self.reorder else {
+            return;
+        };
+        reorder.matches += 1;
+        if reorder.matches % reorder.interval != 0 {
+            return;
+        }
+        let leads = &reorder.leads;
+        self.iters_indices.sort_by(|&a, &b| leads[b].cmp(&leads[a]));
+        self.pos = 0;
+        // Decay, so that the order follows changes in the data.
+        for lead in reorder.leads.iter_mut() {
+            *lead /= 2;
+        }
+    }
+
+    /// Returns the least key >= `key` that lies in a live range, or None if
+    /// there is none, e.g. because `key` is past the bound of an input.
+    fn prune(&self, key: I::Key) -> Option<I::Key> {
+        if self.bound.is_some_and(|bound| key > bound) {
+            return None;
+        }
+        let Some(live) = &self.live else {
+            return Some(key);
+        };
+        let zone = live.get(live.partition_point(|zone| zone.max < key))?;
+        Some(key.max(zone.min))
+    }
+
     fn prev_pos(&self) -> usize {
         (self.pos + self.iters.len() - 1) % self.iters.len()
     }
@@ -200,6 +779,145 @@ where
     }
 }
 
+/// A join produces its keys in ascending order, so it can be an input of
+/// further joins, unions and differences without materializing its keys.
+impl<I: Seekable> Seekable for LeapFrogJoin<I> {
+    type Key = I::Key;
+
+    fn key(&self) -> I::Key {
+        LeapFrogJoin::key(self)
+    }
+
+    fn next(&mut self) {
+        LeapFrogJoin::next(self)
+    }
+
+    fn seek(&mut self, seek_key: I::Key) {
+        LeapFrogJoin::skip_to(self, seek_key)
+    }
+
+    fn at_end(&self) -> bool {
+        LeapFrogJoin::at_end(self)
+    }
+
+    /// The live ranges of the inputs, outside of which no key matches.
+    fn zone_map(&self) -> Option<Vec<Zone<I::Key>>> {
+        self.live.clone()
+    }
+
+    fn estimate(&self) -> Option<usize> {
+        LeapFrogJoin::estimate(self)
+    }
+
+    fn exact_len_hint(&self) -> Option<usize> {
+        LeapFrogJoin::exact_len_hint(self)
+    }
+
+    fn will_need(&mut self, key: I::Key) {
+        for iter in &mut self.iters {
+            iter.will_need(key);
+        }
+    }
+
+    fn upper_bound(&self) -> Option<I::Key> {
+        self.bound
+    }
+}
+
+/// Keys is a LeapFrogJoin as an Iterator. Its size_hint() reports the
+/// join's estimate() and exact_len_hint().
+pub struct Keys<I: Seekable> {
+    join: LeapFrogJoin<I>,
+}
+
+impl<I: Seekable> Keys<I> {
+    pub fn into_join(self) -> LeapFrogJoin<I> {
+        self.join
+    }
+}
+
+impl<I: Seekable> Iterator for Keys<I> {
+    type Item = I::Key;
+
+    fn next(&mut self) -> Option<I::Key> {
+        if self.join.at_end() {
+            return None;
+        }
+        let key = self.join.key();
+        self.join.next();
+        Some(key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.join.exact_len_hint() {
+            Some(len) => (len, Some(len)),
+            None => (0, self.join.estimate()),
+        }
+    }
+}
+
+/// ExactKeys is Keys of a join that knows its exact size, made by
+/// LeapFrogJoin::into_exact_keys().
+pub struct ExactKeys<I: Seekable> {
+    keys: Keys<I>,
+}
+
+impl<I: Seekable> ExactKeys<I> {
+    pub fn into_join(self) -> LeapFrogJoin<I> {
+        self.keys.into_join()
+    }
+}
+
+impl<I: Seekable> Iterator for ExactKeys<I> {
+    type Item = I::Key;
+
+    fn next(&mut self) -> Option<I::Key> {
+        self.keys.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl<I: Seekable> ExactSizeIterator for ExactKeys<I> {}
+
+/// Returns the keys common to all sorted sources, in ascending order.
+pub fn intersect<T: Ord + Copy>(sources: Vec<&[T]>) -> Vec<T> {
+    let mut join = LeapFrogJoin::new(sources);
+    let mut result = Vec::new();
+    while !join.at_end() {
+        result.push(join.key());
+        join.next();
+    }
+    result
+}
+
+/// Unsorted is why try_intersect() refused its sources: source `input` is
+/// not sorted strictly ascending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Unsorted {
+    pub input: usize,
+}
+
+impl fmt::Display for Unsorted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input {} is not sorted strictly ascending", self.input)
+    }
+}
+
+impl std::error::Error for Unsorted {}
+
+/// Like intersect(), but first checks that every source is sorted and free
+/// of duplicates, e.g. for sources from another language.
+pub fn try_intersect<T: Ord + Copy>(sources: Vec<&[T]>) -> Result<Vec<T>, Unsorted> {
+    let strictly_sorted = |keys: &&[T]| keys.windows(2).all(|w| w[0] < w[1]);
+    match sources.iter().position(|keys| !strictly_sorted(keys)) {
+        Some(input) => Err(Unsorted { input }),
+        None => Ok(intersect(sources)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -358,4 +1076,321 @@ mod tests {
         join.next();
         assert!(join.at_end());
     }
+
+    #[test]
+    fn test_intersect() {
+        let tab1 = tab1();
+        let tab2 = tab2();
+        let tab3 = tab3();
+        assert_eq!(intersect(vec![&tab1, &tab2, &tab3]), vec![8]);
+        assert!(intersect::<i32>(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_leapfrog_join_with_empty_input() {
+        let tab0 = tab0();
+        let tab1 = tab1();
+        let join = LeapFrogJoin::new(vec![&tab0, &tab1]);
+        assert!(join.at_end());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_leapfrog_join_reordering() {
+        let dense: Vec<i32> = (0..3000).collect();
+        let medium: Vec<i32> = (0..3000).filter(|x| x % 3 != 1).collect();
+        let sparse: Vec<i32> = (0..3000).filter(|x| x % 50 < 10).collect();
+        let expected = intersect(vec![&dense, &medium, &dense, &sparse]);
+
+        let run = |interval: Option<usize>| {
+            let tracer = trace::Tracer::new();
+            let mut join = LeapFrogJoin::from_iters(vec![
+                tracer.wrap("dense1", LinearIterator::new(&dense)),
+                tracer.wrap("medium", LinearIterator::new(&medium)),
+                tracer.wrap("dense2", LinearIterator::new(&dense)),
+                tracer.wrap("sparse", LinearIterator::new(&sparse)),
+            ]);
+            if let Some(interval) = interval {
+                join = join.with_reordering(interval);
+            }
+            let mut result = vec![];
+            while !join.at_end() {
+                result.push(join.key());
+                join.next();
+            }
+            (result, tracer.len())
+        };
+        let (plain, plain_ops) = run(None);
+        let (reordered, reordered_ops) = run(Some(4));
+        assert_eq!(plain, expected);
+        assert_eq!(reordered, expected);
+        assert!(reordered_ops < plain_ops, "{reordered_ops} >= {plain_ops}");
+    }
+
+    #[test]
+    fn test_nested_join() {
+        let tab1 = tab1();
+        let tab2 = tab2();
+        let tab3 = tab3();
+        let inner = LeapFrogJoin::new(vec![&tab1, &tab2]);
+        let mut join = LeapFrogJoin::from_iters(vec![
+            Box::new(inner) as Box<dyn Seekable<Key = i32>>,
+            Box::new(LinearIterator::new(&tab3)),
+        ]);
+        let mut result = vec![];
+        while !join.at_end() {
+            result.push(join.key());
+            join.next();
+        }
+        assert_eq!(result, intersect(vec![&tab1, &tab2, &tab3]));
+
+        let mut inner = LeapFrogJoin::new(vec![&tab1, &tab2]);
+        let first = Seekable::key(&inner);
+        Seekable::seek(&mut inner, first);
+        assert_eq!(Seekable::key(&inner), first);
+    }
+
+    /// Records the will_need() hints it gets, and counts its seeks.
+    struct Hinted<'a> {
+        iter: LinearIterator<'a, i32>,
+        hints: Vec<i32>,
+        seeks: usize,
+        bounded: bool,
+    }
+
+    impl Seekable for Hinted<'_> {
+        type Key = i32;
+
+        fn key(&self) -> i32 {
+            self.iter.key()
+        }
+
+        fn next(&mut self) {
+            self.iter.next()
+        }
+
+        fn seek(&mut self, seek_key: i32) {
+            self.seeks += 1;
+            self.iter.seek(seek_key)
+        }
+
+        fn at_end(&self) -> bool {
+            self.iter.at_end()
+        }
+
+        fn will_need(&mut self, key: i32) {
+            self.hints.push(key);
+        }
+
+        fn upper_bound(&self) -> Option<i32> {
+            self.bounded.then(|| self.iter.upper_bound().unwrap())
+        }
+    }
+
+    fn hinted(keys: &[i32], bounded: bool) -> Hinted<'_> {
+        Hinted {
+            iter: LinearIterator::new(keys),
+            hints: vec![],
+            seeks: 0,
+            bounded,
+        }
+    }
+
+    #[test]
+    fn test_leapfrog_join_will_need() {
+        let (a, b) = ([1, 2, 50, 51], [2, 40, 51]);
+        let mut join = LeapFrogJoin::from_iters(vec![hinted(&a, false), hinted(&b, false)]);
+        while !join.at_end() {
+            join.next();
+        }
+        let iters = join.into_iters();
+        // Every seek that overshoots hints the other input.
+        assert_eq!(iters[0].hints, [51]);
+        assert_eq!(iters[1].hints, [50]);
+    }
+
+    /// Claims to hold a single key.
+    struct Lying<'a> {
+        iter: LinearIterator<'a, i32>,
+    }
+
+    impl Seekable for Lying<'_> {
+        type Key = i32;
+
+        fn key(&self) -> i32 {
+            self.iter.key()
+        }
+
+        fn next(&mut self) {
+            self.iter.next()
+        }
+
+        fn seek(&mut self, seek_key: i32) {
+            self.iter.seek(seek_key)
+        }
+
+        fn at_end(&self) -> bool {
+            self.iter.at_end()
+        }
+
+        fn estimate(&self) -> Option<usize> {
+            Some(1)
+        }
+    }
+
+    #[test]
+    fn test_leapfrog_join_step_limit() {
+        let even: Vec<i32> = (0..100).map(|x| x * 2).collect();
+        let odd: Vec<i32> = (0..100).map(|x| x * 2 + 1).collect();
+        let lying = |keys| Lying {
+            iter: LinearIterator::new(keys),
+        };
+        let join = LeapFrogJoin::from_iters(vec![lying(&even), lying(&odd)]);
+        assert!(join.at_end());
+        let stalled = join.stalled().unwrap();
+        assert_eq!((stalled.steps, stalled.limit), (5, 4));
+        assert!(stalled.to_string().contains("breaks the Seekable contract"));
+
+        let even = [&even[..], &[1000]].concat();
+        let odd = [&[0], &odd[..], &[1000]].concat();
+        let join = LeapFrogJoin::new(vec![&even, &odd]);
+        assert_eq!(join.into_keys().collect::<Vec<_>>(), [0, 1000]);
+        let mut join = LeapFrogJoin::new(vec![&even, &odd]).with_step_limit(10);
+        assert_eq!(join.key(), 0);
+        join.next();
+        assert!(join.at_end());
+        assert_eq!(join.stalled().map(|s| s.steps), Some(11));
+    }
+
+    #[test]
+    fn test_leapfrog_join_upper_bound() {
+        let (a, b) = ([1, 5, 1000, 2000], [1, 2, 3, 4, 5, 6]);
+        for bounded in [false, true] {
+            let mut join = LeapFrogJoin::from_iters(vec![hinted(&a, false), hinted(&b, bounded)]);
+            assert_eq!(join.upper_bound(), bounded.then_some(6));
+            let mut result = vec![];
+            while !join.at_end() {
+                result.push(join.key());
+                join.next();
+            }
+            assert_eq!(result, [1, 5]);
+            // Without the bound, the last key of `a` is sought in `b`.
+            let seeks = join.into_iters()[1].seeks;
+            assert_eq!(seeks, 2 - bounded as usize);
+        }
+        let mut join = LeapFrogJoin::from_iters(vec![hinted(&a, false), hinted(&b, true)]);
+        join.skip_to(7);
+        assert!(join.at_end());
+        assert_eq!(join.into_iters()[1].seeks, 0);
+    }
+
+    #[test]
+    fn test_leapfrog_join_skip_to() {
+        let tab1 = tab1();
+        let tab2 = tab2();
+        let tab3 = tab3();
+        let expected = intersect(vec![&tab1, &tab2, &tab3]);
+        for interval in [None, Some(1)] {
+            for key in 0..14 {
+                let mut join = LeapFrogJoin::new(vec![&tab1, &tab2, &tab3]);
+                if let Some(interval) = interval {
+                    join = join.with_reordering(interval);
+                }
+                join.skip_to(key);
+                let mut result = vec![];
+                while !join.at_end() {
+                    result.push(join.key());
+                    join.next();
+                }
+                let rest: Vec<i32> = expected.iter().copied().filter(|&k| k >= key).collect();
+                assert_eq!(result, rest, "skip_to({key})");
+                join.skip_to(key);
+                assert!(join.at_end());
+            }
+        }
+
+        let mut join = LeapFrogJoin::new(vec![&tab1, &tab2]);
+        join.skip_to(7);
+        join.skip_to(3);
+        assert_eq!(join.key(), 7);
+    }
+
+    #[test]
+    fn test_try_intersect() {
+        let (a, b) = ([1, 2, 3, 5], [2, 3, 4, 5]);
+        assert_eq!(try_intersect(vec![&a, &b]), Ok(vec![2, 3, 5]));
+        let (unsorted, duplicates) = ([3, 1], [1, 1, 2]);
+        assert_eq!(
+            try_intersect(vec![&a, &unsorted]),
+            Err(Unsorted { input: 1 })
+        );
+        let error = try_intersect(vec![&duplicates, &b]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "input 0 is not sorted strictly ascending"
+        );
+    }
+
+    #[test]
+    fn test_leapfrog_join_ordered() {
+        let tab1 = tab1();
+        let tab2 = tab2();
+        let tab3 = tab3();
+        let expected = intersect(vec![&tab1, &tab2, &tab3]);
+        for order in [[0, 1, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]] {
+            let iters = vec![
+                hinted(&tab1, false),
+                hinted(&tab2, false),
+                hinted(&tab3, false),
+            ];
+            let join = LeapFrogJoin::from_iters_ordered(iters, &order);
+            assert_eq!(join.into_keys().collect::<Vec<_>>(), expected);
+        }
+
+        // The other input is sought to the first key of the leading one.
+        let (lead, other) = ([5, 9], [1, 2, 5, 7, 9]);
+        let join = LeapFrogJoin::from_iters_ordered(
+            vec![hinted(&other, false), hinted(&lead, false)],
+            &[1, 0],
+        );
+        assert_eq!(join.key(), 5);
+        let iters = join.into_iters();
+        assert_eq!((iters[0].seeks, iters[1].seeks), (1, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Order is not a permutation of the iterators")]
+    fn test_leapfrog_join_ordered_permutation() {
+        let tab1 = tab1();
+        LeapFrogJoin::from_iters_ordered(vec![LinearIterator::new(&tab1)], &[0, 0]);
+    }
+
+    #[test]
+    fn test_leapfrog_join_len_hints() {
+        let tab1 = tab1();
+        let tab2 = tab2();
+        let expected = intersect(vec![&tab1, &tab2]);
+        let join = LeapFrogJoin::new(vec![&tab1, &tab2]);
+        assert_eq!(join.exact_len_hint(), None);
+        assert_eq!(join.estimate(), Some(tab2.len()));
+        assert_eq!(join.into_keys().size_hint(), (0, Some(tab2.len())));
+        assert!(
+            LeapFrogJoin::new(vec![&tab1, &tab2])
+                .into_exact_keys()
+                .is_none()
+        );
+
+        let mut keys = LeapFrogJoin::new(vec![&tab1, &tab2])
+            .with_len(expected.len())
+            .into_exact_keys()
+            .unwrap();
+        assert_eq!(keys.len(), expected.len());
+        keys.next();
+        assert_eq!(keys.len(), expected.len() - 1);
+        assert_eq!(keys.collect::<Vec<_>>(), expected[1..]);
+
+        let mut join = LeapFrogJoin::new(vec![&tab1]);
+        join.skip_to(5);
+        assert_eq!(join.into_exact_keys().unwrap().len(), 6);
+    }
 }
@@ -0,0 +1,228 @@
+//! Order-preserving byte encoding of composite keys.
+//!
+//! encode() turns a key like `(i64, String, bool)` into a byte string that
+//! compares, byte by byte, like the key itself, the so-called memcomparable
+//! format; decode() turns it back. Keys of any arity thus sort, dedup and
+//! compare as plain byte strings, e.g. in index files and byte-keyed
+//! backends.
+//!
+//! Integers are stored big-endian, signed ones with the sign bit flipped.
+//! Strings and byte strings are terminated by `00 01`, with every `00` byte
+//! inside them escaped as `00 ff`, so that a string sorts before all its
+//! extensions. Tuples concatenate their fields.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ends within a field.
+    Truncated,
+    /// A bool is neither 0 nor 1.
+    InvalidBool(u8),
+    /// A `00` byte in a string is followed by neither `01` nor `ff`.
+    InvalidEscape(u8),
+    /// A decoded string is not valid UTF-8.
+    InvalidUtf8,
+    /// Bytes are left after the key.
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "key is truncated"),
+            DecodeError::InvalidBool(b) => write!(f, "invalid bool byte {b:#04x}"),
+            DecodeError::InvalidEscape(b) => write!(f, "invalid escape byte {b:#04x}"),
+            DecodeError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            DecodeError::TrailingBytes(n) => write!(f, "{n} bytes left after the key"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// KeyPart is a value, or tuple of values, with a memcomparable encoding.
+pub trait KeyPart: Sized {
+    /// Appends the encoding of the value to `out`.
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    /// Decodes a value from the start of `input`, and advances past it.
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// Returns the memcomparable encoding of `key`.
+pub fn encode<T: KeyPart>(key: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    key.encode_to(&mut out);
+    out
+}
+
+/// Decodes a key encoded by encode(), which must span all of `bytes`.
+pub fn decode<T: KeyPart>(mut bytes: &[u8]) -> Result<T, DecodeError> {
+    let key = T::decode_from(&mut bytes)?;
+    match bytes.len() {
+        0 => Ok(key),
+        n => Err(DecodeError::TrailingBytes(n)),
+    }
+}
+
+fn take<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+    let (head, tail) = input.split_first_chunk().ok_or(DecodeError::Truncated)?;
+    *input = tail;
+    Ok(*head)
+}
+
+macro_rules! impl_key_part_unsigned {
+    ($($t:ty),*) => {$(
+        impl KeyPart for $t {
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+
+            fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                Ok(<$t>::from_be_bytes(take(input)?))
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_key_part_signed {
+    ($($t:ty),*) => {$(
+        impl KeyPart for $t {
+            // Flipping the sign bit moves negative values below positive ones.
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&(*self ^ <$t>::MIN).to_be_bytes());
+            }
+
+            fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                Ok(<$t>::from_be_bytes(take(input)?) ^ <$t>::MIN)
+            }
+        }
+    )*};
+}
+
+impl_key_part_unsigned!(u8, u16, u32, u64);
+impl_key_part_signed!(i8, i16, i32, i64);
+
+impl KeyPart for bool {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match take::<1>(input)? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [b] => Err(DecodeError::InvalidBool(b)),
+        }
+    }
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for &b in bytes {
+        out.push(b);
+        if b == 0 {
+            out.push(0xff);
+        }
+    }
+    out.extend_from_slice(&[0, 1]);
+}
+
+impl KeyPart for Vec<u8> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out);
+    }
+
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = Vec::new();
+        loop {
+            match take::<1>(input)? {
+                [0] => match take::<1>(input)? {
+                    [1] => return Ok(bytes),
+                    [0xff] => bytes.push(0),
+                    [b] => return Err(DecodeError::InvalidEscape(b)),
+                },
+                [b] => bytes.push(b),
+            }
+        }
+    }
+}
+
+impl KeyPart for String {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out);
+    }
+
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        String::from_utf8(Vec::decode_from(input)?).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+macro_rules! impl_key_part_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: KeyPart),+> KeyPart for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_to(out);)+
+            }
+
+            fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                Ok(($($name::decode_from(input)?,)+))
+            }
+        }
+    };
+}
+
+impl_key_part_tuple!(A);
+impl_key_part_tuple!(A, B);
+impl_key_part_tuple!(A, B, C);
+impl_key_part_tuple!(A, B, C, D);
+impl_key_part_tuple!(A, B, C, D, E);
+impl_key_part_tuple!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_preserved() {
+        let mut keys: Vec<(i64, String, bool)> = vec![];
+        for i in [i64::MIN, -256, -1, 0, 1, 255, i64::MAX] {
+            for s in ["", "\0", "\0\0", "a", "a\0", "a\0b", "ab", "b", "\u{ff}"] {
+                for b in [false, true] {
+                    keys.push((i, s.to_string(), b));
+                }
+            }
+        }
+        let mut encoded: Vec<Vec<u8>> = keys.iter().map(encode).collect();
+        encoded.sort();
+        keys.sort();
+        let decoded: Vec<(i64, String, bool)> =
+            encoded.iter().map(|e| decode(e).unwrap()).collect();
+        assert_eq!(decoded, keys);
+
+        let pairs = [(-3i8, 7u16), (-3, 8), (0, 0), (2, 1)];
+        let encoded: Vec<Vec<u8>> = pairs.iter().map(encode).collect();
+        assert!(encoded.is_sorted());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let bytes = encode(&(5u32, "x".to_string()));
+        assert_eq!(
+            decode::<(u32, String)>(&bytes[..5]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(decode::<(u32,)>(&bytes), Err(DecodeError::TrailingBytes(3)));
+        assert_eq!(decode::<bool>(&[2]), Err(DecodeError::InvalidBool(2)));
+        assert_eq!(
+            decode::<Vec<u8>>(&[1, 0, 7]),
+            Err(DecodeError::InvalidEscape(7))
+        );
+        assert_eq!(
+            decode::<String>(&[0xff, 0, 1]),
+            Err(DecodeError::InvalidUtf8)
+        );
+    }
+}
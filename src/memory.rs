@@ -0,0 +1,589 @@
+//! Memory accounting and budgets.
+//!
+//! A MemoryBudget counts the bytes reserved for tries, build buffers and
+//! result sets against an optional limit. Reservations are RAII guards that
+//! give their bytes back when dropped, so whatever holds the memory also
+//! holds the reservation. When a reservation would exceed the limit, it
+//! fails with a MemoryError, and the caller degrades gracefully: a Database
+//! refuses the relation or permutation, and a ResultSet spills the rows
+//! that do not fit to a temporary file, if its Overflow policy allows.
+//!
+//! An embedder running short of memory, e.g. in a sidecar with a tight
+//! cgroup limit, signals the MemoryPressure of the budget, or registers
+//! MemoryPressure::callback() with whatever notifies it. A signal runs the
+//! listeners registered with MemoryPressure::on_signal(), e.g. of a
+//! BlockCache dropping its blocks, and makes the work in flight degrade: a
+//! ResultSet that may spill writes the rows it holds to its file and spills
+//! the rest, and a pipeline sort spills its current run and halves the rows
+//! of the runs after it.
+//!
+//! Spilling needs the `fs` feature; without it, e.g. on
+//! wasm32-unknown-unknown, it fails with io::ErrorKind::Unsupported.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::replay::ReplayKey;
+
+/// What memory is used for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// Relations and their permutations.
+    Tries,
+    /// Temporary buffers, e.g. tuples staged while building a trie.
+    Buffers,
+    /// Collected query results.
+    Results,
+}
+
+const CATEGORIES: [Category; 3] = [Category::Tries, Category::Buffers, Category::Results];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MemoryError {
+    /// Reserving `requested` bytes for `category` would exceed the limit,
+    /// of which `available` bytes are left.
+    Exceeded {
+        category: Category,
+        requested: usize,
+        available: usize,
+    },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::Exceeded {
+                category,
+                requested,
+                available,
+            } => write!(
+                f,
+                "memory budget exceeded: {requested} bytes requested for {category:?}, \
+                 {available} available"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+/// A listener of MemoryPressure, kept while it returns true.
+type Listener = Box<dyn Fn() -> bool + Send + Sync>;
+
+/// MemoryPressure is signaled when the host runs short of memory, see the
+/// module docs.
+#[derive(Default)]
+pub struct MemoryPressure {
+    signals: AtomicUsize,
+    listeners: Mutex<Vec<Listener>>,
+}
+
+impl MemoryPressure {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Registers `listener` to run on every signal until it returns false,
+    /// e.g. once what it frees is gone.
+    pub fn on_signal(&self, listener: impl Fn() -> bool + Send + Sync + 'static) {
+        self.listeners.lock().unwrap().push(Box::new(listener));
+    }
+
+    /// Signals memory pressure to the listeners and the work in flight.
+    /// The listeners run without the lock held, so they may register
+    /// listeners or signal themselves.
+    pub fn signal(&self) {
+        self.signals.fetch_add(1, Ordering::Relaxed);
+        let mut listeners = std::mem::take(&mut *self.listeners.lock().unwrap());
+        listeners.retain(|listener| listener());
+        // Keep the listeners registered meanwhile after the older ones.
+        let mut registered = self.listeners.lock().unwrap();
+        listeners.append(&mut registered);
+        *registered = listeners;
+    }
+
+    /// Number of signals so far, which work in flight compares to the
+    /// number when it started.
+    pub fn signals(&self) -> usize {
+        self.signals.load(Ordering::Relaxed)
+    }
+
+    /// Returns a callback that signals the pressure, for the embedder to
+    /// register with its host. It does not keep the pressure alive.
+    pub fn callback(self: &Arc<Self>) -> impl Fn() + Send + Sync + 'static {
+        let pressure = Arc::downgrade(self);
+        move || {
+            if let Some(pressure) = pressure.upgrade() {
+                pressure.signal();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MemoryPressure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryPressure")
+            .field("signals", &self.signals())
+            .field("listeners", &self.listeners.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// MemoryBudget tracks the memory reserved per category. It is shared by
+/// everything that accounts against it, see MemoryBudget::reserve().
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: Option<usize>,
+    used: AtomicUsize,
+    peak: AtomicUsize,
+    by_category: [AtomicUsize; 3],
+    pressure: Arc<MemoryPressure>,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes.
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self::with_limit(Some(limit)))
+    }
+
+    /// Creates a budget that only accounts, without a limit.
+    pub fn unlimited() -> Arc<Self> {
+        Arc::new(Self::with_limit(None))
+    }
+
+    fn with_limit(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            by_category: Default::default(),
+            pressure: MemoryPressure::new(),
+        }
+    }
+
+    /// Reserves `bytes` for `category`, failing if the limit would be
+    /// exceeded.
+    pub fn reserve(
+        self: &Arc<Self>,
+        category: Category,
+        bytes: usize,
+    ) -> Result<Reservation, MemoryError> {
+        self.acquire(category, bytes)?;
+        Ok(Reservation {
+            budget: self.clone(),
+            category,
+            bytes,
+        })
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Bytes reserved in total.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Bytes reserved for `category`.
+    pub fn used_by(&self, category: Category) -> usize {
+        self.by_category[index(category)].load(Ordering::Relaxed)
+    }
+
+    /// The most bytes reserved at any time.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Bytes left, or None without a limit.
+    pub fn available(&self) -> Option<usize> {
+        self.limit.map(|limit| limit.saturating_sub(self.used()))
+    }
+
+    /// The pressure signal of everything accounting against the budget.
+    pub fn pressure(&self) -> &Arc<MemoryPressure> {
+        &self.pressure
+    }
+
+    fn acquire(&self, category: Category, bytes: usize) -> Result<(), MemoryError> {
+        let exceeded = |used: usize| MemoryError::Exceeded {
+            category,
+            requested: bytes,
+            available: self.limit.map_or(usize::MAX, |l| l.saturating_sub(used)),
+        };
+        let result = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                let total = used.checked_add(bytes)?;
+                self.limit
+                    .is_none_or(|limit| total <= limit)
+                    .then_some(total)
+            });
+        let used = result.map_err(exceeded)? + bytes;
+        self.peak.fetch_max(used, Ordering::Relaxed);
+        self.by_category[index(category)].fetch_add(bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn release(&self, category: Category, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+        self.by_category[index(category)].fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+fn index(category: Category) -> usize {
+    CATEGORIES.iter().position(|&c| c == category).unwrap()
+}
+
+/// Reservation holds bytes of a MemoryBudget until dropped.
+#[derive(Debug)]
+pub struct Reservation {
+    budget: Arc<MemoryBudget>,
+    category: Category,
+    bytes: usize,
+}
+
+impl Reservation {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn category(&self) -> Category {
+        self.category
+    }
+
+    /// Reserves `bytes` more.
+    pub fn grow(&mut self, bytes: usize) -> Result<(), MemoryError> {
+        self.budget.acquire(self.category, bytes)?;
+        self.bytes += bytes;
+        Ok(())
+    }
+
+    /// Gives back up to `bytes`.
+    pub fn shrink(&mut self, bytes: usize) {
+        let bytes = bytes.min(self.bytes);
+        self.budget.release(self.category, bytes);
+        self.bytes -= bytes;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.release(self.category, self.bytes);
+    }
+}
+
+/// What a ResultSet does with rows that exceed the budget.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Fail with a MemoryError.
+    Error,
+    /// Write them to a temporary file in the directory.
+    Spill(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum ResultSetError {
+    Memory(MemoryError),
+    Io(io::Error),
+}
+
+impl fmt::Display for ResultSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResultSetError::Memory(e) => write!(f, "{e}"),
+            ResultSetError::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ResultSetError {}
+
+impl From<MemoryError> for ResultSetError {
+    fn from(e: MemoryError) -> Self {
+        ResultSetError::Memory(e)
+    }
+}
+
+impl From<io::Error> for ResultSetError {
+    fn from(e: io::Error) -> Self {
+        ResultSetError::Io(e)
+    }
+}
+
+/// A temporary file, removed when dropped.
+pub(crate) struct SpillFile {
+    pub(crate) path: PathBuf,
+    pub(crate) writer: BufWriter<File>,
+}
+
+impl SpillFile {
+    /// Creates a new temporary file in `dir`. Fails with
+    /// io::ErrorKind::Unsupported without the `fs` feature.
+    pub(crate) fn create(dir: &Path) -> io::Result<Self> {
+        if cfg!(not(feature = "fs")) {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("leapfrog-spill-{}-{n}", std::process::id()));
+        let writer = BufWriter::new(File::create(&path)?);
+        Ok(Self { path, writer })
+    }
+}
+
+/// Returns the directory to spill to unless another is given: the
+/// temporary directory of the host, which targets without the `fs` feature
+/// may lack.
+pub(crate) fn spill_dir() -> PathBuf {
+    if cfg!(feature = "fs") {
+        std::env::temp_dir()
+    } else {
+        PathBuf::new()
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// ResultSet collects result rows of the same arity against a budget. Rows
+/// that do not fit are spilled or rejected, depending on its Overflow
+/// policy; ResultSet::into_rows() returns all rows in the order they were
+/// pushed.
+pub struct ResultSet<K> {
+    rows: Vec<Vec<K>>,
+    reservation: Reservation,
+    overflow: Overflow,
+    spill: Option<SpillFile>,
+    spilled: usize,
+    arity: usize,
+    /// The pressure signals of the budget seen so far.
+    signals: usize,
+}
+
+impl<K: ReplayKey> ResultSet<K> {
+    pub fn new(budget: &Arc<MemoryBudget>, overflow: Overflow) -> Self {
+        Self {
+            rows: Vec::new(),
+            reservation: budget.reserve(Category::Results, 0).unwrap(),
+            overflow,
+            spill: None,
+            spilled: 0,
+            arity: 0,
+            signals: budget.pressure().signals(),
+        }
+    }
+
+    /// Collects all rows of `rows`.
+    pub fn collect(
+        budget: &Arc<MemoryBudget>,
+        overflow: Overflow,
+        rows: impl IntoIterator<Item = Vec<K>>,
+    ) -> Result<Self, ResultSetError> {
+        let mut set = Self::new(budget, overflow);
+        for row in rows {
+            set.push(row)?;
+        }
+        Ok(set)
+    }
+
+    pub fn push(&mut self, row: Vec<K>) -> Result<(), ResultSetError> {
+        self.arity = row.len();
+        let signals = self.reservation.budget.pressure().signals();
+        if signals != self.signals {
+            self.signals = signals;
+            self.relieve()?;
+        }
+        if self.spill.is_none() {
+            let bytes = size_of::<Vec<K>>() + size_of_val(row.as_slice());
+            match self.reservation.grow(bytes) {
+                Ok(()) => {
+                    self.rows.push(row);
+                    return Ok(());
+                }
+                Err(e) => match &self.overflow {
+                    Overflow::Error => return Err(e.into()),
+                    Overflow::Spill(dir) => self.spill = Some(SpillFile::create(dir)?),
+                },
+            }
+        }
+        let spill = self.spill.as_mut().unwrap();
+        for key in row {
+            spill.writer.write_all(&key.encode().to_le_bytes())?;
+        }
+        self.spilled += 1;
+        Ok(())
+    }
+
+    /// Gives back the memory of the rows held, spilling them if the
+    /// Overflow policy allows and no rows were spilled yet. The rows after
+    /// them spill as well, so they stay in order.
+    fn relieve(&mut self) -> Result<(), ResultSetError> {
+        let (Overflow::Spill(dir), None) = (&self.overflow, &self.spill) else {
+            self.rows.shrink_to_fit();
+            return Ok(());
+        };
+        let spill = self.spill.insert(SpillFile::create(dir)?);
+        for key in self.rows.iter().flatten() {
+            spill.writer.write_all(&key.encode().to_le_bytes())?;
+        }
+        self.spilled += self.rows.len();
+        self.rows = Vec::new();
+        self.reservation.shrink(self.reservation.bytes());
+        Ok(())
+    }
+
+    /// Number of rows.
+    pub fn len(&self) -> usize {
+        self.rows.len() + self.spilled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of rows that were spilled.
+    pub fn spilled(&self) -> usize {
+        self.spilled
+    }
+
+    /// Returns all rows, reading spilled ones back.
+    pub fn into_rows(mut self) -> Result<Vec<Vec<K>>, ResultSetError> {
+        let mut rows = std::mem::take(&mut self.rows);
+        if let Some(mut spill) = self.spill.take() {
+            spill.writer.flush()?;
+            let mut reader = BufReader::new(File::open(&spill.path)?);
+            let mut bytes = [0; 8];
+            for _ in 0..self.spilled {
+                let mut row = Vec::with_capacity(self.arity);
+                for _ in 0..self.arity {
+                    reader.read_exact(&mut bytes)?;
+                    row.push(K::decode(u64::from_le_bytes(bytes)));
+                }
+                rows.push(row);
+            }
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = MemoryBudget::new(100);
+        let mut tries = budget.reserve(Category::Tries, 60).unwrap();
+        assert_eq!(
+            budget.reserve(Category::Results, 50).unwrap_err(),
+            MemoryError::Exceeded {
+                category: Category::Results,
+                requested: 50,
+                available: 40
+            }
+        );
+        let results = budget.reserve(Category::Results, 40).unwrap();
+        assert_eq!(budget.used(), 100);
+        assert!(tries.grow(1).is_err());
+        tries.shrink(20);
+        assert_eq!(budget.used_by(Category::Tries), 40);
+        drop(results);
+        assert_eq!(budget.used(), 40);
+        assert_eq!(budget.available(), Some(60));
+        assert_eq!(budget.peak(), 100);
+        drop(tries);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_result_set_overflow() {
+        let row_size = size_of::<Vec<u32>>() + 8;
+        let rows = (0..10u32).map(|i| vec![i, 100 - i]);
+        let budget = MemoryBudget::new(row_size * 4);
+        let error = ResultSet::collect(&budget, Overflow::Error, rows.clone()).err();
+        assert!(matches!(error, Some(ResultSetError::Memory(_))));
+        assert_eq!(budget.used(), 0);
+
+        let dir = std::env::temp_dir();
+        let set = ResultSet::collect(&budget, Overflow::Spill(dir), rows.clone()).unwrap();
+        assert_eq!(set.len(), 10);
+        assert_eq!(set.spilled(), 6);
+        assert_eq!(budget.used_by(Category::Results), row_size * 4);
+        assert_eq!(set.into_rows().unwrap(), rows.collect::<Vec<_>>());
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_memory_pressure() {
+        let budget = MemoryBudget::unlimited();
+        let pressure = budget.pressure();
+        let cache = Arc::new(crate::cache::BlockCache::new(100));
+        let file = cache.file_id("file");
+        cache.insert(file, 0, vec![1; 10].into());
+        cache.clear_on(pressure);
+        let once = Arc::new(AtomicUsize::new(0));
+        let counter = once.clone();
+        pressure.on_signal(move || counter.fetch_add(1, Ordering::Relaxed) == usize::MAX);
+
+        let dir = std::env::temp_dir();
+        let mut set = ResultSet::new(&budget, Overflow::Spill(dir));
+        for i in 0..4u32 {
+            set.push(vec![i]).unwrap();
+        }
+        assert!(budget.used() > 0);
+        let signal = pressure.callback();
+        signal();
+        signal();
+        assert_eq!(pressure.signals(), 2);
+        assert_eq!(once.load(Ordering::Relaxed), 1);
+        assert!(!cache.contains(file, 0));
+
+        // The rows held are spilled with the next one.
+        set.push(vec![4]).unwrap();
+        assert_eq!(set.spilled(), 5);
+        assert_eq!(budget.used(), 0);
+        let rows: Vec<Vec<u32>> = (0..5).map(|i| vec![i]).collect();
+        assert_eq!(set.into_rows().unwrap(), rows);
+
+        // The callback does not keep the pressure alive.
+        let signal = MemoryPressure::new().callback();
+        signal();
+    }
+
+    /// Registers a listener that counts its calls, signals again on its
+    /// first call, and re-registers itself instead of staying registered.
+    fn register(pressure: &Arc<MemoryPressure>, calls: Arc<AtomicUsize>) {
+        let weak = Arc::downgrade(pressure);
+        pressure.on_signal(move || {
+            let pressure = weak.upgrade().unwrap();
+            if calls.fetch_add(1, Ordering::Relaxed) == 0 {
+                pressure.signal();
+            }
+            register(&pressure, calls.clone());
+            false
+        });
+    }
+
+    #[test]
+    fn test_memory_pressure_reentrant_listener() {
+        let pressure = MemoryPressure::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        register(&pressure, calls.clone());
+        pressure.signal();
+        // The nested signal found no listener, as the only one was running.
+        assert_eq!((pressure.signals(), calls.load(Ordering::Relaxed)), (2, 1));
+        pressure.signal();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert_eq!(pressure.listeners.lock().unwrap().len(), 1);
+    }
+}
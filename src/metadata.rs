@@ -0,0 +1,357 @@
+//! Declared properties of relations.
+//!
+//! Metadata declares what is known about the rows of a relation beyond its
+//! arity: the columns they are sorted by, sets of columns whose values are
+//! unique, and functional dependencies between columns. Planners exploit it
+//! to skip work, and Metadata::check() verifies it against the data.
+//!
+//! - Metadata::build() skips sorting rows that arrive sorted by a prefix of
+//!   the attributes determining all of them, and skips deduplicating them if
+//!   that prefix contains a unique key.
+//! - Metadata::presorted() tells which attribute orders of a relation its
+//!   rows already have. Database builds such permutations by copying the
+//!   columns instead of sorting them.
+//!
+//! Attributes are determined by the closure of a set of attributes: a
+//! unique key determines all attributes, and a dependency `from -> to`
+//! determines `to` once `from` is determined.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::trie::TrieRelation;
+
+/// The attributes `to` are a function of the attributes `from`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FunctionalDependency {
+    pub from: Vec<usize>,
+    pub to: Vec<usize>,
+}
+
+impl fmt::Display for FunctionalDependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} -> {:?}", self.from, self.to)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MetadataError {
+    /// An attribute is not one of the relation's.
+    Attribute { attribute: usize, arity: usize },
+    /// A row sorts before the row before it.
+    Unsorted { row: usize },
+    /// Two rows agree on the unique attributes `key`.
+    Duplicate {
+        key: Vec<usize>,
+        rows: (usize, usize),
+    },
+    /// Two rows agree on the attributes a dependency is from, but not on
+    /// those it determines.
+    Dependency {
+        dependency: FunctionalDependency,
+        rows: (usize, usize),
+    },
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataError::Attribute { attribute, arity } => {
+                write!(f, "attribute {attribute} is out of range for arity {arity}")
+            }
+            MetadataError::Unsorted { row } => write!(f, "row {row} is out of order"),
+            MetadataError::Duplicate { key, rows } => write!(
+                f,
+                "rows {} and {} agree on the unique attributes {key:?}",
+                rows.0, rows.1
+            ),
+            MetadataError::Dependency { dependency, rows } => write!(
+                f,
+                "rows {} and {} violate the dependency {dependency}",
+                rows.0, rows.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+/// Metadata of a relation. The default declares nothing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    sorted_by: Vec<usize>,
+    unique: Vec<Vec<usize>>,
+    dependencies: Vec<FunctionalDependency>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares the rows sorted lexicographically by `attributes`.
+    pub fn sorted_by(mut self, attributes: &[usize]) -> Self {
+        self.sorted_by = attributes.to_vec();
+        self
+    }
+
+    /// Declares that no two rows agree on `attributes`.
+    pub fn unique(mut self, attributes: &[usize]) -> Self {
+        self.unique.push(attributes.to_vec());
+        self
+    }
+
+    /// Declares that rows agreeing on `from` agree on `to`.
+    pub fn dependency(mut self, from: &[usize], to: &[usize]) -> Self {
+        self.dependencies.push(FunctionalDependency {
+            from: from.to_vec(),
+            to: to.to_vec(),
+        });
+        self
+    }
+
+    pub fn sorted_attributes(&self) -> &[usize] {
+        &self.sorted_by
+    }
+
+    pub fn unique_keys(&self) -> &[Vec<usize>] {
+        &self.unique
+    }
+
+    pub fn dependencies(&self) -> &[FunctionalDependency] {
+        &self.dependencies
+    }
+
+    /// Checks that all attributes are below `arity`.
+    pub fn check_arity(&self, arity: usize) -> Result<(), MetadataError> {
+        let attributes = self.sorted_by.iter().chain(self.unique.iter().flatten());
+        let dependencies = self
+            .dependencies
+            .iter()
+            .flat_map(|d| d.from.iter().chain(&d.to));
+        match attributes.chain(dependencies).find(|&&a| a >= arity) {
+            Some(&attribute) => Err(MetadataError::Attribute { attribute, arity }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns, per attribute below `arity`, whether `attributes` determine
+    /// it.
+    pub fn closure(&self, arity: usize, attributes: &[usize]) -> Vec<bool> {
+        let mut determined = vec![false; arity];
+        for &a in attributes {
+            determined[a] = true;
+        }
+        let covered =
+            |determined: &[bool], attributes: &[usize]| attributes.iter().all(|&a| determined[a]);
+        loop {
+            let before = determined.iter().filter(|&&d| d).count();
+            if self.unique.iter().any(|key| covered(&determined, key)) {
+                determined.fill(true);
+            }
+            for dependency in &self.dependencies {
+                if covered(&determined, &dependency.from) {
+                    for &a in &dependency.to {
+                        determined[a] = true;
+                    }
+                }
+            }
+            if determined.iter().filter(|&&d| d).count() == before {
+                return determined;
+            }
+        }
+    }
+
+    /// Checks whether `attributes` determine all attributes below `arity`.
+    pub fn determines_all(&self, arity: usize, attributes: &[usize]) -> bool {
+        self.closure(arity, attributes).into_iter().all(|d| d)
+    }
+
+    /// Checks whether `attributes` determine a unique key, so that no two
+    /// rows agree on them.
+    pub fn is_key(&self, arity: usize, attributes: &[usize]) -> bool {
+        let determined = self.closure(arity, attributes);
+        self.unique
+            .iter()
+            .any(|key| key.iter().all(|&a| determined[a]))
+    }
+
+    /// Checks whether the rows of a relation of distinct rows, sorted in
+    /// attribute order and by the declared attributes, are also sorted by
+    /// `order`. That holds if a prefix of `order` starts either sort order
+    /// and determines all attributes.
+    pub fn presorted(&self, order: &[usize]) -> bool {
+        let arity = order.len();
+        let identity: Vec<usize> = (0..arity).collect();
+        [&identity, &self.sorted_by].into_iter().any(|sorted| {
+            let common = order.iter().zip(sorted).take_while(|(a, b)| a == b).count();
+            common == arity || self.determines_all(arity, &order[..common])
+        })
+    }
+
+    /// Creates a relation from tuples that satisfy the metadata, like
+    /// TrieRelation::new(). Skips sorting if the declared order starts with
+    /// attributes 0, 1, ... that determine all attributes, and then also
+    /// skips dropping duplicates if they contain a unique key.
+    pub fn build<K: Ord + Copy, T: AsRef<[K]>>(
+        &self,
+        arity: usize,
+        tuples: impl IntoIterator<Item = T>,
+    ) -> TrieRelation<K> {
+        let common = self
+            .sorted_by
+            .iter()
+            .enumerate()
+            .take_while(|&(i, &a)| i == a)
+            .count()
+            .min(arity);
+        let prefix: Vec<usize> = (0..common).collect();
+        if common == 0 || !self.determines_all(arity, &prefix) {
+            return TrieRelation::new(arity, tuples);
+        }
+        let mut rows: Vec<Vec<K>> = Vec::new();
+        for tuple in tuples {
+            let tuple = tuple.as_ref();
+            assert_eq!(tuple.len(), arity, "Tuple has wrong arity");
+            if self.is_key(arity, &prefix) || rows.last().is_none_or(|r| r[..] != *tuple) {
+                rows.push(tuple.to_vec());
+            }
+        }
+        let mut keys = Vec::with_capacity(arity * rows.len());
+        for c in 0..arity {
+            keys.extend(rows.iter().map(|r| r[c]));
+        }
+        TrieRelation::from_arena(arity, keys)
+    }
+
+    /// Checks the metadata against `rows` of length `arity`: the declared
+    /// order, the unique keys and the dependencies.
+    pub fn check<K: Ord, T: AsRef<[K]>>(
+        &self,
+        arity: usize,
+        rows: &[T],
+    ) -> Result<(), MetadataError> {
+        self.check_by(arity, rows.len(), |r, a| &rows[r].as_ref()[a])
+    }
+
+    /// Checks the metadata against a relation, like check().
+    pub fn check_relation<K: Ord + Copy>(
+        &self,
+        relation: &TrieRelation<K>,
+    ) -> Result<(), MetadataError> {
+        self.check_by(relation.arity(), relation.len(), |r, a| {
+            &relation.column(a)[r]
+        })
+    }
+
+    fn check_by<'k, K: Ord + 'k>(
+        &self,
+        arity: usize,
+        len: usize,
+        value: impl Fn(usize, usize) -> &'k K,
+    ) -> Result<(), MetadataError> {
+        self.check_arity(arity)?;
+        let cmp = |a: usize, b: usize, attributes: &[usize]| {
+            attributes
+                .iter()
+                .map(|&c| value(a, c).cmp(value(b, c)))
+                .find(|o| o.is_ne())
+                .unwrap_or(Ordering::Equal)
+        };
+        if let Some(row) = (1..len).find(|&r| cmp(r - 1, r, &self.sorted_by).is_gt()) {
+            return Err(MetadataError::Unsorted { row });
+        }
+        // Sort the row numbers by the attributes that are from, so that rows
+        // agreeing on them are adjacent.
+        let adjacent = |from: &[usize]| {
+            let mut rows: Vec<usize> = (0..len).collect();
+            rows.sort_by(|&a, &b| cmp(a, b, from).then(a.cmp(&b)));
+            rows.windows(2)
+                .map(|w| (w[0], w[1]))
+                .filter(|&(a, b)| cmp(a, b, from).is_eq())
+                .collect::<Vec<_>>()
+        };
+        for key in &self.unique {
+            if let Some(&rows) = adjacent(key).first() {
+                return Err(MetadataError::Duplicate {
+                    key: key.clone(),
+                    rows,
+                });
+            }
+        }
+        for dependency in &self.dependencies {
+            let violation = adjacent(&dependency.from)
+                .into_iter()
+                .find(|&(a, b)| cmp(a, b, &dependency.to).is_ne());
+            if let Some(rows) = violation {
+                return Err(MetadataError::Dependency {
+                    dependency: dependency.clone(),
+                    rows,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closure_and_presorted() {
+        // events(id, time, kind, payload): ids are unique and increase with
+        // time, and the kind is a function of the payload.
+        let metadata = Metadata::new()
+            .sorted_by(&[1, 0])
+            .unique(&[0])
+            .dependency(&[3], &[2]);
+        assert_eq!(metadata.closure(4, &[3]), [false, false, true, true]);
+        assert!(metadata.determines_all(4, &[0]));
+        assert!(!metadata.is_key(4, &[1, 2]));
+        assert!(metadata.presorted(&[0, 3, 2, 1]));
+        assert!(metadata.presorted(&[1, 0, 3, 2]));
+        assert!(!metadata.presorted(&[1, 2, 0, 3]));
+        assert!(!metadata.presorted(&[3, 2, 1, 0]));
+        assert!(Metadata::new().presorted(&[0, 1]));
+        assert!(!Metadata::new().presorted(&[1, 0]));
+        assert_eq!(
+            metadata.check_arity(3),
+            Err(MetadataError::Attribute {
+                attribute: 3,
+                arity: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_and_check() {
+        let rows = [[1, 10, 7], [2, 10, 7], [2, 10, 7], [3, 20, 8]];
+        let metadata = Metadata::new().sorted_by(&[0, 1]).dependency(&[0], &[1, 2]);
+        metadata.check(3, &rows).unwrap();
+        let relation = metadata.build(3, rows);
+        assert_eq!(relation, TrieRelation::new(3, rows));
+        metadata.check_relation(&relation).unwrap();
+
+        let unique = Metadata::new().unique(&[0]);
+        assert_eq!(
+            unique.check(3, &rows),
+            Err(MetadataError::Duplicate {
+                key: vec![0],
+                rows: (1, 2)
+            })
+        );
+        let unsorted = [[2, 10, 7], [1, 10, 7]];
+        assert_eq!(
+            metadata.check(3, &unsorted),
+            Err(MetadataError::Unsorted { row: 1 })
+        );
+        let by_time = Metadata::new().dependency(&[1], &[2]);
+        by_time.check(3, &rows).unwrap();
+        let mixed = [[1, 10, 7], [2, 10, 8]];
+        assert!(matches!(
+            by_time.check(3, &mixed),
+            Err(MetadataError::Dependency { rows: (0, 1), .. })
+        ));
+    }
+}
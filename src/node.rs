@@ -0,0 +1,31 @@
+//! Node.js bindings via N-API, built with napi-rs and `--features node`.
+//!
+//! ```js
+//! const { intersectInt32 } = require('./leapfrog.node');
+//! intersectInt32([new Int32Array([1, 2, 3]), new Int32Array([2, 3, 4])]); // Int32Array [2, 3]
+//! ```
+//!
+//! The join reads the typed arrays' buffers in place; only the result is
+//! allocated.
+
+use napi::bindgen_prelude::{BigInt64Array, Int32Array};
+use napi::{Error, Result};
+use napi_derive::napi;
+
+use crate::try_intersect;
+
+/// Intersects strictly ascending Int32Arrays.
+#[napi(js_name = "intersectInt32")]
+pub fn intersect_int32(sources: Vec<Int32Array>) -> Result<Int32Array> {
+    intersect_sorted(sources.iter().map(|s| &s[..]).collect()).map(Int32Array::new)
+}
+
+/// Intersects strictly ascending BigInt64Arrays.
+#[napi(js_name = "intersectBigInt64")]
+pub fn intersect_big_int64(sources: Vec<BigInt64Array>) -> Result<BigInt64Array> {
+    intersect_sorted(sources.iter().map(|s| &s[..]).collect()).map(BigInt64Array::new)
+}
+
+fn intersect_sorted<T: Ord + Copy>(sources: Vec<&[T]>) -> Result<Vec<T>> {
+    try_intersect(sources).map_err(|e| Error::from_reason(e.to_string()))
+}
@@ -0,0 +1,149 @@
+//! NUMA placement of parallel work.
+//!
+//! On machines with several sockets, memory is attached to one NUMA node
+//! each, and a thread reading memory of another node crosses the
+//! interconnect. Linux places a page on the node of the thread that touches
+//! it first, so a partition of parallel work stays node-local if it runs on
+//! a thread pinned to a node and allocates its buffers there.
+//!
+//! NumaTopology::detect() reads the nodes and their CPUs from sysfs, and
+//! NumaTopology::pin() restricts the calling thread to the CPUs of a node.
+//! TrieRelation::merge_shards_numa() merges key ranges on one worker thread
+//! pinned to each node, each node taking an equal share of consecutive ranges.
+
+use std::io;
+use std::path::Path;
+
+/// NumaTopology is the CPUs of every NUMA node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumaTopology {
+    nodes: Vec<Vec<usize>>,
+}
+
+impl NumaTopology {
+    /// Creates a topology of the given CPUs per node. Nodes without CPUs
+    /// are dropped.
+    pub fn from_nodes(nodes: Vec<Vec<usize>>) -> Self {
+        let nodes: Vec<Vec<usize>> = nodes.into_iter().filter(|cpus| !cpus.is_empty()).collect();
+        assert!(!nodes.is_empty(), "Topology has no CPUs");
+        Self { nodes }
+    }
+
+    /// Reads the topology of the host, or returns a single node of all
+    /// CPUs where sysfs does not describe one.
+    pub fn detect() -> Self {
+        Self::read_from(Path::new("/sys/devices/system/node")).unwrap_or_else(|| {
+            let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+            Self::from_nodes(vec![(0..cpus).collect()])
+        })
+    }
+
+    /// Reads the `node<N>/cpulist` files below `dir`.
+    fn read_from(dir: &Path) -> Option<Self> {
+        let mut nodes = Vec::new();
+        for entry in std::fs::read_dir(dir).ok()? {
+            let entry = entry.ok()?;
+            let name = entry.file_name();
+            let Some(id) = name.to_str()?.strip_prefix("node") else {
+                continue;
+            };
+            let Ok(id) = id.parse::<usize>() else {
+                continue;
+            };
+            let list = std::fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            nodes.push((id, parse_cpu_list(&list)?));
+        }
+        nodes.sort_unstable();
+        let nodes: Vec<Vec<usize>> = nodes.into_iter().map(|(_, cpus)| cpus).collect();
+        nodes
+            .iter()
+            .any(|cpus| !cpus.is_empty())
+            .then(|| Self::from_nodes(nodes))
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn cpus(&self, node: usize) -> &[usize] {
+        &self.nodes[node]
+    }
+
+    /// The node that part `part` of `parts` runs on: every node takes an
+    /// equal share of consecutive parts.
+    pub fn node_of(&self, part: usize, parts: usize) -> usize {
+        part * self.nodes.len() / parts.max(1)
+    }
+
+    /// Restricts the calling thread to the CPUs of `node`. CPUs beyond
+    /// what cpu_set_t holds are skipped; a node of only such CPUs fails.
+    #[cfg(target_os = "linux")]
+    pub fn pin(&self, node: usize) -> io::Result<()> {
+        let setsize = libc::CPU_SETSIZE as usize;
+        // SAFETY: cpu_set_t is plain data, and the set outlives the call.
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            for &cpu in self.nodes[node].iter().filter(|&&cpu| cpu < setsize) {
+                libc::CPU_SET(cpu, &mut set);
+            }
+            if libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Restricts the calling thread to the CPUs of `node`, which only
+    /// Linux supports.
+    #[cfg(not(target_os = "linux"))]
+    pub fn pin(&self, node: usize) -> io::Result<()> {
+        let _ = &self.nodes[node];
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Parses a CPU list like `0-3,8-11`, or returns None if it is malformed.
+pub fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numa_topology() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+
+        let topology = NumaTopology::from_nodes(vec![vec![0, 1], vec![], vec![2, 3]]);
+        assert_eq!(topology.nodes(), 2);
+        assert_eq!(topology.cpus(1), [2, 3]);
+        let nodes: Vec<usize> = (0..5).map(|p| topology.node_of(p, 5)).collect();
+        assert_eq!(nodes, [0, 0, 0, 1, 1]);
+
+        // CPU ids that do not fit into cpu_set_t are skipped.
+        #[cfg(target_os = "linux")]
+        {
+            let huge = NumaTopology::from_nodes(vec![vec![1 << 20]]);
+            let pinned = std::thread::spawn(move || huge.pin(0)).join().unwrap();
+            assert!(pinned.is_err());
+        }
+
+        let host = NumaTopology::detect();
+        assert!(host.nodes() >= 1);
+        let cpus = (0..host.nodes()).map(|n| host.cpus(n).len()).sum::<usize>();
+        assert!(cpus >= 1);
+    }
+}
@@ -0,0 +1,678 @@
+//! Persisted relations.
+//!
+//! TrieRelation::write_to() stores a relation in a binary index file, and
+//! TrieRelation::read_from() loads it without sorting again. Keys are
+//! stored column after column, in their ReplayKey encoding as little-endian
+//! u64, and split into blocks of BLOCK_KEYS keys. The header and every
+//! block are followed by their CRC-32, which is verified on read: a flipped
+//! bit in a sorted file would otherwise go unnoticed and silently produce
+//! wrong joins. A mismatch is reported with the region of the file it was
+//! found in.
+//!
+//! The header consists of the magic bytes `LFTR`, a version byte, the byte
+//! order of the keys, their KeyType, and the arity, number of tuples and
+//! keys per block as little-endian u64. Keys are written in the native byte
+//! order of the writer and converted by readers on other platforms; reading
+//! them as another key type fails.
+//!
+//! Files of older versions remain readable. Version 1 files have neither
+//! byte order nor key type and store keys in little-endian order; migrate()
+//! rewrites them in the current version.
+//!
+//! A ResultWriter streams the results of a join into an index file as they
+//! are produced, so they can be read as a relation by the next join without
+//! ever being held in memory at once. It stages all but the last block of
+//! every column in a temporary file, since columns follow each other.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::memory::{SpillFile, spill_dir};
+use crate::replay::ReplayKey;
+use crate::trie::TrieRelation;
+use crate::tuning::Tuning;
+
+const MAGIC: &[u8; 4] = b"LFTR";
+const VERSION: u8 = 2;
+const HEADER_LEN: u64 = 31;
+const V1_HEADER_LEN: u64 = 29;
+
+/// The type of the keys of an index file. Keys of usize and isize are
+/// stored as u64 and i64, respectively.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    U8 = 1,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+}
+
+impl KeyType {
+    fn from_byte(byte: u8) -> Option<Self> {
+        use KeyType::*;
+        [U8, U16, U32, U64, I8, I16, I32, I64]
+            .into_iter()
+            .find(|&t| t as u8 == byte)
+    }
+}
+
+/// PersistKey is implemented by the key types of index files.
+pub trait PersistKey: ReplayKey {
+    const KEY_TYPE: KeyType;
+}
+
+macro_rules! impl_persist_key {
+    ($($t:ty => $k:ident),*) => {$(
+        impl PersistKey for $t {
+            const KEY_TYPE: KeyType = KeyType::$k;
+        }
+    )*};
+}
+
+impl_persist_key!(
+    u8 => U8, u16 => U16, u32 => U32, u64 => U64, usize => U64,
+    i8 => I8, i16 => I16, i32 => I32, i64 => I64, isize => I64
+);
+
+/// The byte order of the keys of an index file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little = 0,
+    Big = 1,
+}
+
+impl ByteOrder {
+    pub fn native() -> Self {
+        if cfg!(target_endian = "little") {
+            ByteOrder::Little
+        } else {
+            ByteOrder::Big
+        }
+    }
+
+    fn encode(self, value: u64) -> [u8; 8] {
+        match self {
+            ByteOrder::Little => value.to_le_bytes(),
+            ByteOrder::Big => value.to_be_bytes(),
+        }
+    }
+
+    pub(crate) fn decode(self, bytes: [u8; 8]) -> u64 {
+        match self {
+            ByteOrder::Little => u64::from_le_bytes(bytes),
+            ByteOrder::Big => u64::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// The header of an index file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub byte_order: ByteOrder,
+    /// None for version 1 files.
+    pub key_type: Option<KeyType>,
+    pub arity: usize,
+    pub len: usize,
+    pub block_keys: usize,
+}
+
+/// Keys per checksummed block.
+pub const BLOCK_KEYS: usize = 4096;
+
+/// A region of an index file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Region {
+    Header,
+    /// Block `block` of column `column`, at bytes [offset, offset + len).
+    Block {
+        column: usize,
+        block: usize,
+        offset: u64,
+        len: u64,
+    },
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Header => write!(f, "header"),
+            Region::Block {
+                column,
+                block,
+                offset,
+                len,
+            } => write!(
+                f,
+                "column {column}, block {block} (bytes {offset}..{})",
+                offset + len
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PersistError {
+    Io(io::Error),
+    /// The file is not an index file, or is truncated.
+    Format(String),
+    /// A region does not match its checksum.
+    Corrupt(Region),
+    /// The file holds keys of another type.
+    KeyType {
+        expected: KeyType,
+        found: KeyType,
+    },
+    /// A tuple written to a ResultWriter does not sort strictly after the
+    /// previous one.
+    Unsorted {
+        row: usize,
+    },
+}
+
+impl fmt::Display for PersistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistError::Io(e) => write!(f, "I/O error: {e}"),
+            PersistError::Format(msg) => write!(f, "invalid index file: {msg}"),
+            PersistError::Corrupt(region) => write!(f, "checksum mismatch in {region}"),
+            PersistError::KeyType { expected, found } => {
+                write!(
+                    f,
+                    "file holds keys of type {found:?}, expected {expected:?}"
+                )
+            }
+            PersistError::Unsorted { row } => {
+                write!(f, "tuple {row} does not sort after the previous one")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PersistError {}
+
+impl From<io::Error> for PersistError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            PersistError::Format("file is truncated".to_string())
+        } else {
+            PersistError::Io(e)
+        }
+    }
+}
+
+impl<K: PersistKey> TrieRelation<K> {
+    /// Writes the relation as an index file.
+    pub fn write_to<W: Write>(&self, w: W) -> io::Result<()> {
+        self.write_with(w, ByteOrder::native(), BLOCK_KEYS)
+    }
+
+    /// Writes the relation as an index file with blocks of
+    /// `tuning.block_size` keys.
+    pub fn write_tuned<W: Write>(&self, w: W, tuning: &Tuning) -> io::Result<()> {
+        assert!(tuning.block_size > 0, "Block size must be > 0");
+        self.write_with(w, ByteOrder::native(), tuning.block_size)
+    }
+
+    fn write_with<W: Write>(
+        &self,
+        mut w: W,
+        byte_order: ByteOrder,
+        block_keys: usize,
+    ) -> io::Result<()> {
+        write_header::<K>(&mut w, byte_order, self.arity(), self.len(), block_keys)?;
+        let mut block = Vec::with_capacity(block_keys.min(BLOCK_KEYS) * 8);
+        for a in 0..self.arity() {
+            for keys in self.column(a).chunks(block_keys) {
+                block.clear();
+                for key in keys {
+                    block.extend_from_slice(&byte_order.encode(key.encode()));
+                }
+                write_block(&mut w, &block)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a relation written by write_to() of this or an older version,
+    /// verifying every checksum.
+    pub fn read_from<R: Read>(mut r: R) -> Result<Self, PersistError> {
+        let header = read_header(&mut r)?;
+        if let Some(found) = header.key_type
+            && found != K::KEY_TYPE
+        {
+            return Err(PersistError::KeyType {
+                expected: K::KEY_TYPE,
+                found,
+            });
+        }
+        let Header {
+            arity,
+            len,
+            block_keys,
+            byte_order,
+            ..
+        } = header;
+        let mut keys = Vec::with_capacity(arity.saturating_mul(len).min(1 << 20));
+        let mut block = Vec::with_capacity(block_keys.min(BLOCK_KEYS) * 8);
+        let mut offset = header_len(header.version) + 4;
+        for column in 0..arity {
+            for (i, start) in (0..len).step_by(block_keys).enumerate() {
+                let n = block_keys.min(len - start);
+                block.resize(n * 8, 0);
+                r.read_exact(&mut block)?;
+                if read_u32(&mut r)? != crc32(&block) {
+                    return Err(PersistError::Corrupt(Region::Block {
+                        column,
+                        block: i,
+                        offset,
+                        len: block.len() as u64,
+                    }));
+                }
+                offset += block.len() as u64 + 4;
+                keys.extend(
+                    block
+                        .chunks_exact(8)
+                        .map(|b| K::decode(byte_order.decode(b.try_into().unwrap()))),
+                );
+            }
+        }
+        Ok(TrieRelation::from_arena(arity, keys))
+    }
+}
+
+/// ResultWriter writes sorted, distinct tuples to an index file as they
+/// come, e.g. the results of a join, or the keys of a LeapFrogJoin as
+/// tuples of one key.
+pub struct ResultWriter<K, W> {
+    out: W,
+    arity: usize,
+    len: usize,
+    last: Vec<K>,
+    dir: PathBuf,
+    /// Per column, its current block and the file holding its full blocks.
+    blocks: Vec<Vec<u8>>,
+    staged: Vec<Option<SpillFile>>,
+}
+
+impl<K: PersistKey, W: Write> ResultWriter<K, W> {
+    /// Creates a writer of tuples of `arity` keys, staging blocks in the
+    /// temporary directory.
+    pub fn new(out: W, arity: usize) -> Self {
+        Self::in_dir(out, arity, spill_dir())
+    }
+
+    /// Creates a writer staging blocks in `dir`.
+    pub fn in_dir(out: W, arity: usize, dir: impl AsRef<Path>) -> Self {
+        assert!(arity > 0, "Arity must be > 0");
+        Self {
+            out,
+            arity,
+            len: 0,
+            last: Vec::with_capacity(arity),
+            dir: dir.as_ref().to_path_buf(),
+            blocks: vec![Vec::with_capacity(BLOCK_KEYS * 8); arity],
+            staged: (0..arity).map(|_| None).collect(),
+        }
+    }
+
+    /// Number of tuples written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends `tuple`, which must sort after the previous one.
+    pub fn write(&mut self, tuple: &[K]) -> Result<(), PersistError> {
+        assert_eq!(tuple.len(), self.arity, "Tuple has wrong arity");
+        if self.len > 0 && tuple <= self.last.as_slice() {
+            return Err(PersistError::Unsorted { row: self.len });
+        }
+        self.last.clear();
+        self.last.extend_from_slice(tuple);
+        let byte_order = ByteOrder::native();
+        for (c, &key) in tuple.iter().enumerate() {
+            self.blocks[c].extend_from_slice(&byte_order.encode(key.encode()));
+        }
+        self.len += 1;
+        if self.len.is_multiple_of(BLOCK_KEYS) {
+            for (block, staged) in self.blocks.iter_mut().zip(&mut self.staged) {
+                if staged.is_none() {
+                    *staged = Some(SpillFile::create(&self.dir)?);
+                }
+                write_block(&mut staged.as_mut().unwrap().writer, block)?;
+                block.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends all tuples of `tuples`.
+    pub fn write_all<T: AsRef<[K]>>(
+        &mut self,
+        tuples: impl IntoIterator<Item = T>,
+    ) -> Result<(), PersistError> {
+        for tuple in tuples {
+            self.write(tuple.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index file and returns the output.
+    pub fn finish(mut self) -> Result<W, PersistError> {
+        write_header::<K>(
+            &mut self.out,
+            ByteOrder::native(),
+            self.arity,
+            self.len,
+            BLOCK_KEYS,
+        )?;
+        for (block, staged) in self.blocks.iter().zip(&mut self.staged) {
+            if let Some(staged) = staged {
+                staged.writer.flush()?;
+                io::copy(
+                    &mut BufReader::new(File::open(&staged.path)?),
+                    &mut self.out,
+                )?;
+            }
+            if !block.is_empty() {
+                write_block(&mut self.out, block)?;
+            }
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn write_header<K: PersistKey>(
+    w: &mut impl Write,
+    byte_order: ByteOrder,
+    arity: usize,
+    len: usize,
+    block_keys: usize,
+) -> io::Result<()> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&[VERSION, byte_order as u8, K::KEY_TYPE as u8]);
+    for value in [arity, len, block_keys] {
+        header.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+    w.write_all(&header)?;
+    w.write_all(&crc32(&header).to_le_bytes())
+}
+
+fn write_block(w: &mut impl Write, block: &[u8]) -> io::Result<()> {
+    w.write_all(block)?;
+    w.write_all(&crc32(block).to_le_bytes())
+}
+
+/// Reads and verifies the header of an index file of any version.
+pub fn read_header<R: Read>(r: &mut R) -> Result<Header, PersistError> {
+    let mut header = vec![0u8; 5];
+    r.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(PersistError::Format("bad magic".to_string()));
+    }
+    let version = header[4];
+    if version == 0 || version > VERSION {
+        return Err(PersistError::Format(format!(
+            "unsupported version {version}"
+        )));
+    }
+    header.resize(header_len(version) as usize, 0);
+    r.read_exact(&mut header[5..])?;
+    if read_u32(r)? != crc32(&header) {
+        return Err(PersistError::Corrupt(Region::Header));
+    }
+    let (byte_order, key_type, fields) = match version {
+        1 => (ByteOrder::Little, None, &header[5..]),
+        _ => {
+            let byte_order = match header[5] {
+                0 => ByteOrder::Little,
+                1 => ByteOrder::Big,
+                order => return Err(PersistError::Format(format!("bad byte order {order}"))),
+            };
+            let key_type = KeyType::from_byte(header[6])
+                .ok_or_else(|| PersistError::Format(format!("unknown key type {}", header[6])))?;
+            (byte_order, Some(key_type), &header[7..])
+        }
+    };
+    let field = |i: usize| {
+        let bytes = fields[8 * i..8 * i + 8].try_into().unwrap();
+        u64::from_le_bytes(bytes) as usize
+    };
+    let (arity, len, block_keys) = (field(0), field(1), field(2));
+    if arity == 0 || block_keys == 0 {
+        return Err(PersistError::Format("empty arity or block".to_string()));
+    }
+    Ok(Header {
+        version,
+        byte_order,
+        key_type,
+        arity,
+        len,
+        block_keys,
+    })
+}
+
+/// Rewrites the index file read from `r` in the current version, and
+/// returns the version it had. Files of version 1 do not record their key
+/// type, which must be known to be K.
+pub fn migrate<K: PersistKey, R: Read, W: Write>(mut r: R, w: W) -> Result<u8, PersistError> {
+    let mut header = Vec::new();
+    r.by_ref().take(5).read_to_end(&mut header)?;
+    let version = header.get(4).copied().unwrap_or_default();
+    let relation = TrieRelation::<K>::read_from(header.chain(r))?;
+    relation.write_to(w)?;
+    Ok(version)
+}
+
+pub(crate) fn header_len(version: u8) -> u64 {
+    match version {
+        1 => V1_HEADER_LEN,
+        _ => HEADER_LEN,
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// The CRC-32 (IEEE) of `bytes`.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !bytes.iter().fold(!0u32, |crc, &b| {
+        TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(relation: &TrieRelation<i64>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        relation.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let n = BLOCK_KEYS as i64 * 2 + 10;
+        let relation = TrieRelation::new(2, (0..n).map(|i| [i / 3 - 100, -i]));
+        let bytes = file(&relation);
+        assert_eq!(TrieRelation::read_from(bytes.as_slice()).unwrap(), relation);
+        let empty = TrieRelation::<i64>::empty(3);
+        assert_eq!(
+            TrieRelation::read_from(file(&empty).as_slice()).unwrap(),
+            empty
+        );
+    }
+
+    #[test]
+    fn test_corruption() {
+        let n = BLOCK_KEYS as i64 * 2 + 10;
+        let relation = TrieRelation::new(2, (0..n).map(|i| [i, i % 7]));
+        let bytes = file(&relation);
+        let read = |bytes: &[u8]| TrieRelation::<i64>::read_from(bytes).unwrap_err();
+
+        // The first block of the second column, after three blocks of the
+        // first one.
+        let offset = HEADER_LEN + 4 + 2 * (BLOCK_KEYS as u64 * 8 + 4) + (10 * 8 + 4);
+        let mut corrupt = bytes.clone();
+        corrupt[offset as usize + 100] ^= 0x10;
+        let error = read(&corrupt);
+        assert!(matches!(
+            &error,
+            PersistError::Corrupt(Region::Block {
+                column: 1,
+                block: 0,
+                ..
+            })
+        ));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "checksum mismatch in column 1, block 0 (bytes {offset}..{})",
+                offset + BLOCK_KEYS as u64 * 8
+            )
+        );
+
+        let mut corrupt = bytes.clone();
+        corrupt[6] ^= 1;
+        assert!(matches!(
+            read(&corrupt),
+            PersistError::Corrupt(Region::Header)
+        ));
+        assert_eq!(
+            read(&bytes[..bytes.len() - 1]).to_string(),
+            "invalid index file: file is truncated"
+        );
+    }
+
+    /// Writes `relation` in the format of version 1.
+    fn v1_file(relation: &TrieRelation<i64>) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.push(1);
+        for value in [relation.arity(), relation.len(), 2] {
+            header.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+        let mut bytes = header.clone();
+        bytes.extend_from_slice(&crc32(&header).to_le_bytes());
+        for a in 0..relation.arity() {
+            for keys in relation.column(a).chunks(2) {
+                let block: Vec<u8> = keys.iter().flat_map(|k| k.encode().to_le_bytes()).collect();
+                bytes.extend_from_slice(&block);
+                bytes.extend_from_slice(&crc32(&block).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_versions() {
+        let relation = TrieRelation::new(2, [[-1, 2], [3, 4], [5, -6]]);
+        let old = v1_file(&relation);
+        let header = read_header(&mut old.as_slice()).unwrap();
+        assert_eq!((header.version, header.key_type), (1, None));
+        assert_eq!(TrieRelation::read_from(old.as_slice()).unwrap(), relation);
+
+        let mut migrated = Vec::new();
+        assert_eq!(
+            migrate::<i64, _, _>(old.as_slice(), &mut migrated).unwrap(),
+            1
+        );
+        assert_eq!(migrated, file(&relation));
+        let header = read_header(&mut migrated.as_slice()).unwrap();
+        assert_eq!(header.version, VERSION);
+        assert_eq!(header.key_type, Some(KeyType::I64));
+        assert_eq!(header.byte_order, ByteOrder::native());
+
+        // Files written on big-endian platforms.
+        let mut big = Vec::new();
+        relation
+            .write_with(&mut big, ByteOrder::Big, BLOCK_KEYS)
+            .unwrap();
+        assert_ne!(big, file(&relation));
+        assert_eq!(TrieRelation::read_from(big.as_slice()).unwrap(), relation);
+
+        let error = TrieRelation::<u32>::read_from(migrated.as_slice()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "file holds keys of type I64, expected U32"
+        );
+        let mut future = migrated.clone();
+        future[4] = VERSION + 1;
+        assert_eq!(
+            TrieRelation::<i64>::read_from(future.as_slice())
+                .unwrap_err()
+                .to_string(),
+            format!("invalid index file: unsupported version {}", VERSION + 1)
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_result_writer() {
+        let edges = TrieRelation::new(2, (0..200i64).flat_map(|a| [[a, a + 1], [a, a + 2]]));
+        let query = crate::query::Query::new()
+            .atom(&edges, &["a", "b"])
+            .atom(&edges, &["b", "c"])
+            .atom(&edges, &["a", "c"]);
+        let mut writer = ResultWriter::new(Vec::new(), 3);
+        writer.write_all(query.execute().unwrap()).unwrap();
+        assert_eq!(writer.len(), 199);
+        let bytes = writer.finish().unwrap();
+        let triangles = TrieRelation::read_from(bytes.as_slice()).unwrap();
+        assert_eq!(triangles, TrieRelation::new(3, query.run().unwrap()));
+
+        // Keys of a LeapFrogJoin, spanning several blocks.
+        let (a, b): (Vec<i64>, Vec<i64>) =
+            ((0..30_000).collect(), (0..30_000).step_by(3).collect());
+        let mut join = crate::LeapFrogJoin::new(vec![&a, &b]);
+        let mut writer = ResultWriter::new(Vec::new(), 1);
+        while !join.at_end() {
+            writer.write(&[join.key()]).unwrap();
+            join.next();
+        }
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes, file(&TrieRelation::new(1, b.iter().map(|&k| [k]))));
+
+        let mut writer = ResultWriter::new(Vec::new(), 1);
+        writer.write(&[2i64]).unwrap();
+        assert_eq!(
+            writer.write(&[2]).unwrap_err().to_string(),
+            "tuple 1 does not sort after the previous one"
+        );
+    }
+}
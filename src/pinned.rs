@@ -0,0 +1,196 @@
+//! Pinned plans.
+//!
+//! The plan of a query depends on the defaults of the library and on the
+//! order atoms were added in, and the tunables of its inputs may come from
+//! calibrating the host. An upgrade or a recalibration can thus change how
+//! a critical query performs without any change to the query itself.
+//!
+//! Query::pin() exports the plan chosen for a query, i.e. its variable order
+//! and steps, together with the tunables it runs with, as a PinnedPlan.
+//! PinnedPlan::write_to() stores it as lines of text, and
+//! Query::with_pinned() re-submits it later: the query executes under the
+//! pinned variable order and tunables, or fails with
+//! QueryError::PlanMismatch if it no longer has the steps the plan was made
+//! for.
+//!
+//! The text format has one `variables=` line listing the variable order, one
+//! `step=label;binds;reads` line per step, and the tunables as written by
+//! Tuning::write_to(), e.g.
+//!
+//! ```text
+//! variables=a,b,c
+//! step=atom 0;a,b;
+//! step=atom 1;b,c;
+//! step=filter 0;;c
+//! LEAPFROG_GALLOPING_THRESHOLD=8
+//! ```
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::query::{PlanStep, QueryPlan};
+use crate::tuning::{Tuning, TuningError};
+
+/// A pinned plan that cannot be read back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PinError {
+    /// A line that is neither part of the plan nor a tunable.
+    Line(String),
+    /// The `variables=` line is missing.
+    NoVariables,
+    Tuning(TuningError),
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinError::Line(line) => write!(f, "invalid line in pinned plan: {line:?}"),
+            PinError::NoVariables => write!(f, "pinned plan lacks its variable order"),
+            PinError::Tuning(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PinError {}
+
+impl From<TuningError> for PinError {
+    fn from(e: TuningError) -> Self {
+        PinError::Tuning(e)
+    }
+}
+
+/// PinnedPlan is the plan of a query and the tunables it runs with, see
+/// Query::pin().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinnedPlan {
+    pub plan: QueryPlan,
+    pub tuning: Tuning,
+}
+
+/// Separates the names in lines; names containing them cannot be pinned.
+const SEPARATORS: [char; 4] = [',', ';', '=', '\n'];
+
+impl PinnedPlan {
+    pub fn new(plan: QueryPlan, tuning: Tuning) -> Self {
+        Self { plan, tuning }
+    }
+
+    /// The variable order to execute the query under.
+    pub fn variables(&self) -> &[String] {
+        &self.plan.variables
+    }
+
+    /// Writes the plan in the text format described in the module docs.
+    /// Fails with io::ErrorKind::InvalidInput if a variable or label
+    /// contains one of `,;=` or a line break.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let steps = self.plan.steps.iter();
+        let names = steps.flat_map(|s| s.binds.iter().chain(&s.reads).chain([&s.label]));
+        if let Some(name) = names
+            .chain(&self.plan.variables)
+            .find(|name| name.contains(SEPARATORS))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot pin name {name:?}"),
+            ));
+        }
+        writeln!(w, "variables={}", self.plan.variables.join(","))?;
+        for step in &self.plan.steps {
+            let (binds, reads) = (step.binds.join(","), step.reads.join(","));
+            writeln!(w, "step={};{binds};{reads}", step.label)?;
+        }
+        self.tuning.write_to(w)
+    }
+
+    /// Reads a plan written by write_to(). Missing tunables keep their
+    /// defaults.
+    pub fn read_from<R: BufRead>(r: R) -> io::Result<Result<Self, PinError>> {
+        let lines = r.lines().collect::<io::Result<Vec<String>>>()?;
+        Ok(Self::parse(&lines))
+    }
+
+    fn parse(lines: &[String]) -> Result<Self, PinError> {
+        let names = |list: &str| -> Vec<String> {
+            list.split(',')
+                .filter(|v| !v.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        let mut variables = None;
+        let mut steps = Vec::new();
+        let mut tunables = Vec::new();
+        for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+            let invalid = || PinError::Line(line.clone());
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "variables" => variables = Some(names(value)),
+                "step" => {
+                    let mut fields = value.split(';');
+                    let (Some(label), Some(binds), Some(reads), None) =
+                        (fields.next(), fields.next(), fields.next(), fields.next())
+                    else {
+                        return Err(invalid());
+                    };
+                    steps.push(PlanStep {
+                        label: label.to_string(),
+                        binds: names(binds),
+                        reads: names(reads),
+                    });
+                }
+                _ if key.starts_with("LEAPFROG_") => tunables.push((key, value)),
+                _ => return Err(invalid()),
+            }
+        }
+        let variables = variables.ok_or(PinError::NoVariables)?;
+        Ok(Self {
+            plan: QueryPlan { variables, steps },
+            tuning: Tuning::default().with_overrides(tunables)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::var;
+    use crate::query::{Query, QueryError};
+    use crate::trie::TrieRelation;
+
+    #[test]
+    fn test_pinned_plan() {
+        let r = TrieRelation::new(2, [[1, 2], [2, 3], [3, 4]]);
+        let s = TrieRelation::new(2, [[2, 5], [3, 6], [4, 7]]);
+        let query = || {
+            Query::new()
+                .atom(&r, &["a", "b"])
+                .atom(&s, &["a", "c"])
+                .filter(var("c").gt(5))
+        };
+        let tuning = Tuning {
+            batch_size: 1024,
+            ..Tuning::default()
+        };
+        let pinned = query().order(&["a", "c", "b"]).pin(tuning).unwrap();
+        let mut text = Vec::new();
+        pinned.write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("variables=a,c,b\nstep=atom 0;a,b;\n"));
+        let read = PinnedPlan::read_from(text.as_bytes()).unwrap().unwrap();
+        assert_eq!(read, pinned);
+
+        // The query's own order differs, the pinned one wins.
+        let results = query().with_pinned(&read).unwrap().run().unwrap();
+        assert_eq!(results, [[3, 6, 4]]);
+
+        let changed = Query::new().atom(&r, &["a", "b"]).atom(&s, &["a", "c"]);
+        assert!(matches!(
+            changed.with_pinned(&read),
+            Err(QueryError::PlanMismatch(_))
+        ));
+        let garbage = PinnedPlan::read_from("order=a".as_bytes()).unwrap();
+        assert_eq!(garbage, Err(PinError::Line("order=a".to_string())));
+        let tunables = PinnedPlan::read_from("LEAPFROG_BLOCK_SIZE=1".as_bytes()).unwrap();
+        assert_eq!(tunables, Err(PinError::NoVariables));
+    }
+}
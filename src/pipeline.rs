@@ -0,0 +1,915 @@
+//! Streaming pipelines over join results.
+//!
+//! A Pipeline chains stages like `join(&query).filter(..).project(..)`
+//! and is driven by a single pull loop in its terminal operation, e.g.
+//! Pipeline::write_to(), so no stage materializes its output. Stages refer
+//! to columns by variable name. Naming an unknown variable does not fail
+//! right away, to keep stages chainable; the terminal operation reports it
+//! before pulling any row.
+//!
+//! Every pipeline declares the order of its rows as an OutputOrder: the
+//! variables the rows are sorted by, and whether they are distinct, so a
+//! stage can tell whether it needs to sort or drop duplicates. The rows of
+//! a join are sorted by all variables and distinct, unless shuffled, see
+//! TrieJoin::output_order(). Filters keep the order. Projections keep the
+//! sort by the leading variables they keep in place, and drop the
+//! duplicates they produce as they come if their rows are sorted by all
+//! their variables. Other projections may produce duplicates.
+//!
+//! Pipeline::distinct() drops duplicate rows, e.g. after a projection, and
+//! Pipeline::distinct_on() keeps the first row per distinct value of some
+//! variables. Both pass rows that are distinct already, and otherwise use
+//! the order of the rows: rows sorted by a prefix of the distinct variables
+//! come in groups, and only the values of the current group are kept in a
+//! hash set. Without such a prefix, the set keeps all values.
+//!
+//! Pipeline::top() and Pipeline::bottom() keep the N rows with the largest
+//! or smallest value of an expression, e.g. for a leaderboard, in a bounded
+//! heap of N rows rather than sorting all rows.
+//!
+//! Pipeline::sort() reorders the rows by other variables, for a consumer
+//! that needs another order than the stage before it provides, unless the
+//! rows are sorted by them already. It is the one stage that holds rows:
+//! Pipeline::sort_spilling() bounds them by sorting runs of rows in memory,
+//! spilling every run to a temporary file, and merging the runs. A stage
+//! that fails while the rows are pulled, like a sort unable to spill, ends
+//! the rows early; run(), count() and write_to() report its error.
+//!
+//! The stages that hold rows, i.e. sorts, distinct and top stages, account
+//! their bytes against the MemoryBudget of the pipeline, which
+//! Pipeline::memory() returns. Its MemoryBudget::peak() is the high-water
+//! mark of the execution, e.g. for capacity planning. When its
+//! MemoryPressure is signaled, e.g. relayed by Pipeline::under_pressure(),
+//! a sort pulling its rows spills the run it holds and halves the rows of
+//! the runs after it. A sort holding all rows in memory starts to spill
+//! to the temporary directory of the host.
+
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::expr::{Expr, ExprKey};
+use crate::memory::{Category, MemoryBudget, MemoryPressure, Reservation, SpillFile, spill_dir};
+use crate::persist::{PersistError, PersistKey, ResultWriter};
+use crate::query::{Query, QueryError};
+use crate::replay::ReplayKey;
+
+#[derive(Debug)]
+pub enum PipelineError {
+    Query(QueryError),
+    Persist(PersistError),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::Query(e) => write!(f, "{e}"),
+            PipelineError::Persist(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+impl From<QueryError> for PipelineError {
+    fn from(e: QueryError) -> Self {
+        PipelineError::Query(e)
+    }
+}
+
+impl From<PersistError> for PipelineError {
+    fn from(e: PersistError) -> Self {
+        PipelineError::Persist(e)
+    }
+}
+
+type Rows<'a, K> = Box<dyn Iterator<Item = Vec<K>> + 'a>;
+
+/// The error of a stage that failed while the rows were pulled.
+type Failure = Rc<RefCell<Option<PipelineError>>>;
+
+const UNLIMITED: &str = "Pipeline budgets are unlimited";
+
+/// Starts accounting the rows a stage holds against `memory`.
+fn reservation(memory: &Arc<MemoryBudget>) -> Reservation {
+    memory.reserve(Category::Buffers, 0).expect(UNLIMITED)
+}
+
+/// The bytes of a row of `arity` keys.
+fn row_bytes<K>(arity: usize) -> usize {
+    size_of::<Vec<K>>() + arity * size_of::<K>()
+}
+
+/// OutputOrder describes the order rows are guaranteed to come in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputOrder {
+    sorted_by: Vec<String>,
+    distinct: bool,
+}
+
+impl OutputOrder {
+    /// Rows in no particular order, possibly with duplicates.
+    pub fn unordered() -> Self {
+        Self::default()
+    }
+
+    /// Rows sorted lexicographically by the values of `variables`, which may
+    /// be a prefix of the variables of the rows.
+    pub fn sorted_by(variables: &[&str]) -> Self {
+        Self {
+            sorted_by: variables.iter().map(|v| v.to_string()).collect(),
+            distinct: false,
+        }
+    }
+
+    /// Additionally guarantees that no row occurs twice.
+    pub fn distinct(mut self) -> Self {
+        self.distinct = true;
+        self
+    }
+
+    /// The variables the rows are sorted by, most significant first.
+    pub fn sort_key(&self) -> &[String] {
+        &self.sorted_by
+    }
+
+    pub fn is_distinct(&self) -> bool {
+        self.distinct
+    }
+
+    /// Whether rows in this order are also sorted by `variables`, i.e.
+    /// whether they are a prefix of the sort key.
+    pub fn satisfies(&self, variables: &[&str]) -> bool {
+        variables.len() <= self.sorted_by.len()
+            && variables.iter().zip(&self.sorted_by).all(|(v, s)| v == s)
+    }
+}
+
+/// Starts a pipeline with the results of `query`.
+pub fn join<'a, K: Ord + Copy + 'a>(query: &Query<'a, K>) -> Pipeline<'a, K> {
+    match query.execute() {
+        Ok(join) => {
+            let variables = join.variables().to_vec();
+            let order = join.output_order();
+            Pipeline::with(variables, Box::new(join), order)
+        }
+        Err(e) => Pipeline::failed(e),
+    }
+}
+
+/// Pipeline is a chain of streaming stages over rows binding variables.
+pub struct Pipeline<'a, K> {
+    variables: Vec<String>,
+    rows: Result<Rows<'a, K>, QueryError>,
+    order: OutputOrder,
+    failure: Failure,
+    memory: Arc<MemoryBudget>,
+}
+
+impl<'a, K: Ord + Copy + 'a> Pipeline<'a, K> {
+    /// Starts a pipeline with `rows` binding `variables`, which need not be
+    /// sorted.
+    pub fn from_rows(variables: &[&str], rows: impl IntoIterator<Item = Vec<K>> + 'a) -> Self {
+        Self::from_ordered_rows(variables, rows, OutputOrder::unordered())
+    }
+
+    /// Starts a pipeline with `rows` binding `variables`, which come in
+    /// `order`. The order is trusted, not checked.
+    pub fn from_ordered_rows(
+        variables: &[&str],
+        rows: impl IntoIterator<Item = Vec<K>> + 'a,
+        order: OutputOrder,
+    ) -> Self {
+        let unknown = order
+            .sort_key()
+            .iter()
+            .find(|v| !variables.contains(&v.as_str()));
+        let unknown = unknown.map(|v| QueryError::UnknownVariable(v.clone()));
+        let variables = variables.iter().map(|v| v.to_string()).collect();
+        let pipeline = Self::with(variables, Box::new(rows.into_iter()), order);
+        match unknown {
+            Some(e) => pipeline.fail(e),
+            None => pipeline,
+        }
+    }
+
+    fn with(variables: Vec<String>, rows: Rows<'a, K>, order: OutputOrder) -> Self {
+        Self {
+            variables,
+            rows: Ok(rows),
+            order,
+            failure: Failure::default(),
+            memory: MemoryBudget::unlimited(),
+        }
+    }
+
+    fn failed(e: QueryError) -> Self {
+        Self {
+            variables: Vec::new(),
+            rows: Err(e),
+            order: OutputOrder::unordered(),
+            failure: Failure::default(),
+            memory: MemoryBudget::unlimited(),
+        }
+    }
+
+    /// The variables bound by each row, in column order.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// The order the rows are guaranteed to come in.
+    pub fn order(&self) -> &OutputOrder {
+        &self.order
+    }
+
+    /// The budget the stages account the rows they hold against, without a
+    /// limit. Its peak() is the most bytes held at once by the execution.
+    pub fn memory(&self) -> Arc<MemoryBudget> {
+        self.memory.clone()
+    }
+
+    /// Relays the signals of `pressure`, e.g. of the budget of a Database,
+    /// to the pressure of memory(), while the pipeline lives.
+    pub fn under_pressure(self, pressure: &MemoryPressure) -> Self {
+        let memory = Arc::downgrade(&self.memory);
+        pressure.on_signal(move || {
+            let memory = memory.upgrade();
+            memory.map(|memory| memory.pressure().signal()).is_some()
+        });
+        self
+    }
+
+    /// Whether the rows are known to be sorted by all variables and
+    /// distinct.
+    pub fn is_sorted(&self) -> bool {
+        self.order.distinct && self.order.sorted_by == self.variables
+    }
+
+    /// Keeps the rows for which `f` holds. `f` receives the values of
+    /// `variables` in the order given.
+    pub fn filter(self, variables: &[&str], f: impl Fn(&[K]) -> bool + 'a) -> Self {
+        let columns = match self.columns(variables) {
+            Ok(columns) => columns,
+            Err(e) => return self.fail(e),
+        };
+        let mut args = Vec::with_capacity(columns.len());
+        let order = self.order.clone();
+        self.stage(order, move |rows| {
+            Box::new(rows.filter(move |row| {
+                args.clear();
+                args.extend(columns.iter().map(|&c| row[c]));
+                f(&args)
+            }))
+        })
+    }
+
+    /// Keeps the columns of `variables`, in the order given.
+    pub fn project(self, variables: &[&str]) -> Self {
+        let columns = match self.columns(variables) {
+            Ok(columns) => columns,
+            Err(e) => return self.fail(e),
+        };
+        // The leading variables kept in place still sort the rows.
+        let kept = (0..variables.len())
+            .take_while(|&i| {
+                self.order
+                    .sorted_by
+                    .get(i)
+                    .is_some_and(|s| s == variables[i])
+            })
+            .count();
+        let sorted = kept == variables.len();
+        let all = (0..self.variables.len()).all(|c| columns.contains(&c));
+        let mut order = OutputOrder::sorted_by(&variables[..kept]);
+        order.distinct = sorted || (self.order.distinct && all);
+        let mut pipeline = self.stage(order, move |rows| {
+            let mut last: Option<Vec<K>> = None;
+            Box::new(rows.filter_map(move |row| {
+                let row: Vec<K> = columns.iter().map(|&c| row[c]).collect();
+                if sorted && last.as_ref() == Some(&row) {
+                    return None;
+                }
+                if sorted {
+                    last = Some(row.clone());
+                }
+                Some(row)
+            }))
+        });
+        pipeline.variables = variables.iter().map(|v| v.to_string()).collect();
+        pipeline
+    }
+
+    /// Returns an iterator pulling the rows through all stages. The rows
+    /// end early if a stage fails.
+    pub fn rows(self) -> Result<impl Iterator<Item = Vec<K>> + 'a, QueryError> {
+        self.rows
+    }
+
+    /// Collects all rows.
+    pub fn run(self) -> Result<Vec<Vec<K>>, PipelineError> {
+        let failure = self.failure.clone();
+        let rows = self.rows()?.collect();
+        check(&failure, rows)
+    }
+
+    /// Counts the rows without keeping them.
+    pub fn count(self) -> Result<usize, PipelineError> {
+        let failure = self.failure.clone();
+        let count = self.rows()?.count();
+        check(&failure, count)
+    }
+
+    /// Fails the pipeline with `e`, unless it failed before.
+    fn fail(mut self, e: QueryError) -> Self {
+        if self.rows.is_ok() {
+            self.rows = Err(e);
+        }
+        self
+    }
+
+    fn columns(&self, variables: &[&str]) -> Result<Vec<usize>, QueryError> {
+        variables
+            .iter()
+            .map(|v| {
+                self.variables
+                    .iter()
+                    .position(|w| w == v)
+                    .ok_or_else(|| QueryError::UnknownVariable(v.to_string()))
+            })
+            .collect()
+    }
+
+    fn stage(self, order: OutputOrder, f: impl FnOnce(Rows<'a, K>) -> Rows<'a, K>) -> Self {
+        Self {
+            variables: self.variables,
+            rows: self.rows.map(f),
+            order,
+            failure: self.failure,
+            memory: self.memory,
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy + Hash + 'a> Pipeline<'a, K> {
+    /// Drops duplicate rows, keeping the first of each.
+    pub fn distinct(self) -> Self {
+        if self.order.distinct {
+            return self;
+        }
+        let variables = self.variables.clone();
+        self.distinct_on(&variables.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    /// Keeps the first row of every distinct combination of values of
+    /// `variables`.
+    pub fn distinct_on(self, variables: &[&str]) -> Self {
+        let columns = match self.columns(variables) {
+            Ok(columns) => columns,
+            Err(e) => return self.fail(e),
+        };
+        // Rows with equal values of `variables` are adjacent within the
+        // groups of the longest prefix of the sort key among them.
+        let prefix = self.order.sorted_by.iter();
+        let prefix = prefix.take_while(|v| variables.contains(&v.as_str()));
+        let prefix: Vec<&str> = prefix.map(String::as_str).collect();
+        let groups = self.columns(&prefix).unwrap();
+        let order = OutputOrder {
+            sorted_by: self.order.sorted_by.clone(),
+            distinct: true,
+        };
+        let mut held = reservation(&self.memory);
+        self.stage(order, move |rows| {
+            let mut group: Option<Vec<K>> = None;
+            let mut seen = HashSet::new();
+            Box::new(rows.filter(move |row| {
+                let values = |columns: &[usize]| columns.iter().map(|&c| row[c]).collect();
+                if !groups.is_empty() {
+                    let current: Vec<K> = values(&groups);
+                    if group.as_ref() != Some(&current) {
+                        seen.clear();
+                        held.shrink(held.bytes());
+                        group = Some(current);
+                    }
+                }
+                let new = seen.insert(values(&columns));
+                if new {
+                    held.grow(row_bytes::<K>(columns.len())).expect(UNLIMITED);
+                }
+                new
+            }))
+        })
+    }
+}
+
+impl<'a, K: Ord + Copy + ExprKey + 'a> Pipeline<'a, K> {
+    /// Keeps the `n` rows with the largest values of `expr`, largest first.
+    /// Of rows with equal values, the earlier ones are kept and come first.
+    /// Rows whose value overflows are dropped.
+    pub fn top(self, n: usize, expr: Expr) -> Self {
+        self.top_by(n, expr, true)
+    }
+
+    /// Keeps the `n` rows with the smallest values of `expr`, smallest
+    /// first, like top().
+    pub fn bottom(self, n: usize, expr: Expr) -> Self {
+        self.top_by(n, expr, false)
+    }
+
+    fn top_by(self, n: usize, expr: Expr, largest: bool) -> Self {
+        let index_of = |name: &str| self.variables.iter().position(|v| v == name);
+        let compiled = match expr.compile(&index_of) {
+            Ok((compiled, _)) => compiled,
+            Err(e) => return self.fail(e.into()),
+        };
+        let mut order = OutputOrder::unordered();
+        order.distinct = self.order.distinct;
+        let mut held = Some(reservation(&self.memory));
+        let bytes = row_bytes::<K>(self.variables.len());
+        self.stage(order, move |rows| {
+            let mut rows = Some(rows);
+            Box::new(std::iter::once(()).flat_map(move |()| {
+                // The best rows so far, the worst on top: by value, with
+                // !value reversing the order for the smallest, then by
+                // arrival.
+                let mut held = held.take().unwrap();
+                let mut heap = BinaryHeap::with_capacity(n.saturating_add(1).min(1024));
+                for (seq, row) in rows.take().unwrap().enumerate() {
+                    let Some(value) = compiled.eval(&row) else {
+                        continue;
+                    };
+                    let rank = if largest { value } else { !value };
+                    heap.push(Reverse((rank, Reverse(seq), row)));
+                    held.grow(bytes).expect(UNLIMITED);
+                    if heap.len() > n {
+                        heap.pop();
+                        held.shrink(bytes);
+                    }
+                }
+                let best = heap.into_sorted_vec();
+                // The rows are held until they are all pulled.
+                best.into_iter().map(move |Reverse((_, _, row))| {
+                    let _ = &held;
+                    row
+                })
+            }))
+        })
+    }
+}
+
+impl<'a, K: ReplayKey + 'a> Pipeline<'a, K> {
+    /// Sorts the rows by `variables`, then by the other variables in column
+    /// order, holding all rows in memory. Rows sorted by `variables`
+    /// already pass unchanged.
+    pub fn sort(self, variables: &[&str]) -> Self {
+        self.sort_by(variables, None)
+    }
+
+    /// Sorts the rows like sort(), holding up to `run_rows` rows in memory
+    /// and spilling the sorted runs to temporary files in `dir`.
+    pub fn sort_spilling(self, variables: &[&str], run_rows: usize, dir: impl AsRef<Path>) -> Self {
+        assert!(run_rows > 0, "Runs must hold at least one row");
+        self.sort_by(variables, Some((run_rows, dir.as_ref().to_path_buf())))
+    }
+
+    fn sort_by(self, variables: &[&str], spill: Option<(usize, PathBuf)>) -> Self {
+        let mut columns = match self.columns(variables) {
+            Ok(columns) => columns,
+            Err(e) => return self.fail(e),
+        };
+        if self.order.satisfies(variables) {
+            return self;
+        }
+        let rest: Vec<usize> = (0..self.variables.len())
+            .filter(|c| !columns.contains(c))
+            .collect();
+        columns.extend(rest);
+        let sort_key: Vec<&str> = columns
+            .iter()
+            .map(|&c| self.variables[c].as_str())
+            .collect();
+        let mut order = OutputOrder::sorted_by(&sort_key);
+        order.distinct = self.order.distinct;
+        let failure = self.failure.clone();
+        let held = reservation(&self.memory);
+        let pressure = self.memory.pressure().clone();
+        self.stage(order, move |rows| {
+            Box::new(SortedRows {
+                state: SortState::Input(rows),
+                columns,
+                spill,
+                failure,
+                held,
+                signals: pressure.signals(),
+                pressure,
+            })
+        })
+    }
+}
+
+/// Reports the failure of a stage, if any, instead of `value`.
+fn check<T>(failure: &Failure, value: T) -> Result<T, PipelineError> {
+    match failure.borrow_mut().take() {
+        Some(e) => Err(e),
+        None => Ok(value),
+    }
+}
+
+/// The rows of a sort stage, sorted when the first one is pulled.
+struct SortedRows<'a, K> {
+    state: SortState<'a, K>,
+    /// The columns to compare, most significant first.
+    columns: Vec<usize>,
+    /// The rows per run and the directory to spill runs to, if bounded.
+    spill: Option<(usize, PathBuf)>,
+    failure: Failure,
+    /// The rows of the runs kept in memory.
+    held: Reservation,
+    pressure: Arc<MemoryPressure>,
+    /// The pressure signals seen so far.
+    signals: usize,
+}
+
+enum SortState<'a, K> {
+    Input(Rows<'a, K>),
+    /// The sorted runs and the next row of each.
+    Merge(Vec<Run<K>>, Vec<Option<Vec<K>>>),
+}
+
+/// A sorted run, kept in memory or spilled.
+enum Run<K> {
+    Memory(std::vec::IntoIter<Vec<K>>),
+    Spilled {
+        reader: BufReader<File>,
+        rows: usize,
+        arity: usize,
+        /// Removes the file once the run is merged.
+        _file: SpillFile,
+    },
+}
+
+impl<K: ReplayKey> Run<K> {
+    /// Sorts `rows` and spills them to a file in `dir`.
+    fn spill(rows: &[Vec<K>], dir: &Path) -> io::Result<Self> {
+        let mut file = SpillFile::create(dir)?;
+        for key in rows.iter().flatten() {
+            file.writer.write_all(&key.encode().to_le_bytes())?;
+        }
+        file.writer.flush()?;
+        Ok(Run::Spilled {
+            reader: BufReader::new(File::open(&file.path)?),
+            rows: rows.len(),
+            arity: rows.first().map_or(0, Vec::len),
+            _file: file,
+        })
+    }
+
+    fn next(&mut self) -> io::Result<Option<Vec<K>>> {
+        match self {
+            Run::Memory(rows) => Ok(rows.next()),
+            Run::Spilled {
+                reader,
+                rows,
+                arity,
+                ..
+            } => {
+                if *rows == 0 {
+                    return Ok(None);
+                }
+                *rows -= 1;
+                let mut bytes = [0; 8];
+                let mut row = Vec::with_capacity(*arity);
+                for _ in 0..*arity {
+                    reader.read_exact(&mut bytes)?;
+                    row.push(K::decode(u64::from_le_bytes(bytes)));
+                }
+                Ok(Some(row))
+            }
+        }
+    }
+}
+
+impl<K: ReplayKey> SortedRows<'_, K> {
+    fn compare(columns: &[usize], a: &[K], b: &[K]) -> Ordering {
+        columns
+            .iter()
+            .map(|&c| a[c].cmp(&b[c]))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /// Checks for a new pressure signal, which spills the run being pulled
+    /// and halves the rows of the runs after it.
+    fn pressured(&mut self, run_rows: usize) -> bool {
+        let signals = self.pressure.signals();
+        if signals == self.signals {
+            return false;
+        }
+        self.signals = signals;
+        let dir = match self.spill.take() {
+            Some((_, dir)) => dir,
+            None => spill_dir(),
+        };
+        self.spill = Some(((run_rows / 2).max(1), dir));
+        true
+    }
+
+    /// Pulls all input rows into sorted runs.
+    fn runs(&mut self, rows: &mut Rows<'_, K>) -> io::Result<Vec<Run<K>>> {
+        let mut runs = Vec::new();
+        loop {
+            let mut run_rows = self.spill.as_ref().map_or(usize::MAX, |(n, _)| *n);
+            let mut run: Vec<Vec<K>> = Vec::new();
+            let mut full = false;
+            for row in rows.by_ref() {
+                self.held.grow(row_bytes::<K>(row.len())).expect(UNLIMITED);
+                run.push(row);
+                if self.pressured(run_rows.min(run.len())) {
+                    run_rows = run.len();
+                }
+                if run.len() >= run_rows {
+                    full = true;
+                    break;
+                }
+            }
+            let bytes = run.iter().map(|row| row_bytes::<K>(row.len())).sum();
+            run.sort_unstable_by(|a, b| Self::compare(&self.columns, a, b));
+            match &self.spill {
+                Some((_, dir)) if full || !runs.is_empty() => {
+                    runs.push(Run::spill(&run, dir)?);
+                    self.held.shrink(bytes);
+                }
+                _ => runs.push(Run::Memory(run.into_iter())),
+            }
+            if !full {
+                return Ok(runs);
+            }
+        }
+    }
+
+    fn pull(&mut self) -> io::Result<Option<Vec<K>>> {
+        if let SortState::Input(_) = self.state {
+            let done = SortState::Merge(Vec::new(), Vec::new());
+            let SortState::Input(mut rows) = std::mem::replace(&mut self.state, done) else {
+                unreachable!()
+            };
+            let mut runs = self.runs(&mut rows)?;
+            let heads = runs.iter_mut().map(Run::next).collect::<io::Result<_>>()?;
+            self.state = SortState::Merge(runs, heads);
+        }
+        let SortState::Merge(runs, heads) = &mut self.state else {
+            unreachable!()
+        };
+        let columns = &self.columns;
+        let next = (0..heads.len())
+            .filter(|&r| heads[r].is_some())
+            .min_by(|&r, &s| {
+                let (a, b) = (heads[r].as_ref().unwrap(), heads[s].as_ref().unwrap());
+                Self::compare(columns, a, b)
+            });
+        let Some(r) = next else {
+            return Ok(None);
+        };
+        let head = runs[r].next()?;
+        Ok(std::mem::replace(&mut heads[r], head))
+    }
+}
+
+impl<K: ReplayKey> Iterator for SortedRows<'_, K> {
+    type Item = Vec<K>;
+
+    fn next(&mut self) -> Option<Vec<K>> {
+        match self.pull() {
+            Ok(row) => row,
+            Err(e) => {
+                *self.failure.borrow_mut() = Some(PersistError::Io(e).into());
+                self.state = SortState::Merge(Vec::new(), Vec::new());
+                None
+            }
+        }
+    }
+}
+
+impl<'a, K: PersistKey + 'a> Pipeline<'a, K> {
+    /// Writes the rows to an index file, see ResultWriter. The rows must be
+    /// sorted and distinct.
+    pub fn write_to<W: Write>(self, out: W) -> Result<W, PipelineError> {
+        let arity = self.variables.len();
+        let failure = self.failure.clone();
+        let mut writer = ResultWriter::new(out, arity);
+        writer.write_all(self.rows()?)?;
+        check(&failure, ())?;
+        Ok(writer.finish()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::var;
+    use crate::trie::TrieRelation;
+
+    fn edges() -> TrieRelation<u32> {
+        TrieRelation::new(2, [[1, 2], [1, 3], [2, 3], [2, 4], [3, 4], [1, 4]])
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let edges = edges();
+        let query = Query::new()
+            .atom(&edges, &["a", "b"])
+            .atom(&edges, &["b", "c"]);
+        let paths = join(&query).filter(&["c"], |v| v[0] == 4);
+        assert!(paths.is_sorted());
+        assert_eq!(paths.variables(), ["a", "b", "c"]);
+        let starts = paths.project(&["a", "b"]);
+        assert!(starts.is_sorted());
+        assert_eq!(starts.run().unwrap(), [[1, 2], [1, 3], [2, 3]]);
+
+        let ends = join(&query).project(&["c", "a"]);
+        assert!(!ends.is_sorted());
+        assert_eq!(ends.order(), &OutputOrder::unordered());
+        assert_eq!(ends.count().unwrap(), 4);
+
+        let file = join(&query).project(&["a"]).write_to(Vec::new()).unwrap();
+        let written = TrieRelation::<u32>::read_from(file.as_slice()).unwrap();
+        assert_eq!(written, TrieRelation::new(1, [[1], [2]]));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_memory_peak() {
+        let rows = || (0..100u32).rev().map(|i| vec![i % 10, i]);
+        let row = row_bytes::<u32>(2);
+        let peak = |pipeline: Pipeline<'_, u32>| {
+            let memory = pipeline.memory();
+            pipeline.run().unwrap();
+            assert_eq!(memory.used(), 0);
+            memory.peak()
+        };
+        let unsorted = || Pipeline::from_rows(&["a", "b"], rows());
+        assert_eq!(peak(unsorted().filter(&["a"], |_| true)), 0);
+        assert_eq!(peak(unsorted().sort(&["b"])), 100 * row);
+        let spilled = unsorted().sort_spilling(&["b"], 10, std::env::temp_dir());
+        assert_eq!(peak(spilled), 10 * row);
+        assert_eq!(peak(unsorted().top(3, var("b"))), 4 * row);
+        assert_eq!(
+            peak(unsorted().distinct_on(&["a"])),
+            10 * row_bytes::<u32>(1)
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_sort_under_pressure() {
+        let pressure = MemoryPressure::new();
+        let signal = pressure.callback();
+        // Signals when the 40th row is pulled.
+        let rows = (0..100u32).rev().map(move |i| {
+            if i == 60 {
+                signal();
+            }
+            vec![i % 10, i]
+        });
+        let sorted = Pipeline::from_rows(&["a", "b"], rows)
+            .under_pressure(&pressure)
+            .sort(&["b"]);
+        let memory = sorted.memory();
+        let expected: Vec<Vec<u32>> = (0..100).map(|i| vec![i % 10, i]).collect();
+        assert_eq!(sorted.run().unwrap(), expected);
+        // The 40 rows held are spilled, the runs after them hold 20 each.
+        assert_eq!(memory.peak(), 40 * row_bytes::<u32>(2));
+        assert_eq!(memory.pressure().signals(), 1);
+    }
+
+    #[test]
+    fn test_output_order() {
+        let edges = edges();
+        let query = Query::new()
+            .atom(&edges, &["a", "b"])
+            .atom(&edges, &["b", "c"]);
+        let paths = join(&query);
+        assert_eq!(
+            paths.order(),
+            &OutputOrder::sorted_by(&["a", "b", "c"]).distinct()
+        );
+        assert!(paths.order().satisfies(&["a", "b"]));
+        assert!(!paths.order().satisfies(&["b"]));
+        let skipping = paths.project(&["a", "c"]);
+        assert_eq!(skipping.order(), &OutputOrder::sorted_by(&["a"]));
+        assert!(!skipping.is_sorted());
+
+        let rows = [vec![1u32, 2], vec![1, 1], vec![2, 0]];
+        let rows = Pipeline::from_ordered_rows(&["x", "y"], rows, OutputOrder::sorted_by(&["x"]));
+        assert_eq!(rows.project(&["x"]).run().unwrap(), [[1], [2]]);
+        let unknown =
+            Pipeline::from_ordered_rows(&["x"], [vec![1u32]], OutputOrder::sorted_by(&["z"]));
+        assert_eq!(
+            unknown.rows().err(),
+            Some(QueryError::UnknownVariable("z".into()))
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_sort() {
+        let edges = edges();
+        let query = Query::new()
+            .atom(&edges, &["a", "b"])
+            .atom(&edges, &["b", "c"]);
+        let mut expected = join(&query).run().unwrap();
+        expected.sort_by_key(|row| (row[2], row[0], row[1]));
+
+        let sorted = join(&query).sort(&["c", "a"]);
+        assert_eq!(
+            sorted.order(),
+            &OutputOrder::sorted_by(&["c", "a", "b"]).distinct()
+        );
+        assert_eq!(sorted.run().unwrap(), expected);
+        let dir = std::env::temp_dir();
+        let spilled = join(&query).sort_spilling(&["c", "a"], 2, &dir);
+        assert_eq!(spilled.run().unwrap(), expected);
+        // Sorted by a already.
+        let unchanged = join(&query).sort(&["a"]);
+        assert!(unchanged.is_sorted());
+
+        let missing = dir.join(format!("leapfrog-no-such-dir-{}", std::process::id()));
+        let failed = join(&query).sort_spilling(&["c"], 2, missing).count();
+        assert!(matches!(
+            failed,
+            Err(PipelineError::Persist(PersistError::Io(_)))
+        ));
+    }
+
+    #[test]
+    fn test_top() {
+        let edges = edges();
+        let query = Query::new()
+            .atom(&edges, &["a", "b"])
+            .atom(&edges, &["b", "c"]);
+        let paths = join(&query).run().unwrap();
+        assert_eq!(paths.len(), 4);
+        let longest = join(&query).top(2, var("c") - var("a")).run().unwrap();
+        assert_eq!(longest, [[1, 2, 4], [1, 3, 4]]);
+        let shortest = join(&query).bottom(3, var("c") - var("a"));
+        assert_eq!(shortest.order(), &OutputOrder::unordered().distinct());
+        assert_eq!(shortest.run().unwrap(), [[1, 2, 3], [2, 3, 4], [1, 2, 4]]);
+        assert_eq!(join(&query).top(9, var("a")).count().unwrap(), 4);
+        assert_eq!(join(&query).top(0, var("a")).count().unwrap(), 0);
+        let unknown = join(&query).top(1, var("x")).count();
+        assert!(matches!(
+            unknown,
+            Err(PipelineError::Query(QueryError::UnknownVariable(_)))
+        ));
+    }
+
+    #[test]
+    fn test_distinct() {
+        let edges = edges();
+        let query = Query::new()
+            .atom(&edges, &["a", "b"])
+            .atom(&edges, &["b", "c"]);
+        // Sorted by a, so only the values of c per a are kept.
+        let pairs = join(&query).project(&["a", "c"]);
+        assert_eq!(pairs.order(), &OutputOrder::sorted_by(&["a"]));
+        let pairs = pairs.distinct();
+        assert!(pairs.order().is_distinct());
+        assert_eq!(pairs.run().unwrap(), [[1, 3], [1, 4], [2, 4]]);
+        // Unsorted, so all values are kept.
+        let ends = join(&query).project(&["c", "a"]).distinct();
+        assert_eq!(ends.run().unwrap(), [[3, 1], [4, 1], [4, 2]]);
+
+        let firsts = join(&query).distinct_on(&["c"]).run().unwrap();
+        assert_eq!(firsts, [[1, 2, 3], [1, 2, 4]]);
+        let unknown = join(&query).distinct_on(&["x"]).count();
+        assert!(matches!(
+            unknown,
+            Err(PipelineError::Query(QueryError::UnknownVariable(_)))
+        ));
+    }
+
+    #[test]
+    fn test_pipeline_errors() {
+        let edges = edges();
+        let query = Query::new().atom(&edges, &["a", "b"]);
+        let error = join(&query).filter(&["x"], |_| true).project(&["a"]).run();
+        assert!(matches!(
+            error,
+            Err(PipelineError::Query(QueryError::UnknownVariable(x))) if x == "x"
+        ));
+
+        let rows = Pipeline::from_rows(&["a"], [vec![2u32], vec![1]]);
+        let error = rows.write_to(Vec::new()).unwrap_err();
+        assert!(matches!(
+            error,
+            PipelineError::Persist(PersistError::Unsorted { row: 1 })
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+//! A cache of compiled query plans.
+//!
+//! Workloads often execute a few query templates over and over. A
+//! PlanCache keeps the plans compiled for them, keyed by the normalized
+//! text of the query and the version of the data it was planned against,
+//! so a repeated query skips normalization checks and planning. A plan
+//! compiled against another version is stale: it is compiled again and
+//! replaces the cached one.
+//!
+//! The cache holds up to its capacity of plans and evicts the least
+//! recently used one beyond that. PlanCacheStats counts hits and misses to
+//! judge whether the capacity covers the hot templates.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Counters describing how a PlanCache was used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlanCacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// Plans compiled again since their version was outdated; also misses.
+    pub stale: usize,
+    pub evictions: usize,
+    pub entries: usize,
+}
+
+impl PlanCacheStats {
+    /// Fraction of lookups that were hits, or 0 without lookups.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+struct Cached<P> {
+    plan: Arc<P>,
+    version: u64,
+    tick: u64,
+}
+
+struct Inner<P> {
+    plans: HashMap<String, Cached<P>>,
+    tick: u64,
+    stats: PlanCacheStats,
+}
+
+/// PlanCache maps query texts to the plans compiled for them.
+pub struct PlanCache<P> {
+    capacity: usize,
+    inner: Mutex<Inner<P>>,
+}
+
+impl<P> PlanCache<P> {
+    /// Creates a cache of up to `capacity` plans.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner {
+                plans: HashMap::new(),
+                tick: 0,
+                stats: PlanCacheStats::default(),
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the plan cached for `key` at `version`, or compiles and
+    /// caches it. Failures to compile are not cached.
+    pub fn get_or_compile<E>(
+        &self,
+        key: &str,
+        version: u64,
+        compile: impl FnOnce() -> Result<P, E>,
+    ) -> Result<Arc<P>, E> {
+        if let Some(plan) = self.get(key, version) {
+            return Ok(plan);
+        }
+        let plan = Arc::new(compile()?);
+        self.insert(key, version, plan.clone());
+        Ok(plan)
+    }
+
+    /// Returns the plan cached for `key` if it was compiled at `version`.
+    pub fn get(&self, key: &str, version: u64) -> Option<Arc<P>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let Inner { plans, stats, .. } = &mut *inner;
+        match plans.get_mut(key) {
+            Some(cached) if cached.version == version => {
+                cached.tick = tick;
+                stats.hits += 1;
+                Some(cached.plan.clone())
+            }
+            Some(_) => {
+                stats.misses += 1;
+                stats.stale += 1;
+                None
+            }
+            None => {
+                stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Caches `plan` for `key` at `version`, evicting the least recently
+    /// used plan if the cache is full.
+    pub fn insert(&self, key: &str, version: u64, plan: Arc<P>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        if !inner.plans.contains_key(key) && inner.plans.len() >= self.capacity {
+            let oldest = inner.plans.iter().min_by_key(|(_, c)| c.tick);
+            let oldest = oldest.map(|(key, _)| key.clone()).unwrap();
+            inner.plans.remove(&oldest);
+            inner.stats.evictions += 1;
+        }
+        let cached = Cached {
+            plan,
+            version,
+            tick,
+        };
+        inner.plans.insert(key.to_string(), cached);
+    }
+
+    pub fn stats(&self) -> PlanCacheStats {
+        let inner = self.inner.lock().unwrap();
+        PlanCacheStats {
+            entries: inner.plans.len(),
+            ..inner.stats
+        }
+    }
+
+    /// Drops all plans and resets the counters.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.plans.clear();
+        inner.stats = PlanCacheStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_cache() {
+        let cache = PlanCache::new(2);
+        let mut compiled = 0;
+        let mut compile = |key: &str, version| {
+            cache
+                .get_or_compile(key, version, || {
+                    compiled += 1;
+                    Ok::<_, ()>(key.len())
+                })
+                .unwrap()
+        };
+        assert_eq!(*compile("a AND b", 1), 7);
+        assert_eq!(*compile("a AND b", 1), 7);
+        compile("c", 1);
+        compile("a AND b", 1);
+        // Evicts "c", the least recently used.
+        compile("d", 1);
+        compile("a AND b", 2);
+        assert_eq!(compiled, 4);
+        assert!(cache.get("c", 1).is_none());
+        assert_eq!(
+            cache.stats(),
+            PlanCacheStats {
+                hits: 2,
+                misses: 5,
+                stale: 1,
+                evictions: 1,
+                entries: 2,
+            }
+        );
+        assert!((cache.stats().hit_rate() - 2.0 / 7.0).abs() < 1e-9);
+        assert_eq!(cache.get_or_compile("e", 1, || Err("fails")), Err("fails"));
+        assert_eq!(cache.stats().entries, 2);
+    }
+}
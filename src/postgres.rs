@@ -0,0 +1,91 @@
+//! Postgres query results as join sources.
+//!
+//! query_source() streams the first column of a query's rows into a join,
+//! as a KeySource, so one side of an intersection can stay in the database.
+//! The query should sort by that column, e.g. with `ORDER BY key`. Rows are
+//! fetched from the server as the source advances, and seeks read the rows
+//! in between.
+//!
+//! This uses the blocking client of the `postgres` crate, which drives
+//! tokio-postgres on a runtime of its own.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use ::postgres::fallible_iterator::FallibleIterator;
+use ::postgres::types::{FromSqlOwned, ToSql};
+use ::postgres::{Client, Error, RowIter};
+
+use crate::ingest::{KeyReader, KeySource, Order};
+
+#[derive(Debug)]
+pub enum SourceError {
+    Postgres(Error),
+    /// The key of row `row`, counted from 0, is smaller than the one before.
+    Unsorted {
+        row: usize,
+    },
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Postgres(e) => write!(f, "Postgres error: {e}"),
+            SourceError::Unsorted { row } => {
+                write!(f, "row {row}: key is smaller than the one before")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<Error> for SourceError {
+    fn from(e: Error) -> Self {
+        SourceError::Postgres(e)
+    }
+}
+
+/// PostgresSource is the keys of a query, see query_source().
+pub type PostgresSource<'a, K> = KeySource<PostgresKeys<'a, K>>;
+
+/// Runs `query` with `params` and returns the first column of its rows as a
+/// source, positioned at the first key. NULL keys are errors, so filter
+/// them in the query.
+pub fn query_source<'a, K: FromSqlOwned + Ord + Copy>(
+    client: &'a mut Client,
+    query: &str,
+    params: &[&(dyn ToSql + Sync)],
+    order: Order,
+) -> Result<PostgresSource<'a, K>, SourceError> {
+    let keys = PostgresKeys {
+        rows: client.query_raw(query, params.iter().copied())?,
+        row: 0,
+        _key: PhantomData,
+    };
+    KeySource::new(keys, order)
+}
+
+/// The keys of a query, in row order.
+pub struct PostgresKeys<'a, K> {
+    rows: RowIter<'a>,
+    row: usize,
+    _key: PhantomData<K>,
+}
+
+impl<K: FromSqlOwned + Ord + Copy> KeyReader for PostgresKeys<'_, K> {
+    type Key = K;
+    type Error = SourceError;
+
+    fn read_key(&mut self) -> Result<Option<K>, SourceError> {
+        let Some(row) = self.rows.next()? else {
+            return Ok(None);
+        };
+        self.row += 1;
+        Ok(Some(row.try_get(0)?))
+    }
+
+    fn unsorted(&self) -> SourceError {
+        SourceError::Unsorted { row: self.row - 1 }
+    }
+}
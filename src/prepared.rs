@@ -0,0 +1,149 @@
+//! Prepared queries with placeholders.
+//!
+//! Atoms of a query may use placeholders `$1`, `$2`, ... as variables, e.g.
+//! `R(a, $1), S(a, b)`. Query::prepare() checks the query once and binds
+//! every placeholder with a generator that yields the constant passed for
+//! it at execution. The generator takes part in the leapfrog join of its
+//! variable like an atom, so on the trie level of a placeholder every
+//! relation is sought straight to the constant instead of scanned.
+//!
+//! PreparedQuery::run() executes the query with the constants passed for
+//! the placeholders and returns the results without their columns.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::query::{Query, QueryError, TrieJoin};
+
+/// PreparedQuery is a query checked once and executed with different
+/// constants for its placeholders.
+pub struct PreparedQuery<'a, K> {
+    query: Query<'a, K>,
+    parameters: Rc<RefCell<Vec<K>>>,
+    placeholders: usize,
+    /// The variables of the results, without placeholders.
+    variables: Vec<String>,
+    /// The positions of `variables` in the results of the query.
+    columns: Vec<usize>,
+}
+
+impl<'a, K: Ord + Copy + 'a> PreparedQuery<'a, K> {
+    /// Prepares `query`, see Query::prepare().
+    pub fn new(mut query: Query<'a, K>) -> Result<Self, QueryError> {
+        let all = query.plan()?.variables;
+        let mut placeholders: Vec<usize> = Vec::new();
+        for name in all.iter().filter(|v| v.starts_with('$')) {
+            match name[1..].parse() {
+                Ok(n) if n > 0 && name[1..].starts_with(|c: char| c != '0') => placeholders.push(n),
+                _ => return Err(QueryError::Placeholder(name.clone())),
+            }
+        }
+        placeholders.sort_unstable();
+        if let Some(missing) = (1..).zip(&placeholders).find(|&(i, &n)| i != n) {
+            return Err(QueryError::Placeholder(format!("${}", missing.0)));
+        }
+
+        let parameters = Rc::new(RefCell::new(Vec::new()));
+        for i in 0..placeholders.len() {
+            let parameters = parameters.clone();
+            let name = format!("${}", i + 1);
+            query = query.generator(&[], &name, move |_| [parameters.borrow()[i]]);
+        }
+        query.execute()?;
+        let columns: Vec<usize> = (0..all.len())
+            .filter(|&c| !all[c].starts_with('$'))
+            .collect();
+        Ok(Self {
+            query,
+            parameters,
+            placeholders: placeholders.len(),
+            variables: columns.iter().map(|&c| all[c].clone()).collect(),
+            columns,
+        })
+    }
+
+    /// The number of placeholders, which execution needs constants for.
+    pub fn placeholders(&self) -> usize {
+        self.placeholders
+    }
+
+    /// The variables of the results of run(), in variable order.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Executes the query with `parameters[i]` for the placeholder `$i+1`.
+    /// The results bind all variables, placeholders included.
+    pub fn execute(&self, parameters: &[K]) -> Result<TrieJoin<'a, K>, QueryError> {
+        if parameters.len() != self.placeholders {
+            return Err(QueryError::Parameters {
+                expected: self.placeholders,
+                found: parameters.len(),
+            });
+        }
+        *self.parameters.borrow_mut() = parameters.to_vec();
+        self.query.execute()
+    }
+
+    /// Executes the query like execute() and collects its results without
+    /// the placeholders.
+    pub fn run(&self, parameters: &[K]) -> Result<Vec<Vec<K>>, QueryError> {
+        let results = self.execute(parameters)?;
+        Ok(results
+            .map(|row| self.columns.iter().map(|&c| row[c]).collect())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::TrieRelation;
+
+    #[test]
+    fn test_prepared_query() {
+        let r = TrieRelation::new(2, [[1, 10], [2, 20], [3, 10], [4, 30]]);
+        let s = TrieRelation::new(2, [[1, 100], [2, 200], [3, 300], [3, 301]]);
+        let prepared = Query::new()
+            .atom(&r, &["a", "$1"])
+            .atom(&s, &["a", "b"])
+            .prepare()
+            .unwrap();
+        assert_eq!(prepared.placeholders(), 1);
+        assert_eq!(prepared.variables(), ["a", "b"]);
+        assert_eq!(
+            prepared.run(&[10]).unwrap(),
+            [vec![1, 100], vec![3, 300], vec![3, 301]]
+        );
+        assert_eq!(prepared.run(&[20]).unwrap(), [vec![2, 200]]);
+        assert!(prepared.run(&[30]).unwrap().is_empty());
+        assert_eq!(
+            prepared.execute(&[10]).unwrap().next(),
+            Some(vec![1, 10, 100])
+        );
+        assert_eq!(
+            prepared.run(&[]).err(),
+            Some(QueryError::Parameters {
+                expected: 1,
+                found: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let r = TrieRelation::new(2, [[1, 2], [2, 3]]);
+        let prepared = Query::new().atom(&r, &["$2", "$1"]).order(&["$2", "$1"]);
+        assert_eq!(
+            prepared.prepare().unwrap().run(&[3, 2]).unwrap(),
+            [Vec::<u32>::new()]
+        );
+        let gap = Query::new().atom(&r, &["a", "$2"]).prepare();
+        assert_eq!(gap.err(), Some(QueryError::Placeholder("$1".to_string())));
+        let invalid = Query::new().atom(&r, &["a", "$x"]).prepare();
+        assert_eq!(
+            invalid.err(),
+            Some(QueryError::Placeholder("$x".to_string()))
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Python bindings, built as the `leapfrog` extension module (e.g. with
+//! maturin and `--features python`).
+//!
+//! ```python
+//! import numpy as np, leapfrog
+//! leapfrog.intersect([np.array([1, 2, 3]), np.array([2, 3, 4])])  # array([2, 3])
+//! ```
+//!
+//! Contiguous one-dimensional NumPy arrays of int32, int64, uint32 or uint64
+//! are read in place without copying, and the GIL is released while the join
+//! runs. All arrays of one call must share the same dtype.
+//!
+//! `leapfrog.triejoin()` runs the triejoin over structured arrays, one per
+//! relation, joining records on equally named integer fields:
+//!
+//! ```python
+//! r = np.array([(1, 2), (2, 3)], dtype=[("a", "i8"), ("b", "i8")])
+//! s = np.array([(2, 5), (4, 7)], dtype=[("b", "i8"), ("c", "i8")])
+//! leapfrog.triejoin([r, s])  # array([(1, 2, 5)], dtype=[('a', '<i8'), ...])
+//! ```
+//!
+//! The fields of a record array are interleaved, so they are copied into
+//! the relations as int64; casts that could lose values are refused.
+
+use numpy::{Element, PyArray1, PyReadonlyArray1};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::IntoPyDict;
+
+use crate::query::{Query, QueryError};
+use crate::trie::TrieRelation;
+use crate::try_intersect;
+
+/// Returns the values common to all strictly ascending arrays as a new array.
+#[pyfunction]
+#[pyo3(name = "intersect")]
+fn py_intersect<'py>(
+    py: Python<'py>,
+    arrays: Vec<Bound<'py, PyAny>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let Some(first) = arrays.first() else {
+        return Err(PyValueError::new_err(
+            "intersect() needs at least one array",
+        ));
+    };
+    if first.cast::<PyArray1<i64>>().is_ok() {
+        intersect_as::<i64>(py, &arrays)
+    } else if first.cast::<PyArray1<i32>>().is_ok() {
+        intersect_as::<i32>(py, &arrays)
+    } else if first.cast::<PyArray1<u64>>().is_ok() {
+        intersect_as::<u64>(py, &arrays)
+    } else if first.cast::<PyArray1<u32>>().is_ok() {
+        intersect_as::<u32>(py, &arrays)
+    } else {
+        Err(PyTypeError::new_err(
+            "intersect() expects 1-d arrays of int32, int64, uint32 or uint64",
+        ))
+    }
+}
+
+fn intersect_as<'py, T>(
+    py: Python<'py>,
+    arrays: &[Bound<'py, PyAny>],
+) -> PyResult<Bound<'py, PyAny>>
+where
+    T: Element + Ord + Copy + Send + Sync,
+{
+    let arrays = arrays
+        .iter()
+        .map(|array| array.extract::<PyReadonlyArray1<T>>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| PyTypeError::new_err("all arrays must have the same dtype"))?;
+    let mut slices = Vec::with_capacity(arrays.len());
+    for (i, array) in arrays.iter().enumerate() {
+        let slice = array.as_slice().map_err(|_| {
+            PyValueError::new_err(format!(
+                "array {i} is not contiguous, pass np.ascontiguousarray() instead"
+            ))
+        })?;
+        slices.push(slice);
+    }
+    let result = py.detach(|| try_intersect(slices)).map_err(|e| {
+        PyValueError::new_err(format!(
+            "array {} is not sorted strictly ascending",
+            e.input
+        ))
+    })?;
+    Ok(PyArray1::from_vec(py, result).into_any())
+}
+
+/// Joins structured arrays, one per relation, on their equally named
+/// fields, and returns the results as a structured array with one int64
+/// field per variable, in the variable order `order` if given.
+#[pyfunction]
+#[pyo3(name = "triejoin", signature = (relations, order=None))]
+fn py_triejoin<'py>(
+    py: Python<'py>,
+    relations: Vec<Bound<'py, PyAny>>,
+    order: Option<Vec<String>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let casting = [("casting", "safe")].into_py_dict(py)?;
+    let mut atoms = Vec::with_capacity(relations.len());
+    for (i, relation) in relations.iter().enumerate() {
+        let names: Option<Vec<String>> = relation.getattr("dtype")?.getattr("names")?.extract()?;
+        let names = names.ok_or_else(|| {
+            PyTypeError::new_err(format!("relation {i} is not a structured array"))
+        })?;
+        let mut columns = Vec::with_capacity(names.len());
+        for name in &names {
+            let field = relation.get_item(name)?;
+            let field = field.call_method("astype", ("int64",), Some(&casting))?;
+            columns.push(
+                field
+                    .extract::<PyReadonlyArray1<i64>>()?
+                    .as_slice()?
+                    .to_vec(),
+            );
+        }
+        atoms.push((columns, names));
+    }
+    let (variables, results) = py
+        .detach(|| triejoin(&atoms, order.as_deref()))
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    let dtype: Vec<(&str, &str)> = variables.iter().map(|v| (v.as_str(), "i8")).collect();
+    let output = py
+        .import("numpy")?
+        .call_method1("empty", (results.len(), dtype))?;
+    for (v, variable) in variables.iter().enumerate() {
+        let column: Vec<i64> = results.iter().map(|result| result[v]).collect();
+        output.set_item(variable, PyArray1::from_vec(py, column))?;
+    }
+    Ok(output)
+}
+
+/// Joins atoms given as (columns, variables) under `order`, or in the
+/// order the variables first occur, returning the variables in the order of
+/// the results and the results.
+fn triejoin(
+    atoms: &[(Vec<Vec<i64>>, Vec<String>)],
+    order: Option<&[String]>,
+) -> Result<(Vec<String>, Vec<Vec<i64>>), QueryError> {
+    let order: Vec<&str> = match order {
+        Some(order) => order.iter().map(String::as_str).collect(),
+        None => {
+            let mut order = Vec::new();
+            for variable in atoms.iter().flat_map(|(_, variables)| variables) {
+                if !order.contains(&variable.as_str()) {
+                    order.push(variable.as_str());
+                }
+            }
+            order
+        }
+    };
+    // Per atom, its fields sorted by the variable order.
+    let atoms: Vec<Vec<(&Vec<i64>, &str)>> = atoms
+        .iter()
+        .map(|(columns, variables)| {
+            let mut fields: Vec<_> = columns
+                .iter()
+                .zip(variables.iter().map(String::as_str))
+                .collect();
+            fields.sort_by_key(|&(_, v)| order.iter().position(|&o| o == v));
+            fields
+        })
+        .collect();
+    let relations: Vec<TrieRelation<i64>> = atoms
+        .iter()
+        .map(|fields| {
+            let len = fields.first().map_or(0, |(column, _)| column.len());
+            let tuples = (0..len).map(|r| fields.iter().map(|(c, _)| c[r]).collect::<Vec<_>>());
+            TrieRelation::new(fields.len(), tuples)
+        })
+        .collect();
+    let mut query = Query::new().order(&order);
+    for (relation, fields) in relations.iter().zip(&atoms) {
+        let variables: Vec<&str> = fields.iter().map(|&(_, v)| v).collect();
+        query = query.atom(relation, &variables);
+    }
+    let join = query.execute()?;
+    let variables = join.variables().to_vec();
+    Ok((variables, join.collect()))
+}
+
+#[pymodule]
+fn leapfrog(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(py_intersect, m)?)?;
+    m.add_function(wrap_pyfunction!(py_triejoin, m)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(columns: &[&[i64]], variables: &[&str]) -> (Vec<Vec<i64>>, Vec<String>) {
+        let columns = columns.iter().map(|c| c.to_vec()).collect();
+        (columns, variables.iter().map(|v| v.to_string()).collect())
+    }
+
+    #[test]
+    fn test_triejoin() {
+        let atoms = [
+            atom(&[&[1, 2, 2], &[2, 3, 4]], &["a", "b"]),
+            atom(&[&[2, 3, 4], &[5, 6, 7]], &["b", "c"]),
+        ];
+        let (variables, results) = triejoin(&atoms, None).unwrap();
+        assert_eq!(variables, ["a", "b", "c"]);
+        assert_eq!(results, [[1, 2, 5], [2, 3, 6], [2, 4, 7]]);
+
+        let order = ["c".to_string(), "b".to_string(), "a".to_string()];
+        let (variables, results) = triejoin(&atoms, Some(&order)).unwrap();
+        assert_eq!(variables, order);
+        assert_eq!(results, [[5, 2, 1], [6, 3, 2], [7, 4, 2]]);
+    }
+
+    #[test]
+    fn test_triejoin_errors() {
+        let atoms = [atom(&[&[1]], &["a"])];
+        let order = ["x".to_string()];
+        assert_eq!(
+            triejoin(&atoms, Some(&order)).err(),
+            Some(QueryError::UnknownVariable("x".to_string()))
+        );
+        assert_eq!(triejoin(&[], None).err(), Some(QueryError::Empty));
+    }
+}
@@ -0,0 +1,2566 @@
+//! Conjunctive queries, evaluated by the leapfrog triejoin.
+//!
+//! A Query is a conjunction of atoms like `R(a, b), S(b, c), T(a, c)` over
+//! TrieRelations, plus optional filters over the variables. Variables are
+//! bound one at a time, in the variable order: for each variable, the trie
+//! iterators of all atoms containing it are leapfrogged against each other on
+//! the trie level that variable occupies in the atom.
+//!
+//! Every atom must list its variables in the variable order, since its trie
+//! can only be descended in attribute order. The variable order defaults to
+//! the order in which variables first appear in the atoms.
+//!
+//! EXISTS atoms only check that a matching tuple exists, without binding
+//! anything: their variables that occur in no other atom are existentially
+//! quantified. They are probed like filters, as soon as their other
+//! variables are bound, and the probe stops at the first match. Negated
+//! atoms (NOT) are the anti-join counterpart: they reject bindings with a
+//! matching tuple. To be safe, all their variables must be bound elsewhere.
+//!
+//! A filter `var == c` pins its variable to the constant: besides being
+//! checked, c takes part in the leapfrog join of the variable like a
+//! generator of one value, so the tries on its level are sought straight
+//! to c by binary search instead of enumerating the level.
+//! TrieRelation::probe() offers the same descent for applications.
+//!
+//! Temporal atoms (Query::temporal()) join relations whose tuples carry a
+//! validity interval in their last two attributes, valid from (inclusive)
+//! and to (exclusive), without binding it. A tuple may be valid during
+//! several intervals. Under Query::valid_at(), a temporal atom only matches
+//! tuples valid at the given time; under Query::valid_overlapping(), the
+//! tuples of all temporal atoms must be valid at some common time, and
+//! TrieJoin::validity() returns the intervals in which they all are. The
+//! intervals are read from the trie levels below each tuple right when its
+//! last variable is bound, so the checks prune like filters.
+//!
+//! Domain logic plugs in as user-defined functions: predicates are checked
+//! like filters, and generators are atoms that bind a variable to the values a
+//! closure computes from variables bound before it, e.g. `y` in `x..x + 10`.
+//!
+//! TrieJoin::hooks() installs JoinHooks that are told whenever a variable
+//! is bound and unbound again. They see the partial result at every depth
+//! and may prune the subtree below a binding or stop the enumeration, which
+//! suffices for custom pruning, aggregation along the trie, or early exits.
+//!
+//! TrieJoin::run_for() enumerates any-time: it returns the results found
+//! within a Budget of results or steps, and a Resume token if there may be
+//! more. Calling it again continues where it stopped; a token also resumes a
+//! fresh TrieJoin of the same query by TrieJoin::resume(), which seeks every
+//! level straight to the last result returned, e.g. for the next page of a
+//! UI that does not keep the join around.
+//!
+//! TrieJoin::shuffled() enumerates the bindings of every variable in a
+//! seeded random order instead of ascending, e.g. to sample results or to
+//! present them fairly. Each level is leapfrogged once to collect its keys,
+//! which are shuffled and then visited by seeking the iterators to them.
+//!
+//! TrieJoin::sampler() draws results uniformly at random without
+//! enumerating them. A trial descends the variable order, binding every
+//! variable to the key of a random row below the current node of its
+//! smallest trie, which picks a key with probability proportional to its
+//! subtree. The trial is accepted with the probability that corrects these
+//! weights to a uniform distribution, so every result is returned with the
+//! same probability, and JoinSampler::estimate() extrapolates the number of
+//! results from the acceptance rate.
+//!
+//! Query::plan() describes the evaluation without running it, and renders
+//! the variable order and what binds and checks each variable as a Graphviz
+//! DOT graph. After running, TrieJoin::report() compares the work done with
+//! the AGM bound, the worst-case output size for the sizes of the joined
+//! relations, and lists the fan-out per variable.
+//!
+//! Query::verify() runs a query twice, the second time under a random
+//! variable order, and checks that both runs find the same results.
+
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+use crate::expr::{Compiled, Expr, ExprError, ExprKey, Type};
+use crate::pinned::PinnedPlan;
+use crate::pipeline::OutputOrder;
+use crate::prepared::PreparedQuery;
+use crate::random::Rng;
+use crate::trie::{TrieIterator, TrieRelation};
+use crate::tuning::Tuning;
+use crate::{Seekable, cmp_seekable};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    /// The query has no atoms to join and no generators.
+    Empty,
+    /// The number of variables of an atom differs from its relation's arity.
+    Arity {
+        atom: usize,
+        arity: usize,
+        variables: usize,
+    },
+    /// A temporal atom has no variables besides its validity.
+    TemporalVariables {
+        atom: usize,
+    },
+    /// A variable occurs twice in one atom.
+    RepeatedVariable {
+        atom: usize,
+        variable: String,
+    },
+    /// A variable of the order or a filter does not occur in any atom.
+    UnknownVariable(String),
+    /// The variable order does not list every variable exactly once.
+    InvalidOrder(String),
+    /// The variables of an atom are not in the variable order.
+    AtomOrder {
+        atom: usize,
+    },
+    /// A variable occurs in neither an atom nor as a generator output.
+    UnboundVariable(String),
+    /// A generator input does not precede its output in the variable order.
+    GeneratorOrder {
+        generator: usize,
+    },
+    /// A variable of a negated atom is not bound by any other atom.
+    UnsafeNegation {
+        atom: usize,
+        variable: String,
+    },
+    Expr(ExprError),
+    /// Query::verify() found different results under the variable order
+    /// `order`: `missing` results of the query's own order are not found
+    /// under it, and `extra` ones are found only under it.
+    Mismatch {
+        order: Vec<String>,
+        missing: usize,
+        extra: usize,
+    },
+    /// A variable starting with `$` is not a placeholder `$1`, `$2`, ...,
+    /// or a placeholder below the highest one is missing.
+    Placeholder(String),
+    /// A prepared query was executed with the wrong number of constants.
+    Parameters {
+        expected: usize,
+        found: usize,
+    },
+    /// A variable is bound by generators only, so it cannot be sampled.
+    Unsampled(String),
+    /// The steps of the query differ from those of a pinned plan, at the
+    /// labeled step.
+    PlanMismatch(String),
+    /// A resume token holds `found` keys, but the query has `expected`
+    /// variables, so the token stems from another query.
+    ResumeToken {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Empty => write!(f, "query has no atoms"),
+            QueryError::Arity {
+                atom,
+                arity,
+                variables,
+            } => write!(
+                f,
+                "atom {atom} has {variables} variables, but its relation has arity {arity}"
+            ),
+            QueryError::TemporalVariables { atom } => {
+                write!(f, "temporal atom {atom} has no variables")
+            }
+            QueryError::RepeatedVariable { atom, variable } => {
+                write!(f, "variable {variable:?} occurs twice in atom {atom}")
+            }
+            QueryError::UnknownVariable(name) => write!(f, "unknown variable {name:?}"),
+            QueryError::InvalidOrder(msg) => write!(f, "invalid variable order: {msg}"),
+            QueryError::AtomOrder { atom } => {
+                write!(f, "variables of atom {atom} are not in the variable order")
+            }
+            QueryError::UnboundVariable(name) => {
+                write!(f, "variable {name:?} is not bound by any atom or generator")
+            }
+            QueryError::GeneratorOrder { generator } => write!(
+                f,
+                "inputs of generator {generator} must precede its output in the variable order"
+            ),
+            QueryError::UnsafeNegation { atom, variable } => write!(
+                f,
+                "variable {variable:?} of negated atom {atom} is not bound by any other atom"
+            ),
+            QueryError::Expr(e) => write!(f, "{e}"),
+            QueryError::Mismatch {
+                order,
+                missing,
+                extra,
+            } => write!(
+                f,
+                "results differ under variable order {order:?}: {missing} missing, {extra} extra"
+            ),
+            QueryError::Placeholder(name) => write!(f, "invalid or missing placeholder {name}"),
+            QueryError::Parameters { expected, found } => write!(
+                f,
+                "query has {expected} placeholders, but {found} constants were passed"
+            ),
+            QueryError::Unsampled(name) => {
+                write!(
+                    f,
+                    "variable {name:?} is bound by generators only and cannot be sampled"
+                )
+            }
+            QueryError::PlanMismatch(step) => {
+                write!(f, "query does not match its pinned plan at {step}")
+            }
+            QueryError::ResumeToken { expected, found } => write!(
+                f,
+                "resume token has {found} keys, but the query has {expected} variables"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl From<ExprError> for QueryError {
+    fn from(e: ExprError) -> Self {
+        match e {
+            ExprError::UnknownVariable(name) => QueryError::UnknownVariable(name),
+            e => QueryError::Expr(e),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AtomKind {
+    Join,
+    Exists,
+    Not,
+}
+
+struct Atom<'a, K> {
+    relation: &'a TrieRelation<K>,
+    variables: Vec<String>,
+    kind: AtomKind,
+    /// Whether the last two attributes are the validity of the tuple.
+    temporal: bool,
+}
+
+/// When the tuples of temporal atoms must be valid.
+#[derive(Clone, Copy)]
+enum Validity<K> {
+    At(K),
+    Overlapping,
+}
+
+/// A filter together with the conversion of keys for evaluating it, and
+/// the key it pins its variable to, if any.
+struct Filter<K> {
+    expr: Expr,
+    eval: fn(&Compiled, &[K]) -> bool,
+    pin: Option<K>,
+}
+
+type PredicateFn<'a, K> = Rc<dyn Fn(&[K]) -> bool + 'a>;
+
+/// A generator returns the sorted, distinct values of its output.
+type GeneratorFn<'a, K> = Rc<dyn Fn(&[K]) -> Vec<K> + 'a>;
+
+struct Predicate<'a, K> {
+    variables: Vec<String>,
+    f: PredicateFn<'a, K>,
+}
+
+struct Generator<'a, K> {
+    inputs: Vec<String>,
+    output: String,
+    f: GeneratorFn<'a, K>,
+}
+
+/// Query is a conjunctive query under construction.
+pub struct Query<'a, K> {
+    atoms: Vec<Atom<'a, K>>,
+    generators: Vec<Generator<'a, K>>,
+    filters: Vec<Filter<K>>,
+    predicates: Vec<Predicate<'a, K>>,
+    order: Option<Vec<String>>,
+    validity: Option<Validity<K>>,
+    tuning: Option<Tuning>,
+}
+
+impl<K> Default for Query<'_, K> {
+    fn default() -> Self {
+        Self {
+            atoms: Vec::new(),
+            generators: Vec::new(),
+            filters: Vec::new(),
+            predicates: Vec::new(),
+            order: None,
+            validity: None,
+            tuning: None,
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy> Query<'a, K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the atom `relation(variables...)`.
+    pub fn atom(self, relation: &'a TrieRelation<K>, variables: &[&str]) -> Self {
+        self.push_atom(relation, variables, AtomKind::Join)
+    }
+
+    /// Adds the atom `EXISTS relation(variables...)`. Variables occurring
+    /// only here are existentially quantified and not part of the results.
+    pub fn exists(self, relation: &'a TrieRelation<K>, variables: &[&str]) -> Self {
+        self.push_atom(relation, variables, AtomKind::Exists)
+    }
+
+    /// Adds the atom `NOT relation(variables...)`. All variables must be
+    /// bound by other atoms or generators.
+    pub fn not(self, relation: &'a TrieRelation<K>, variables: &[&str]) -> Self {
+        self.push_atom(relation, variables, AtomKind::Not)
+    }
+
+    /// Adds the temporal atom `relation(variables..., from, to)`, whose last
+    /// two attributes are the validity of its tuples. Which tuples match
+    /// depends on valid_at() or valid_overlapping(); without either, all do.
+    pub fn temporal(mut self, relation: &'a TrieRelation<K>, variables: &[&str]) -> Self {
+        self = self.push_atom(relation, variables, AtomKind::Join);
+        self.atoms.last_mut().unwrap().temporal = true;
+        self
+    }
+
+    /// Matches only tuples of temporal atoms valid at `time`.
+    pub fn valid_at(mut self, time: K) -> Self {
+        self.validity = Some(Validity::At(time));
+        self
+    }
+
+    /// Matches only tuples of temporal atoms that are all valid at some
+    /// common time, see TrieJoin::validity().
+    pub fn valid_overlapping(mut self) -> Self {
+        self.validity = Some(Validity::Overlapping);
+        self
+    }
+
+    fn push_atom(
+        mut self,
+        relation: &'a TrieRelation<K>,
+        variables: &[&str],
+        kind: AtomKind,
+    ) -> Self {
+        self.atoms.push(Atom {
+            relation,
+            variables: variables.iter().map(|v| v.to_string()).collect(),
+            kind,
+            temporal: false,
+        });
+        self
+    }
+
+    /// Adds a predicate on `variables`, checked as soon as they are bound.
+    /// `f` receives their values in the order given.
+    pub fn predicate(mut self, variables: &[&str], f: impl Fn(&[K]) -> bool + 'a) -> Self {
+        self.predicates.push(Predicate {
+            variables: variables.iter().map(|v| v.to_string()).collect(),
+            f: Rc::new(f),
+        });
+        self
+    }
+
+    /// Adds a generator atom, which binds `output` to each value `f` returns
+    /// for the values of `inputs`. The values may come in any order, but must
+    /// be finite. All inputs must precede `output` in the variable order.
+    pub fn generator<I: IntoIterator<Item = K>>(
+        mut self,
+        inputs: &[&str],
+        output: &str,
+        f: impl Fn(&[K]) -> I + 'a,
+    ) -> Self {
+        self.generators.push(Generator {
+            inputs: inputs.iter().map(|v| v.to_string()).collect(),
+            output: output.to_string(),
+            f: Rc::new(move |args| {
+                let mut values: Vec<K> = f(args).into_iter().collect();
+                values.sort_unstable();
+                values.dedup();
+                values
+            }),
+        });
+        self
+    }
+
+    /// Sets the variable order, which must list every variable once.
+    pub fn order(mut self, variables: &[&str]) -> Self {
+        self.order = Some(variables.iter().map(|v| v.to_string()).collect());
+        self
+    }
+
+    /// Executes the query with `tuning`: the seeks into its relations scan
+    /// `tuning.galloping_threshold` keys before they binary search.
+    pub fn with_tuning(mut self, tuning: Tuning) -> Self {
+        self.tuning = Some(tuning);
+        self
+    }
+
+    /// Returns an iterator over `relation` with the tuning of the query.
+    fn iter(&self, relation: &'a TrieRelation<K>) -> TrieIterator<'a, K> {
+        match &self.tuning {
+            Some(tuning) => TrieIterator::with_tuning(relation, tuning),
+            None => relation.iter(),
+        }
+    }
+
+    /// Checks the query and returns an iterator over its results. Every
+    /// result binds the variables in variable order.
+    pub fn execute(&self) -> Result<TrieJoin<'a, K>, QueryError> {
+        let joins = || self.atoms.iter().filter(|a| a.kind == AtomKind::Join);
+        if joins().next().is_none() && self.generators.is_empty() {
+            return Err(QueryError::Empty);
+        }
+        let mut variables: Vec<String> = Vec::new();
+        for (i, atom) in self.atoms.iter().enumerate() {
+            if atom.temporal && atom.variables.is_empty() {
+                return Err(QueryError::TemporalVariables { atom: i });
+            }
+            if atom.variables.len() + 2 * atom.temporal as usize != atom.relation.arity() {
+                return Err(QueryError::Arity {
+                    atom: i,
+                    arity: atom.relation.arity(),
+                    variables: atom.variables.len(),
+                });
+            }
+            for (j, v) in atom.variables.iter().enumerate() {
+                if atom.variables[..j].contains(v) {
+                    return Err(QueryError::RepeatedVariable {
+                        atom: i,
+                        variable: v.clone(),
+                    });
+                }
+            }
+        }
+        for atom in joins() {
+            for v in &atom.variables {
+                if !variables.contains(v) {
+                    variables.push(v.clone());
+                }
+            }
+        }
+        for g in &self.generators {
+            for v in g.inputs.iter().chain([&g.output]) {
+                if !variables.contains(v) {
+                    variables.push(v.clone());
+                }
+            }
+        }
+        if let Some(order) = &self.order {
+            for v in order {
+                if !variables.contains(v) {
+                    return Err(QueryError::UnknownVariable(v.clone()));
+                }
+            }
+            for v in &variables {
+                if order.iter().filter(|&o| o == v).count() != 1 {
+                    return Err(QueryError::InvalidOrder(format!(
+                        "{v:?} must occur exactly once"
+                    )));
+                }
+            }
+            variables = order.clone();
+        }
+        let index_of = |name: &str| variables.iter().position(|v| v == name);
+
+        // Per variable, the iterators of the atoms it occurs in.
+        let mut participants = vec![Vec::new(); variables.len()];
+        let mut iters = Vec::new();
+        let mut joined = Vec::new();
+        // Per temporal atom, its iterator and the index of its last variable.
+        let mut temporal = Vec::new();
+        for (i, atom) in self.atoms.iter().enumerate() {
+            if atom.kind != AtomKind::Join {
+                continue;
+            }
+            let indices: Vec<usize> = atom
+                .variables
+                .iter()
+                .map(|v| index_of(v).unwrap())
+                .collect();
+            if !indices.is_sorted() {
+                return Err(QueryError::AtomOrder { atom: i });
+            }
+            if atom.temporal {
+                temporal.push((iters.len(), *indices.last().unwrap()));
+            }
+            for index in indices {
+                participants[index].push(iters.len());
+            }
+            joined.push((i, iters.len()));
+            iters.push(Source::Trie(self.iter(atom.relation)));
+        }
+        for (i, g) in self.generators.iter().enumerate() {
+            let output = index_of(&g.output).unwrap();
+            if g.inputs.iter().any(|v| index_of(v).unwrap() >= output) {
+                return Err(QueryError::GeneratorOrder { generator: i });
+            }
+            participants[output].push(iters.len());
+            iters.push(Source::Generated {
+                inputs: g.inputs.iter().map(|v| index_of(v).unwrap()).collect(),
+                f: g.f.clone(),
+                values: Vec::new(),
+                pos: 0,
+                peak: 0,
+            });
+        }
+        for filter in &self.filters {
+            let Some(key) = filter.pin else { continue };
+            let (name, _) = filter
+                .expr
+                .pinned()
+                .expect("Pinning filters are equalities");
+            if let Some(index) = index_of(name) {
+                participants[index].push(iters.len());
+                iters.push(Source::Generated {
+                    inputs: Vec::new(),
+                    f: Rc::new(move |_| vec![key]),
+                    values: Vec::new(),
+                    pos: 0,
+                    peak: 0,
+                });
+            }
+        }
+        if let Some(i) = participants.iter().position(Vec::is_empty) {
+            return Err(QueryError::UnboundVariable(variables[i].clone()));
+        }
+
+        let mut checks: Vec<Vec<Check<'a, K>>> = (0..variables.len()).map(|_| Vec::new()).collect();
+        for filter in &self.filters {
+            let (compiled, ty) = filter.expr.compile(&index_of)?;
+            if ty != Type::Bool {
+                return Err(
+                    ExprError::Type(format!("filter {:?} is not boolean", filter.expr)).into(),
+                );
+            }
+            // Filters without variables apply as soon as the first is bound.
+            let depth = compiled.max_var().unwrap_or(0);
+            checks[depth].push(Check::Expr(compiled, filter.eval));
+        }
+        for predicate in &self.predicates {
+            let args = predicate
+                .variables
+                .iter()
+                .map(|v| index_of(v).ok_or_else(|| QueryError::UnknownVariable(v.clone())))
+                .collect::<Result<Vec<_>, _>>()?;
+            let depth = args.iter().copied().max().unwrap_or(0);
+            checks[depth].push(Check::Predicate(args, predicate.f.clone()));
+        }
+        for (i, atom) in self.atoms.iter().enumerate() {
+            if atom.kind == AtomKind::Join {
+                continue;
+            }
+            let pattern: Vec<Option<usize>> = atom.variables.iter().map(|v| index_of(v)).collect();
+            for (v, _) in atom
+                .variables
+                .iter()
+                .zip(&pattern)
+                .filter(|(_, p)| p.is_none())
+            {
+                if atom.kind == AtomKind::Not {
+                    return Err(QueryError::UnsafeNegation {
+                        atom: i,
+                        variable: v.clone(),
+                    });
+                }
+                // Probes are independent, so they cannot share local variables.
+                let uses = self.atoms.iter().filter(|a| a.variables.contains(v));
+                if uses.count() > 1 {
+                    return Err(QueryError::UnboundVariable(v.clone()));
+                }
+            }
+            let depth = pattern.iter().flatten().copied().max().unwrap_or(0);
+            let iter = self.iter(atom.relation);
+            checks[depth].push(match atom.kind {
+                AtomKind::Exists => Check::Exists(iter, pattern),
+                _ => Check::NotExists(iter, pattern),
+            });
+        }
+        match self.validity {
+            Some(Validity::At(time)) => {
+                for &(iter, depth) in &temporal {
+                    checks[depth].push(Check::ValidAt(iter, time));
+                }
+            }
+            Some(Validity::Overlapping) if !temporal.is_empty() => {
+                let depth = temporal.iter().map(|&(_, depth)| depth).max().unwrap();
+                let iters = temporal.iter().map(|&(iter, _)| iter).collect();
+                checks[depth].push(Check::Overlapping(iters));
+            }
+            _ => {}
+        }
+
+        Ok(TrieJoin {
+            iters,
+            joined,
+            levels: participants
+                .into_iter()
+                .map(|atoms| Level {
+                    order: atoms.clone(),
+                    atoms,
+                    pos: 0,
+                    at_end: false,
+                    candidates: 0,
+                    bindings: 0,
+                    seeks: 0,
+                })
+                .collect(),
+            checks,
+            args: Vec::new(),
+            binding: Vec::with_capacity(variables.len()),
+            variables,
+            state: State::Start,
+            hooks: None,
+            after: None,
+            shuffled: None,
+            validity: Vec::new(),
+        })
+    }
+
+    /// Checks the query and describes how it is evaluated.
+    pub fn plan(&self) -> Result<QueryPlan, QueryError> {
+        let variables = self.execute()?.variables().to_vec();
+        let names = |vars: &[String]| vars.to_vec();
+        let bound = |vars: &[String]| {
+            let bound = vars.iter().filter(|v| variables.contains(v));
+            bound.cloned().collect()
+        };
+        let mut steps = Vec::new();
+        for (i, atom) in self.atoms.iter().enumerate() {
+            steps.push(match atom.kind {
+                AtomKind::Join => {
+                    PlanStep::new(format!("atom {i}"), names(&atom.variables), vec![])
+                }
+                AtomKind::Exists => {
+                    PlanStep::new(format!("exists {i}"), vec![], bound(&atom.variables))
+                }
+                AtomKind::Not => PlanStep::new(format!("not {i}"), vec![], bound(&atom.variables)),
+            });
+        }
+        for (i, g) in self.generators.iter().enumerate() {
+            let output = vec![g.output.clone()];
+            steps.push(PlanStep::new(
+                format!("generator {i}"),
+                output,
+                names(&g.inputs),
+            ));
+        }
+        for (i, filter) in self.filters.iter().enumerate() {
+            let reads = filter
+                .expr
+                .variables()
+                .into_iter()
+                .map(String::from)
+                .collect();
+            steps.push(PlanStep::new(format!("filter {i}"), vec![], reads));
+        }
+        for (i, predicate) in self.predicates.iter().enumerate() {
+            let reads = names(&predicate.variables);
+            steps.push(PlanStep::new(format!("predicate {i}"), vec![], reads));
+        }
+        Ok(QueryPlan { variables, steps })
+    }
+
+    /// Prepares the query for executing it many times with constants for
+    /// its placeholders, see PreparedQuery.
+    pub fn prepare(self) -> Result<PreparedQuery<'a, K>, QueryError> {
+        PreparedQuery::new(self)
+    }
+
+    /// Checks the query and pins its plan, to be executed with `tuning`, see
+    /// PinnedPlan.
+    pub fn pin(&self, tuning: Tuning) -> Result<PinnedPlan, QueryError> {
+        Ok(PinnedPlan::new(self.plan()?, tuning))
+    }
+
+    /// Executes the query under the variable order and the tuning of
+    /// `pinned` from now on. Fails with QueryError::PlanMismatch unless the
+    /// query still has the steps it was pinned with.
+    pub fn with_pinned(self, pinned: &PinnedPlan) -> Result<Self, QueryError> {
+        let order: Vec<&str> = pinned.variables().iter().map(String::as_str).collect();
+        let query = self.order(&order).with_tuning(pinned.tuning);
+        let steps = query.plan()?.steps;
+        let expected = &pinned.plan.steps;
+        if steps != *expected {
+            let differs = steps.iter().zip(expected).find(|(s, e)| s != e);
+            let step = match differs {
+                Some((step, _)) => step.label.clone(),
+                None => format!("step {}", steps.len().min(expected.len())),
+            };
+            return Err(QueryError::PlanMismatch(step));
+        }
+        Ok(query)
+    }
+
+    /// Executes the query and collects all results.
+    pub fn run(&self) -> Result<Vec<Vec<K>>, QueryError> {
+        Ok(self.execute()?.collect())
+    }
+
+    /// Collects all results like run(), and runs the query once more under
+    /// a random variable order drawn from `seed`, with the relations of its
+    /// atoms permuted to match. Fails with QueryError::Mismatch unless both
+    /// runs find the same results, which catches bugs that only show under
+    /// some orders, at the cost of a second run and of copying the
+    /// relations.
+    pub fn verify(&self, seed: u64) -> Result<Vec<Vec<K>>, QueryError> {
+        let join = self.execute()?;
+        let variables = join.variables().to_vec();
+        let results: Vec<Vec<K>> = join.collect();
+
+        let order = self.random_order(&variables, seed);
+        let position = |v: &String| order.iter().position(|o| o == v);
+        let permuted: Vec<Option<(TrieRelation<K>, Vec<String>)>> = self
+            .atoms
+            .iter()
+            .map(|atom| {
+                (atom.kind == AtomKind::Join).then(|| {
+                    let mut attributes: Vec<usize> = (0..atom.variables.len()).collect();
+                    attributes.sort_by_key(|&a| position(&atom.variables[a]));
+                    let variables = attributes.iter().map(|&a| atom.variables[a].clone());
+                    let variables: Vec<String> = variables.collect();
+                    // The validity stays last.
+                    attributes.extend(atom.variables.len()..atom.relation.arity());
+                    (atom.relation.permuted(&attributes), variables)
+                })
+            })
+            .collect();
+        let atoms = self.atoms.iter().zip(&permuted).map(|(atom, permuted)| {
+            let (relation, variables) = match permuted {
+                Some((relation, variables)) => (relation, variables.clone()),
+                None => (atom.relation, atom.variables.clone()),
+            };
+            Atom {
+                relation,
+                variables,
+                kind: atom.kind,
+                temporal: atom.temporal,
+            }
+        });
+        let generators = self.generators.iter().map(|g| Generator {
+            inputs: g.inputs.clone(),
+            output: g.output.clone(),
+            f: g.f.clone(),
+        });
+        let filters = self.filters.iter().map(|f| Filter {
+            expr: f.expr.clone(),
+            eval: f.eval,
+            pin: f.pin,
+        });
+        let predicates = self.predicates.iter().map(|p| Predicate {
+            variables: p.variables.clone(),
+            f: p.f.clone(),
+        });
+        let other = Query {
+            atoms: atoms.collect(),
+            generators: generators.collect(),
+            filters: filters.collect(),
+            predicates: predicates.collect(),
+            order: Some(order.clone()),
+            validity: self.validity,
+            tuning: self.tuning,
+        };
+
+        let columns: Vec<usize> = variables.iter().map(|v| position(v).unwrap()).collect();
+        let mut other_results: Vec<Vec<K>> = other
+            .execute()?
+            .map(|result| columns.iter().map(|&c| result[c]).collect())
+            .collect();
+        other_results.sort_unstable();
+        let (mut missing, mut extra) = (0, 0);
+        let (mut i, mut j) = (0, 0);
+        while i < results.len() || j < other_results.len() {
+            match (results.get(i), other_results.get(j)) {
+                (Some(a), Some(b)) if a == b => (i, j) = (i + 1, j + 1),
+                (Some(a), Some(b)) if a < b => (i, missing) = (i + 1, missing + 1),
+                (Some(_), None) => (i, missing) = (i + 1, missing + 1),
+                _ => (j, extra) = (j + 1, extra + 1),
+            }
+        }
+        if missing + extra > 0 {
+            return Err(QueryError::Mismatch {
+                order,
+                missing,
+                extra,
+            });
+        }
+        Ok(results)
+    }
+
+    /// Returns a random order of `variables` in which the inputs of every
+    /// generator precede its output, and which differs from the given one
+    /// unless no other order is found in a few tries.
+    fn random_order(&self, variables: &[String], seed: u64) -> Vec<String> {
+        let mut rng = Rng::new(seed);
+        for _ in 0..8 {
+            let mut order: Vec<String> = Vec::with_capacity(variables.len());
+            let mut remaining = variables.to_vec();
+            while !remaining.is_empty() {
+                let ready: Vec<usize> = (0..remaining.len())
+                    .filter(|&i| {
+                        let mut generators = self.generators.iter();
+                        generators.all(|g| {
+                            g.output != remaining[i] || g.inputs.iter().all(|v| order.contains(v))
+                        })
+                    })
+                    .collect();
+                let pick = ready[rng.index(ready.len())];
+                order.push(remaining.remove(pick));
+            }
+            if order != variables {
+                return order;
+            }
+        }
+        variables.to_vec()
+    }
+}
+
+impl<K: Ord + ExprKey> Query<'_, K> {
+    /// Adds a boolean filter. It is evaluated as soon as its variables are
+    /// bound, so failing bindings are never extended.
+    pub fn filter(mut self, expr: Expr) -> Self {
+        let pin = expr.pinned().and_then(|(_, c)| K::from_i128(c));
+        self.filters.push(Filter {
+            expr,
+            eval: Compiled::eval_bool::<K>,
+            pin,
+        });
+        self
+    }
+}
+
+/// QueryPlan describes the evaluation of a query: the variable order, and
+/// per atom, generator, filter and predicate the variables it binds or
+/// reads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryPlan {
+    pub variables: Vec<String>,
+    pub steps: Vec<PlanStep>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlanStep {
+    /// Like "atom 0" or "filter 1", numbered per kind in order of addition.
+    pub label: String,
+    /// The variables leapfrogged or generated by the step.
+    pub binds: Vec<String>,
+    /// The variables the step checks or computes from.
+    pub reads: Vec<String>,
+}
+
+impl PlanStep {
+    fn new(label: String, binds: Vec<String>, reads: Vec<String>) -> Self {
+        Self {
+            label,
+            binds,
+            reads,
+        }
+    }
+}
+
+impl QueryPlan {
+    /// Renders the plan as a Graphviz digraph: the variables form a chain in
+    /// variable order, steps point to the variables they bind, and variables
+    /// point to the steps reading them with dashed edges.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph plan {{").unwrap();
+        writeln!(dot, "  rankdir=LR;").unwrap();
+        writeln!(dot, "  node [shape=ellipse];").unwrap();
+        for (i, v) in self.variables.iter().enumerate() {
+            writeln!(dot, "  v{i} [label=\"{}. {v}\"];", i + 1).unwrap();
+        }
+        if self.variables.len() > 1 {
+            let chain: Vec<String> = (0..self.variables.len()).map(|i| format!("v{i}")).collect();
+            writeln!(dot, "  {} [style=bold];", chain.join(" -> ")).unwrap();
+        }
+        let index_of = |name: &String| self.variables.iter().position(|v| v == name).unwrap();
+        for (s, step) in self.steps.iter().enumerate() {
+            writeln!(dot, "  s{s} [label=\"{}\", shape=box];", step.label).unwrap();
+            for v in &step.binds {
+                writeln!(dot, "  s{s} -> v{};", index_of(v)).unwrap();
+            }
+            for v in &step.reads {
+                writeln!(dot, "  v{} -> s{s} [style=dashed];", index_of(v)).unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+/// The iterator of an atom: a trie iterator for relations, or the values of
+/// a generator for the current binding of its inputs.
+enum Source<'a, K> {
+    Trie(TrieIterator<'a, K>),
+    Generated {
+        inputs: Vec<usize>,
+        f: GeneratorFn<'a, K>,
+        values: Vec<K>,
+        pos: usize,
+        /// The most values held at once.
+        peak: usize,
+    },
+}
+
+impl<K: Ord + Copy> Source<'_, K> {
+    fn open(&mut self, binding: &[K]) {
+        match self {
+            Source::Trie(iter) => iter.open(),
+            Source::Generated {
+                inputs,
+                f,
+                values,
+                pos,
+                peak,
+            } => {
+                let args: Vec<K> = inputs.iter().map(|&i| binding[i]).collect();
+                *values = f(&args);
+                *pos = 0;
+                *peak = (*peak).max(values.capacity());
+            }
+        }
+    }
+
+    fn up(&mut self) {
+        match self {
+            Source::Trie(iter) => iter.up(),
+            Source::Generated { values, .. } => values.clear(),
+        }
+    }
+}
+
+impl<K: Ord + Copy> Seekable for Source<'_, K> {
+    type Key = K;
+
+    fn key(&self) -> K {
+        match self {
+            Source::Trie(iter) => iter.key(),
+            Source::Generated { values, pos, .. } => values[*pos],
+        }
+    }
+
+    fn next(&mut self) {
+        match self {
+            Source::Trie(iter) => iter.next(),
+            Source::Generated { pos, .. } => *pos += 1,
+        }
+    }
+
+    fn seek(&mut self, seek_key: K) {
+        match self {
+            Source::Trie(iter) => iter.seek(seek_key),
+            Source::Generated { values, pos, .. } => {
+                *pos += values[*pos..].partition_point(|&k| k < seek_key)
+            }
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        match self {
+            Source::Trie(iter) => iter.at_end(),
+            Source::Generated { values, pos, .. } => *pos >= values.len(),
+        }
+    }
+}
+
+/// A condition on the binding, checked once its last variable is bound.
+enum Check<'a, K> {
+    Expr(Compiled, fn(&Compiled, &[K]) -> bool),
+    /// A predicate and the binding indices of its arguments.
+    Predicate(Vec<usize>, PredicateFn<'a, K>),
+    /// An EXISTS atom and, per attribute, the binding index of its variable
+    /// or None for local variables.
+    Exists(TrieIterator<'a, K>, Vec<Option<usize>>),
+    /// A negated atom, with the binding indices of its variables.
+    NotExists(TrieIterator<'a, K>, Vec<Option<usize>>),
+    /// A temporal atom, by its iterator, whose tuple must be valid at the
+    /// time.
+    ValidAt(usize, K),
+    /// The temporal atoms, by their iterators, whose tuples must be valid
+    /// at a common time.
+    Overlapping(Vec<usize>),
+}
+
+/// Checks whether the tuple at the current key of the temporal `iter` is
+/// valid at `time`.
+fn valid_at<K: Ord + Copy>(iter: &mut TrieIterator<'_, K>, time: K) -> bool {
+    let mut found = false;
+    iter.open();
+    while !found && !iter.at_end() && iter.key() <= time {
+        iter.open();
+        iter.seek(time);
+        if !iter.at_end() && iter.key() == time {
+            iter.next();
+        }
+        found = !iter.at_end();
+        iter.up();
+        iter.next();
+    }
+    iter.up();
+    found
+}
+
+/// Returns the sorted, disjoint intervals in which the tuple at the
+/// current key of the temporal `iter` is valid.
+fn validity_of<K: Ord + Copy>(iter: &mut TrieIterator<'_, K>) -> Vec<(K, K)> {
+    let mut intervals: Vec<(K, K)> = Vec::new();
+    iter.open();
+    while !iter.at_end() {
+        let from = iter.key();
+        iter.open();
+        while !iter.at_end() {
+            let to = iter.key();
+            // Intervals come sorted by from; merge overlapping ones.
+            match intervals.last_mut() {
+                _ if to <= from => {}
+                Some(last) if last.1 >= from => last.1 = last.1.max(to),
+                _ => intervals.push((from, to)),
+            }
+            iter.next();
+        }
+        iter.up();
+        iter.next();
+    }
+    iter.up();
+    intervals
+}
+
+/// Intersects two lists of sorted, disjoint intervals.
+fn intersect<K: Ord + Copy>(a: &[(K, K)], b: &[(K, K)]) -> Vec<(K, K)> {
+    let (mut i, mut j) = (0, 0);
+    let mut both = Vec::new();
+    while i < a.len() && j < b.len() {
+        let (from, to) = (a[i].0.max(b[j].0), a[i].1.min(b[j].1));
+        if from < to {
+            both.push((from, to));
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    both
+}
+
+/// The trie iterator of a join atom.
+fn trie<'b, 'a, K>(iters: &'b mut [Source<'a, K>], i: usize) -> &'b mut TrieIterator<'a, K> {
+    match &mut iters[i] {
+        Source::Trie(iter) => iter,
+        Source::Generated { .. } => unreachable!("Join atoms are tries"),
+    }
+}
+
+/// Checks whether the trie below the current key of `iter` has a path
+/// matching `pattern`, stopping at the first match.
+fn probe<K: Ord + Copy>(
+    iter: &mut TrieIterator<'_, K>,
+    pattern: &[Option<usize>],
+    binding: &[K],
+) -> bool {
+    if pattern.iter().all(Option::is_none) {
+        return iter.depth() > 0 || !iter.relation().is_empty();
+    }
+    iter.open();
+    let mut found = false;
+    match pattern[0] {
+        Some(i) => {
+            if !iter.at_end() {
+                iter.seek(binding[i]);
+                found = !iter.at_end()
+                    && iter.key() == binding[i]
+                    && probe(iter, &pattern[1..], binding);
+            }
+        }
+        None => {
+            while !found && !iter.at_end() {
+                found = probe(iter, &pattern[1..], binding);
+                iter.next();
+            }
+        }
+    }
+    iter.up();
+    found
+}
+
+/// The atoms of one variable and the leapfrog state among their iterators.
+struct Level {
+    atoms: Vec<usize>,
+    /// The atoms sorted by the key of their iterator, cyclically from pos.
+    order: Vec<usize>,
+    pos: usize,
+    at_end: bool,
+    /// Keys the iterators agreed on, bindings that passed the checks, and
+    /// seeks.
+    candidates: u64,
+    bindings: u64,
+    seeks: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    /// The current binding was returned; the last level must advance.
+    Emitted,
+    /// The budget of steps ran out; the enumeration continues as is.
+    Paused,
+    Done,
+}
+
+/// Budget limits how much TrieJoin::run_for() does before returning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    results: usize,
+    steps: u64,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Budget {
+    /// An unlimited budget.
+    pub fn new() -> Self {
+        Self {
+            results: usize::MAX,
+            steps: u64::MAX,
+        }
+    }
+
+    /// Returns after `results` results.
+    pub fn results(mut self, results: usize) -> Self {
+        self.results = results;
+        self
+    }
+
+    /// Returns after `steps` steps, each considering one candidate key or
+    /// leaving one exhausted level.
+    pub fn steps(mut self, steps: u64) -> Self {
+        self.steps = steps;
+        self
+    }
+}
+
+/// Resume is a continuation token: the position after the last result a
+/// TrieJoin returned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resume<K> {
+    after: Option<Vec<K>>,
+}
+
+impl<K> Resume<K> {
+    /// A token resuming after `result`.
+    pub fn after(result: Vec<K>) -> Self {
+        Self {
+            after: Some(result),
+        }
+    }
+
+    /// The last result returned, or None if there was none.
+    pub fn last(&self) -> Option<&[K]> {
+        self.after.as_deref()
+    }
+}
+
+/// Results of TrieJoin::run_for().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partial<K> {
+    pub results: Vec<Vec<K>>,
+    /// None if the enumeration is complete.
+    pub resume: Option<Resume<K>>,
+}
+
+/// What a TrieJoin does after JoinHooks::enter() saw a binding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visit {
+    /// Binds the next variable, or returns the result at the last one.
+    Descend,
+    /// Skips all results extending the binding.
+    Prune,
+    /// Ends the enumeration.
+    Stop,
+}
+
+/// JoinHooks observe and steer a TrieJoin as it binds variables.
+///
+/// `binding` holds the values of the variables up to and including the one
+/// at `depth`, in variable order.
+pub trait JoinHooks<K> {
+    /// Called when the variable at `depth` is bound and passed its checks.
+    fn enter(&mut self, depth: usize, binding: &[K]) -> Visit {
+        let _ = (depth, binding);
+        Visit::Descend
+    }
+
+    /// Called when the variable at `depth` is unbound again, for every
+    /// binding enter() returned Descend for, including on Stop.
+    fn leave(&mut self, depth: usize, binding: &[K]) {
+        let _ = (depth, binding);
+    }
+}
+
+/// TrieJoin enumerates the results of a query in lexicographic order of the
+/// variable order.
+pub struct TrieJoin<'a, K> {
+    iters: Vec<Source<'a, K>>,
+    /// Per join atom, its index in the query and the index of its iterator.
+    joined: Vec<(usize, usize)>,
+    levels: Vec<Level>,
+    /// Per variable, the checks to apply once it is bound.
+    checks: Vec<Vec<Check<'a, K>>>,
+    /// Scratch space for predicate arguments.
+    args: Vec<K>,
+    variables: Vec<String>,
+    binding: Vec<K>,
+    state: State,
+    hooks: Option<Box<dyn JoinHooks<K> + 'a>>,
+    /// The last result run_for() returned or resumed after.
+    after: Option<Vec<K>>,
+    shuffled: Option<Shuffled<K>>,
+    /// The intervals the temporal atoms of the binding are valid in.
+    validity: Vec<(K, K)>,
+}
+
+/// The state of a TrieJoin enumerating in random order.
+struct Shuffled<K> {
+    rng: Rng,
+    /// Per variable, the keys of its level in random order and the index
+    /// of the current one.
+    keys: Vec<(Vec<K>, usize)>,
+}
+
+impl<'a, K: Ord + Copy> TrieJoin<'a, K> {
+    /// Installs `hooks`, replacing any installed before.
+    pub fn hooks(mut self, hooks: impl JoinHooks<K> + 'a) -> Self {
+        self.hooks = Some(Box::new(hooks));
+        self
+    }
+
+    /// Enumerates the bindings of every variable in a random order drawn
+    /// from `seed`, rather than ascending. Every result is still returned
+    /// exactly once.
+    pub fn shuffled(mut self, seed: u64) -> Self {
+        assert!(self.state == State::Start, "The join already started");
+        self.shuffled = Some(Shuffled {
+            rng: Rng::new(seed),
+            keys: vec![(Vec::new(), 0); self.variables.len()],
+        });
+        self
+    }
+
+    /// Turns the join, which must not have started, into a sampler of its
+    /// results, drawing from `seed`. Every variable must occur in an atom.
+    pub fn sampler(self, seed: u64) -> Result<JoinSampler<'a, K>, QueryError> {
+        assert!(self.state == State::Start, "The join already started");
+        // Per iterator, the trie levels opened before the current variable.
+        let mut opened = vec![0; self.iters.len()];
+        let mut bounds = Vec::with_capacity(self.levels.len());
+        for (level, variable) in self.levels.iter().zip(&self.variables) {
+            let mut bound: Option<usize> = None;
+            for &atom in &level.atoms {
+                if let Source::Trie(iter) = &self.iters[atom] {
+                    let extent = max_extent(iter.relation(), opened[atom]);
+                    bound = Some(bound.map_or(extent, |b| b.min(extent)));
+                }
+                opened[atom] += 1;
+            }
+            bounds.push(bound.ok_or_else(|| QueryError::Unsampled(variable.clone()))?);
+        }
+        Ok(JoinSampler {
+            join: self,
+            rng: Rng::new(seed),
+            bounds,
+            trials: 0,
+            accepted: 0,
+        })
+    }
+
+    /// The order of the results: sorted by all variables and distinct, or
+    /// only distinct if shuffled.
+    pub fn output_order(&self) -> OutputOrder {
+        let order = match self.shuffled {
+            Some(_) => OutputOrder::unordered(),
+            None => {
+                let variables: Vec<&str> = self.variables.iter().map(String::as_str).collect();
+                OutputOrder::sorted_by(&variables)
+            }
+        };
+        order.distinct()
+    }
+
+    /// Returns the next results within `budget`, and a token to resume
+    /// after them unless the enumeration is complete.
+    pub fn run_for(&mut self, budget: Budget) -> Partial<K> {
+        if self.state == State::Emitted {
+            self.after = Some(self.binding.clone());
+        }
+        let mut steps = budget.steps;
+        let mut results = Vec::new();
+        while results.len() < budget.results {
+            match self.next_within(&mut steps) {
+                Some(result) => results.push(result),
+                None => break,
+            }
+        }
+        if let Some(last) = results.last() {
+            self.after = Some(last.clone());
+        }
+        let resume = (self.state != State::Done).then(|| Resume {
+            after: self.after.clone(),
+        });
+        Partial { results, resume }
+    }
+
+    /// Continues the enumeration of a join not started yet after the result
+    /// of `token`. Fails with QueryError::ResumeToken if the token cannot
+    /// stem from a join of the same query. Shuffled joins cannot resume, as
+    /// they do not enumerate in order.
+    pub fn resume(mut self, token: &Resume<K>) -> Result<Self, QueryError> {
+        assert!(self.state == State::Start, "The join already started");
+        assert!(self.shuffled.is_none(), "Shuffled joins cannot resume");
+        if let Some(after) = &token.after
+            && after.len() != self.variables.len()
+        {
+            return Err(QueryError::ResumeToken {
+                expected: self.variables.len(),
+                found: after.len(),
+            });
+        }
+        self.after = token.after.clone();
+        if let Some(after) = &token.after {
+            self.enter(0);
+            self.state = State::Paused;
+            self.restore(after);
+        }
+        Ok(self)
+    }
+
+    /// Binds the prefix of `after` the levels still contain, leaving the
+    /// join where it was after returning `after`.
+    fn restore(&mut self, after: &[K]) {
+        for (depth, &key) in after.iter().enumerate() {
+            let level = &mut self.levels[depth];
+            if !level.at_end && self.iters[level.order[level.pos]].key() < key {
+                let iter = &mut self.iters[level.order[level.pos]];
+                iter.seek(key);
+                level.seeks += 1;
+                if iter.at_end() {
+                    level.at_end = true;
+                } else {
+                    level.pos = (level.pos + 1) % level.order.len();
+                    self.search(depth);
+                }
+            }
+            let level = &self.levels[depth];
+            if level.at_end || self.iters[level.order[level.pos]].key() != key {
+                return;
+            }
+            self.binding.push(key);
+            if !self.passes_checks(depth) {
+                self.binding.pop();
+                self.advance(depth);
+                return;
+            }
+            match self.visit(depth) {
+                Visit::Descend => {}
+                Visit::Prune => {
+                    self.binding.pop();
+                    self.advance(depth);
+                    return;
+                }
+                Visit::Stop => return self.stop(),
+            }
+            if depth + 1 == after.len() {
+                self.state = State::Emitted;
+                return;
+            }
+            self.enter(depth + 1);
+        }
+    }
+
+    /// The variables, in the order they appear in results.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Returns the provenance of the result last returned: for every join
+    /// atom, its index in the query and the row of the tuple of its relation
+    /// that matched. EXISTS and negated atoms are not included.
+    pub fn provenance(&self) -> Vec<(usize, usize)> {
+        assert!(self.state == State::Emitted, "No current result");
+        self.joined
+            .iter()
+            .map(|&(atom, iter)| match &self.iters[iter] {
+                Source::Trie(iter) => (atom, iter.row()),
+                Source::Generated { .. } => unreachable!("Join atoms are tries"),
+            })
+            .collect()
+    }
+
+    /// Under Query::valid_overlapping(), returns the sorted, disjoint
+    /// intervals in which the tuples of all temporal atoms of the result
+    /// last returned are valid.
+    pub fn validity(&self) -> &[(K, K)] {
+        assert!(self.state == State::Emitted, "No current result");
+        &self.validity
+    }
+
+    /// Reports the work done so far, typically after all results were
+    /// returned.
+    pub fn report(&self) -> JoinReport {
+        // The variables of every join atom, as indices into the variable order.
+        let mut atoms: Vec<Vec<usize>> = vec![Vec::new(); self.iters.len()];
+        for (depth, level) in self.levels.iter().enumerate() {
+            for &atom in &level.atoms {
+                atoms[atom].push(depth);
+            }
+        }
+        let relations = self
+            .joined
+            .iter()
+            .map(|&(_, iter)| match &self.iters[iter] {
+                Source::Trie(trie) => (trie.relation().len(), atoms[iter].clone()),
+                Source::Generated { .. } => unreachable!("Join atoms are tries"),
+            });
+        JoinReport {
+            agm_bound: agm_bound(&relations.collect::<Vec<_>>(), self.variables.len()),
+            peak_memory: self.peak_memory(),
+            levels: self
+                .levels
+                .iter()
+                .zip(&self.variables)
+                .map(|(level, variable)| LevelStats {
+                    variable: variable.clone(),
+                    candidates: level.candidates,
+                    bindings: level.bindings,
+                    seeks: level.seeks,
+                })
+                .collect(),
+        }
+    }
+
+    /// Bytes of the buffers of the join at the most they held, see
+    /// JoinReport::peak_memory.
+    fn peak_memory(&self) -> usize {
+        let keys = |n: usize| n * size_of::<K>();
+        let generated = self.iters.iter().map(|iter| match iter {
+            Source::Trie(_) => 0,
+            Source::Generated { peak, .. } => keys(*peak),
+        });
+        let generated: usize = generated.sum();
+        let levels = self.levels.iter().map(|level| {
+            let indices = level.atoms.capacity() + level.order.capacity();
+            size_of::<Level>() + indices * size_of::<usize>()
+        });
+        // Shuffled levels reuse their buffer, which only grows.
+        let shuffled = self.shuffled.iter().flat_map(|s| &s.keys);
+        let shuffled: usize = shuffled.map(|(keys, _)| keys.capacity()).sum();
+        let scratch = self.binding.capacity() + self.args.capacity();
+        let after = self.after.as_ref().map_or(0, Vec::capacity);
+        generated
+            + levels.sum::<usize>()
+            + keys(shuffled + scratch + after + 2 * self.validity.capacity())
+    }
+
+    /// Opens the iterators of the variable at `depth` and finds its first key.
+    fn enter(&mut self, depth: usize) {
+        let level = &mut self.levels[depth];
+        for &atom in &level.atoms {
+            self.iters[atom].open(&self.binding);
+        }
+        level.at_end = level.atoms.iter().any(|&a| self.iters[a].at_end());
+        if !level.at_end {
+            let iters = &self.iters;
+            level
+                .order
+                .sort_by(|&a, &b| cmp_seekable(&iters[a], &iters[b]));
+            level.pos = 0;
+            self.search(depth);
+        }
+        if self.shuffled.is_some() {
+            self.shuffle(depth);
+        }
+    }
+
+    /// Collects the keys of the level at `depth`, shuffles them and moves
+    /// to the first.
+    fn shuffle(&mut self, depth: usize) {
+        let shuffled = self.shuffled.as_mut().unwrap();
+        let mut keys = std::mem::take(&mut shuffled.keys[depth].0);
+        keys.clear();
+        while !self.levels[depth].at_end {
+            let level = &self.levels[depth];
+            keys.push(self.iters[level.order[level.pos]].key());
+            self.leapfrog_next(depth);
+        }
+        let shuffled = self.shuffled.as_mut().unwrap();
+        shuffled.rng.shuffle(&mut keys);
+        shuffled.keys[depth] = (keys, 0);
+        self.jump(depth);
+    }
+
+    /// Moves the iterators of the level at `depth` to its current key in
+    /// random order, by opening the level again and seeking to it.
+    fn jump(&mut self, depth: usize) {
+        let (keys, i) = &self.shuffled.as_ref().unwrap().keys[depth];
+        let level = &mut self.levels[depth];
+        level.at_end = *i >= keys.len();
+        if level.at_end {
+            return;
+        }
+        for &atom in &level.atoms {
+            let iter = &mut self.iters[atom];
+            iter.up();
+            iter.open(&self.binding);
+            iter.seek(keys[*i]);
+        }
+        level.seeks += level.atoms.len() as u64;
+        level.pos = 0;
+    }
+
+    fn leave(&mut self, depth: usize) {
+        for &atom in &self.levels[depth].atoms {
+            self.iters[atom].up();
+        }
+    }
+
+    fn search(&mut self, depth: usize) {
+        let level = &mut self.levels[depth];
+        let n = level.order.len();
+        let mut max_key = self.iters[level.order[(level.pos + n - 1) % n]].key();
+        loop {
+            let iter = &mut self.iters[level.order[level.pos]];
+            if iter.key() == max_key {
+                return;
+            }
+            iter.seek(max_key);
+            level.seeks += 1;
+            if iter.at_end() {
+                level.at_end = true;
+                return;
+            }
+            max_key = iter.key();
+            level.pos = (level.pos + 1) % n;
+        }
+    }
+
+    fn advance(&mut self, depth: usize) {
+        match &mut self.shuffled {
+            Some(shuffled) => {
+                shuffled.keys[depth].1 += 1;
+                self.jump(depth);
+            }
+            None => self.leapfrog_next(depth),
+        }
+    }
+
+    /// Moves the level at `depth` to its next key in ascending order.
+    fn leapfrog_next(&mut self, depth: usize) {
+        let level = &mut self.levels[depth];
+        let iter = &mut self.iters[level.order[level.pos]];
+        iter.next();
+        if iter.at_end() {
+            level.at_end = true;
+        } else {
+            level.pos = (level.pos + 1) % level.order.len();
+            self.search(depth);
+        }
+    }
+
+    /// Finds the next result, or returns None when the enumeration is done or
+    /// paused since `steps` ran out.
+    fn next_within(&mut self, steps: &mut u64) -> Option<Vec<K>> {
+        match self.state {
+            State::Done => return None,
+            State::Start => self.enter(0),
+            State::Emitted => {
+                self.unbind();
+                self.advance(self.binding.len());
+            }
+            State::Paused => {}
+        }
+        loop {
+            if *steps == 0 {
+                self.state = State::Paused;
+                return None;
+            }
+            *steps -= 1;
+            let depth = self.binding.len();
+            if self.levels[depth].at_end {
+                self.leave(depth);
+                if depth == 0 {
+                    self.state = State::Done;
+                    return None;
+                }
+                self.unbind();
+                self.advance(depth - 1);
+                continue;
+            }
+            let level = &mut self.levels[depth];
+            self.binding.push(self.iters[level.order[level.pos]].key());
+            level.candidates += 1;
+            if !self.passes_checks(depth) {
+                self.binding.pop();
+                self.advance(depth);
+                continue;
+            }
+            self.levels[depth].bindings += 1;
+            match self.visit(depth) {
+                Visit::Descend => {}
+                Visit::Prune => {
+                    self.binding.pop();
+                    self.advance(depth);
+                    continue;
+                }
+                Visit::Stop => {
+                    self.stop();
+                    return None;
+                }
+            }
+            if depth + 1 == self.variables.len() {
+                self.state = State::Emitted;
+                return Some(self.binding.clone());
+            }
+            self.enter(depth + 1);
+        }
+    }
+
+    /// Asks the hooks about the binding of the variable at `depth`.
+    fn visit(&mut self, depth: usize) -> Visit {
+        match &mut self.hooks {
+            Some(hooks) => hooks.enter(depth, &self.binding),
+            None => Visit::Descend,
+        }
+    }
+
+    /// Ends the enumeration on Visit::Stop for the last variable bound.
+    fn stop(&mut self) {
+        self.binding.pop();
+        while !self.binding.is_empty() {
+            self.unbind();
+        }
+        self.state = State::Done;
+    }
+
+    /// Unbinds the last variable bound, telling the hooks.
+    fn unbind(&mut self) {
+        if let Some(hooks) = &mut self.hooks {
+            hooks.leave(self.binding.len() - 1, &self.binding);
+        }
+        self.binding.pop();
+    }
+
+    fn passes_checks(&mut self, depth: usize) -> bool {
+        let binding = &self.binding;
+        let args = &mut self.args;
+        let iters = &mut self.iters;
+        let validity = &mut self.validity;
+        self.checks[depth].iter_mut().all(|check| match check {
+            Check::Expr(compiled, eval) => eval(compiled, binding),
+            Check::Predicate(indices, f) => {
+                args.clear();
+                args.extend(indices.iter().map(|&i| binding[i]));
+                f(args)
+            }
+            Check::Exists(iter, pattern) => probe(iter, pattern, binding),
+            Check::NotExists(iter, pattern) => !probe(iter, pattern, binding),
+            Check::ValidAt(iter, time) => valid_at(trie(iters, *iter), *time),
+            Check::Overlapping(atoms) => {
+                *validity = validity_of(trie(iters, atoms[0]));
+                for &atom in &atoms[1..] {
+                    *validity = intersect(validity, &validity_of(trie(iters, atom)));
+                }
+                !validity.is_empty()
+            }
+        })
+    }
+}
+
+/// The largest number of rows of `relation` below one node on the trie level
+/// of `attribute`.
+fn max_extent<K: Ord + Copy>(relation: &TrieRelation<K>, attribute: usize) -> usize {
+    let len = relation.len();
+    if attribute == 0 {
+        return len;
+    }
+    let columns: Vec<&[K]> = (0..attribute).map(|a| relation.column(a)).collect();
+    let (mut max, mut start) = (0, 0);
+    for row in 1..=len {
+        if row == len || columns.iter().any(|c| c[row] != c[row - 1]) {
+            max = max.max(row - start);
+            start = row;
+        }
+    }
+    max
+}
+
+/// JoinSampler draws uniform random results of a TrieJoin, with
+/// replacement.
+pub struct JoinSampler<'a, K> {
+    join: TrieJoin<'a, K>,
+    rng: Rng,
+    /// Per variable, a bound on the rows below the node of its smallest
+    /// trie, over all bindings of the variables before it.
+    bounds: Vec<usize>,
+    trials: u64,
+    accepted: u64,
+}
+
+impl<K: Ord + Copy> JoinSampler<'_, K> {
+    /// Returns a uniform random result, or None if no trial out of
+    /// `max_trials` was accepted, e.g. since there are no results.
+    pub fn sample(&mut self, max_trials: u64) -> Option<Vec<K>> {
+        (0..max_trials).find_map(|_| self.trial())
+    }
+
+    /// Returns up to `k` uniform random results, drawn independently within
+    /// `max_trials` trials in total.
+    pub fn samples(&mut self, k: usize, max_trials: u64) -> Vec<Vec<K>> {
+        let mut samples = Vec::with_capacity(k);
+        let mut trials = 0;
+        while samples.len() < k && trials < max_trials {
+            trials += 1;
+            samples.extend(self.trial());
+        }
+        samples
+    }
+
+    /// Trials run so far, and how many of them were accepted.
+    pub fn trials(&self) -> (u64, u64) {
+        (self.trials, self.accepted)
+    }
+
+    /// Estimates the number of results from the acceptance rate so far, or
+    /// returns None before the first trial.
+    pub fn estimate(&self) -> Option<f64> {
+        let space: f64 = self.bounds.iter().map(|&b| b as f64).product();
+        (self.trials > 0).then(|| self.accepted as f64 / self.trials as f64 * space)
+    }
+
+    /// Descends to one random binding. Every result is reached and accepted
+    /// with probability 1 / the product of the bounds.
+    fn trial(&mut self) -> Option<Vec<K>> {
+        self.trials += 1;
+        self.join.binding.clear();
+        let mut weight = 1.0;
+        let mut opened = 0;
+        let mut found = true;
+        while found && opened < self.bounds.len() {
+            for &atom in &self.join.levels[opened].atoms {
+                self.join.iters[atom].open(&self.join.binding);
+            }
+            opened += 1;
+            found = self.pick(opened - 1, &mut weight);
+        }
+        for depth in (0..opened).rev() {
+            self.join.leave(depth);
+        }
+        // A uniform number in [0, 1) with 53 random bits.
+        let draw = (self.rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        if !found || draw >= weight {
+            return None;
+        }
+        self.accepted += 1;
+        Some(self.join.binding.clone())
+    }
+
+    /// Binds the variable at `depth` to the key of a random row of its
+    /// smallest trie, multiplying `weight` by the probability that corrects
+    /// for the size of its subtree. Returns false if the other atoms lack
+    /// the key or a check fails.
+    fn pick(&mut self, depth: usize, weight: &mut f64) -> bool {
+        let join = &mut self.join;
+        let atoms = &join.levels[depth].atoms;
+        let smallest = atoms
+            .iter()
+            .filter_map(|&atom| match &join.iters[atom] {
+                Source::Trie(iter) => Some((atom, iter.extent())),
+                Source::Generated { .. } => None,
+            })
+            .min_by_key(|&(_, (lo, hi))| hi - lo);
+        let Some((smallest, (lo, hi))) = smallest else {
+            unreachable!("Sampled variables occur in an atom")
+        };
+        if lo == hi {
+            return false;
+        }
+        let Source::Trie(iter) = &mut join.iters[smallest] else {
+            unreachable!()
+        };
+        let key = iter.relation().column(iter.depth() - 1)[lo + self.rng.index(hi - lo)];
+        iter.seek(key);
+        let (start, end) = iter.run();
+        *weight *= (hi - lo) as f64 / ((end - start) as f64 * self.bounds[depth] as f64);
+        for &atom in atoms {
+            let iter = &mut join.iters[atom];
+            if !iter.at_end() {
+                iter.seek(key);
+            }
+            if iter.at_end() || iter.key() != key {
+                return false;
+            }
+        }
+        join.binding.push(key);
+        join.passes_checks(depth)
+    }
+}
+
+/// JoinReport compares the work of a TrieJoin with the worst case.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JoinReport {
+    /// The AGM bound on the number of results, or None if a variable is only
+    /// bound by generators.
+    pub agm_bound: Option<f64>,
+    /// The high-water mark of the bytes the join allocated for bindings,
+    /// generated values and shuffled keys, not counting the relations. The
+    /// peaks of separate buffers are summed, so this bounds the peak of
+    /// them all from above.
+    pub peak_memory: usize,
+    /// Per variable, in variable order.
+    pub levels: Vec<LevelStats>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LevelStats {
+    pub variable: String,
+    /// Keys all iterators of the variable agreed on.
+    pub candidates: u64,
+    /// Candidates that passed the checks of the variable.
+    pub bindings: u64,
+    pub seeks: u64,
+}
+
+impl JoinReport {
+    /// Number of results returned.
+    pub fn results(&self) -> u64 {
+        self.levels.last().map_or(0, |l| l.bindings)
+    }
+
+    /// Number of bindings of all variables, i.e. of partial and complete
+    /// results.
+    pub fn work(&self) -> u64 {
+        self.levels.iter().map(|l| l.bindings).sum()
+    }
+
+    /// Per variable, the average number of bindings per binding of the
+    /// previous variable.
+    pub fn fan_outs(&self) -> Vec<f64> {
+        let mut parents = 1;
+        self.levels
+            .iter()
+            .map(|level| {
+                let fan_out = level.bindings as f64 / parents.max(1) as f64;
+                parents = level.bindings;
+                fan_out
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for JoinReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.agm_bound {
+            Some(bound) => writeln!(f, "AGM bound: {bound:.1}")?,
+            None => writeln!(f, "AGM bound: unbounded")?,
+        }
+        writeln!(f, "results: {}, work: {}", self.results(), self.work())?;
+        writeln!(f, "peak memory: {} bytes", self.peak_memory)?;
+        writeln!(f, "variable  candidates  bindings  seeks  fan-out")?;
+        for (level, fan_out) in self.levels.iter().zip(self.fan_outs()) {
+            writeln!(
+                f,
+                "{:<8}  {:>10}  {:>8}  {:>5}  {:>7.2}",
+                level.variable, level.candidates, level.bindings, level.seeks, fan_out
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the AGM bound of a join of `atoms`, given as their sizes and
+/// the indices of their variables: the product of the sizes raised to the
+/// weights of an optimal fractional edge cover of the variables. Returns
+/// None if a variable is in no atom.
+fn agm_bound(atoms: &[(usize, Vec<usize>)], variables: usize) -> Option<f64> {
+    if atoms.iter().any(|(len, _)| *len == 0) {
+        return Some(0.0);
+    }
+    // By LP duality, the minimal weighted cover equals the maximal sum of
+    // variable weights y such that the weights of every atom sum to at most
+    // the logarithm of its size.
+    let a: Vec<Vec<f64>> = atoms
+        .iter()
+        .map(|(_, vars)| {
+            (0..variables)
+                .map(|v| vars.contains(&v) as u8 as f64)
+                .collect()
+        })
+        .collect();
+    let b: Vec<f64> = atoms.iter().map(|(len, _)| (*len as f64).ln()).collect();
+    maximize_sum(&a, &b).map(f64::exp)
+}
+
+/// Maximizes the sum of y subject to `a y <= b` and `y >= 0`, where b >= 0,
+/// by the simplex method with Bland's rule. Returns None if unbounded.
+fn maximize_sum(a: &[Vec<f64>], b: &[f64]) -> Option<f64> {
+    const EPSILON: f64 = 1e-9;
+    let (m, n) = (a.len(), a.first().map_or(0, Vec::len));
+    // Rows of [a | I | b], with the objective row last.
+    let mut tableau: Vec<Vec<f64>> = (0..m)
+        .map(|r| {
+            let mut row = a[r].clone();
+            row.extend((0..m).map(|s| (s == r) as u8 as f64));
+            row.push(b[r]);
+            row
+        })
+        .collect();
+    let mut objective = vec![-1.0; n];
+    objective.extend(vec![0.0; m + 1]);
+    tableau.push(objective);
+    let mut basis: Vec<usize> = (n..n + m).collect();
+    let rhs = n + m;
+    loop {
+        let Some(column) = (0..n + m).find(|&c| tableau[m][c] < -EPSILON) else {
+            return Some(tableau[m][rhs]);
+        };
+        let row = (0..m)
+            .filter(|&r| tableau[r][column] > EPSILON)
+            .min_by(|&r, &s| {
+                let ratio = |r: usize| tableau[r][rhs] / tableau[r][column];
+                ratio(r).total_cmp(&ratio(s)).then(basis[r].cmp(&basis[s]))
+            })?;
+        let pivot = tableau[row][column];
+        tableau[row].iter_mut().for_each(|x| *x /= pivot);
+        let pivot_row = tableau[row].clone();
+        for r in (0..=m).filter(|&r| r != row) {
+            let factor = tableau[r][column];
+            if factor != 0.0 {
+                for (x, p) in tableau[r].iter_mut().zip(&pivot_row) {
+                    *x -= factor * p;
+                }
+            }
+        }
+        basis[row] = column;
+    }
+}
+
+impl<K: Ord + Copy> Iterator for TrieJoin<'_, K> {
+    type Item = Vec<K>;
+
+    fn next(&mut self) -> Option<Vec<K>> {
+        let mut steps = u64::MAX;
+        self.next_within(&mut steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::var;
+
+    fn edges() -> TrieRelation<u32> {
+        TrieRelation::new(
+            2,
+            [
+                [1, 2],
+                [1, 3],
+                [2, 3],
+                [2, 4],
+                [3, 4],
+                [3, 5],
+                [4, 5],
+                [1, 5],
+            ],
+        )
+    }
+
+    /// All triangles a < b < c by brute force.
+    fn triangles(rel: &TrieRelation<u32>) -> Vec<Vec<u32>> {
+        let tuples: Vec<Vec<u32>> = (0..rel.len()).map(|i| rel.tuple(i)).collect();
+        let has = |a, b| tuples.contains(&vec![a, b]);
+        let mut result = vec![];
+        for t in &tuples {
+            for c in 0..10 {
+                if has(t[1], c) && has(t[0], c) {
+                    result.push(vec![t[0], t[1], c]);
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_triangle_query() {
+        let e = edges();
+        let query = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .atom(&e, &["a", "c"]);
+        let join = query.execute().unwrap();
+        assert_eq!(join.variables(), &["a", "b", "c"]);
+        assert_eq!(join.collect::<Vec<_>>(), triangles(&e));
+    }
+
+    #[test]
+    fn test_query_verify() {
+        let e = edges();
+        let v = TrieRelation::new(1, (1..=4).map(|v| [v]));
+        let query = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .atom(&e, &["a", "c"])
+            .exists(&v, &["b"]);
+        for seed in 0..10 {
+            assert_eq!(query.verify(seed).unwrap(), query.run().unwrap());
+        }
+
+        // A generator that is not a function of its inputs.
+        let calls = std::cell::Cell::new(0);
+        let query = Query::new()
+            .atom(&e, &["a", "b"])
+            .generator(&["a"], "c", |_| {
+                calls.set(calls.get() + 1);
+                [calls.get()]
+            });
+        let Err(QueryError::Mismatch { order, .. }) = query.verify(1) else {
+            panic!("expected a mismatch");
+        };
+        assert_eq!(order, ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn test_query_provenance() {
+        let e = edges();
+        let v = TrieRelation::new(1, (1..=6).map(|v| [v]));
+        let mut join = Query::new()
+            .atom(&e, &["a", "b"])
+            .exists(&v, &["a"])
+            .atom(&e, &["b", "c"])
+            .filter(var("c").eq(5))
+            .execute()
+            .unwrap();
+        let mut results = 0;
+        while let Some(binding) = join.next() {
+            let provenance = join.provenance();
+            assert_eq!(
+                provenance.iter().map(|p| p.0).collect::<Vec<_>>(),
+                vec![0, 2]
+            );
+            assert_eq!(e.tuple(provenance[0].1), &binding[..2]);
+            assert_eq!(e.tuple(provenance[1].1), &binding[1..]);
+            results += 1;
+        }
+        assert_eq!(results, 4);
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let e = edges();
+        let result = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .filter((var("b") + 1).eq(var("c")))
+            .filter(var("a").lt(2))
+            .run()
+            .unwrap();
+        assert_eq!(result, vec![vec![1, 2, 3], vec![1, 3, 4]]);
+    }
+
+    #[test]
+    fn test_pinning_filter() {
+        let r = TrieRelation::new(2, (0..100u32).map(|i| [i % 10, i]));
+        let s = TrieRelation::new(1, (0..100u32).step_by(3).map(|i| [i]));
+        let query = |expr: Expr| {
+            let mut join = Query::new()
+                .atom(&r, &["a", "b"])
+                .atom(&s, &["b"])
+                .filter(expr)
+                .execute()
+                .unwrap();
+            let results: Vec<Vec<u32>> = join.by_ref().collect();
+            (results, join.report().levels[0].candidates)
+        };
+        // Pinned, a is only ever bound to 4; checked, to every value.
+        let (pinned, pinned_candidates) = query(var("a").eq(4));
+        let (checked, checked_candidates) = query(var("a").ge(4).and(var("a").le(4)));
+        assert_eq!(pinned, checked);
+        assert_eq!(pinned.len(), 3);
+        assert_eq!((pinned_candidates, checked_candidates), (1, 10));
+        assert!(query(Expr::from(-1).eq(var("a"))).0.is_empty());
+    }
+
+    #[test]
+    fn test_query_udfs() {
+        let r = TrieRelation::new(1, [[1], [5], [20]]);
+        let s = TrieRelation::new(1, [[3], [7], [12], [25]]);
+        let result = Query::new()
+            .atom(&r, &["x"])
+            .atom(&s, &["y"])
+            .generator(&["x"], "y", |b| (b[0]..b[0] + 10).rev())
+            .predicate(&["y", "x"], |b| b[0] != b[1] + 2)
+            .run()
+            .unwrap();
+        assert_eq!(result, vec![vec![1, 7], vec![5, 12], vec![20, 25]]);
+
+        // A generator alone can bind its output.
+        let result = Query::new()
+            .atom(&r, &["x"])
+            .generator(&["x"], "y", |b| [b[0] * 2, b[0] * 3])
+            .predicate(&["y"], |b| b[0] > 10)
+            .run()
+            .unwrap();
+        assert_eq!(result, vec![vec![5, 15], vec![20, 40], vec![20, 60]]);
+
+        let q = |query: Query<'_, u32>| query.execute().err();
+        assert_eq!(
+            q(Query::new()
+                .atom(&r, &["x"])
+                .generator(&["y"], "x", |b| [b[0]])),
+            Some(QueryError::GeneratorOrder { generator: 0 })
+        );
+        assert_eq!(
+            q(Query::new()
+                .atom(&r, &["x"])
+                .generator(&["z"], "y", |b| [b[0]])
+                .order(&["x", "z", "y"])),
+            Some(QueryError::UnboundVariable("z".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_query_exists() {
+        let e = edges();
+        // Edges into a vertex with outgoing edges.
+        let result = Query::new()
+            .atom(&e, &["a", "b"])
+            .exists(&e, &["b", "c"])
+            .filter(var("a").gt(1))
+            .run()
+            .unwrap();
+        assert_eq!(result, vec![vec![2, 3], vec![2, 4], vec![3, 4]]);
+
+        // Vertices with incoming edges, and paths a -> b -> c closing a
+        // triangle.
+        let v = TrieRelation::new(1, (1..=6).map(|v| [v]));
+        let result = Query::new()
+            .atom(&v, &["b"])
+            .exists(&e, &["a", "b"])
+            .run()
+            .unwrap();
+        assert_eq!(result, vec![vec![2], vec![3], vec![4], vec![5]]);
+        let result = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .exists(&e, &["a", "c"])
+            .run()
+            .unwrap();
+        assert_eq!(result, triangles(&e));
+
+        let empty = TrieRelation::new(2, Vec::<[u32; 2]>::new());
+        let query = Query::new().atom(&v, &["b"]).exists(&empty, &["x", "y"]);
+        assert_eq!(query.run().unwrap(), Vec::<Vec<u32>>::new());
+        assert_eq!(
+            Query::new()
+                .atom(&v, &["b"])
+                .exists(&e, &["b", "x"])
+                .exists(&e, &["x", "b"])
+                .execute()
+                .err(),
+            Some(QueryError::UnboundVariable("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_query_not() {
+        let e = edges();
+        // Paths a -> b -> c without a shortcut a -> c.
+        let result = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .not(&e, &["a", "c"])
+            .run()
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![vec![1, 2, 4], vec![1, 3, 4], vec![2, 3, 5], vec![2, 4, 5]]
+        );
+
+        // Vertices without outgoing edges.
+        let v = TrieRelation::new(1, (1..=6).map(|v| [v]));
+        let result = Query::new()
+            .atom(&v, &["a"])
+            .not(&e, &["a", "b"])
+            .execute()
+            .err();
+        assert_eq!(
+            result,
+            Some(QueryError::UnsafeNegation {
+                atom: 1,
+                variable: "b".to_string()
+            })
+        );
+        let sources = TrieRelation::new(1, (0..e.len()).map(|i| [e.column(0)[i]]));
+        let result = Query::new()
+            .atom(&v, &["a"])
+            .not(&sources, &["a"])
+            .run()
+            .unwrap();
+        assert_eq!(result, vec![vec![5], vec![6]]);
+    }
+
+    #[test]
+    fn test_query_plan() {
+        let e = edges();
+        let plan = Query::new()
+            .atom(&e, &["a", "b"])
+            .exists(&e, &["b", "x"])
+            .generator(&["a"], "c", |args: &[u32]| [args[0] + 1])
+            .filter(var("a").lt(var("c")))
+            .plan()
+            .unwrap();
+        assert_eq!(plan.variables, vec!["a", "b", "c"]);
+        let labels: Vec<&str> = plan.steps.iter().map(|s| s.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec!["atom 0", "exists 1", "generator 0", "filter 0"]
+        );
+        assert_eq!(plan.steps[1].reads, vec!["b"]);
+        assert_eq!(plan.steps[2].binds, vec!["c"]);
+        let dot = plan.to_dot();
+        assert!(dot.starts_with("digraph plan {"));
+        assert!(dot.contains("v0 -> v1 -> v2 [style=bold];"));
+        assert!(dot.contains("s0 -> v1;"));
+        assert!(dot.contains("v2 -> s3 [style=dashed];"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn test_pinned_tuning() {
+        let e = edges();
+        let query = || Query::new().atom(&e, &["a", "b"]).atom(&e, &["b", "c"]);
+        let tuning = Tuning {
+            galloping_threshold: 1,
+            ..Tuning::default()
+        };
+        let mut text = Vec::new();
+        query().pin(tuning).unwrap().write_to(&mut text).unwrap();
+        let pinned = PinnedPlan::read_from(text.as_slice()).unwrap().unwrap();
+        let pinned_query = query().with_pinned(&pinned).unwrap();
+        assert_eq!(pinned_query.tuning, Some(tuning));
+        assert_eq!(pinned_query.run().unwrap(), query().run().unwrap());
+    }
+
+    #[test]
+    fn test_hooks() {
+        /// Counts the results below every binding of the first variable,
+        /// prunes a == 2 and stops at a == 4.
+        struct Counter<'a> {
+            counts: &'a mut Vec<(u32, usize)>,
+        }
+        impl JoinHooks<u32> for Counter<'_> {
+            fn enter(&mut self, depth: usize, binding: &[u32]) -> Visit {
+                match (depth, binding[0]) {
+                    (0, 2) => Visit::Prune,
+                    (0, 4) => Visit::Stop,
+                    (0, a) => {
+                        self.counts.push((a, 0));
+                        Visit::Descend
+                    }
+                    (1, _) => Visit::Descend,
+                    _ => {
+                        self.counts.last_mut().unwrap().1 += 1;
+                        Visit::Descend
+                    }
+                }
+            }
+
+            fn leave(&mut self, depth: usize, binding: &[u32]) {
+                assert_eq!(binding.len(), depth + 1);
+            }
+        }
+        let e = edges();
+        let mut counts = Vec::new();
+        let join = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .execute()
+            .unwrap()
+            .hooks(Counter {
+                counts: &mut counts,
+            });
+        let paths: Vec<Vec<u32>> = join.collect();
+        assert_eq!(paths.iter().filter(|p| p[0] == 2).count(), 0);
+        assert!(paths.iter().all(|p| p[0] < 4));
+        assert_eq!(counts, [(1, 4), (3, 1)]);
+        assert_eq!(paths.len(), 5);
+    }
+
+    #[test]
+    fn test_run_for() {
+        let e = edges();
+        let query = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .filter(var("c").gt(3));
+        let all = query.run().unwrap();
+        assert_eq!(all.len(), 7);
+
+        let mut join = query.execute().unwrap();
+        let first = join.run_for(Budget::new().results(3));
+        assert_eq!(first.results, all[..3]);
+        let token = first.resume.unwrap();
+        assert_eq!(token.last(), Some(&all[2][..]));
+        // A fresh join resumes from the token, the old one where it stopped.
+        let resumed = query.execute().unwrap().resume(&token).unwrap();
+        assert_eq!(resumed.collect::<Vec<_>>(), all[3..]);
+        let rest = join.run_for(Budget::new());
+        assert_eq!((rest.results, rest.resume), (all[3..].to_vec(), None));
+
+        // A budget of one step at a time still finds everything.
+        let mut join = query.execute().unwrap();
+        let mut found = Vec::new();
+        let mut calls = 1;
+        loop {
+            let partial = join.run_for(Budget::new().steps(1));
+            found.extend(partial.results);
+            if partial.resume.is_none() {
+                break;
+            }
+            calls += 1;
+        }
+        assert_eq!(found, all);
+        assert!(calls > all.len());
+        let done = query
+            .execute()
+            .unwrap()
+            .resume(&Resume::after(vec![9, 9, 9]))
+            .unwrap();
+        assert_eq!(done.count(), 0);
+        let other = query.execute().unwrap().resume(&Resume::after(vec![1, 2]));
+        assert_eq!(
+            other.err(),
+            Some(QueryError::ResumeToken {
+                expected: 3,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_shuffled() {
+        let e = edges();
+        let query = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .filter(var("c").gt(3));
+        let sorted = query.run().unwrap();
+        let shuffled = |seed| {
+            let join = query.execute().unwrap().shuffled(seed);
+            join.collect::<Vec<_>>()
+        };
+        let orders: Vec<Vec<Vec<u32>>> = (0..8).map(shuffled).collect();
+        assert_eq!(orders[0], shuffled(0));
+        assert!(orders.iter().any(|order| *order != sorted));
+        for mut order in orders {
+            order.sort();
+            assert_eq!(order, sorted);
+        }
+    }
+
+    #[test]
+    fn test_sampler() {
+        let e = edges();
+        let query = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .filter(var("c").gt(3));
+        let all = query.run().unwrap();
+        let mut sampler = query.execute().unwrap().sampler(7).unwrap();
+        let samples = sampler.samples(7000, u64::MAX);
+        assert_eq!(samples.len(), 7000);
+        // Every one of the 7 results is drawn about 1000 times.
+        for result in &all {
+            let drawn = samples.iter().filter(|s| *s == result).count();
+            assert!(
+                (850..1150).contains(&drawn),
+                "{result:?} drawn {drawn} times"
+            );
+        }
+        assert!(samples.iter().all(|s| all.contains(s)));
+        let estimate = sampler.estimate().unwrap();
+        assert!((estimate - 7.0).abs() < 0.5, "estimated {estimate}");
+
+        let none = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .filter(var("c").gt(5))
+            .execute()
+            .unwrap();
+        assert_eq!(none.sampler(7).unwrap().sample(1000), None);
+        let generated = Query::new()
+            .atom(&e, &["a", "b"])
+            .generator(&["b"], "c", |b| [b[0] + 1])
+            .execute()
+            .unwrap();
+        assert_eq!(
+            generated.sampler(7).err(),
+            Some(QueryError::Unsampled("c".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_temporal() {
+        // Who owned which account when, and its balances over time.
+        let owners = TrieRelation::new(4, [[1, 10, 0, 50], [1, 11, 50, 100], [2, 20, 0, 100]]);
+        let balances = TrieRelation::new(
+            4,
+            [
+                [10, 5, 0, 30],
+                [10, 7, 30, 60],
+                [11, 9, 40, 100],
+                [20, 1, 0, 10],
+                [20, 1, 20, 30],
+            ],
+        );
+        let query = || {
+            Query::new()
+                .temporal(&owners, &["owner", "account"])
+                .temporal(&balances, &["account", "balance"])
+        };
+        let at = |time| query().valid_at(time).run().unwrap();
+        assert_eq!(at(5), [[1, 10, 5], [2, 20, 1]]);
+        assert_eq!(at(10), [[1, 10, 5]]);
+        assert_eq!(at(45), [[1, 10, 7]]);
+        assert_eq!(at(55), [[1, 11, 9]]);
+        assert_eq!(query().run().unwrap().len(), 4);
+
+        let mut join = query().valid_overlapping().execute().unwrap();
+        let mut valid = Vec::new();
+        while let Some(result) = join.next() {
+            valid.push((result, join.validity().to_vec()));
+        }
+        assert_eq!(
+            valid,
+            [
+                (vec![1, 10, 5], vec![(0, 30)]),
+                (vec![1, 10, 7], vec![(30, 50)]),
+                (vec![1, 11, 9], vec![(50, 100)]),
+                (vec![2, 20, 1], vec![(0, 10), (20, 30)]),
+            ]
+        );
+        assert_eq!(query().valid_at(45).verify(3).unwrap(), at(45));
+        let wrong = Query::new().temporal(&owners, &["owner", "account", "from"]);
+        assert!(matches!(wrong.run(), Err(QueryError::Arity { .. })));
+        let empty = Query::new().temporal(&owners, &[]);
+        assert_eq!(
+            empty.run().err(),
+            Some(QueryError::TemporalVariables { atom: 0 })
+        );
+    }
+
+    #[test]
+    fn test_join_report() {
+        let e = edges();
+        let mut join = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .atom(&e, &["a", "c"])
+            .execute()
+            .unwrap();
+        let results = join.by_ref().count();
+        let report = join.report();
+        // Triangles are bounded by |E|^1.5.
+        assert!((report.agm_bound.unwrap() - 8f64.powf(1.5)).abs() < 1e-6);
+        assert_eq!(report.results(), results as u64);
+        let bindings: Vec<u64> = report.levels.iter().map(|l| l.bindings).collect();
+        assert_eq!(bindings, vec![4, 5, results as u64]);
+        assert_eq!(report.work(), 9 + results as u64);
+        assert_eq!(report.fan_outs()[1], 5.0 / 4.0);
+        assert!(report.to_string().starts_with("AGM bound: 22.6\n"));
+        assert!(report.peak_memory >= 3 * size_of::<u32>());
+
+        // Generated values count towards the peak.
+        let wide = Query::new()
+            .atom(&e, &["a", "b"])
+            .generator(&["a"], "x", |args| args[0]..args[0] + 1000);
+        let mut join = wide.execute().unwrap();
+        join.by_ref().count();
+        assert!(join.report().peak_memory >= 1000 * size_of::<u32>());
+
+        // A path is bounded by the product of the sizes.
+        let mut join = Query::new()
+            .atom(&e, &["a", "b"])
+            .atom(&e, &["b", "c"])
+            .filter(var("c").gt(4))
+            .execute()
+            .unwrap();
+        join.by_ref().for_each(drop);
+        let report = join.report();
+        assert!((report.agm_bound.unwrap() - 64.0).abs() < 1e-6);
+        assert!(report.levels[2].candidates > report.levels[2].bindings);
+
+        let join = Query::new()
+            .atom(&e, &["a", "b"])
+            .generator(&["b"], "c", |args: &[u32]| [args[0]])
+            .execute()
+            .unwrap();
+        assert_eq!(join.report().agm_bound, None);
+    }
+
+    #[test]
+    fn test_query_order() {
+        let r = TrieRelation::new(2, [[1, 10], [2, 20], [3, 10]]);
+        let s = TrieRelation::new(1, [[10], [30]]);
+        let result = Query::new()
+            .atom(&s, &["y"])
+            .atom(&r, &["x", "y"])
+            .order(&["x", "y"])
+            .run()
+            .unwrap();
+        assert_eq!(result, vec![vec![1, 10], vec![3, 10]]);
+    }
+
+    #[test]
+    fn test_query_errors() {
+        let e = edges();
+        let q = |query: Query<'_, u32>| query.execute().err();
+        assert_eq!(q(Query::new()), Some(QueryError::Empty));
+        assert_eq!(
+            q(Query::new().atom(&e, &["a"])),
+            Some(QueryError::Arity {
+                atom: 0,
+                arity: 2,
+                variables: 1
+            })
+        );
+        assert_eq!(
+            q(Query::new().atom(&e, &["a", "a"])),
+            Some(QueryError::RepeatedVariable {
+                atom: 0,
+                variable: "a".to_string()
+            })
+        );
+        assert_eq!(
+            q(Query::new().atom(&e, &["a", "b"]).order(&["b", "a"])),
+            Some(QueryError::AtomOrder { atom: 0 })
+        );
+        assert_eq!(
+            q(Query::new().atom(&e, &["a", "b"]).filter(var("c").lt(1))),
+            Some(QueryError::UnknownVariable("c".to_string()))
+        );
+        assert!(matches!(
+            q(Query::new().atom(&e, &["a", "b"]).filter(var("a") + 1)),
+            Some(QueryError::Expr(ExprError::Type(_)))
+        ));
+    }
+}
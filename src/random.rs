@@ -0,0 +1,88 @@
+//! Seeded randomness.
+//!
+//! Everything in the crate that draws random numbers takes an explicit seed
+//! and draws from an Rng, a SplitMix64 generator. Its output depends on the
+//! seed alone: it uses 64-bit arithmetic only, independent of the pointer
+//! width and byte order of the platform, and is fixed across releases. So
+//! the same seed gives byte-identical results in every run and on every
+//! platform:
+//!
+//! - bench::Workload::prepare_seeded() generates the same inputs.
+//! - Query::verify() tries the same variable order.
+//! - TrieJoin::shuffled() enumerates results in the same order.
+//!
+//! Parallel code does not depend on randomness or on the number of
+//! threads: TrieRelation::new_parallel() and merge_shards() build the same
+//! relations as their sequential counterparts for any partition count.
+//!
+//! Tuning::auto_calibrate() is the exception, as it measures time. Runs that
+//! must be reproducible should pass a fixed Tuning, e.g. one cached with
+//! Tuning::calibrated_at().
+
+/// Rng is the SplitMix64 generator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number below `bound`, which must be > 0, by multiplying
+    /// rather than by taking the remainder.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        assert!(bound > 0, "Bound must be > 0");
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+
+    /// Returns an index into a slice of `len` elements.
+    pub fn index(&mut self, len: usize) -> usize {
+        self.below(len as u64) as usize
+    }
+
+    /// Shuffles `items` with the Fisher-Yates algorithm.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.index(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "time")]
+    use crate::bench::Workload;
+
+    #[test]
+    fn test_rng_is_fixed() {
+        let mut rng = Rng::new(0);
+        assert_eq!(rng.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(rng.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+        let mut rng = Rng::new(42);
+        let draws: Vec<u64> = (0..6).map(|_| rng.below(10)).collect();
+        assert_eq!(draws, [7, 1, 2, 3, 0, 8]);
+        let mut items = [1, 2, 3, 4, 5, 6];
+        Rng::new(42).shuffle(&mut items);
+        assert_eq!(items, [3, 6, 4, 2, 1, 5]);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_seeded_outputs() {
+        let prepared = Workload::DenseOverlap.prepare_seeded(1000, 42);
+        assert_eq!(&prepared.inputs()[0][..5], [0, 4, 5, 6, 8]);
+        assert_eq!(prepared.run(), 211);
+        assert_eq!(Workload::Triangles.prepare_seeded(1000, 42).run(), 86);
+    }
+}
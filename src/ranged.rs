@@ -0,0 +1,720 @@
+//! Index files read by byte ranges.
+//!
+//! IndexSource reads the distinct keys of the first column of an index file
+//! (see persist) block by block through a RangeRead, e.g. ranged GETs on an
+//! object store, so that a join over a relation in S3 transfers only the
+//! blocks it visits. Every block is verified against its checksum.
+//!
+//! The file format has no skip index: all blocks but the last of a column
+//! hold block_keys keys, so their offsets follow from the header. The source
+//! builds a sparse one as it goes, the first key of every block it has read.
+//! A seek beyond the current block binary-searches the blocks after it,
+//! reading those whose first key is not known yet; readers that serve
+//! several reads at once, see RangeRead::queue_depth(), get that many probes
+//! per round of the search. Blocks are kept in a
+//! BlockCache, so the probes of one seek are mostly hits for the next, and
+//! sources that share the cache share the blocks of a file.
+//!
+//! With prefetching enabled, which needs the `threads` feature, will_need()
+//! hints start reading the block the next seek will read first on a
+//! background thread, so that the read overlaps with the work of the join
+//! on the other inputs. At most MAX_PREFETCHES reads are in flight; hints
+//! beyond them are dropped.
+
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "fs")]
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+#[cfg(feature = "threads")]
+use std::thread;
+use std::thread::JoinHandle;
+
+use crate::Seekable;
+use crate::cache::{BlockCache, FileId};
+use crate::persist::{self, Header, PersistError, PersistKey, Region, read_header};
+
+/// RangeRead is a file that can be read at any byte range, e.g. an object
+/// in an object store.
+pub trait RangeRead {
+    /// Returns the bytes in `range`.
+    fn read_range(&mut self, range: Range<u64>) -> io::Result<Vec<u8>>;
+
+    /// Returns the bytes in each of `ranges`. Readers that can have several
+    /// reads in flight issue them at once.
+    fn read_ranges(&mut self, ranges: &[Range<u64>]) -> Vec<io::Result<Vec<u8>>> {
+        ranges.iter().map(|r| self.read_range(r.clone())).collect()
+    }
+
+    /// Returns how many reads read_ranges() serves in about the time of one.
+    fn queue_depth(&self) -> usize {
+        1
+    }
+}
+
+impl RangeRead for &[u8] {
+    fn read_range(&mut self, range: Range<u64>) -> io::Result<Vec<u8>> {
+        match self.get(range.start as usize..range.end as usize) {
+            Some(bytes) => Ok(bytes.to_vec()),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+impl RangeRead for File {
+    fn read_range(&mut self, range: Range<u64>) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; (range.end - range.start) as usize];
+        self.seek(SeekFrom::Start(range.start))?;
+        self.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// DiskReader reads index files from disk, with the `fs` feature: a
+/// UringReader on Linux with the `io_uring` feature, and a File elsewhere.
+/// Both open with `DiskReader::open(path)`.
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub type DiskReader = crate::uring::UringReader;
+#[cfg(all(feature = "fs", not(all(feature = "io_uring", target_os = "linux"))))]
+pub type DiskReader = File;
+
+/// ObjectReader reads an object of an object store, e.g. S3.
+///
+/// Reads block on the store's futures. Stores that need a Tokio runtime,
+/// like the S3 one, must be read from a thread that has entered one, but
+/// not from within an async task.
+#[cfg(feature = "object_store")]
+pub struct ObjectReader {
+    store: Arc<dyn object_store::ObjectStore>,
+    path: object_store::path::Path,
+}
+
+#[cfg(feature = "object_store")]
+impl ObjectReader {
+    pub fn new(store: Arc<dyn object_store::ObjectStore>, path: object_store::path::Path) -> Self {
+        Self { store, path }
+    }
+}
+
+#[cfg(feature = "object_store")]
+impl RangeRead for ObjectReader {
+    fn read_range(&mut self, range: Range<u64>) -> io::Result<Vec<u8>> {
+        let bytes = futures::executor::block_on(self.store.get_range(&self.path, range));
+        bytes.map(|b| b.to_vec()).map_err(io::Error::other)
+    }
+}
+
+/// Counters describing the reads of an IndexSource.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RangeStats {
+    /// Number of ranges read.
+    pub requests: usize,
+    /// Number of bytes read by those requests.
+    pub bytes_read: u64,
+    /// Number of blocks found in the cache.
+    pub cache_hits: usize,
+    /// Number of blocks read in the background.
+    pub prefetches: usize,
+}
+
+/// Spawns a thread reading block `block` at `offset`, with keys of `len`
+/// bytes, into the cache.
+type SpawnFn = Box<dyn FnMut(usize, u64, u64) -> JoinHandle<()> + Send>;
+
+/// Bytes of blocks an IndexSource caches if it does not share a cache.
+pub const CACHE_BYTES: usize = 16 * persist::BLOCK_KEYS * 8;
+
+/// Number of blocks an IndexSource reads in the background at most; hints
+/// beyond it are dropped.
+pub const MAX_PREFETCHES: usize = 4;
+
+/// IndexSource is the distinct keys of the first column of an index file,
+/// read by ranges, see the module documentation.
+///
+/// Read errors end the source and are kept for error().
+pub struct IndexSource<R, K> {
+    reader: R,
+    header: Header,
+    /// Offset of the first block.
+    data_start: u64,
+    num_blocks: usize,
+    /// The first key of every block read so far.
+    fences: Vec<Option<K>>,
+    cache: Arc<BlockCache>,
+    file: FileId,
+    block: usize,
+    keys: Arc<[K]>,
+    pos: usize,
+    error: Option<PersistError>,
+    stats: RangeStats,
+    prefetch: Option<SpawnFn>,
+    in_flight: Vec<(usize, JoinHandle<()>)>,
+}
+
+impl<R: RangeRead, K: PersistKey> IndexSource<R, K> {
+    /// Opens the index file behind `reader`, positioned at its first key,
+    /// with a cache of its own of CACHE_BYTES.
+    pub fn open(reader: R) -> Result<Self, PersistError> {
+        Self::with_cache(reader, Arc::new(BlockCache::new(CACHE_BYTES)), "")
+    }
+
+    /// Opens the file called `name` in `cache`, caching its blocks there.
+    pub fn with_cache(
+        mut reader: R,
+        cache: Arc<BlockCache>,
+        name: &str,
+    ) -> Result<Self, PersistError> {
+        let mut stats = RangeStats::default();
+        let mut read = |range: Range<u64>| {
+            stats.requests += 1;
+            stats.bytes_read += range.end - range.start;
+            reader.read_range(range)
+        };
+        let version = *read(0..5)?.last().unwrap();
+        let data_start = persist::header_len(version.clamp(1, 2)) + 4;
+        let header = read_header(&mut read(0..data_start)?.as_slice())?;
+        if let Some(found) = header.key_type
+            && found != K::KEY_TYPE
+        {
+            return Err(PersistError::KeyType {
+                expected: K::KEY_TYPE,
+                found,
+            });
+        }
+        let num_blocks = header.len.div_ceil(header.block_keys);
+        let mut source = Self {
+            reader,
+            header,
+            data_start,
+            num_blocks,
+            fences: vec![None; num_blocks],
+            file: cache.file_id(name),
+            cache,
+            block: 0,
+            keys: Arc::new([]),
+            pos: 0,
+            error: None,
+            stats,
+            prefetch: None,
+            in_flight: Vec::new(),
+        };
+        if num_blocks > 0 {
+            source.keys = source.load(0)?;
+        }
+        Ok(source)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn stats(&self) -> RangeStats {
+        self.stats
+    }
+
+    /// Returns the error that ended the source, if any.
+    pub fn error(&self) -> Option<&PersistError> {
+        self.error.as_ref()
+    }
+
+    pub fn into_reader(self) -> R {
+        self.reader
+    }
+
+    /// Returns the offset of block `block` of the first column, and the
+    /// length of its keys.
+    fn extent(&self, block: usize) -> (u64, u64) {
+        let block_keys = self.header.block_keys;
+        let n = block_keys.min(self.header.len - block * block_keys);
+        let offset = self.data_start + (block * (block_keys * 8 + 4)) as u64;
+        (offset, n as u64 * 8)
+    }
+
+    /// Returns the keys of block `block` of the first column.
+    fn load(&mut self, block: usize) -> Result<Arc<[K]>, PersistError> {
+        Ok(self.load_all(&[block])?.pop().unwrap())
+    }
+
+    /// Returns the keys of each of `blocks`, reading those not cached at
+    /// once.
+    fn load_all(&mut self, blocks: &[usize]) -> Result<Vec<Arc<[K]>>, PersistError> {
+        let mut data = Vec::with_capacity(blocks.len());
+        let mut missing = vec![];
+        for (i, &block) in blocks.iter().enumerate() {
+            if let Some(j) = self.in_flight.iter().position(|(b, _)| *b == block) {
+                // A failed prefetch leaves the block to be read again below.
+                let _ = self.in_flight.swap_remove(j).1.join();
+            }
+            let (offset, len) = self.extent(block);
+            let cached = self.cache.get(self.file, offset);
+            match cached {
+                Some(_) => self.stats.cache_hits += 1,
+                None => missing.push((i, offset..offset + len + 4)),
+            }
+            data.push(cached);
+        }
+        if !missing.is_empty() {
+            let ranges: Vec<Range<u64>> = missing.iter().map(|(_, r)| r.clone()).collect();
+            let results = self.reader.read_ranges(&ranges);
+            for ((i, range), bytes) in missing.into_iter().zip(results) {
+                self.stats.requests += 1;
+                self.stats.bytes_read += range.end - range.start;
+                let bytes: Arc<[u8]> = verify_block(bytes?, blocks[i], range.clone())?.into();
+                self.cache.insert(self.file, range.start, bytes.clone());
+                data[i] = Some(bytes);
+            }
+        }
+        let byte_order = self.header.byte_order;
+        let mut all = Vec::with_capacity(blocks.len());
+        for (&block, data) in blocks.iter().zip(data) {
+            let keys: Arc<[K]> = data
+                .unwrap()
+                .chunks_exact(8)
+                .map(|b| K::decode(byte_order.decode(b.try_into().unwrap())))
+                .collect();
+            self.fences[block] = Some(keys[0]);
+            all.push(keys);
+        }
+        Ok(all)
+    }
+
+    /// Starts reading block `block` in the background, unless it is cached
+    /// or being read already, or MAX_PREFETCHES blocks are.
+    fn prefetch(&mut self, block: usize) {
+        let (offset, len) = self.extent(block);
+        let Some(spawn) = &mut self.prefetch else {
+            return;
+        };
+        self.in_flight.retain(|(_, handle)| !handle.is_finished());
+        if self.in_flight.len() >= MAX_PREFETCHES
+            || self.in_flight.iter().any(|(b, _)| *b == block)
+            || self.cache.contains(self.file, offset)
+        {
+            return;
+        }
+        self.in_flight.push((block, spawn(block, offset, len)));
+        self.stats.prefetches += 1;
+    }
+
+    /// Moves to block `block`, or to the end past the last block.
+    fn enter(&mut self, block: usize) {
+        self.pos = 0;
+        self.block = block;
+        if block == self.num_blocks {
+            self.keys = Arc::new([]);
+            return;
+        }
+        match self.load(block) {
+            Ok(keys) => self.keys = keys,
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn fail(&mut self, error: PersistError) {
+        self.error = Some(error);
+        self.keys = Arc::new([]);
+        self.pos = 0;
+    }
+
+    /// Learns the first keys of `blocks`.
+    fn learn_fences(&mut self, blocks: &[usize]) -> Result<(), PersistError> {
+        let unknown: Vec<usize> = blocks
+            .iter()
+            .copied()
+            .filter(|&b| self.fences[b].is_none())
+            .collect();
+        self.load_all(&unknown)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "threads")]
+impl<R: RangeRead + Clone + Send + 'static, K: PersistKey> IndexSource<R, K> {
+    /// Enables prefetching, reading blocks with clones of the reader.
+    pub fn with_prefetch(mut self) -> Self {
+        let (reader, cache, file) = (self.reader.clone(), self.cache.clone(), self.file);
+        self.prefetch = Some(Box::new(move |block, offset, len| {
+            let mut reader = reader.clone();
+            let cache = cache.clone();
+            thread::spawn(move || {
+                let range = offset..offset + len + 4;
+                let bytes = reader.read_range(range.clone());
+                if let Ok(bytes) = bytes
+                    .map_err(PersistError::from)
+                    .and_then(|bytes| verify_block(bytes, block, range))
+                {
+                    cache.insert(file, offset, bytes.into());
+                }
+            })
+        }));
+        self
+    }
+}
+
+/// Verifies the length and checksum of block `block`, read from `range`,
+/// and returns its keys.
+fn verify_block(
+    mut bytes: Vec<u8>,
+    block: usize,
+    range: Range<u64>,
+) -> Result<Vec<u8>, PersistError> {
+    if (bytes.len() as u64) < range.end - range.start {
+        return Err(PersistError::Format("file is truncated".to_string()));
+    }
+    let crc = bytes.split_off(bytes.len() - 4);
+    if crc != persist::crc32(&bytes).to_le_bytes() {
+        return Err(PersistError::Corrupt(Region::Block {
+            column: 0,
+            block,
+            offset: range.start,
+            len: bytes.len() as u64,
+        }));
+    }
+    Ok(bytes)
+}
+
+impl<R: RangeRead, K: PersistKey> Seekable for IndexSource<R, K> {
+    type Key = K;
+
+    fn key(&self) -> K {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.keys[self.pos]
+    }
+
+    fn next(&mut self) {
+        let key = self.key();
+        loop {
+            self.pos += 1;
+            if self.pos == self.keys.len() {
+                self.enter(self.block + 1);
+                if self.at_end() {
+                    return;
+                }
+            }
+            if self.keys[self.pos] != key {
+                return;
+            }
+        }
+    }
+
+    fn seek(&mut self, seek_key: K) {
+        assert!(!self.at_end(), "Iterator is at end");
+        assert!(seek_key >= self.key(), "Seek key must be >= current key");
+        if seek_key <= self.keys[self.keys.len() - 1] {
+            self.pos += self.keys[self.pos..].partition_point(|&k| k < seek_key);
+            return;
+        }
+        // Find the last block starting at or before the seek key: all keys
+        // >= seek_key are in it or start the block after it. Every round
+        // splits the blocks left evenly by as many probes as the reader
+        // reads at once.
+        let depth = self.reader.queue_depth().max(1);
+        let (mut lo, mut hi) = (self.block, self.num_blocks);
+        while hi - lo > 1 {
+            let d = depth.min(hi - lo - 1);
+            let probes: Vec<usize> = (1..=d).map(|i| lo + (hi - lo) * i / (d + 1)).collect();
+            if let Err(e) = self.learn_fences(&probes) {
+                return self.fail(e);
+            }
+            for probe in probes {
+                match self.fences[probe] {
+                    Some(key) if key <= seek_key => lo = probe,
+                    _ => {
+                        hi = probe;
+                        break;
+                    }
+                }
+            }
+        }
+        if lo > self.block {
+            self.enter(lo);
+            self.pos = self.keys.partition_point(|&k| k < seek_key);
+        } else {
+            self.pos = self.keys.len();
+        }
+        if self.pos == self.keys.len() && self.error.is_none() {
+            self.enter(lo + 1);
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.keys.len()
+    }
+
+    /// Prefetches the block a seek to `key` would read first: the first
+    /// block of its binary search whose first key is not known yet, or the
+    /// block the search ends in.
+    fn will_need(&mut self, key: K) {
+        if self.prefetch.is_none() || self.at_end() || key <= self.keys[self.keys.len() - 1] {
+            return;
+        }
+        let (mut lo, mut hi) = (self.block, self.num_blocks);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            match self.fences[mid] {
+                Some(fence) if fence <= key => lo = mid,
+                Some(_) => hi = mid,
+                None => return self.prefetch(mid),
+            }
+        }
+        if lo > self.block {
+            self.prefetch(lo);
+        } else if lo + 1 < self.num_blocks {
+            self.prefetch(lo + 1);
+        }
+    }
+
+    fn estimate(&self) -> Option<usize> {
+        if self.at_end() {
+            Some(0)
+        } else {
+            Some(self.header.len - self.block * self.header.block_keys - self.pos)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::TrieRelation;
+    use crate::{LeapFrogJoin, LinearIterator, persist::BLOCK_KEYS};
+
+    fn file(relation: &TrieRelation<i64>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        relation.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_index_source_scan() {
+        let n = BLOCK_KEYS as i64 * 3 + 5;
+        let relation = TrieRelation::new(2, (0..n).map(|i| [i / 2 - 10, i]));
+        let bytes = file(&relation);
+        let mut source = IndexSource::<_, i64>::open(bytes.as_slice()).unwrap();
+        let mut keys = vec![];
+        while !source.at_end() {
+            keys.push(source.key());
+            source.next();
+        }
+        assert!(source.error().is_none());
+        let mut expected: Vec<i64> = (0..n).map(|i| i / 2 - 10).collect();
+        expected.dedup();
+        assert_eq!(keys, expected);
+        assert_eq!(source.stats().requests, 2 + 4);
+
+        let empty = file(&TrieRelation::empty(2));
+        assert!(
+            IndexSource::<_, i64>::open(empty.as_slice())
+                .unwrap()
+                .at_end()
+        );
+        let error = IndexSource::<_, u32>::open(bytes.as_slice()).err();
+        assert!(matches!(error, Some(PersistError::KeyType { .. })));
+    }
+
+    #[test]
+    fn test_index_source_seek() {
+        let n = BLOCK_KEYS as i64 * 64;
+        let bytes = file(&TrieRelation::new(1, (0..n).map(|i| [i * 3])));
+        let others = [5, 3 * 9000, 3 * 9001 + 1, 3 * 200_000, 3 * (n - 1)];
+        let cache = Arc::new(BlockCache::new(4 * BLOCK_KEYS * 8));
+        let source =
+            IndexSource::<_, i64>::with_cache(bytes.as_slice(), cache.clone(), "r").unwrap();
+        let mut join = LeapFrogJoin::from_iters(vec![
+            Box::new(source) as Box<dyn Seekable<Key = i64>>,
+            Box::new(LinearIterator::new(&others)),
+        ]);
+        let mut result = vec![];
+        while !join.at_end() {
+            result.push(join.key());
+            join.next();
+        }
+        assert_eq!(result, [3 * 9000, 3 * 200_000, 3 * (n - 1)]);
+        let mut shared = IndexSource::<_, i64>::with_cache(bytes.as_slice(), cache, "r").unwrap();
+        shared.seek(3 * (n - 1));
+        assert_eq!(shared.key(), 3 * (n - 1));
+        assert!(shared.stats().cache_hits > 0);
+
+        // A binary search reads about log2(64) blocks per seek.
+        let mut source = IndexSource::<_, i64>::open(bytes.as_slice()).unwrap();
+        source.seek(3 * 100_000);
+        assert_eq!(source.key(), 3 * 100_000);
+        assert!(source.stats().requests <= 2 + 1 + 7);
+        source.seek(3 * 100_000 + 1);
+        assert_eq!(source.key(), 3 * 100_001);
+        source.seek(3 * n);
+        assert!(source.at_end() && source.error().is_none());
+
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 10;
+        corrupt[last] ^= 1;
+        let mut source = IndexSource::<_, i64>::open(corrupt.as_slice()).unwrap();
+        source.seek(3 * (n - 1));
+        assert!(source.at_end());
+        assert!(matches!(
+            source.error(),
+            Some(PersistError::Corrupt(Region::Block { block: 63, .. }))
+        ));
+    }
+
+    /// Cuts reads short once `short` is set, like a truncated response.
+    struct Truncating<'a> {
+        bytes: &'a [u8],
+        short: bool,
+    }
+
+    impl RangeRead for Truncating<'_> {
+        fn read_range(&mut self, range: Range<u64>) -> io::Result<Vec<u8>> {
+            let mut bytes = self.bytes.read_range(range)?;
+            if self.short {
+                bytes.truncate(2);
+            }
+            Ok(bytes)
+        }
+    }
+
+    #[test]
+    fn test_index_source_truncated_read() {
+        let n = BLOCK_KEYS as i64 * 4;
+        let bytes = file(&TrieRelation::new(1, (0..n).map(|i| [i])));
+        let reader = Truncating {
+            bytes: &bytes,
+            short: false,
+        };
+        let mut source = IndexSource::<_, i64>::open(reader).unwrap();
+        source.reader.short = true;
+        source.seek(n - 1);
+        assert!(source.at_end());
+        assert_eq!(
+            source.error().map(ToString::to_string),
+            Some("invalid index file: file is truncated".to_string())
+        );
+    }
+
+    /// Serves up to four reads per call, and counts the calls.
+    struct Parallel<'a> {
+        bytes: &'a [u8],
+        rounds: usize,
+    }
+
+    impl RangeRead for Parallel<'_> {
+        fn read_range(&mut self, range: Range<u64>) -> io::Result<Vec<u8>> {
+            self.bytes.read_range(range)
+        }
+
+        fn read_ranges(&mut self, ranges: &[Range<u64>]) -> Vec<io::Result<Vec<u8>>> {
+            assert!(ranges.len() <= 4);
+            self.rounds += 1;
+            ranges
+                .iter()
+                .map(|r| self.bytes.read_range(r.clone()))
+                .collect()
+        }
+
+        fn queue_depth(&self) -> usize {
+            4
+        }
+    }
+
+    #[test]
+    fn test_index_source_queue_depth() {
+        let n = BLOCK_KEYS as i64 * 100;
+        let bytes = file(&TrieRelation::new(1, (0..n).map(|i| [i * 2])));
+        for target in [
+            1,
+            2 * 5000 + 1,
+            2 * 99 * BLOCK_KEYS as i64,
+            2 * n - 2,
+            2 * n,
+        ] {
+            let reader = Parallel {
+                bytes: &bytes,
+                rounds: 0,
+            };
+            let mut source = IndexSource::<_, i64>::open(reader).unwrap();
+            source.seek(target);
+            if target < 2 * n {
+                assert_eq!(source.key(), target + target % 2);
+            } else {
+                assert!(source.at_end() && source.error().is_none());
+            }
+            // log5(99) rounds of probes, the block found, and the next one.
+            assert!(source.into_reader().rounds <= 1 + 3 + 2);
+        }
+    }
+
+    #[cfg(feature = "threads")]
+    #[test]
+    fn test_index_source_prefetch() {
+        let n = BLOCK_KEYS as i64 * 64;
+        let bytes = file(&TrieRelation::new(1, (0..n).map(|i| [i])));
+        let bytes: &'static [u8] = Box::leak(bytes.into_boxed_slice());
+        let mut source = IndexSource::<_, i64>::open(bytes).unwrap().with_prefetch();
+        source.will_need(5);
+        assert_eq!(source.stats().prefetches, 0);
+        source.will_need(n - 1);
+        source.will_need(n - 1);
+        assert_eq!(source.stats().prefetches, 1);
+        let requests = source.stats().requests;
+        source.seek(n - 1);
+        assert_eq!(source.key(), n - 1);
+        // The first probe was prefetched; the other five are read now.
+        assert_eq!(source.stats().requests, requests + 5);
+        assert_eq!(source.stats().cache_hits, 1 + 1);
+    }
+
+    /// Gated reads the bytes only while its gate is open.
+    #[cfg(feature = "threads")]
+    #[derive(Clone)]
+    struct Gated {
+        bytes: &'static [u8],
+        gate: Arc<std::sync::RwLock<()>>,
+    }
+
+    #[cfg(feature = "threads")]
+    impl RangeRead for Gated {
+        fn read_range(&mut self, range: Range<u64>) -> io::Result<Vec<u8>> {
+            let _open = self.gate.read().unwrap();
+            self.bytes.read_range(range)
+        }
+    }
+
+    #[cfg(feature = "threads")]
+    #[test]
+    fn test_index_source_prefetch_cap() {
+        let n = BLOCK_KEYS as i64 * 64;
+        let bytes = file(&TrieRelation::new(1, (0..n).map(|i| [i])));
+        let reader = Gated {
+            bytes: Box::leak(bytes.into_boxed_slice()),
+            gate: Arc::default(),
+        };
+        let gate = reader.gate.clone();
+        let mut source = IndexSource::<_, i64>::open(reader).unwrap().with_prefetch();
+        let closed = gate.write().unwrap();
+        for block in 1..64 {
+            source.prefetch(block);
+        }
+        assert_eq!(source.stats().prefetches, MAX_PREFETCHES);
+        assert_eq!(source.in_flight.len(), MAX_PREFETCHES);
+        drop(closed);
+        source.seek(n - 1);
+        assert_eq!(source.key(), n - 1);
+    }
+
+    #[cfg(feature = "object_store")]
+    #[test]
+    fn test_object_reader() {
+        use object_store::{ObjectStore, PutPayload, memory::InMemory, path::Path};
+
+        let bytes = file(&TrieRelation::new(1, (0..10).map(|i| [i])));
+        let store = Arc::new(InMemory::new());
+        let path = Path::from("relations/r.lftr");
+        futures::executor::block_on(store.put(&path, PutPayload::from(bytes))).unwrap();
+        let mut source = IndexSource::<_, i64>::open(ObjectReader::new(store, path)).unwrap();
+        source.seek(7);
+        assert_eq!(source.key(), 7);
+    }
+}
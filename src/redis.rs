@@ -0,0 +1,250 @@
+//! Redis sorted sets as join sources.
+//!
+//! A sorted set (ZSET) keeps its members in order, so it can answer the range
+//! requests of a RemoteSource directly: ScoreSource reads integer keys from
+//! the scores with ZRANGEBYSCORE, LexSource reads memcomparable-encoded keys
+//! from the members with ZRANGEBYLEX. Wrapped in a RemoteIterator, which the
+//! `iter` constructors do, seeks become range requests that start at the seek
+//! key, and keys are fetched in batches.
+//!
+//! Both sources are blocking and take any ConnectionLike, e.g. a
+//! redis::Connection. Since RemoteSource::fetch() cannot fail, the first
+//! error ends the source and is kept for error().
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Bound;
+
+use ::redis::{ConnectionLike, RedisError, cmd};
+
+use crate::memcomparable::{self, DecodeError, KeyPart};
+use crate::remote::{RemoteIterator, RemoteSource};
+
+#[derive(Debug)]
+pub enum SourceError {
+    Redis(RedisError),
+    /// A score is not an integer.
+    Score(f64),
+    /// A member is not a valid encoded key.
+    Member(DecodeError),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Redis(e) => write!(f, "Redis error: {e}"),
+            SourceError::Score(score) => write!(f, "score {score} is not an integer"),
+            SourceError::Member(e) => write!(f, "invalid member: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<RedisError> for SourceError {
+    fn from(e: RedisError) -> Self {
+        SourceError::Redis(e)
+    }
+}
+
+/// ScoreSource is the distinct scores of a sorted set, as integers.
+///
+/// Scores are doubles, so keys beyond ±2^53 lose precision on the server.
+/// Several members may share a score; the key is then read once.
+pub struct ScoreSource<C> {
+    conn: C,
+    key: String,
+    error: Option<SourceError>,
+}
+
+impl<C: ConnectionLike> ScoreSource<C> {
+    pub fn new(conn: C, key: &str) -> Self {
+        Self {
+            conn,
+            key: key.to_string(),
+            error: None,
+        }
+    }
+
+    /// Returns an iterator over the scores that fetches `batch_size` members
+    /// per request.
+    pub fn iter(conn: C, key: &str, batch_size: usize) -> RemoteIterator<Self> {
+        RemoteIterator::new(Self::new(conn, key), batch_size)
+    }
+
+    /// Returns the error that ended the source, if any.
+    pub fn error(&self) -> Option<&SourceError> {
+        self.error.as_ref()
+    }
+
+    fn scores(&mut self, start: Bound<i64>, limit: usize) -> Result<Vec<i64>, SourceError> {
+        let min = match start {
+            Bound::Included(k) => k.to_string(),
+            Bound::Excluded(k) => format!("({k}"),
+            Bound::Unbounded => "-inf".to_string(),
+        };
+        let members: Vec<(Vec<u8>, f64)> = cmd("ZRANGEBYSCORE")
+            .arg(&self.key)
+            .arg(min)
+            .arg("+inf")
+            .arg("WITHSCORES")
+            .arg("LIMIT")
+            .arg(0)
+            .arg(limit)
+            .query(&mut self.conn)?;
+        members
+            .into_iter()
+            .map(|(_, score)| {
+                if score.fract() == 0.0 {
+                    Ok(score as i64)
+                } else {
+                    Err(SourceError::Score(score))
+                }
+            })
+            .collect()
+    }
+}
+
+impl<C: ConnectionLike> RemoteSource for ScoreSource<C> {
+    type Key = i64;
+
+    fn fetch(&mut self, start: Bound<i64>, limit: usize) -> Vec<i64> {
+        if self.error.is_some() {
+            return vec![];
+        }
+        distinct(start, limit, |start, limit| self.scores(start, limit)).unwrap_or_else(|e| {
+            self.error = Some(e);
+            vec![]
+        })
+    }
+}
+
+/// Fetches up to `limit` distinct keys with `fetch`, which may return
+/// duplicates. As duplicates shrink a batch, a full batch is followed by
+/// another request, so that a short result still means there are no more keys.
+fn distinct<K: Ord + Copy, E>(
+    mut start: Bound<K>,
+    limit: usize,
+    mut fetch: impl FnMut(Bound<K>, usize) -> Result<Vec<K>, E>,
+) -> Result<Vec<K>, E> {
+    let mut keys: Vec<K> = Vec::with_capacity(limit);
+    loop {
+        let want = limit - keys.len();
+        let batch = fetch(start, want)?;
+        let full = batch.len() == want;
+        for key in batch {
+            if keys.last() != Some(&key) {
+                keys.push(key);
+            }
+        }
+        match keys.last() {
+            Some(&last) if full && keys.len() < limit => start = Bound::Excluded(last),
+            _ => return Ok(keys),
+        }
+    }
+}
+
+/// LexSource is the members of a sorted set, decoded as keys.
+///
+/// ZRANGEBYLEX orders members bytewise only if they all have the same score,
+/// so add them with score 0 and encode them with memcomparable::encode().
+pub struct LexSource<C, K> {
+    conn: C,
+    key: String,
+    error: Option<SourceError>,
+    _key: PhantomData<K>,
+}
+
+impl<C: ConnectionLike, K: KeyPart + Ord + Copy> LexSource<C, K> {
+    pub fn new(conn: C, key: &str) -> Self {
+        Self {
+            conn,
+            key: key.to_string(),
+            error: None,
+            _key: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the members that fetches `batch_size` of them
+    /// per request.
+    pub fn iter(conn: C, key: &str, batch_size: usize) -> RemoteIterator<Self> {
+        RemoteIterator::new(Self::new(conn, key), batch_size)
+    }
+
+    /// Returns the error that ended the source, if any.
+    pub fn error(&self) -> Option<&SourceError> {
+        self.error.as_ref()
+    }
+
+    fn members(&mut self, start: Bound<K>, limit: usize) -> Result<Vec<K>, SourceError> {
+        let min = match start {
+            Bound::Included(k) => [b"[".as_slice(), &memcomparable::encode(&k)].concat(),
+            Bound::Excluded(k) => [b"(".as_slice(), &memcomparable::encode(&k)].concat(),
+            Bound::Unbounded => b"-".to_vec(),
+        };
+        let members: Vec<Vec<u8>> = cmd("ZRANGEBYLEX")
+            .arg(&self.key)
+            .arg(min)
+            .arg("+")
+            .arg("LIMIT")
+            .arg(0)
+            .arg(limit)
+            .query(&mut self.conn)?;
+        members
+            .iter()
+            .map(|member| memcomparable::decode(member).map_err(SourceError::Member))
+            .collect()
+    }
+}
+
+impl<C: ConnectionLike, K: KeyPart + Ord + Copy> RemoteSource for LexSource<C, K> {
+    type Key = K;
+
+    fn fetch(&mut self, start: Bound<K>, limit: usize) -> Vec<K> {
+        if self.error.is_some() {
+            return vec![];
+        }
+        self.members(start, limit).unwrap_or_else(|e| {
+            self.error = Some(e);
+            vec![]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves a sorted list with duplicates the way ZRANGEBYSCORE does.
+    fn fetch_from(keys: &[i64]) -> impl FnMut(Bound<i64>, usize) -> Result<Vec<i64>, ()> + '_ {
+        |start, limit| {
+            let from = match start {
+                Bound::Included(k) => keys.partition_point(|&x| x < k),
+                Bound::Excluded(k) => keys.partition_point(|&x| x <= k),
+                Bound::Unbounded => 0,
+            };
+            Ok(keys[from..].iter().copied().take(limit).collect())
+        }
+    }
+
+    #[test]
+    fn test_distinct_scores() {
+        let keys = [1, 1, 1, 2, 3, 3, 3, 3, 7, 9, 9];
+        assert_eq!(
+            distinct(Bound::Unbounded, 3, fetch_from(&keys)),
+            Ok(vec![1, 2, 3])
+        );
+        assert_eq!(
+            distinct(Bound::Excluded(3), 3, fetch_from(&keys)),
+            Ok(vec![7, 9])
+        );
+        assert_eq!(
+            distinct(Bound::Included(9), 1, fetch_from(&keys)),
+            Ok(vec![9])
+        );
+        assert_eq!(
+            distinct(Bound::Excluded(9), 2, fetch_from(&keys)),
+            Ok(vec![])
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Mapping plain structs to joinable relations.
+//!
+//! With the `derive` feature, `#[derive(Relation)]` implements Relation for
+//! a struct. Every field marked `#[relation(key)]` gets a sorted index, from
+//! which the derived `<Struct>Index` type hands out one LinearIterator per
+//! field, so a `Vec<MyEvent>` can be joined on that field directly:
+//!
+//! ```ignore
+//! #[derive(Relation)]
+//! struct Event {
+//!     #[relation(key)]
+//!     user: u64,
+//!     payload: String,
+//! }
+//!
+//! let index = Event::index(&events);
+//! let join = LeapFrogJoin::from_iters(vec![index.user(), other.user()]);
+//! ```
+
+/// A row type whose slices can be indexed for joining.
+pub trait Relation: Sized {
+    type Index<'a>
+    where
+        Self: 'a;
+
+    /// Sorts `rows` by every key field.
+    fn index(rows: &[Self]) -> Self::Index<'_>;
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use super::*;
+    use crate::{LeapFrogJoin, Relation};
+
+    #[derive(Relation)]
+    struct Event {
+        #[relation(key)]
+        user: u64,
+        #[relation(key)]
+        day: i32,
+        payload: &'static str,
+    }
+
+    #[derive(Relation)]
+    struct Account {
+        #[relation(key)]
+        id: u64,
+    }
+
+    #[test]
+    fn test_derive_relation() {
+        let events = vec![
+            Event {
+                user: 3,
+                day: 2,
+                payload: "c",
+            },
+            Event {
+                user: 1,
+                day: 1,
+                payload: "a",
+            },
+            Event {
+                user: 1,
+                day: 3,
+                payload: "b",
+            },
+        ];
+        let accounts = vec![Account { id: 1 }, Account { id: 2 }];
+        let events = Event::index(&events);
+        let accounts = Account::index(&accounts);
+
+        let mut join = LeapFrogJoin::from_iters(vec![events.user(), accounts.id()]);
+        assert_eq!(join.key(), 1);
+        let payloads: Vec<&str> = events.user_rows(1).iter().map(|e| e.payload).collect();
+        assert_eq!(payloads, vec!["a", "b"]);
+        join.next();
+        assert!(join.at_end());
+
+        assert_eq!(events.day_rows(2)[0].user, 3);
+        assert!(events.day_rows(4).is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+use std::ops::Bound;
+
+use crate::tuning::Tuning;
+use crate::{Seekable, instrument};
+
+/// RemoteSource is a sorted key set that lives behind some request/response
+/// protocol, e.g. a key-value service or an S3-select query.
+///
+/// The only operation is a range request: "give me at most `limit` keys in
+/// ascending order, starting at `start`". A RemoteIterator turns the seeks of
+/// the leapfrog join into such requests, so keys the join skips over are never
+/// transferred.
+pub trait RemoteSource {
+    type Key: Ord + Copy;
+
+    /// Returns up to `limit` keys >= (or > for Bound::Excluded) `start`, in
+    /// ascending order. Returning fewer than `limit` keys signals that there
+    /// are no more keys after the returned ones.
+    fn fetch(&mut self, start: Bound<Self::Key>, limit: usize) -> Vec<Self::Key>;
+}
+
+/// Counters describing the traffic a RemoteIterator caused.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RemoteStats {
+    /// Number of range requests sent to the source.
+    pub requests: usize,
+    /// Number of keys transferred by those requests.
+    pub keys_fetched: usize,
+    /// Number of seeks that were answered from the local buffer.
+    pub buffered_seeks: usize,
+}
+
+/// RemoteIterator adapts a RemoteSource to the Seekable interface.
+///
+/// Keys are fetched in batches and buffered client-side. A seek that lands
+/// inside the buffer is answered locally; one that lands beyond it drops the
+/// buffer and issues a new range request starting at the seek key.
+///
+/// Runs of next() calls prefetch increasingly larger batches (doubling up to
+/// `max_batch_size`), so sequential scans need few requests while the batch
+/// size falls back to `batch_size` after every remote seek.
+pub struct RemoteIterator<S: RemoteSource> {
+    source: S,
+    buffer: Vec<S::Key>,
+    pos: usize,
+    exhausted: bool,
+    batch_size: usize,
+    max_batch_size: usize,
+    next_batch_size: usize,
+    stats: RemoteStats,
+}
+
+impl<S: RemoteSource> RemoteIterator<S> {
+    pub fn new(source: S, batch_size: usize) -> Self {
+        Self::with_prefetch(source, batch_size, batch_size)
+    }
+
+    /// Creates an iterator that fetches `tuning.batch_size` keys per request.
+    pub fn with_tuning(source: S, tuning: &Tuning) -> Self {
+        Self::new(source, tuning.batch_size)
+    }
+
+    /// Creates an iterator whose sequential batches grow up to
+    /// `max_batch_size` keys.
+    pub fn with_prefetch(source: S, batch_size: usize, max_batch_size: usize) -> Self {
+        assert!(batch_size > 0, "Batch size must be > 0");
+        assert!(
+            max_batch_size >= batch_size,
+            "Max batch size must be >= batch size"
+        );
+        let mut iter = Self {
+            source,
+            buffer: Vec::new(),
+            pos: 0,
+            exhausted: false,
+            batch_size,
+            max_batch_size,
+            next_batch_size: batch_size,
+            stats: RemoteStats::default(),
+        };
+        iter.fill(Bound::Unbounded, batch_size);
+        iter
+    }
+
+    pub fn stats(&self) -> RemoteStats {
+        self.stats
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    fn fill(&mut self, start: Bound<S::Key>, limit: usize) {
+        let keys = self.source.fetch(start, limit);
+        assert!(keys.len() <= limit, "Remote source returned too many keys");
+        self.stats.requests += 1;
+        self.stats.keys_fetched += keys.len();
+        instrument::bytes_decoded(std::mem::size_of_val(keys.as_slice()));
+        self.exhausted = keys.len() < limit;
+        self.buffer = keys;
+        self.pos = 0;
+    }
+}
+
+impl<S: RemoteSource> Seekable for RemoteIterator<S> {
+    type Key = S::Key;
+
+    fn key(&self) -> S::Key {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.buffer[self.pos]
+    }
+
+    fn next(&mut self) {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.pos += 1;
+        if self.pos == self.buffer.len() && !self.exhausted {
+            let last = self.buffer[self.pos - 1];
+            let limit = self.next_batch_size;
+            self.next_batch_size = (limit * 2).min(self.max_batch_size);
+            self.fill(Bound::Excluded(last), limit);
+        }
+    }
+
+    fn seek(&mut self, seek_key: S::Key) {
+        assert!(!self.at_end(), "Iterator is at end");
+        assert!(seek_key >= self.key(), "Seek key must be >= current key");
+        let last = self.buffer[self.buffer.len() - 1];
+        if seek_key <= last || self.exhausted {
+            self.stats.buffered_seeks += 1;
+            self.pos += self.buffer[self.pos..].partition_point(|&k| k < seek_key);
+        } else {
+            self.next_batch_size = self.batch_size;
+            self.fill(Bound::Included(seek_key), self.batch_size);
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.buffer.len()
+    }
+
+    /// Known once the last batch has been fetched.
+    fn upper_bound(&self) -> Option<S::Key> {
+        self.buffer.last().copied().filter(|_| self.exhausted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LeapFrogJoin;
+
+    /// An in-memory stand-in for a remote key store.
+    struct VecSource {
+        keys: Vec<i32>,
+    }
+
+    impl RemoteSource for VecSource {
+        type Key = i32;
+
+        fn fetch(&mut self, start: Bound<i32>, limit: usize) -> Vec<i32> {
+            let from = match start {
+                Bound::Included(k) => self.keys.partition_point(|&x| x < k),
+                Bound::Excluded(k) => self.keys.partition_point(|&x| x <= k),
+                Bound::Unbounded => 0,
+            };
+            self.keys[from..].iter().copied().take(limit).collect()
+        }
+    }
+
+    fn remote(keys: Vec<i32>, batch_size: usize) -> RemoteIterator<VecSource> {
+        RemoteIterator::new(VecSource { keys }, batch_size)
+    }
+
+    #[test]
+    fn test_remote_iterator_scan() {
+        let mut iter = remote((0..10).collect(), 3);
+        let mut seen = vec![];
+        while !iter.at_end() {
+            seen.push(iter.key());
+            iter.next();
+        }
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+        assert_eq!(iter.stats().requests, 4);
+        assert_eq!(iter.stats().keys_fetched, 10);
+    }
+
+    #[test]
+    fn test_remote_iterator_empty() {
+        let iter = remote(vec![], 4);
+        assert!(iter.at_end());
+    }
+
+    #[test]
+    fn test_remote_iterator_seek() {
+        let mut iter = remote((0..100).map(|x| x * 2).collect(), 4);
+        iter.seek(3);
+        assert_eq!(iter.key(), 4);
+        assert_eq!(iter.stats().buffered_seeks, 1);
+        iter.seek(101);
+        assert_eq!(iter.key(), 102);
+        assert_eq!(iter.stats().requests, 2);
+        assert_eq!(iter.stats().keys_fetched, 8);
+        iter.seek(1000);
+        assert!(iter.at_end());
+    }
+
+    #[test]
+    fn test_remote_iterator_prefetch_grows() {
+        let mut iter = RemoteIterator::with_prefetch(
+            VecSource {
+                keys: (0..100).collect(),
+            },
+            2,
+            16,
+        );
+        while !iter.at_end() {
+            iter.next();
+        }
+        // 2 + 2 + 4 + 8 + 16 * 5 + a final short batch
+        assert_eq!(iter.stats().requests, 10);
+    }
+
+    #[test]
+    fn test_remote_join_skips_keys() {
+        let sparse = remote(vec![500, 9000, 9999], 2);
+        let dense = remote((0..10000).collect(), 4);
+        let mut join = LeapFrogJoin::from_iters(vec![sparse, dense]);
+        let mut result = vec![];
+        while !join.at_end() {
+            result.push(join.key());
+            join.next();
+        }
+        assert_eq!(result, vec![500, 9000, 9999]);
+        let fetched: usize = join
+            .into_iters()
+            .iter()
+            .map(|iter| iter.stats().keys_fetched)
+            .sum();
+        assert!(fetched < 100);
+    }
+}
@@ -0,0 +1,393 @@
+//! Deterministic replay of join executions.
+//!
+//! A JoinRecorder wraps a LeapFrogJoin over slices and logs its construction,
+//! every next() and seek() call, and the outcome of each, together with a
+//! fingerprint of every source. replay() re-executes such a log against a set
+//! of sources, after checking the fingerprints, and reports the first
+//! operation whose outcome differs from the recorded one.
+//!
+//! The log format is compact: a header with the magic bytes `LFRL`, a version
+//! byte and per source its length and fingerprint, followed by one tag byte per
+//! operation and LEB128-encoded keys.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::{LeapFrogJoin, LinearIterator};
+
+const MAGIC: &[u8; 4] = b"LFRL";
+const VERSION: u8 = 1;
+
+const OP_NEXT: u8 = 0;
+const OP_SEEK: u8 = 1;
+
+const OUTCOME_END: u8 = 0;
+const OUTCOME_KEY: u8 = 1;
+
+/// ReplayKey converts keys to and from the u64 representation stored in logs.
+pub trait ReplayKey: Ord + Copy {
+    fn encode(self) -> u64;
+    fn decode(value: u64) -> Self;
+}
+
+macro_rules! impl_replay_key_unsigned {
+    ($($t:ty),*) => {$(
+        impl ReplayKey for $t {
+            fn encode(self) -> u64 {
+                self as u64
+            }
+
+            fn decode(value: u64) -> Self {
+                value as $t
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_replay_key_signed {
+    ($($t:ty),*) => {$(
+        impl ReplayKey for $t {
+            // Zigzag encoding keeps small negative keys short.
+            fn encode(self) -> u64 {
+                let v = self as i64;
+                ((v << 1) ^ (v >> 63)) as u64
+            }
+
+            fn decode(value: u64) -> Self {
+                ((value >> 1) as i64 ^ -((value & 1) as i64)) as $t
+            }
+        }
+    )*};
+}
+
+impl_replay_key_unsigned!(u8, u16, u32, u64, usize);
+impl_replay_key_signed!(i8, i16, i32, i64, isize);
+
+/// Returns the FNV-1a hash of the source's length and encoded keys.
+pub fn fingerprint<T: ReplayKey>(source: &[T]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let len = source.len() as u64;
+    for byte in len
+        .to_le_bytes()
+        .into_iter()
+        .chain(source.iter().flat_map(|k| k.encode().to_le_bytes()))
+    {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Outcome of a join operation: the encoded key (see ReplayKey) the join is
+/// positioned at, or None once it is at end.
+pub type Outcome = Option<u64>;
+
+/// JoinRecorder is a LeapFrogJoin that logs everything done to it.
+pub struct JoinRecorder<'a, T: ReplayKey, W: Write> {
+    join: LeapFrogJoin<LinearIterator<'a, T>>,
+    log: W,
+}
+
+impl<'a, T: ReplayKey, W: Write> JoinRecorder<'a, T, W> {
+    pub fn new(sources: Vec<&'a [T]>, mut log: W) -> io::Result<Self> {
+        log.write_all(MAGIC)?;
+        log.write_all(&[VERSION])?;
+        write_varint(&mut log, sources.len() as u64)?;
+        for source in &sources {
+            write_varint(&mut log, source.len() as u64)?;
+            log.write_all(&fingerprint(source).to_le_bytes())?;
+        }
+        let mut recorder = Self {
+            join: LeapFrogJoin::new(sources),
+            log,
+        };
+        recorder.write_outcome()?;
+        Ok(recorder)
+    }
+
+    pub fn key(&self) -> T {
+        self.join.key()
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.join.at_end()
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<()> {
+        self.log.write_all(&[OP_NEXT])?;
+        self.join.next();
+        self.write_outcome()
+    }
+
+    pub fn seek(&mut self, seek_key: T) -> io::Result<()> {
+        self.log.write_all(&[OP_SEEK])?;
+        write_varint(&mut self.log, seek_key.encode())?;
+        self.join.seek(seek_key);
+        self.write_outcome()
+    }
+
+    /// Flushes and returns the log.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.log.flush()?;
+        Ok(self.log)
+    }
+
+    fn write_outcome(&mut self) -> io::Result<()> {
+        if self.join.at_end() {
+            self.log.write_all(&[OUTCOME_END])
+        } else {
+            self.log.write_all(&[OUTCOME_KEY])?;
+            write_varint(&mut self.log, self.join.key().encode())
+        }
+    }
+}
+
+/// Errors detected while replaying a log.
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    /// The log is not a replay log or is truncated/corrupt.
+    Format(String),
+    /// The log was recorded over a different number of sources.
+    SourceCount {
+        recorded: usize,
+        given: usize,
+    },
+    /// A source differs from the one the log was recorded with.
+    Fingerprint {
+        source: usize,
+    },
+    /// The operation with the given index (0 is construction) produced a
+    /// different outcome than recorded.
+    Diverged {
+        op: usize,
+        recorded: Outcome,
+        replayed: Outcome,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "I/O error: {e}"),
+            ReplayError::Format(msg) => write!(f, "invalid replay log: {msg}"),
+            ReplayError::SourceCount { recorded, given } => {
+                write!(f, "log has {recorded} sources, {given} given")
+            }
+            ReplayError::Fingerprint { source } => {
+                write!(f, "source {source} does not match the recorded one")
+            }
+            ReplayError::Diverged {
+                op,
+                recorded,
+                replayed,
+            } => write!(
+                f,
+                "operation {op} diverged: recorded {recorded:?}, replayed {replayed:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            ReplayError::Format("log is truncated".to_string())
+        } else {
+            ReplayError::Io(e)
+        }
+    }
+}
+
+/// Re-executes a log against the given sources. Returns the number of
+/// replayed operations, including construction.
+pub fn replay<T: ReplayKey, R: Read>(mut log: R, sources: Vec<&[T]>) -> Result<usize, ReplayError> {
+    let mut magic = [0u8; 4];
+    log.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(ReplayError::Format("bad magic".to_string()));
+    }
+    let version = read_byte(&mut log)?.unwrap_or_default();
+    if version != VERSION {
+        return Err(ReplayError::Format(format!(
+            "unsupported version {version}"
+        )));
+    }
+    let recorded = read_varint(&mut log)? as usize;
+    if recorded != sources.len() {
+        return Err(ReplayError::SourceCount {
+            recorded,
+            given: sources.len(),
+        });
+    }
+    for (i, source) in sources.iter().enumerate() {
+        let len = read_varint(&mut log)?;
+        let mut hash = [0u8; 8];
+        log.read_exact(&mut hash)?;
+        if len != source.len() as u64 || u64::from_le_bytes(hash) != fingerprint(source) {
+            return Err(ReplayError::Fingerprint { source: i });
+        }
+    }
+
+    let mut join = LeapFrogJoin::new(sources);
+    check_outcome(&mut log, &join, 0)?;
+    let mut op = 1;
+    while let Some(tag) = read_byte(&mut log)? {
+        if join.at_end() {
+            return Err(ReplayError::Format(format!("operation {op} after end")));
+        }
+        match tag {
+            OP_NEXT => join.next(),
+            OP_SEEK => join.seek(T::decode(read_varint(&mut log)?)),
+            _ => return Err(ReplayError::Format(format!("unknown operation {tag}"))),
+        }
+        check_outcome(&mut log, &join, op)?;
+        op += 1;
+    }
+    Ok(op)
+}
+
+fn check_outcome<T: ReplayKey, R: Read>(
+    log: &mut R,
+    join: &LeapFrogJoin<LinearIterator<'_, T>>,
+    op: usize,
+) -> Result<(), ReplayError> {
+    let recorded = match read_byte(log)? {
+        Some(OUTCOME_END) => None,
+        Some(OUTCOME_KEY) => Some(read_varint(log)?),
+        _ => {
+            return Err(ReplayError::Format(format!(
+                "bad outcome of operation {op}"
+            )));
+        }
+    };
+    let replayed = (!join.at_end()).then(|| join.key().encode());
+    if recorded != replayed {
+        return Err(ReplayError::Diverged {
+            op,
+            recorded,
+            replayed,
+        });
+    }
+    Ok(())
+}
+
+fn write_varint<W: Write>(w: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint<R: Read>(r: &mut R) -> Result<u64, ReplayError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_byte(r)?.ok_or_else(|| ReplayError::Format("log is truncated".into()))?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(ReplayError::Format("varint is too long".to_string()))
+}
+
+fn read_byte<R: Read>(r: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    match r.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(tab1: &[i32], tab2: &[i32]) -> Vec<u8> {
+        let mut recorder = JoinRecorder::new(vec![tab1, tab2], Vec::new()).unwrap();
+        recorder.next().unwrap();
+        recorder.seek(8).unwrap();
+        while !recorder.at_end() {
+            recorder.next().unwrap();
+        }
+        recorder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_replay_roundtrip() {
+        let tab1 = [-3, 0, 1, 3, 4, 5, 6, 7, 8, 9, 11];
+        let tab2 = [-3, 0, 2, 6, 7, 8, 9, 11];
+        let log = record(&tab1, &tab2);
+        // construction, next, seek, and three nexts to reach the end
+        assert_eq!(
+            replay(log.as_slice(), vec![&tab1[..], &tab2[..]]).unwrap(),
+            6
+        );
+    }
+
+    #[test]
+    fn test_replay_detects_changed_source() {
+        let tab1 = [0, 1, 3, 4, 5, 6, 7, 8, 9, 11];
+        let tab2 = [0, 2, 6, 7, 8, 9, 11];
+        let log = record(&tab1, &tab2);
+        let changed = [0, 2, 6, 7, 8, 10, 11];
+        assert!(matches!(
+            replay(log.as_slice(), vec![&tab1[..], &changed[..]]),
+            Err(ReplayError::Fingerprint { source: 1 })
+        ));
+        assert!(matches!(
+            replay(log.as_slice(), vec![&tab1[..]]),
+            Err(ReplayError::SourceCount {
+                recorded: 2,
+                given: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        let tab1 = [0, 1, 3, 4, 5, 6, 7, 8, 9, 11];
+        let tab2 = [0, 2, 6, 7, 8, 9, 11];
+        let mut log = record(&tab1, &tab2);
+        // The outcome of the construction is the last byte of the header
+        // block: OUTCOME_KEY followed by varint(zigzag(0)) == 0. Claim 1.
+        let header_len = 4 + 1 + 1 + 2 * (1 + 8);
+        log[header_len + 1] = 2;
+        assert!(matches!(
+            replay(log.as_slice(), vec![&tab1[..], &tab2[..]]),
+            Err(ReplayError::Diverged {
+                op: 0,
+                recorded: Some(2),
+                replayed: Some(0)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_replay_rejects_garbage() {
+        let tab: [i32; 0] = [];
+        assert!(matches!(
+            replay(&b"nope"[..], vec![&tab[..]]),
+            Err(ReplayError::Format(_))
+        ));
+        assert!(matches!(
+            replay(&b"LF"[..], vec![&tab[..]]),
+            Err(ReplayError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn test_replay_key_zigzag() {
+        for k in [i64::MIN, -1, 0, 1, i64::MAX] {
+            assert_eq!(i64::decode(k.encode()), k);
+        }
+        assert_eq!((-1i32).encode(), 1);
+    }
+}
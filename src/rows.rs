@@ -0,0 +1,487 @@
+//! Joining rows on a key field, with the matching rows as output.
+//!
+//! zip_join() takes a tuple of row slices, each sorted by a key field, and
+//! yields `(key, (&RowA, &RowB, ...))` per match. The cursors over the rows
+//! take part in the join directly, so the rows are available right at the
+//! match, without looking each key up in every source afterwards.
+//!
+//! With DuplicatePolicy::CrossProduct, a key occurring in several rows of the
+//! sources yields every combination of those rows, as an SQL inner join does.
+//! The combinations are enumerated lazily, one per call of next().
+//!
+//! To avoid that blowup, a source can instead be folded with Rows::fold(): all
+//! of its rows with the key are combined into a single value (a count, a sum,
+//! the first row, ...) before pairing. Folded sources never contribute more
+//! than one value per key, whatever the DuplicatePolicy.
+
+use crate::{LeapFrogJoin, Seekable};
+
+/// Which rows to return for a key that occurs in several rows of a source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// The first row with the key.
+    First,
+    /// The last row with the key.
+    Last,
+    /// Skip keys that occur more than once in any source.
+    Skip,
+    /// Every combination of rows with the key.
+    CrossProduct,
+}
+
+/// Rows of type R, sorted by the key `key` extracts.
+pub struct Rows<'a, R, K> {
+    rows: &'a [R],
+    key: fn(&R) -> K,
+}
+
+impl<'a, R, K: Ord + Copy> Rows<'a, R, K> {
+    pub fn new(rows: &'a [R], key: fn(&R) -> K) -> Self {
+        assert!(rows.is_sorted_by_key(key), "Rows must be sorted by key");
+        Self { rows, key }
+    }
+
+    /// Combines the rows of every key with `fold` instead of returning them
+    /// one by one.
+    pub fn fold<V, F: Fn(&'a [R]) -> V>(self, fold: F) -> Folded<'a, R, K, F> {
+        Folded { rows: self, fold }
+    }
+
+    /// Folds the rows of every key into their count.
+    pub fn count(self) -> Folded<'a, R, K, impl Fn(&'a [R]) -> usize> {
+        self.fold(<[R]>::len)
+    }
+
+    /// Folds the rows of every key into the first of them.
+    pub fn first(self) -> Folded<'a, R, K, impl Fn(&'a [R]) -> &'a R> {
+        self.fold(|rows| &rows[0])
+    }
+
+    fn cursor(&self) -> RowCursor<'a, R, K> {
+        RowCursor {
+            rows: self.rows,
+            key: self.key,
+            pos: 0,
+        }
+    }
+}
+
+/// Rows whose rows per key are combined into one value, see Rows::fold().
+pub struct Folded<'a, R, K, F> {
+    rows: Rows<'a, R, K>,
+    fold: F,
+}
+
+/// One element of the tuple passed to zip_join(): Rows or Folded.
+pub trait RowSource<'a> {
+    type Key: Ord + Copy;
+    /// What the source contributes to a match.
+    type Item;
+
+    #[doc(hidden)]
+    fn cursor(&self) -> BoxedCursor<'a, Self::Key>;
+
+    /// Whether all rows of a key yield a single item.
+    #[doc(hidden)]
+    fn is_folded(&self) -> bool;
+
+    /// The item for the rows `run` of a key, at `pos` within them.
+    #[doc(hidden)]
+    fn item(&self, run: (usize, usize), pos: usize) -> Self::Item;
+}
+
+impl<'a, R: 'a, K: Ord + Copy + 'a> RowSource<'a> for Rows<'a, R, K> {
+    type Key = K;
+    type Item = &'a R;
+
+    fn cursor(&self) -> BoxedCursor<'a, K> {
+        Box::new(Rows::cursor(self))
+    }
+
+    fn is_folded(&self) -> bool {
+        false
+    }
+
+    fn item(&self, _run: (usize, usize), pos: usize) -> &'a R {
+        &self.rows[pos]
+    }
+}
+
+impl<'a, R: 'a, K: Ord + Copy + 'a, V, F: Fn(&'a [R]) -> V> RowSource<'a> for Folded<'a, R, K, F> {
+    type Key = K;
+    type Item = V;
+
+    fn cursor(&self) -> BoxedCursor<'a, K> {
+        Box::new(self.rows.cursor())
+    }
+
+    fn is_folded(&self) -> bool {
+        true
+    }
+
+    fn item(&self, (start, end): (usize, usize), _pos: usize) -> V {
+        (self.fold)(&self.rows.rows[start..end])
+    }
+}
+
+/// Seekable over the distinct keys of some rows.
+pub(crate) struct RowCursor<'a, R, K> {
+    rows: &'a [R],
+    key: fn(&R) -> K,
+    pos: usize,
+}
+
+impl<R, K: Ord + Copy> RowCursor<'_, R, K> {
+    /// The rows holding the current key.
+    fn run(&self) -> (usize, usize) {
+        let key = self.key();
+        let len = self.rows[self.pos..].partition_point(|row| (self.key)(row) <= key);
+        (self.pos, self.pos + len)
+    }
+}
+
+impl<R, K: Ord + Copy> Seekable for RowCursor<'_, R, K> {
+    type Key = K;
+
+    fn key(&self) -> K {
+        assert!(!self.at_end(), "Iterator is at end");
+        (self.key)(&self.rows[self.pos])
+    }
+
+    fn next(&mut self) {
+        self.pos = self.run().1;
+    }
+
+    fn seek(&mut self, seek_key: K) {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.pos += self.rows[self.pos..].partition_point(|row| (self.key)(row) < seek_key);
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.rows.len()
+    }
+}
+
+/// Object-safe view of a RowCursor, so cursors over different row types can
+/// take part in one join.
+#[doc(hidden)]
+pub trait Cursor<K>: Seekable<Key = K> {
+    fn run(&self) -> (usize, usize);
+}
+
+impl<R, K: Ord + Copy> Cursor<K> for RowCursor<'_, R, K> {
+    fn run(&self) -> (usize, usize) {
+        RowCursor::run(self)
+    }
+}
+
+#[doc(hidden)]
+pub type BoxedCursor<'a, K> = Box<dyn Cursor<K> + 'a>;
+
+/// RowSources is implemented for tuples of up to six RowSources with the
+/// same key type.
+pub trait RowSources<'a> {
+    type Key: Ord + Copy;
+    /// A tuple of the items of every source.
+    type Rows;
+
+    #[doc(hidden)]
+    fn cursors(&self) -> Vec<BoxedCursor<'a, Self::Key>>;
+
+    #[doc(hidden)]
+    fn folded(&self) -> Vec<bool>;
+
+    #[doc(hidden)]
+    fn rows(&self, runs: &[(usize, usize)], positions: &[usize]) -> Self::Rows;
+}
+
+macro_rules! impl_row_sources {
+    ($($s:ident $i:tt),*) => {
+        impl<'a, K: Ord + Copy + 'a, $($s: RowSource<'a, Key = K>),*> RowSources<'a> for ($($s,)*) {
+            type Key = K;
+            type Rows = ($($s::Item,)*);
+
+            fn cursors(&self) -> Vec<BoxedCursor<'a, K>> {
+                vec![$(self.$i.cursor()),*]
+            }
+
+            fn folded(&self) -> Vec<bool> {
+                vec![$(self.$i.is_folded()),*]
+            }
+
+            fn rows(&self, runs: &[(usize, usize)], positions: &[usize]) -> Self::Rows {
+                ($(self.$i.item(runs[$i], positions[$i]),)*)
+            }
+        }
+    };
+}
+
+impl_row_sources!(A 0, B 1);
+impl_row_sources!(A 0, B 1, C 2);
+impl_row_sources!(A 0, B 1, C 2, D 3);
+impl_row_sources!(A 0, B 1, C 2, D 3, E 4);
+impl_row_sources!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Iterator over the matches of zip_join().
+pub struct ZipJoin<'a, S: RowSources<'a>> {
+    sources: S,
+    join: LeapFrogJoin<BoxedCursor<'a, S::Key>>,
+    policy: DuplicatePolicy,
+    folded: Vec<bool>,
+    expansion: Option<Expansion<S::Key>>,
+}
+
+/// The combinations of rows of one key still to be returned.
+struct Expansion<K> {
+    key: K,
+    /// The rows of the key per source.
+    runs: Vec<(usize, usize)>,
+    /// The rows to enumerate per source, a single one for folded sources.
+    ranges: Vec<(usize, usize)>,
+    positions: Vec<usize>,
+}
+
+impl<K: Copy> Expansion<K> {
+    /// Returns the current combination and advances to the next one like an
+    /// odometer. Returns None after the last one.
+    fn next(&mut self) -> Option<(K, Vec<usize>)> {
+        if self.positions.is_empty() {
+            return None;
+        }
+        let current = self.positions.clone();
+        for (pos, &(start, end)) in self.positions.iter_mut().zip(&self.ranges).rev() {
+            *pos += 1;
+            if *pos < end {
+                return Some((self.key, current));
+            }
+            *pos = start;
+        }
+        self.positions.clear();
+        Some((self.key, current))
+    }
+}
+
+/// Joins `sources` on their keys. See DuplicatePolicy for keys occurring in
+/// several rows of one source.
+pub fn zip_join<'a, S: RowSources<'a>>(sources: S, policy: DuplicatePolicy) -> ZipJoin<'a, S> {
+    ZipJoin {
+        join: LeapFrogJoin::from_iters(sources.cursors()),
+        folded: sources.folded(),
+        sources,
+        policy,
+        expansion: None,
+    }
+}
+
+impl<'a, S: RowSources<'a>> Iterator for ZipJoin<'a, S> {
+    type Item = (S::Key, S::Rows);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(expansion) = &mut self.expansion {
+                match expansion.next() {
+                    Some((key, positions)) => {
+                        return Some((key, self.sources.rows(&expansion.runs, &positions)));
+                    }
+                    None => self.expansion = None,
+                }
+            }
+            if self.join.at_end() {
+                return None;
+            }
+            let key = self.join.key();
+            let runs: Vec<(usize, usize)> = self.join.iters().iter().map(|c| c.run()).collect();
+            self.join.next();
+            // A folded source has a single item per key.
+            let ranges: Vec<(usize, usize)> = runs
+                .iter()
+                .zip(&self.folded)
+                .map(|(&(start, end), &folded)| (start, if folded { start + 1 } else { end }))
+                .collect();
+            if self.policy == DuplicatePolicy::CrossProduct {
+                self.expansion = Some(Expansion {
+                    key,
+                    positions: ranges.iter().map(|&(start, _)| start).collect(),
+                    runs,
+                    ranges,
+                });
+                continue;
+            }
+            let positions: Option<Vec<usize>> = ranges
+                .iter()
+                .map(|&(start, end)| match self.policy {
+                    DuplicatePolicy::First => Some(start),
+                    DuplicatePolicy::Last => Some(end - 1),
+                    DuplicatePolicy::Skip => (end - start == 1).then_some(start),
+                    DuplicatePolicy::CrossProduct => unreachable!(),
+                })
+                .collect();
+            if let Some(positions) = positions {
+                return Some((key, self.sources.rows(&runs, &positions)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct User {
+        id: u32,
+        name: &'static str,
+    }
+
+    struct Order {
+        user: u32,
+        amount: u64,
+    }
+
+    fn users() -> Vec<User> {
+        vec![
+            User {
+                id: 1,
+                name: "alice",
+            },
+            User { id: 2, name: "bob" },
+            User {
+                id: 4,
+                name: "dave",
+            },
+        ]
+    }
+
+    fn orders() -> Vec<Order> {
+        vec![
+            Order {
+                user: 1,
+                amount: 10,
+            },
+            Order {
+                user: 2,
+                amount: 20,
+            },
+            Order {
+                user: 2,
+                amount: 21,
+            },
+            Order {
+                user: 3,
+                amount: 30,
+            },
+            Order {
+                user: 4,
+                amount: 40,
+            },
+        ]
+    }
+
+    fn summary(
+        users: &[User],
+        orders: &[Order],
+        ids: &[u32],
+        policy: DuplicatePolicy,
+    ) -> Vec<(u32, &'static str, u64)> {
+        let sources = (
+            Rows::new(users, |u| u.id),
+            Rows::new(orders, |o| o.user),
+            Rows::new(ids, |&id| id),
+        );
+        zip_join(sources, policy)
+            .map(|(key, (user, order, _))| (key, user.name, order.amount))
+            .collect()
+    }
+
+    #[test]
+    fn test_zip_join_policies() {
+        let (users, orders) = (users(), orders());
+        let ids = [1, 2, 3, 4];
+        assert_eq!(
+            summary(&users, &orders, &ids, DuplicatePolicy::First),
+            vec![(1, "alice", 10), (2, "bob", 20), (4, "dave", 40)]
+        );
+        assert_eq!(
+            summary(&users, &orders, &ids, DuplicatePolicy::Last),
+            vec![(1, "alice", 10), (2, "bob", 21), (4, "dave", 40)]
+        );
+        assert_eq!(
+            summary(&users, &orders, &ids, DuplicatePolicy::Skip),
+            vec![(1, "alice", 10), (4, "dave", 40)]
+        );
+    }
+
+    #[test]
+    fn test_zip_join_cross_product() {
+        let orders = orders();
+        let payments = [(2, "card"), (2, "cash"), (4, "card")];
+        let mut join = zip_join(
+            (
+                Rows::new(&orders, |o| o.user),
+                Rows::new(&payments, |p| p.0),
+            ),
+            DuplicatePolicy::CrossProduct,
+        );
+        let (key, (order, payment)) = join.next().unwrap();
+        assert_eq!((key, order.amount, payment.1), (2, 20, "card"));
+        let rest: Vec<_> = join
+            .map(|(key, (order, payment))| (key, order.amount, payment.1))
+            .collect();
+        assert_eq!(
+            rest,
+            vec![
+                (2, 20, "cash"),
+                (2, 21, "card"),
+                (2, 21, "cash"),
+                (4, 40, "card")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zip_join_folded() {
+        let (users, orders) = (users(), orders());
+        let payments = [(2, "card"), (2, "cash"), (4, "card")];
+        let result: Vec<_> = zip_join(
+            (
+                Rows::new(&users, |u| u.id).first(),
+                Rows::new(&orders, |o| o.user).fold(|os| os.iter().map(|o| o.amount).sum::<u64>()),
+                Rows::new(&payments, |p| p.0).count(),
+            ),
+            DuplicatePolicy::CrossProduct,
+        )
+        .map(|(key, (user, total, payments))| (key, user.name, total, payments))
+        .collect();
+        assert_eq!(result, vec![(2, "bob", 41, 2), (4, "dave", 40, 1)]);
+
+        // Only the source that is not folded is expanded.
+        let result: Vec<_> = zip_join(
+            (
+                Rows::new(&orders, |o| o.user),
+                Rows::new(&payments, |p| p.0).count(),
+            ),
+            DuplicatePolicy::CrossProduct,
+        )
+        .map(|(key, (order, payments))| (key, order.amount, payments))
+        .collect();
+        assert_eq!(result, vec![(2, 20, 2), (2, 21, 2), (4, 40, 1)]);
+    }
+
+    #[test]
+    fn test_zip_join_two_sources() {
+        let (users, orders) = (users(), orders());
+        let result: Vec<u32> = zip_join(
+            (Rows::new(&users, |u| u.id), Rows::new(&orders, |o| o.user)),
+            DuplicatePolicy::First,
+        )
+        .map(|(key, _)| key)
+        .collect();
+        assert_eq!(result, vec![1, 2, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Rows must be sorted by key")]
+    fn test_rows_unsorted() {
+        let orders = [Order { user: 2, amount: 0 }, Order { user: 1, amount: 0 }];
+        Rows::new(&orders, |o| o.user);
+    }
+}
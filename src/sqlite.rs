@@ -0,0 +1,332 @@
+//! SQLite integration: the leapfrog join as an eponymous table-valued function.
+//!
+//! After load_module(), a query like
+//!
+//! ```sql
+//! SELECT key FROM leapfrog_join('idx_a', 'idx_b', 'events.user_id');
+//! ```
+//!
+//! returns the keys common to all named sources in ascending order. A source
+//! is either a table name, whose `key` column is used, or `table.column`. Each
+//! source is read with `ORDER BY`, so an index on the key column avoids a
+//! sort. Keys must be integers.
+//!
+//! The other way around, query_source() streams the first column of a
+//! query's rows into a join, as a KeySource. The query should sort by that
+//! column, e.g. with `ORDER BY key`; seeks read the rows in between.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::os::raw::c_int;
+
+use rusqlite::types::{FromSql, Null};
+use rusqlite::vtab::{
+    Context, IndexConstraintOp, IndexInfo, VTab, VTabConnection, VTabCursor, Values,
+    eponymous_only_module, escape_double_quote,
+};
+use rusqlite::{Connection, Error, Params, Result, Rows, Statement, ffi};
+
+use crate::ingest::{KeyReader, KeySource, Order};
+use crate::intersect;
+
+/// Maximum number of sources a single leapfrog_join() call accepts.
+pub const MAX_SOURCES: usize = 8;
+
+/// Registers the `leapfrog_join` table-valued function on the connection.
+pub fn load_module(conn: &Connection) -> Result<()> {
+    conn.create_module("leapfrog_join", eponymous_only_module::<JoinTab>(), None)
+}
+
+#[repr(C)]
+struct JoinTab {
+    /// Base class. Must be first
+    base: ffi::sqlite3_vtab,
+    /// The connection the function was registered on, used to read sources.
+    db: *mut ffi::sqlite3,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for JoinTab {
+    type Aux = ();
+    type Cursor = JoinTabCursor<'vtab>;
+
+    fn connect(
+        db: &mut VTabConnection,
+        _aux: Option<&()>,
+        _args: &[&[u8]],
+    ) -> Result<(String, JoinTab)> {
+        let hidden: Vec<String> = (0..MAX_SOURCES)
+            .map(|i| format!("source{i} hidden"))
+            .collect();
+        let vtab = JoinTab {
+            base: ffi::sqlite3_vtab::default(),
+            db: unsafe { db.handle() },
+        };
+        Ok((format!("CREATE TABLE x(key,{})", hidden.join(",")), vtab))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
+        // Function arguments arrive as equality constraints on the hidden
+        // source columns; pass them to filter() in column order.
+        let mut sources: Vec<(c_int, usize)> = Vec::new();
+        for (i, constraint) in info.constraints().enumerate() {
+            if constraint.column() < 1 {
+                continue;
+            }
+            if !constraint.is_usable() {
+                return Err(Error::SqliteFailure(
+                    ffi::Error::new(ffi::SQLITE_CONSTRAINT),
+                    None,
+                ));
+            }
+            if constraint.operator() == IndexConstraintOp::SQLITE_INDEX_CONSTRAINT_EQ {
+                sources.push((constraint.column(), i));
+            }
+        }
+        sources.sort_unstable();
+        for (argv_index, &(_, i)) in sources.iter().enumerate() {
+            let mut usage = info.constraint_usage(i);
+            usage.set_argv_index(argv_index as c_int + 1);
+            usage.set_omit(true);
+        }
+
+        let ascending_by_key = {
+            let mut order_bys = info.order_bys();
+            matches!(order_bys.next(), Some(o) if o.column() == 0 && !o.is_order_by_desc())
+                && order_bys.next().is_none()
+        };
+        if ascending_by_key {
+            info.set_order_by_consumed(true);
+        }
+        info.set_idx_num(sources.len() as c_int);
+        info.set_estimated_cost(1.0);
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> Result<JoinTabCursor<'vtab>> {
+        Ok(JoinTabCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            db: self.db,
+            keys: Vec::new(),
+            pos: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+#[repr(C)]
+struct JoinTabCursor<'vtab> {
+    /// Base class. Must be first
+    base: ffi::sqlite3_vtab_cursor,
+    db: *mut ffi::sqlite3,
+    keys: Vec<i64>,
+    pos: usize,
+    phantom: PhantomData<&'vtab JoinTab>,
+}
+
+impl JoinTabCursor<'_> {
+    fn read_source(&self, conn: &Connection, source: &str) -> Result<Vec<i64>> {
+        let (table, column) = source.split_once('.').unwrap_or((source, "key"));
+        let sql = format!(
+            "SELECT \"{column}\" FROM \"{table}\" WHERE \"{column}\" IS NOT NULL ORDER BY \"{column}\"",
+            table = escape_double_quote(table),
+            column = escape_double_quote(column),
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let mut keys = stmt
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>>>()?;
+        keys.dedup();
+        Ok(keys)
+    }
+}
+
+unsafe impl VTabCursor for JoinTabCursor<'_> {
+    fn filter(&mut self, idx_num: c_int, _idx_str: Option<&str>, args: &Values<'_>) -> Result<()> {
+        // The connection handle stays open for as long as the vtab exists, and
+        // from_handle() does not close it when the Connection is dropped.
+        let conn = unsafe { Connection::from_handle(self.db)? };
+        let mut sources = Vec::with_capacity(idx_num as usize);
+        for i in 0..idx_num as usize {
+            let source: String = args.get(i).map_err(|_| {
+                Error::ModuleError(format!("leapfrog_join argument {} must be a name", i + 1))
+            })?;
+            sources.push(self.read_source(&conn, &source)?);
+        }
+        self.keys = intersect(sources.iter().map(Vec::as_slice).collect());
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.pos >= self.keys.len()
+    }
+
+    fn column(&self, ctx: &mut Context, i: c_int) -> Result<()> {
+        if i == 0 {
+            ctx.set_result(&self.keys[self.pos])
+        } else {
+            ctx.set_result(&Null)
+        }
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.pos as i64 + 1)
+    }
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    Sqlite(Error),
+    /// The key of row `row`, counted from 0, is smaller than the one before.
+    Unsorted {
+        row: usize,
+    },
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Sqlite(e) => write!(f, "SQLite error: {e}"),
+            SourceError::Unsorted { row } => {
+                write!(f, "row {row}: key is smaller than the one before")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<Error> for SourceError {
+    fn from(e: Error) -> Self {
+        SourceError::Sqlite(e)
+    }
+}
+
+/// SqliteSource is the keys of a query, see query_source().
+pub type SqliteSource<'stmt, K> = KeySource<SqliteKeys<'stmt, K>>;
+
+/// Runs the query of `stmt` and returns the first column of its rows as a
+/// source, positioned at the first key. Rows are fetched as the source
+/// advances. NULL keys are errors, so filter them in the query.
+pub fn query_source<'stmt, K: FromSql + Ord + Copy>(
+    stmt: &'stmt mut Statement<'_>,
+    params: impl Params,
+    order: Order,
+) -> std::result::Result<SqliteSource<'stmt, K>, SourceError> {
+    let keys = SqliteKeys {
+        rows: stmt.query(params)?,
+        row: 0,
+        _key: PhantomData,
+    };
+    KeySource::new(keys, order)
+}
+
+/// The keys of a query, in row order.
+pub struct SqliteKeys<'stmt, K> {
+    rows: Rows<'stmt>,
+    row: usize,
+    _key: PhantomData<K>,
+}
+
+impl<K: FromSql + Ord + Copy> KeyReader for SqliteKeys<'_, K> {
+    type Key = K;
+    type Error = SourceError;
+
+    fn read_key(&mut self) -> std::result::Result<Option<K>, SourceError> {
+        let Some(row) = self.rows.next()? else {
+            return Ok(None);
+        };
+        self.row += 1;
+        Ok(Some(row.get(0)?))
+    }
+
+    fn unsorted(&self) -> SourceError {
+        SourceError::Unsorted { row: self.row - 1 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE idx_a(key INTEGER);
+             INSERT INTO idx_a VALUES (9), (0), (1), (3), (4), (5), (6), (7), (8), (11);
+             CREATE TABLE idx_b(key INTEGER);
+             INSERT INTO idx_b VALUES (0), (2), (6), (7), (8), (9), (11), (11);
+             CREATE TABLE events(id INTEGER PRIMARY KEY, user_id INTEGER);
+             INSERT INTO events(user_id) VALUES (2), (4), (5), (8), (10);",
+        )
+        .unwrap();
+        load_module(&conn).unwrap();
+        conn
+    }
+
+    fn query(conn: &Connection, sql: &str) -> Vec<i64> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<i64>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sqlite_join_two_tables() {
+        let conn = setup();
+        let keys = query(&conn, "SELECT key FROM leapfrog_join('idx_a', 'idx_b')");
+        assert_eq!(keys, vec![0, 6, 7, 8, 9, 11]);
+    }
+
+    #[test]
+    fn test_sqlite_join_with_column() {
+        let conn = setup();
+        let keys = query(
+            &conn,
+            "SELECT key FROM leapfrog_join('idx_a', 'idx_b', 'events.user_id') ORDER BY key",
+        );
+        assert_eq!(keys, vec![8]);
+    }
+
+    #[test]
+    fn test_sqlite_join_unknown_table() {
+        let conn = setup();
+        let mut stmt = conn
+            .prepare("SELECT key FROM leapfrog_join('idx_a', 'missing')")
+            .unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        assert!(rows.next().is_err());
+    }
+
+    #[test]
+    fn test_query_source() {
+        use crate::{LeapFrogJoin, LinearIterator, Seekable};
+
+        let conn = setup();
+        let mut stmt = conn
+            .prepare("SELECT key FROM idx_b WHERE key > ? ORDER BY key")
+            .unwrap();
+        let source = query_source::<i64>(&mut stmt, [1], Order::Verify).unwrap();
+        let others = [1, 2, 5, 8, 11];
+        let join = LeapFrogJoin::from_iters(vec![
+            Box::new(source) as Box<dyn Seekable<Key = i64>>,
+            Box::new(LinearIterator::new(&others)),
+        ]);
+        assert_eq!(join.into_keys().collect::<Vec<_>>(), [2, 8, 11]);
+
+        let mut stmt = conn.prepare("SELECT key FROM idx_a").unwrap();
+        let mut source = query_source::<i64>(&mut stmt, [], Order::Verify).unwrap();
+        source.next();
+        assert!(source.at_end());
+        assert!(matches!(
+            source.error(),
+            Some(SourceError::Unsorted { row: 1 })
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+//! Single-stepping through the leapfrog join.
+//!
+//! JoinStepper runs the same algorithm as LeapFrogJoin, but as an explicit
+//! state machine: every call to step() performs exactly one transition, i.e.
+//! the initial sort, one seek, one next, or one reconciliation that detects a
+//! match, and returns a StepEvent describing it.
+
+use crate::{Seekable, cmp_seekable};
+
+/// One transition of the join. Sources are identified by their index in the
+/// vector passed to JoinStepper::new().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepEvent<K> {
+    /// The iterators were ordered by their first key; `order` lists the
+    /// sources in round-robin order.
+    Sorted { order: Vec<usize> },
+    /// `source` was sought to `seek_key` and landed on `landed` (None: at end).
+    Seek {
+        source: usize,
+        seek_key: K,
+        landed: Option<K>,
+    },
+    /// All iterators agree on `key`.
+    Match { key: K },
+    /// `source` was advanced past the last match and landed on `landed`.
+    Next { source: usize, landed: Option<K> },
+    /// The join is exhausted because `source` ran out of keys (None: there
+    /// were no inputs at all).
+    Done { source: Option<usize> },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Start,
+    Search,
+    Matched,
+    Done,
+}
+
+/// JoinStepper executes a leapfrog join one transition at a time.
+pub struct JoinStepper<I: Seekable> {
+    iters: Vec<I>,
+    order: Vec<usize>,
+    pos: usize,
+    max_key: Option<I::Key>,
+    phase: Phase,
+}
+
+impl<I: Seekable> JoinStepper<I> {
+    pub fn new(iters: Vec<I>) -> Self {
+        Self {
+            order: (0..iters.len()).collect(),
+            iters,
+            pos: 0,
+            max_key: None,
+            phase: Phase::Start,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.phase == Phase::Done
+    }
+
+    /// The iterators in their current state.
+    pub fn iters(&self) -> &[I] {
+        &self.iters
+    }
+
+    /// Performs one transition. Returns None once the join is done.
+    pub fn step(&mut self) -> Option<StepEvent<I::Key>> {
+        match self.phase {
+            Phase::Start => Some(self.start()),
+            Phase::Search => Some(self.search_step()),
+            Phase::Matched => Some(self.next_step()),
+            Phase::Done => None,
+        }
+    }
+
+    fn start(&mut self) -> StepEvent<I::Key> {
+        if let Some(source) = self.iters.iter().position(|iter| iter.at_end()) {
+            return self.finish(Some(source));
+        }
+        if self.iters.is_empty() {
+            return self.finish(None);
+        }
+        let iters = &self.iters;
+        self.order
+            .sort_by(|&a, &b| cmp_seekable(&iters[a], &iters[b]));
+        self.max_key = Some(self.iters[self.order[self.order.len() - 1]].key());
+        self.phase = Phase::Search;
+        StepEvent::Sorted {
+            order: self.order.clone(),
+        }
+    }
+
+    fn search_step(&mut self) -> StepEvent<I::Key> {
+        let source = self.order[self.pos];
+        let max_key = self.max_key.expect("Searching requires a max key");
+        let key = self.iters[source].key();
+        if key == max_key {
+            self.phase = Phase::Matched;
+            return StepEvent::Match { key };
+        }
+        self.iters[source].seek(max_key);
+        let landed = self.landed(source);
+        match landed {
+            Some(key) => {
+                self.max_key = Some(key);
+                self.pos = (self.pos + 1) % self.iters.len();
+            }
+            None => self.phase = Phase::Done,
+        }
+        StepEvent::Seek {
+            source,
+            seek_key: max_key,
+            landed,
+        }
+    }
+
+    fn next_step(&mut self) -> StepEvent<I::Key> {
+        let source = self.order[self.pos];
+        self.iters[source].next();
+        let landed = self.landed(source);
+        match landed {
+            Some(key) => {
+                self.max_key = Some(key);
+                self.pos = (self.pos + 1) % self.iters.len();
+                self.phase = Phase::Search;
+            }
+            None => self.phase = Phase::Done,
+        }
+        StepEvent::Next { source, landed }
+    }
+
+    fn landed(&self, source: usize) -> Option<I::Key> {
+        let iter = &self.iters[source];
+        (!iter.at_end()).then(|| iter.key())
+    }
+
+    fn finish(&mut self, source: Option<usize>) -> StepEvent<I::Key> {
+        self.phase = Phase::Done;
+        StepEvent::Done { source }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LinearIterator, intersect};
+
+    fn matches(sources: &[&[i32]]) -> (Vec<i32>, Vec<StepEvent<i32>>) {
+        let mut stepper =
+            JoinStepper::new(sources.iter().map(|s| LinearIterator::new(s)).collect());
+        let mut events = vec![];
+        while let Some(event) = stepper.step() {
+            events.push(event);
+        }
+        let keys = events
+            .iter()
+            .filter_map(|e| match e {
+                StepEvent::Match { key } => Some(*key),
+                _ => None,
+            })
+            .collect();
+        (keys, events)
+    }
+
+    #[test]
+    fn test_stepper_events() {
+        let tab1 = [0, 1, 3, 4, 5, 6, 7, 8, 9, 11];
+        let tab3 = [2, 4, 5, 8, 10];
+        let (_, events) = matches(&[&tab1, &tab3]);
+        assert_eq!(events[0], StepEvent::Sorted { order: vec![0, 1] });
+        assert_eq!(
+            events[1],
+            StepEvent::Seek {
+                source: 0,
+                seek_key: 2,
+                landed: Some(3)
+            }
+        );
+        assert_eq!(
+            events[2],
+            StepEvent::Seek {
+                source: 1,
+                seek_key: 3,
+                landed: Some(4)
+            }
+        );
+        assert_eq!(
+            events[3],
+            StepEvent::Seek {
+                source: 0,
+                seek_key: 4,
+                landed: Some(4)
+            }
+        );
+        assert_eq!(events[4], StepEvent::Match { key: 4 });
+        assert!(matches!(
+            events.last(),
+            Some(StepEvent::Seek { landed: None, .. } | StepEvent::Next { landed: None, .. })
+        ));
+    }
+
+    #[test]
+    fn test_stepper_matches_join() {
+        let tab1: Vec<i32> = (0..200).filter(|x| x % 2 == 0).collect();
+        let tab2: Vec<i32> = (0..200).filter(|x| x % 3 == 0).collect();
+        let tab3: Vec<i32> = (0..200).filter(|x| x % 5 != 1).collect();
+        let (keys, _) = matches(&[&tab1, &tab2, &tab3]);
+        assert_eq!(keys, intersect(vec![&tab1, &tab2, &tab3]));
+    }
+
+    #[test]
+    fn test_stepper_empty_input() {
+        let tab1 = [1, 2];
+        let (keys, events) = matches(&[&tab1, &[]]);
+        assert!(keys.is_empty());
+        assert_eq!(events, vec![StepEvent::Done { source: Some(1) }]);
+        let (_, events) = matches(&[]);
+        assert_eq!(events, vec![StepEvent::Done { source: None }]);
+    }
+}
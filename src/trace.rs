@@ -0,0 +1,285 @@
+//! Execution tracing in the Chrome trace event format.
+//!
+//! Wrap every join input with Tracer::wrap() and, after running the join,
+//! write the timeline with Tracer::write_chrome_trace(). The resulting JSON
+//! file can be opened in about://tracing or https://ui.perfetto.dev, where
+//! every source shows up as its own track of next() and seek() slices.
+//! Tracer::write_text() writes the same events without timings, one per
+//! line, which is stable across runs, e.g. for golden files.
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::Seekable;
+use crate::zonemap::Zone;
+
+struct TraceEvent {
+    source: usize,
+    name: &'static str,
+    start: Duration,
+    duration: Duration,
+    /// The formatted seek key of seeks.
+    seek_key: Option<String>,
+    /// The formatted key moved to, or None at end.
+    to: Option<String>,
+}
+
+impl TraceEvent {
+    fn json_args(&self) -> String {
+        let to = match &self.to {
+            Some(key) => json_string(key),
+            None => "\"at_end\"".to_string(),
+        };
+        match &self.seek_key {
+            Some(seek_key) => format!("\"seek_key\":{},\"to\":{to}", json_string(seek_key)),
+            None => format!("\"to\":{to}"),
+        }
+    }
+}
+
+/// Tracer collects the events of all iterators wrapped by it.
+pub struct Tracer {
+    origin: Instant,
+    sources: RefCell<Vec<String>>,
+    events: RefCell<Vec<TraceEvent>>,
+}
+
+impl Tracer {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Self {
+            origin: Instant::now(),
+            sources: RefCell::new(Vec::new()),
+            events: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Wraps an iterator so that its operations are recorded under `name`.
+    pub fn wrap<I: Seekable>(self: &Rc<Self>, name: &str, iter: I) -> TracingIterator<I> {
+        let mut sources = self.sources.borrow_mut();
+        sources.push(name.to_string());
+        TracingIterator {
+            iter,
+            source: sources.len() - 1,
+            tracer: Rc::clone(self),
+        }
+    }
+
+    /// Number of events recorded so far.
+    pub fn len(&self) -> usize {
+        self.events.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes all events as a Chrome trace JSON document.
+    pub fn write_chrome_trace<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "{{\"traceEvents\":[")?;
+        let mut first = true;
+        for (tid, name) in self.sources.borrow().iter().enumerate() {
+            if !first {
+                write!(w, ",")?;
+            }
+            first = false;
+            write!(
+                w,
+                "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{tid},\"args\":{{\"name\":{}}}}}",
+                json_string(name)
+            )?;
+        }
+        for event in self.events.borrow().iter() {
+            if !first {
+                write!(w, ",")?;
+            }
+            first = false;
+            write!(
+                w,
+                "{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3},\"args\":{{{}}}}}",
+                event.name,
+                event.source,
+                micros(event.start),
+                micros(event.duration),
+                event.json_args()
+            )?;
+        }
+        write!(w, "],\"displayTimeUnit\":\"ns\"}}")
+    }
+
+    /// Writes all events in order, one line each, like `tab1 seek 5 -> 7`
+    /// or `tab1 next -> end`, with keys formatted by Debug.
+    pub fn write_text<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let sources = self.sources.borrow();
+        for event in self.events.borrow().iter() {
+            write!(w, "{} {}", sources[event.source], event.name)?;
+            if let Some(seek_key) = &event.seek_key {
+                write!(w, " {seek_key}")?;
+            }
+            writeln!(w, " -> {}", event.to.as_deref().unwrap_or("end"))?;
+        }
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        source: usize,
+        name: &'static str,
+        start: Instant,
+        seek_key: Option<String>,
+        to: Option<String>,
+    ) {
+        let now = Instant::now();
+        self.events.borrow_mut().push(TraceEvent {
+            source,
+            name,
+            start: start - self.origin,
+            duration: now - start,
+            seek_key,
+            to,
+        });
+    }
+}
+
+/// TracingIterator records every next() and seek() on the wrapped iterator.
+pub struct TracingIterator<I> {
+    iter: I,
+    source: usize,
+    tracer: Rc<Tracer>,
+}
+
+impl<I> TracingIterator<I> {
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I> TracingIterator<I>
+where
+    I: Seekable,
+    I::Key: Debug,
+{
+    fn position(&self) -> Option<String> {
+        (!self.iter.at_end()).then(|| format!("{:?}", self.iter.key()))
+    }
+}
+
+impl<I> Seekable for TracingIterator<I>
+where
+    I: Seekable,
+    I::Key: Debug,
+{
+    type Key = I::Key;
+
+    fn key(&self) -> I::Key {
+        self.iter.key()
+    }
+
+    fn next(&mut self) {
+        let start = Instant::now();
+        self.iter.next();
+        let to = self.position();
+        self.tracer.record(self.source, "next", start, None, to);
+    }
+
+    fn seek(&mut self, seek_key: I::Key) {
+        let start = Instant::now();
+        self.iter.seek(seek_key);
+        let to = self.position();
+        let seek_key = Some(format!("{seek_key:?}"));
+        self.tracer.record(self.source, "seek", start, seek_key, to);
+    }
+
+    fn at_end(&self) -> bool {
+        self.iter.at_end()
+    }
+
+    fn zone_map(&self) -> Option<Vec<Zone<I::Key>>> {
+        self.iter.zone_map()
+    }
+
+    fn estimate(&self) -> Option<usize> {
+        self.iter.estimate()
+    }
+
+    fn exact_len_hint(&self) -> Option<usize> {
+        self.iter.exact_len_hint()
+    }
+
+    fn will_need(&mut self, key: I::Key) {
+        self.iter.will_need(key)
+    }
+
+    fn upper_bound(&self) -> Option<I::Key> {
+        self.iter.upper_bound()
+    }
+}
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e6
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LeapFrogJoin, LinearIterator};
+
+    #[test]
+    fn test_trace_join() {
+        let tab1 = [0, 1, 3, 4, 5, 6, 7, 8, 9, 11];
+        let tab3 = [2, 4, 5, 8, 10];
+        let tracer = Tracer::new();
+        let mut join = LeapFrogJoin::from_iters(vec![
+            tracer.wrap("tab1", LinearIterator::new(&tab1)),
+            tracer.wrap("tab3", LinearIterator::new(&tab3)),
+        ]);
+        let mut result = vec![];
+        while !join.at_end() {
+            result.push(join.key());
+            join.next();
+        }
+        assert_eq!(result, vec![4, 5, 8]);
+        assert!(!tracer.is_empty());
+        // The join never seeks past the last key of tab3, its upper bound.
+        let mut iter = tracer.wrap("tab1", LinearIterator::new(&tab1));
+        iter.seek(12);
+
+        let mut json = Vec::new();
+        tracer.write_chrome_trace(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"traceEvents\":["));
+        assert!(json.contains("\"args\":{\"name\":\"tab1\"}"));
+        assert!(json.contains("\"args\":{\"name\":\"tab3\"}"));
+        assert!(json.contains("\"name\":\"seek\""));
+        assert!(json.contains("\"to\":\"at_end\""));
+        assert_eq!(json.matches("\"ph\":\"X\"").count(), tracer.len());
+
+        let mut text = Vec::new();
+        tracer.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(text.lines().count(), tracer.len());
+        assert_eq!(text.lines().last(), Some("tab1 seek 12 -> end"));
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+}
@@ -0,0 +1,1230 @@
+//! Relations of arbitrary arity, accessed as tries.
+//!
+//! A TrieRelation stores its tuples sorted lexicographically and without
+//! duplicates, one column per attribute. Viewed as a trie, level i holds the
+//! values of attribute i, and the children of a node are the values of the
+//! next attribute among the tuples sharing the path to that node.
+//!
+//! TrieIterator is the trie iterator interface from the leapfrog triejoin
+//! paper: the linear iterator operations (Seekable) on the current level,
+//! plus open() to descend to the children of the current key and up() to
+//! return to the parent.
+//!
+//! All keys of a relation live in one arena, column after column, so a trie
+//! is built with a single allocation for its nodes besides the staging
+//! buffer, and dropped with a single deallocation. The arena comes from a
+//! TrieAllocator: by default a fresh Vec, but a BufferPool recycles the
+//! arenas of relations given back with TrieRelation::into_buffer(), e.g.
+//! when rebuilding relations repeatedly.
+//!
+//! Input that is sorted already, e.g. columnar arrays from Arrow, needs no
+//! copy: TrieRelation::from_sorted_columns() checks the order while
+//! scanning for group breaks and keeps shared references to the columns.
+//!
+//! TrieRelation::with_counts() annotates every node with the number of
+//! tuples and of children below it, at the cost of two words per row and
+//! attribute. TrieIterator::run() and TrieIterator::children() then take
+//! constant time, the iterator reports its exact number of keys left to the
+//! planner, and TrieRelation::count_by() visits one row per group. Relations
+//! derived from an annotated one, e.g. by merge(), are not annotated.
+//!
+//! TrieRelation::merge_shards_on() merges relations built separately, e.g.
+//! from shards of the input, partitioning the key space among the tasks of
+//! an Executor. With the `rayon` feature, TrieRelation::merge_shards() does
+//! so on the rayon thread pool, and TrieRelation::new_parallel() sorts the
+//! tuples and fills the columns there.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Range};
+use std::sync::Arc;
+
+use crate::Seekable;
+#[cfg(feature = "rayon")]
+use crate::executor::RayonExecutor;
+use crate::executor::{Executor, Task};
+#[cfg(feature = "numa")]
+use crate::numa::NumaTopology;
+use crate::tuning::Tuning;
+
+/// TrieAllocator provides the arenas relations store their keys in.
+pub trait TrieAllocator<K> {
+    /// Returns an empty buffer with room for at least `capacity` keys.
+    fn allocate(&mut self, capacity: usize) -> Vec<K>;
+
+    /// Takes back the buffer of a relation that is no longer needed.
+    fn release(&mut self, buffer: Vec<K>) {
+        drop(buffer);
+    }
+}
+
+/// The global allocator, allocating a new buffer per relation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Global;
+
+impl<K> TrieAllocator<K> for Global {
+    fn allocate(&mut self, capacity: usize) -> Vec<K> {
+        Vec::with_capacity(capacity)
+    }
+}
+
+/// BufferPool keeps released buffers and hands out the smallest one that is
+/// large enough, allocating only if none is.
+#[derive(Clone, Debug)]
+pub struct BufferPool<K> {
+    free: Vec<Vec<K>>,
+}
+
+impl<K> Default for BufferPool<K> {
+    fn default() -> Self {
+        Self { free: Vec::new() }
+    }
+}
+
+impl<K> BufferPool<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of buffers kept for reuse.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+}
+
+impl<K> TrieAllocator<K> for BufferPool<K> {
+    fn allocate(&mut self, capacity: usize) -> Vec<K> {
+        let fitting = (0..self.free.len())
+            .filter(|&i| self.free[i].capacity() >= capacity)
+            .min_by_key(|&i| self.free[i].capacity());
+        match fitting {
+            Some(i) => self.free.swap_remove(i),
+            None => Vec::with_capacity(capacity),
+        }
+    }
+
+    fn release(&mut self, mut buffer: Vec<K>) {
+        buffer.clear();
+        self.free.push(buffer);
+    }
+}
+
+/// A column shared with its owner, e.g. the values of an Arrow array.
+pub type SharedColumn<K> = Arc<dyn AsRef<[K]> + Send + Sync>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SortedColumnsError {
+    /// No columns were given.
+    NoColumns,
+    /// A column has a different length than the first one.
+    Length {
+        column: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// The tuple at `row` does not sort after the previous one, or equals
+    /// it.
+    Unsorted { row: usize },
+}
+
+impl fmt::Display for SortedColumnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortedColumnsError::NoColumns => write!(f, "no columns"),
+            SortedColumnsError::Length {
+                column,
+                expected,
+                found,
+            } => write!(f, "column {column} has {found} keys, expected {expected}"),
+            SortedColumnsError::Unsorted { row } => {
+                write!(f, "row {row} is not sorted strictly after row {}", row - 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SortedColumnsError {}
+
+#[derive(Clone)]
+enum Keys<K> {
+    /// The columns, one after the other, each `len` keys long.
+    Arena(Vec<K>),
+    /// The sorted columns the relation was created from.
+    Shared(Vec<SharedColumn<K>>),
+}
+
+#[derive(Clone)]
+pub struct TrieRelation<K> {
+    arity: usize,
+    len: usize,
+    keys: Keys<K>,
+    counts: Option<Arc<Counts>>,
+}
+
+/// The subtree counts of a relation, see TrieRelation::with_counts(). Level
+/// i is the level of attribute i.
+#[derive(Debug)]
+struct Counts {
+    /// Per level, for every row, the end of the rows holding its key.
+    ends: Vec<usize>,
+    /// Per level, for every row and the end, the number of keys on the
+    /// level in the rows before it.
+    keys: Vec<usize>,
+}
+
+impl Counts {
+    fn of<K: Ord + Copy>(relation: &TrieRelation<K>) -> Self {
+        let (arity, len) = (relation.arity, relation.len);
+        // Per row but the last, the first level on which it differs from
+        // the next row.
+        let breaks: Vec<usize> = (1..len)
+            .map(|r| {
+                (0..arity)
+                    .find(|&a| relation.column(a)[r - 1] != relation.column(a)[r])
+                    .unwrap_or(arity)
+            })
+            .collect();
+        let mut ends = vec![0; arity * len];
+        let mut keys = vec![0; arity * (len + 1)];
+        for level in 0..arity {
+            let ends = &mut ends[level * len..(level + 1) * len];
+            for r in (0..len).rev() {
+                ends[r] = if r + 1 < len && breaks[r] > level {
+                    ends[r + 1]
+                } else {
+                    r + 1
+                };
+            }
+            let keys = &mut keys[level * (len + 1)..(level + 1) * (len + 1)];
+            for r in 0..len {
+                keys[r + 1] = keys[r] + (r == 0 || breaks[r - 1] <= level) as usize;
+            }
+        }
+        Self { ends, keys }
+    }
+
+    fn heap_size(&self) -> usize {
+        (self.ends.capacity() + self.keys.capacity()) * size_of::<usize>()
+    }
+}
+
+impl<K> TrieRelation<K> {
+    pub fn column(&self, attribute: usize) -> &[K] {
+        assert!(attribute < self.arity, "Attribute out of range");
+        match &self.keys {
+            Keys::Arena(keys) => &keys[attribute * self.len..(attribute + 1) * self.len],
+            Keys::Shared(columns) => (*columns[attribute]).as_ref(),
+        }
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for TrieRelation<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let columns: Vec<&[K]> = (0..self.arity).map(|a| self.column(a)).collect();
+        f.debug_struct("TrieRelation")
+            .field("arity", &self.arity)
+            .field("columns", &columns)
+            .finish()
+    }
+}
+
+impl<K: PartialEq> PartialEq for TrieRelation<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.arity == other.arity
+            && self.len == other.len
+            && (0..self.arity).all(|a| self.column(a) == other.column(a))
+    }
+}
+
+impl<K: Eq> Eq for TrieRelation<K> {}
+
+impl<K: Ord + Copy> TrieRelation<K> {
+    /// Creates a relation from tuples of length `arity` in any order.
+    /// Duplicate tuples are dropped.
+    pub fn new<T: AsRef<[K]>>(arity: usize, tuples: impl IntoIterator<Item = T>) -> Self {
+        Self::new_in(arity, tuples, &mut Global)
+    }
+
+    /// Creates a relation like new(), storing its keys in a buffer from
+    /// `allocator`.
+    pub fn new_in<T: AsRef<[K]>>(
+        arity: usize,
+        tuples: impl IntoIterator<Item = T>,
+        allocator: &mut impl TrieAllocator<K>,
+    ) -> Self {
+        // Sort the row numbers of the staged tuples rather than the rows
+        // themselves.
+        let staged = stage(arity, tuples);
+        let row = |r: usize| &staged[r * arity..(r + 1) * arity];
+        let mut rows: Vec<usize> = (0..staged.len() / arity).collect();
+        rows.sort_unstable_by(|&a, &b| row(a).cmp(row(b)));
+        rows.dedup_by(|a, b| row(*a) == row(*b));
+
+        let len = rows.len();
+        let mut keys = allocator.allocate(arity * len);
+        for c in 0..arity {
+            keys.extend(rows.iter().map(|&r| staged[r * arity + c]));
+        }
+        Self {
+            arity,
+            len,
+            keys: Keys::Arena(keys),
+            counts: None,
+        }
+    }
+
+    /// Creates a relation from one column per attribute, whose tuples must
+    /// be sorted lexicographically and without duplicates. The columns are
+    /// shared, not copied.
+    pub fn from_sorted_columns(columns: Vec<SharedColumn<K>>) -> Result<Self, SortedColumnsError> {
+        let first = columns.first().ok_or(SortedColumnsError::NoColumns)?;
+        let len = (**first).as_ref().len();
+        for (column, keys) in columns.iter().enumerate() {
+            let found = (**keys).as_ref().len();
+            if found != len {
+                return Err(SortedColumnsError::Length {
+                    column,
+                    expected: len,
+                    found,
+                });
+            }
+        }
+        // Scan level by level for group breaks, i.e. rows whose key differs
+        // from the previous row. Rows without a break on the levels so far
+        // continue the group of the previous row and must be ordered by the
+        // next level; rows left without any break are duplicates.
+        let mut ties: Vec<usize> = (1..len).collect();
+        let mut unsorted = None;
+        for keys in &columns {
+            let keys = (**keys).as_ref();
+            ties.retain(|&row| match keys[row - 1].cmp(&keys[row]) {
+                Ordering::Less => false,
+                Ordering::Equal => true,
+                Ordering::Greater => {
+                    unsorted = Some(unsorted.map_or(row, |u: usize| u.min(row)));
+                    false
+                }
+            });
+        }
+        if let Some(row) = unsorted.into_iter().chain(ties.first().copied()).min() {
+            return Err(SortedColumnsError::Unsorted { row });
+        }
+        Ok(Self {
+            arity: columns.len(),
+            len,
+            keys: Keys::Shared(columns),
+            counts: None,
+        })
+    }
+
+    /// The first row whose tuple is not less than `tuple`.
+    fn lower_bound(&self, tuple: &[K]) -> usize {
+        self.probe(tuple).start
+    }
+
+    /// Returns the rows whose tuples start with `prefix`, the subtree below
+    /// it, found by one binary search per attribute of the prefix within
+    /// the rows matched so far. Without such rows, the range is empty and
+    /// starts at the first row after `prefix`.
+    pub fn probe(&self, prefix: &[K]) -> Range<usize> {
+        assert!(
+            prefix.len() <= self.arity,
+            "Prefix is longer than the arity"
+        );
+        let (mut lo, mut hi) = (0, self.len());
+        for (a, &key) in prefix.iter().enumerate() {
+            let column = &self.column(a)[lo..hi];
+            let start = column.partition_point(|&k| k < key);
+            let end = column.partition_point(|&k| k <= key);
+            if start == end {
+                return lo + start..lo + start;
+            }
+            (lo, hi) = (lo + start, lo + end);
+        }
+        lo..hi
+    }
+
+    /// Annotates the relation with subtree counts, see the module docs.
+    pub fn with_counts(mut self) -> Self {
+        self.counts = Some(Arc::new(Counts::of(&self)));
+        self
+    }
+
+    pub fn has_counts(&self) -> bool {
+        self.counts.is_some()
+    }
+
+    /// Counts the tuples per distinct prefix of `attributes` attributes,
+    /// i.e. COUNT(*) grouped by them, in sort order.
+    pub fn count_by(&self, attributes: usize) -> Vec<(Vec<K>, usize)> {
+        assert!(
+            attributes > 0 && attributes <= self.arity,
+            "Attributes out of range"
+        );
+        let mut groups = Vec::new();
+        let mut row = 0;
+        while row < self.len() {
+            let prefix: Vec<K> = (0..attributes).map(|a| self.column(a)[row]).collect();
+            let end = match &self.counts {
+                Some(counts) => counts.ends[(attributes - 1) * self.len + row],
+                None => self.probe(&prefix).end,
+            };
+            groups.push((prefix, end - row));
+            row = end;
+        }
+        groups
+    }
+
+    /// Merges the relation with `other`, of the same arity, into their union
+    /// in one pass over both.
+    pub fn merge(self, other: Self) -> Self {
+        assert_eq!(self.arity, other.arity, "Relations have different arities");
+        let (arity, shards) = (self.arity, [self, other]);
+        let mut cursors = shards.each_ref().map(|s| (0, s.len()));
+        let mut rows = merge_range(&shards, &mut cursors);
+        rows.dedup_by(|&mut b, &mut a| compare_rows(&shards, a, b, arity).is_eq());
+        let mut keys = Vec::with_capacity(arity * rows.len());
+        for a in 0..arity {
+            keys.extend(rows.iter().map(|&(s, r)| shards[s].column(a)[r]));
+        }
+        Self::from_arena(arity, keys)
+    }
+
+    /// Returns the buffer holding the keys, e.g. to release it to the
+    /// allocator the relation was built with. Shared columns are copied.
+    pub fn into_buffer(self) -> Vec<K> {
+        match self.keys {
+            Keys::Arena(keys) => keys,
+            Keys::Shared(columns) => columns
+                .iter()
+                .flat_map(|c| (**c).as_ref().iter().copied())
+                .collect(),
+        }
+    }
+
+    pub fn empty(arity: usize) -> Self {
+        Self::new(arity, Vec::<Vec<K>>::new())
+    }
+
+    /// Creates a relation from its columns, one after the other, whose
+    /// tuples must be sorted and distinct.
+    pub(crate) fn from_arena(arity: usize, keys: Vec<K>) -> Self {
+        assert!(arity > 0, "Arity must be > 0");
+        Self {
+            arity,
+            len: keys.len() / arity,
+            keys: Keys::Arena(keys),
+            counts: None,
+        }
+    }
+
+    /// Returns the relation with its attributes reordered: attribute i of the
+    /// result is attribute `attributes[i]` of this relation.
+    pub fn permuted(&self, attributes: &[usize]) -> Self {
+        assert_eq!(attributes.len(), self.arity, "Permutation has wrong arity");
+        Self::new(
+            self.arity,
+            (0..self.len()).map(|row| {
+                attributes
+                    .iter()
+                    .map(|&a| self.column(a)[row])
+                    .collect::<Vec<_>>()
+            }),
+        )
+    }
+
+    /// Checks whether the relation contains `tuple`.
+    pub fn contains(&self, tuple: &[K]) -> bool {
+        self.position(tuple).is_some()
+    }
+
+    /// Returns the row of `tuple`, if the relation contains it.
+    pub fn position(&self, tuple: &[K]) -> Option<usize> {
+        assert_eq!(tuple.len(), self.arity, "Tuple has wrong arity");
+        let row = self.lower_bound(tuple);
+        let found = row < self.len()
+            && tuple
+                .iter()
+                .enumerate()
+                .all(|(a, &k)| self.column(a)[row] == k);
+        found.then_some(row)
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Number of tuples.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes allocated for the columns and subtree counts. Shared columns
+    /// are owned elsewhere and not counted.
+    pub fn heap_size(&self) -> usize {
+        let counts = self.counts.as_ref().map_or(0, |c| c.heap_size());
+        match &self.keys {
+            Keys::Arena(keys) => keys.capacity() * size_of::<K>() + counts,
+            Keys::Shared(_) => counts,
+        }
+    }
+
+    /// Returns the tuple at `row`, in sort order.
+    pub fn tuple(&self, row: usize) -> Vec<K> {
+        (0..self.arity).map(|a| self.column(a)[row]).collect()
+    }
+
+    /// Iterates over the tuples in sort order.
+    pub fn tuples(&self) -> impl Iterator<Item = Vec<K>> + '_ {
+        (0..self.len()).map(|row| self.tuple(row))
+    }
+
+    pub fn iter(&self) -> TrieIterator<'_, K> {
+        TrieIterator::new(self)
+    }
+}
+
+impl<K: Ord + Copy + Add<Output = K> + Default> TrieRelation<K> {
+    /// Merges two counted relations, whose last attribute is the
+    /// multiplicity of the tuple made of the others. The multiplicities of
+    /// equal tuples are added, and tuples whose multiplicities add up to
+    /// zero, i.e. K::default(), are dropped, so deletions can be recorded
+    /// as negative multiplicities.
+    pub fn merge_counted(self, other: Self) -> Self {
+        assert_eq!(self.arity, other.arity, "Relations have different arities");
+        assert!(self.arity > 1, "Counted relations need an arity > 1");
+        let (arity, shards) = (self.arity, [self, other]);
+        let mut cursors = shards.each_ref().map(|s| (0, s.len()));
+        let rows = merge_range(&shards, &mut cursors);
+        let count = |(s, r): (usize, usize)| shards[s].column(arity - 1)[r];
+        // Per distinct tuple, its first row and its total multiplicity.
+        let mut counted: Vec<((usize, usize), K)> = Vec::new();
+        for row in rows {
+            match counted.last_mut() {
+                Some((first, total)) if compare_rows(&shards, *first, row, arity - 1).is_eq() => {
+                    *total = *total + count(row)
+                }
+                _ => counted.push((row, count(row))),
+            }
+        }
+        counted.retain(|&(_, total)| total != K::default());
+        let mut keys = Vec::with_capacity(arity * counted.len());
+        for a in 0..arity - 1 {
+            keys.extend(counted.iter().map(|&((s, r), _)| shards[s].column(a)[r]));
+        }
+        keys.extend(counted.iter().map(|&(_, total)| total));
+        Self::from_arena(arity, keys)
+    }
+}
+
+/// Copies tuples of length `arity` into one buffer, row after row.
+fn stage<K: Copy, T: AsRef<[K]>>(arity: usize, tuples: impl IntoIterator<Item = T>) -> Vec<K> {
+    assert!(arity > 0, "Arity must be > 0");
+    let mut staged = Vec::new();
+    for tuple in tuples {
+        let tuple = tuple.as_ref();
+        assert_eq!(tuple.len(), arity, "Tuple has wrong arity");
+        staged.extend_from_slice(tuple);
+    }
+    staged
+}
+
+#[cfg(feature = "rayon")]
+impl<K: Ord + Copy + Send + Sync> TrieRelation<K> {
+    /// Creates a relation like new(), sorting the tuples, dropping
+    /// duplicates and filling the columns in parallel.
+    pub fn new_parallel<T: AsRef<[K]>>(arity: usize, tuples: impl IntoIterator<Item = T>) -> Self {
+        use rayon::prelude::*;
+
+        let staged = stage(arity, tuples);
+        let row = |r: usize| &staged[r * arity..(r + 1) * arity];
+        let mut rows: Vec<usize> = (0..staged.len() / arity).collect();
+        rows.par_sort_unstable_by(|&a, &b| row(a).cmp(row(b)));
+        // A row starts a new tuple unless it equals the previous one.
+        let rows: Vec<usize> = (0..rows.len())
+            .into_par_iter()
+            .filter(|&i| i == 0 || row(rows[i - 1]) != row(rows[i]))
+            .map(|i| rows[i])
+            .collect();
+        Self::from_rows(
+            arity,
+            &[&rows],
+            |r, a| staged[r * arity + a],
+            &RayonExecutor,
+        )
+    }
+
+    /// Merges relations of the same arity into one without duplicates. The
+    /// key space is split into one range per thread at evenly spaced tuples
+    /// of the largest relation, and the ranges are merged in parallel.
+    pub fn merge_shards(shards: &[TrieRelation<K>]) -> Self {
+        Self::merge_shards_tuned(shards, &Tuning::default())
+    }
+
+    /// Merges relations like merge_shards(), into `tuning.partitions` ranges
+    /// unless that is 0.
+    pub fn merge_shards_tuned(shards: &[TrieRelation<K>], tuning: &Tuning) -> Self {
+        Self::merge_shards_on(shards, tuning, &RayonExecutor)
+    }
+}
+
+impl<K: Ord + Copy + Send + Sync> TrieRelation<K> {
+    /// Merges relations of the same arity into one without duplicates, like
+    /// merge_shards(), with a task per range on `executor`. The key space is
+    /// split into `tuning.partitions` ranges, or one per thread of the
+    /// executor if that is 0.
+    pub fn merge_shards_on(
+        shards: &[TrieRelation<K>],
+        tuning: &Tuning,
+        executor: &dyn Executor,
+    ) -> Self {
+        Self::merge_ranges(shards, tuning, executor, |parts, merge| {
+            let mut merged = vec![Vec::new(); parts];
+            let tasks = merged.iter_mut().enumerate().map(|(p, rows)| {
+                let task: Task<'_> = Box::new(move || *rows = merge(p));
+                task
+            });
+            executor.scope(tasks.collect());
+            merged
+        })
+    }
+
+    /// Merges relations like merge_shards_on(), with `run(parts, merge)`
+    /// calling `merge(p)` for every range `p` of `parts` and returning the
+    /// merged tuples of the ranges in order.
+    fn merge_ranges(
+        shards: &[TrieRelation<K>],
+        tuning: &Tuning,
+        executor: &dyn Executor,
+        run: impl FnOnce(usize, &MergeRange<'_>) -> Vec<Vec<(usize, usize)>>,
+    ) -> Self {
+        let largest = shards.iter().max_by_key(|s| s.len()).expect("No shards");
+        let arity = largest.arity;
+        assert!(
+            shards.iter().all(|s| s.arity == arity),
+            "Shards have different arities"
+        );
+        let parts = match tuning.partitions {
+            0 => executor.threads(),
+            partitions => partitions,
+        };
+        let parts = parts.clamp(1, largest.len().max(1));
+        let splitters: Vec<Vec<K>> = (1..parts)
+            .map(|p| largest.tuple(p * largest.len() / parts))
+            .collect();
+        // Per shard, the rows at which the ranges start, and its end.
+        let bounds: Vec<Vec<usize>> = shards
+            .iter()
+            .map(|shard| {
+                let starts = splitters.iter().map(|t| shard.lower_bound(t));
+                std::iter::once(0)
+                    .chain(starts)
+                    .chain(std::iter::once(shard.len()))
+                    .collect()
+            })
+            .collect();
+        // Per range, the merged tuples as (shard, row).
+        let merge = |p: usize| {
+            let mut cursors: Vec<(usize, usize)> =
+                bounds.iter().map(|b| (b[p], b[p + 1])).collect();
+            let mut rows = merge_range(shards, &mut cursors);
+            rows.dedup_by(|&mut b, &mut a| compare_rows(shards, a, b, arity).is_eq());
+            rows
+        };
+        let merged = run(parts, &merge);
+        let merged: Vec<&[(usize, usize)]> = merged.iter().map(Vec::as_slice).collect();
+        let key = |(s, r): (usize, usize), a| shards[s].column(a)[r];
+        Self::from_rows(arity, &merged, key, executor)
+    }
+
+    /// Builds the columns from sorted, distinct rows given in consecutive
+    /// parts, one column per task, with `key(row, a)` returning attribute
+    /// `a` of a row.
+    fn from_rows<R: Copy + Sync>(
+        arity: usize,
+        parts: &[&[R]],
+        key: impl Fn(R, usize) -> K + Sync,
+        executor: &dyn Executor,
+    ) -> Self {
+        let len = parts.iter().map(|p| p.len()).sum();
+        let Some(&first) = parts.iter().find_map(|p| p.first()) else {
+            return Self::empty(arity);
+        };
+        let mut keys = vec![key(first, 0); arity * len];
+        let key = &key;
+        let tasks = keys.chunks_mut(len).enumerate().map(|(a, column)| {
+            let task: Task<'_> = Box::new(move || {
+                let rows = parts.iter().flat_map(|p| p.iter());
+                for (k, &r) in column.iter_mut().zip(rows) {
+                    *k = key(r, a);
+                }
+            });
+            task
+        });
+        executor.scope(tasks.collect());
+        Self {
+            arity,
+            len,
+            keys: Keys::Arena(keys),
+            counts: None,
+        }
+    }
+}
+
+/// Merges the range of the given index into tuples as (shard, row).
+type MergeRange<'a> = dyn Fn(usize) -> Vec<(usize, usize)> + Sync + 'a;
+
+#[cfg(feature = "numa")]
+impl<K: Ord + Copy + Send + Sync> TrieRelation<K> {
+    /// Merges relations like merge_shards_tuned(), but on one worker thread
+    /// per node of `topology`, pinned to it. Each worker merges its share of
+    /// the ranges (see NumaTopology::node_of()), so that the merged rows of a
+    /// range are allocated on the node that merged them. Workers that cannot
+    /// be pinned merge their share unpinned.
+    pub fn merge_shards_numa(
+        shards: &[TrieRelation<K>],
+        tuning: &Tuning,
+        topology: &NumaTopology,
+    ) -> Self {
+        Self::merge_ranges(shards, tuning, &RayonExecutor, |parts, merge| {
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..topology.nodes())
+                    .map(|node| {
+                        let share = (0..parts).filter(|&p| topology.node_of(p, parts) == node);
+                        (node, share.collect::<Vec<_>>())
+                    })
+                    .filter(|(_, share)| !share.is_empty())
+                    .map(|(node, share)| {
+                        scope.spawn(move || {
+                            let _ = topology.pin(node);
+                            share.into_iter().map(merge).collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                // Nodes take consecutive ranges, so their results are in order.
+                workers
+                    .into_iter()
+                    .flat_map(|worker| worker.join().unwrap())
+                    .collect()
+            })
+        })
+    }
+}
+
+/// Compares the first `width` attributes of two rows, given as (shard,
+/// row).
+fn compare_rows<K: Ord + Copy>(
+    shards: &[TrieRelation<K>],
+    (s, r): (usize, usize),
+    (t, q): (usize, usize),
+    width: usize,
+) -> Ordering {
+    (0..width)
+        .map(|a| shards[s].column(a)[r].cmp(&shards[t].column(a)[q]))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Merges the rows [lo, hi) of each shard, given as `cursors`, into one
+/// sorted list of (shard, row). Equal rows are all kept, in shard order.
+fn merge_range<K: Ord + Copy>(
+    shards: &[TrieRelation<K>],
+    cursors: &mut [(usize, usize)],
+) -> Vec<(usize, usize)> {
+    let arity = shards[0].arity;
+    let total = cursors.iter().map(|(lo, hi)| hi - lo).sum();
+    let mut merged = Vec::with_capacity(total);
+    loop {
+        let next = (0..cursors.len())
+            .filter(|&s| cursors[s].0 < cursors[s].1)
+            .min_by(|&s, &t| compare_rows(shards, (s, cursors[s].0), (t, cursors[t].0), arity));
+        let Some(s) = next else {
+            return merged;
+        };
+        merged.push((s, cursors[s].0));
+        cursors[s].0 += 1;
+    }
+}
+
+/// One level the iterator has descended into: the rows [lo, hi) sharing the
+/// path to it, and the current row.
+#[derive(Clone, Copy, Debug)]
+struct Level {
+    lo: usize,
+    hi: usize,
+    pos: usize,
+}
+
+/// TrieIterator walks a TrieRelation. It starts at the root, above the first
+/// level; open() must be called before any other operation.
+#[derive(Clone, Debug)]
+pub struct TrieIterator<'a, K> {
+    relation: &'a TrieRelation<K>,
+    levels: Vec<Level>,
+    scan_first: usize,
+}
+
+impl<'a, K: Ord + Copy> TrieIterator<'a, K> {
+    pub fn new(relation: &'a TrieRelation<K>) -> Self {
+        Self {
+            relation,
+            levels: Vec::with_capacity(relation.arity),
+            scan_first: 0,
+        }
+    }
+
+    /// Creates an iterator whose seeks scan `tuning.galloping_threshold`
+    /// keys before they binary search the rest of the level.
+    pub fn with_tuning(relation: &'a TrieRelation<K>, tuning: &Tuning) -> Self {
+        Self {
+            scan_first: tuning.galloping_threshold,
+            ..Self::new(relation)
+        }
+    }
+
+    /// Number of levels opened, i.e. 0 at the root.
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Descends to the first child of the current key, or to the first level
+    /// if at the root.
+    pub fn open(&mut self) {
+        let level = match self.levels.last() {
+            None => Level {
+                lo: 0,
+                hi: self.relation.len(),
+                pos: 0,
+            },
+            Some(_) => {
+                assert!(!self.at_end(), "Iterator is at end");
+                assert!(self.depth() < self.relation.arity, "Cannot open a leaf");
+                let (start, end) = self.run();
+                Level {
+                    lo: start,
+                    hi: end,
+                    pos: start,
+                }
+            }
+        };
+        self.levels.push(level);
+    }
+
+    /// Returns to the parent level, positioned at the key that was opened.
+    pub fn up(&mut self) {
+        assert!(self.levels.pop().is_some(), "Iterator is at the root");
+    }
+
+    /// The rows holding the current key on the current level, i.e. the
+    /// tuples in its subtree.
+    pub fn run(&self) -> (usize, usize) {
+        let level = self.level();
+        if let Some(counts) = &self.relation.counts {
+            let len = self.relation.len;
+            return (level.pos, counts.ends[(self.depth() - 1) * len + level.pos]);
+        }
+        let column = &self.column()[..level.hi];
+        let key = column[level.pos];
+        (
+            level.pos,
+            level.pos + column[level.pos..].partition_point(|&k| k <= key),
+        )
+    }
+
+    /// The first row holding the current key. On the last level, this is
+    /// the row of the tuple the iterator is at.
+    pub fn row(&self) -> usize {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.level().pos
+    }
+
+    /// The number of keys below the current key, i.e. on the level open()
+    /// descends to.
+    pub fn children(&self) -> usize {
+        assert!(self.depth() < self.relation.arity, "A leaf has no children");
+        let (start, end) = self.run();
+        match &self.relation.counts {
+            Some(counts) => {
+                let keys = &counts.keys[self.depth() * (self.relation.len + 1)..];
+                keys[end] - keys[start]
+            }
+            None => {
+                let column = &self.relation.column(self.depth())[start..end];
+                1 + column.windows(2).filter(|w| w[0] != w[1]).count()
+            }
+        }
+    }
+
+    /// The rows [lo, hi) below the parent of the current level.
+    pub fn extent(&self) -> (usize, usize) {
+        let level = self.level();
+        (level.lo, level.hi)
+    }
+
+    /// The relation the iterator walks.
+    pub fn relation(&self) -> &'a TrieRelation<K> {
+        self.relation
+    }
+
+    fn level(&self) -> &Level {
+        self.levels.last().expect("Iterator is at the root")
+    }
+
+    fn column(&self) -> &'a [K] {
+        self.relation.column(self.depth() - 1)
+    }
+}
+
+impl<K: Ord + Copy> Seekable for TrieIterator<'_, K> {
+    type Key = K;
+
+    fn key(&self) -> K {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.column()[self.level().pos]
+    }
+
+    fn next(&mut self) {
+        assert!(!self.at_end(), "Iterator is at end");
+        let (_, end) = self.run();
+        self.levels.last_mut().unwrap().pos = end;
+    }
+
+    fn seek(&mut self, seek_key: K) {
+        assert!(!self.at_end(), "Iterator is at end");
+        let column = self.column();
+        let level = self.levels.last_mut().unwrap();
+        let end = level.hi.min(level.pos.saturating_add(self.scan_first));
+        while level.pos < end && column[level.pos] < seek_key {
+            level.pos += 1;
+        }
+        if level.pos == end {
+            level.pos += column[level.pos..level.hi].partition_point(|&k| k < seek_key);
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        let level = self.level();
+        level.pos >= level.hi
+    }
+
+    /// The rows left below the parent, an upper bound on the keys left,
+    /// unless the keys left are counted exactly.
+    fn estimate(&self) -> Option<usize> {
+        let level = self.level();
+        self.exact_len_hint()
+            .or(Some(level.hi.saturating_sub(level.pos)))
+    }
+
+    /// The last key below the parent.
+    fn upper_bound(&self) -> Option<K> {
+        Some(self.column()[self.level().hi - 1])
+    }
+
+    /// On the last level, every row holds a distinct key. Subtree counts
+    /// tell the keys left on any level.
+    fn exact_len_hint(&self) -> Option<usize> {
+        let level = self.level();
+        let rows = level.hi.saturating_sub(level.pos);
+        match &self.relation.counts {
+            _ if self.depth() == self.relation.arity => Some(rows),
+            Some(counts) if rows > 0 => {
+                let keys = &counts.keys[(self.depth() - 1) * (self.relation.len + 1)..];
+                Some(keys[level.hi] - keys[level.pos])
+            }
+            _ => (rows == 0).then_some(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::CurrentThread;
+    #[cfg(feature = "threads")]
+    use crate::executor::ThreadExecutor;
+
+    fn relation() -> TrieRelation<i32> {
+        TrieRelation::new(2, [[3, 1], [1, 2], [1, 5], [3, 4], [1, 2], [2, 0]])
+    }
+
+    #[test]
+    fn test_trie_relation() {
+        let rel = relation();
+        assert_eq!(rel.len(), 5);
+        assert_eq!(rel.column(0), &[1, 1, 2, 3, 3]);
+        assert_eq!(rel.column(1), &[2, 5, 0, 1, 4]);
+        assert_eq!(rel.tuple(3), vec![3, 1]);
+    }
+
+    #[test]
+    fn test_permuted_contains() {
+        let rel = relation().permuted(&[1, 0]);
+        assert_eq!(rel.column(0), &[0, 1, 2, 4, 5]);
+        assert_eq!(rel.column(1), &[2, 3, 1, 3, 1]);
+        assert!(rel.contains(&[4, 3]));
+        assert!(!rel.contains(&[4, 1]));
+        assert!(!rel.contains(&[3, 3]));
+        assert!(!TrieRelation::empty(2).contains(&[0, 0]));
+        assert_eq!(rel.position(&[2, 1]), Some(2));
+        assert_eq!(rel.position(&[2, 2]), None);
+        assert_eq!(rel.probe(&[4]), 3..4);
+        assert_eq!(rel.probe(&[4, 3]), 3..4);
+        assert_eq!(rel.probe(&[4, 1]), 3..3);
+        assert_eq!(rel.probe(&[6]), 5..5);
+        assert_eq!(rel.probe(&[]), 0..5);
+    }
+
+    #[test]
+    fn test_counts() {
+        let plain = TrieRelation::new(3, [[1, 1, 1], [1, 1, 2], [1, 2, 1], [2, 1, 1], [3, 1, 1]]);
+        let counted = plain.clone().with_counts();
+        assert!(counted.has_counts() && !plain.has_counts());
+        assert!(counted.heap_size() > plain.heap_size());
+        for rel in [&plain, &counted] {
+            assert_eq!(rel.count_by(1), [(vec![1], 3), (vec![2], 1), (vec![3], 1)]);
+            assert_eq!(rel.count_by(2)[..2], [(vec![1, 1], 2), (vec![1, 2], 1)]);
+            let mut iter = rel.iter();
+            iter.open();
+            assert_eq!(iter.exact_len_hint(), rel.has_counts().then_some(3));
+            assert_eq!(iter.estimate(), Some(if rel.has_counts() { 3 } else { 5 }));
+            assert_eq!((iter.run(), iter.children()), ((0, 3), 2));
+            iter.open();
+            assert_eq!((iter.run(), iter.children()), ((0, 2), 2));
+            iter.next();
+            assert_eq!((iter.run(), iter.children()), ((2, 3), 1));
+            iter.up();
+            iter.next();
+            assert_eq!((iter.run(), iter.children()), ((3, 4), 1));
+        }
+    }
+
+    #[test]
+    fn test_buffer_pool() {
+        let mut pool = BufferPool::new();
+        let rel = TrieRelation::new_in(2, [[3, 1], [1, 2], [3, 1]], &mut pool);
+        assert_eq!(rel, TrieRelation::new(2, [[1, 2], [3, 1]]));
+        let buffer = rel.into_buffer();
+        let address = buffer.as_ptr();
+        pool.release(buffer);
+        pool.release(Vec::with_capacity(100));
+        let rel = TrieRelation::new_in(1, [[7], [5], [6]], &mut pool);
+        assert_eq!(rel.column(0), &[5, 6, 7]);
+        assert_eq!(rel.column(0).as_ptr(), address);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_from_sorted_columns() {
+        let column = |keys: Vec<i32>| -> SharedColumn<i32> { Arc::new(keys) };
+        let first = column(vec![1, 1, 2, 3, 3]);
+        let rel =
+            TrieRelation::from_sorted_columns(vec![first.clone(), column(vec![2, 5, 0, 1, 4])])
+                .unwrap();
+        assert_eq!(rel, relation());
+        assert_eq!(rel.column(0).as_ptr(), (*first).as_ref().as_ptr());
+        assert_eq!(rel.heap_size(), 0);
+        let mut iter = rel.iter();
+        iter.open();
+        iter.seek(3);
+        iter.open();
+        assert_eq!(iter.extent(), (3, 5));
+
+        let error = |columns| TrieRelation::from_sorted_columns(columns).unwrap_err();
+        assert_eq!(
+            error(vec![column(vec![1, 1, 2]), column(vec![2, 1, 0])]),
+            SortedColumnsError::Unsorted { row: 1 }
+        );
+        assert_eq!(
+            error(vec![column(vec![1, 2, 2, 0]), column(vec![0, 1, 1, 0])]),
+            SortedColumnsError::Unsorted { row: 2 }
+        );
+        assert_eq!(
+            error(vec![column(vec![1, 2]), column(vec![0])]),
+            SortedColumnsError::Length {
+                column: 1,
+                expected: 2,
+                found: 1
+            }
+        );
+        assert_eq!(error(Vec::new()), SortedColumnsError::NoColumns);
+    }
+
+    #[test]
+    fn test_merge() {
+        let old = TrieRelation::new(2, [[1, 2], [3, 1], [5, 0]]);
+        let new = TrieRelation::new(2, [[0, 7], [3, 1], [3, 2], [6, 6]]);
+        let expected = TrieRelation::new(2, [[0, 7], [1, 2], [3, 1], [3, 2], [5, 0], [6, 6]]);
+        assert_eq!(old.clone().merge(new.clone()), expected);
+        assert_eq!(new.merge(old.clone()), expected);
+        assert_eq!(old.clone().merge(TrieRelation::empty(2)), old);
+
+        // Counts per (day, user), with a retraction.
+        let monday = TrieRelation::new(3, [[1, 10, 2], [1, 11, 1], [2, 10, 1]]);
+        let tuesday = TrieRelation::new(3, [[1, 10, 3], [1, 11, -1], [3, 12, 4]]);
+        assert_eq!(
+            monday.merge_counted(tuesday),
+            TrieRelation::new(3, [[1, 10, 5], [2, 10, 1], [3, 12, 4]])
+        );
+    }
+
+    #[test]
+    fn test_trie_iterator() {
+        let rel = relation();
+        let mut iter = rel.iter();
+        assert_eq!(iter.depth(), 0);
+        iter.open();
+        assert_eq!(iter.key(), 1);
+        iter.open();
+        assert_eq!(iter.key(), 2);
+        iter.next();
+        assert_eq!(iter.key(), 5);
+        iter.next();
+        assert!(iter.at_end());
+        iter.up();
+        assert_eq!(iter.key(), 1);
+        iter.next();
+        assert_eq!(iter.key(), 2);
+        iter.seek(3);
+        assert_eq!(iter.key(), 3);
+        iter.open();
+        assert_eq!(iter.extent(), (3, 5));
+        iter.seek(2);
+        assert_eq!(iter.key(), 4);
+        iter.up();
+        iter.next();
+        assert!(iter.at_end());
+    }
+
+    #[test]
+    fn test_trie_iterator_with_tuning() {
+        let rel = TrieRelation::new(1, (0..100).map(|i| [i * 2]));
+        for threshold in [0, 1, 8, 1000] {
+            let tuning = Tuning {
+                galloping_threshold: threshold,
+                ..Tuning::default()
+            };
+            let mut iter = TrieIterator::with_tuning(&rel, &tuning);
+            iter.open();
+            for (target, key) in [(3, 4), (4, 4), (21, 22), (150, 150), (197, 198)] {
+                iter.seek(target);
+                assert_eq!(iter.key(), key);
+            }
+            iter.seek(199);
+            assert!(iter.at_end());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot open a leaf")]
+    fn test_trie_iterator_open_leaf() {
+        let rel = relation();
+        let mut iter = rel.iter();
+        iter.open();
+        iter.open();
+        iter.open();
+    }
+
+    #[test]
+    fn test_merge_shards_on() {
+        let shards: Vec<Vec<[u32; 2]>> = (0..3)
+            .map(|s| (0..500).map(|i| [i * (s + 2) % 97, i % 7]).collect())
+            .collect();
+        let relations: Vec<TrieRelation<u32>> =
+            shards.iter().map(|s| TrieRelation::new(2, s)).collect();
+        let expected = TrieRelation::new(2, shards.concat());
+        let tuning = Tuning {
+            partitions: 4,
+            ..Tuning::default()
+        };
+        #[cfg(feature = "threads")]
+        let threads = ThreadExecutor::new(3);
+        let executors: Vec<&dyn Executor> = vec![
+            &CurrentThread,
+            #[cfg(feature = "threads")]
+            &threads,
+        ];
+        for executor in executors {
+            let merged = TrieRelation::merge_shards_on(&relations, &tuning, executor);
+            assert_eq!(merged, expected);
+        }
+        let merged = TrieRelation::merge_shards_on(&relations, &Tuning::default(), &CurrentThread);
+        assert_eq!(merged, expected);
+    }
+}
+
+#[cfg(all(test, feature = "rayon"))]
+mod parallel_tests {
+    use super::*;
+
+    fn tuples(n: u32, seed: u32) -> Vec<[u32; 3]> {
+        (0..n)
+            .map(|i| {
+                let x = i.wrapping_mul(2654435761).wrapping_add(seed);
+                [x % 17, x / 17 % 13, x % 5]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_new_parallel() {
+        let input = tuples(10_000, 1);
+        assert_eq!(
+            TrieRelation::new_parallel(3, &input),
+            TrieRelation::new(3, &input)
+        );
+        assert!(TrieRelation::new_parallel(2, Vec::<[u32; 2]>::new()).is_empty());
+    }
+
+    #[test]
+    fn test_merge_shards() {
+        let shards: Vec<Vec<[u32; 3]>> = (0..4).map(|s| tuples(2_000 + s * 500, s)).collect();
+        let relations: Vec<TrieRelation<u32>> =
+            shards.iter().map(|s| TrieRelation::new(3, s)).collect();
+        assert_eq!(
+            TrieRelation::merge_shards(&relations),
+            TrieRelation::new(3, shards.concat())
+        );
+        let tuning = Tuning {
+            partitions: 7,
+            ..Tuning::default()
+        };
+        assert_eq!(
+            TrieRelation::merge_shards_tuned(&relations, &tuning),
+            TrieRelation::new(3, shards.concat())
+        );
+        let empty = TrieRelation::empty(3);
+        assert_eq!(
+            TrieRelation::merge_shards(&[empty.clone(), relations[0].clone(), empty]),
+            relations[0]
+        );
+    }
+
+    #[cfg(feature = "numa")]
+    #[test]
+    fn test_merge_shards_numa() {
+        let shards: Vec<Vec<[u32; 3]>> = (0..3).map(|s| tuples(1_000 + s * 300, s)).collect();
+        let relations: Vec<TrieRelation<u32>> =
+            shards.iter().map(|s| TrieRelation::new(3, s)).collect();
+        let tuning = Tuning {
+            partitions: 5,
+            ..Tuning::default()
+        };
+        for topology in [
+            NumaTopology::detect(),
+            NumaTopology::from_nodes(vec![vec![0]; 2]),
+        ] {
+            assert_eq!(
+                TrieRelation::merge_shards_numa(&relations, &tuning, &topology),
+                TrieRelation::new(3, shards.concat())
+            );
+        }
+    }
+}
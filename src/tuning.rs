@@ -0,0 +1,357 @@
+//! Tunables of the join and its inputs.
+//!
+//! Tuning bundles the parameters that trade work against memory or
+//! requests, and is accepted by the builders that use them:
+//!
+//! - LinearIterator::with_tuning() and LeapFrogJoin::tuned() gallop once a
+//!   seek has scanned `galloping_threshold` keys, and
+//!   TrieIterator::with_tuning() and Query::with_tuning() binary search
+//!   after it.
+//! - TrieRelation::write_tuned() writes blocks of `block_size` keys.
+//! - RemoteIterator::with_tuning() fetches `batch_size` keys per request.
+//! - TrieRelation::merge_shards_on() merges `partitions` key ranges in
+//!   parallel on an Executor, and TrieRelation::merge_shards_tuned() on the
+//!   rayon pool, with the `rayon` feature.
+//!
+//! Tuning::from_env() applies overrides from the environment on top of the
+//! defaults, e.g. `LEAPFROG_BATCH_SIZE=1024`, so that parameters can be
+//! tried out without recompiling.
+//!
+//! With the `time` feature, Tuning::auto_calibrate() measures the host
+//! instead: where galloping starts to beat a linear scan, how large a
+//! working set the caches hold, and how many threads there are. With the
+//! `fs` feature as well, Tuning::calibrated_at() caches the result in a file
+//! of `NAME=value` lines, which also works as an environment file.
+
+use std::fmt;
+#[cfg(all(feature = "fs", feature = "time"))]
+use std::fs::File;
+#[cfg(feature = "time")]
+use std::hint::black_box;
+#[cfg(all(feature = "fs", feature = "time"))]
+use std::io::BufReader;
+use std::io::{self, BufRead, Write};
+#[cfg(all(feature = "fs", feature = "time"))]
+use std::path::Path;
+#[cfg(feature = "time")]
+use std::time::Instant;
+
+use crate::persist;
+#[cfg(feature = "time")]
+use crate::{LinearIterator, random::Rng};
+
+/// The environment variables read by Tuning::from_env(), in field order.
+pub const ENV_VARS: [&str; 4] = [
+    "LEAPFROG_GALLOPING_THRESHOLD",
+    "LEAPFROG_BLOCK_SIZE",
+    "LEAPFROG_BATCH_SIZE",
+    "LEAPFROG_PARTITIONS",
+];
+
+/// An override that is not a valid value of its parameter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TuningError {
+    pub var: String,
+    pub value: String,
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid value {:?} for {}", self.value, self.var)
+    }
+}
+
+impl std::error::Error for TuningError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tuning {
+    /// Keys a seek scans one by one before it gallops, i.e. probes
+    /// exponentially growing distances and binary searches the last one.
+    pub galloping_threshold: usize,
+    /// Keys per block of written index files. Must be > 0.
+    pub block_size: usize,
+    /// Keys per request to remote sources. Must be > 0.
+    pub batch_size: usize,
+    /// Key ranges merged in parallel, or 0 for one per thread.
+    pub partitions: usize,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            galloping_threshold: 8,
+            block_size: persist::BLOCK_KEYS,
+            batch_size: 256,
+            partitions: 0,
+        }
+    }
+}
+
+impl Tuning {
+    /// Returns the defaults with the overrides of the environment applied.
+    pub fn from_env() -> Result<Self, TuningError> {
+        Self::default().with_overrides(std::env::vars())
+    }
+
+    /// Applies the overrides among `vars`, given as (name, value) pairs
+    /// named as in ENV_VARS. Other pairs are ignored.
+    pub fn with_overrides<N: AsRef<str>, V: AsRef<str>>(
+        mut self,
+        vars: impl IntoIterator<Item = (N, V)>,
+    ) -> Result<Self, TuningError> {
+        for (var, value) in vars {
+            let (var, value) = (var.as_ref(), value.as_ref());
+            let Some(field) = ENV_VARS.iter().position(|&v| v == var) else {
+                continue;
+            };
+            let min = match field {
+                1 | 2 => 1,
+                _ => 0,
+            };
+            let parsed = value.trim().parse().ok().filter(|&v| v >= min);
+            let Some(parsed) = parsed else {
+                return Err(TuningError {
+                    var: var.to_string(),
+                    value: value.to_string(),
+                });
+            };
+            match field {
+                0 => self.galloping_threshold = parsed,
+                1 => self.block_size = parsed,
+                2 => self.batch_size = parsed,
+                _ => self.partitions = parsed,
+            }
+        }
+        Ok(self)
+    }
+
+    fn values(&self) -> [usize; 4] {
+        [
+            self.galloping_threshold,
+            self.block_size,
+            self.batch_size,
+            self.partitions,
+        ]
+    }
+
+    /// Writes every parameter as a line `NAME=value`, named as in ENV_VARS.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for (var, value) in ENV_VARS.iter().zip(self.values()) {
+            writeln!(w, "{var}={value}")?;
+        }
+        Ok(())
+    }
+
+    /// Reads parameters written by write_to(). Missing ones keep their
+    /// defaults.
+    pub fn read_from<R: BufRead>(r: R) -> io::Result<Result<Self, TuningError>> {
+        let lines = r.lines().collect::<io::Result<Vec<String>>>()?;
+        let vars = lines.iter().filter_map(|line| line.split_once('='));
+        Ok(Self::default().with_overrides(vars))
+    }
+}
+
+#[cfg(feature = "time")]
+impl Tuning {
+    /// Measures the host with micro-benchmarks, which take some tens of
+    /// milliseconds, and returns the defaults adapted to it:
+    ///
+    /// - The galloping threshold is the longest seek distance at which a
+    ///   linear scan, whose branches predict well, still beats galloping.
+    /// - The block size is chosen such that 16 blocks fit into the largest
+    ///   cache level found, as the largest working set that is sped up by
+    ///   random accesses much less than the next larger one.
+    /// - The partitions are one per hardware thread.
+    ///
+    /// The batch size depends on the remote source, not the host, and keeps
+    /// its default.
+    pub fn auto_calibrate() -> Self {
+        let cache_bytes = measure_cache_bytes();
+        let block_size = (cache_bytes / (16 * 8)).next_power_of_two();
+        Self {
+            galloping_threshold: measure_galloping_threshold(),
+            block_size: block_size.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE),
+            batch_size: Self::default().batch_size,
+            partitions: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Returns the tuning cached in the file at `path`, or calibrates the
+    /// host and writes the file if it is missing or invalid.
+    #[cfg(feature = "fs")]
+    pub fn calibrated_at(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if let Ok(file) = File::open(path)
+            && let Ok(Ok(tuning)) = Self::read_from(BufReader::new(file))
+        {
+            return Ok(tuning);
+        }
+        let tuning = Self::auto_calibrate();
+        tuning.write_to(File::create(path)?)?;
+        Ok(tuning)
+    }
+}
+
+#[cfg(feature = "time")]
+const MIN_BLOCK_SIZE: usize = 512;
+#[cfg(feature = "time")]
+const MAX_BLOCK_SIZE: usize = 1 << 16;
+
+/// Returns the nanoseconds per seek over `keys` with seeks `distance` keys
+/// apart, the best of a few rounds.
+#[cfg(feature = "time")]
+fn seek_ns(keys: &[u64], gallop_after: usize, distance: u64) -> f64 {
+    let tuning = Tuning {
+        galloping_threshold: gallop_after,
+        ..Tuning::default()
+    };
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            let mut iter = LinearIterator::with_tuning(keys, &tuning);
+            let mut seeks = 0;
+            while !iter.at_end() {
+                let key = black_box(iter.key());
+                iter.seek(key + distance);
+                seeks += 1;
+            }
+            start.elapsed().as_nanos() as f64 / seeks as f64
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+#[cfg(feature = "time")]
+fn measure_galloping_threshold() -> usize {
+    let keys: Vec<u64> = (0..1 << 14).collect();
+    let mut threshold = 0;
+    for distance in (0..10).map(|i| 1 << i) {
+        if seek_ns(&keys, usize::MAX, distance) > seek_ns(&keys, 0, distance) {
+            break;
+        }
+        threshold = distance as usize;
+    }
+    threshold
+}
+
+/// Returns the nanoseconds per access when chasing pointers around a random
+/// cycle through `len` slots, so that every access depends on the last.
+#[cfg(feature = "time")]
+fn chase_ns(len: usize) -> f64 {
+    // Sattolo's algorithm yields a single cycle.
+    let mut next: Vec<u32> = (0..len as u32).collect();
+    let mut rng = Rng::new(len as u64);
+    for i in (1..len).rev() {
+        next.swap(i, rng.index(i));
+    }
+    const STEPS: usize = 1 << 16;
+    let start = Instant::now();
+    let mut slot = 0;
+    for _ in 0..STEPS {
+        slot = next[slot] as usize;
+    }
+    black_box(slot);
+    start.elapsed().as_nanos() as f64 / STEPS as f64
+}
+
+/// Returns the size of the largest working set, between 16 KiB and 8 MiB,
+/// after which the access latency at most doubles.
+#[cfg(feature = "time")]
+fn measure_cache_bytes() -> usize {
+    let sizes: Vec<usize> = (14..=23).map(|i| 1 << i).collect();
+    let latencies: Vec<f64> = sizes.iter().map(|&bytes| chase_ns(bytes / 4)).collect();
+    let mut cache_bytes = sizes[0];
+    for (i, &bytes) in sizes.iter().enumerate().skip(1) {
+        if latencies[i] > 2.0 * latencies[i - 1] {
+            break;
+        }
+        cache_bytes = bytes;
+    }
+    cache_bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::TrieRelation;
+    use crate::{LeapFrogJoin, LinearIterator, intersect};
+
+    #[test]
+    fn test_overrides() {
+        let tuning = Tuning::default()
+            .with_overrides([("LEAPFROG_BATCH_SIZE", "1024"), ("HOME", "/root")])
+            .unwrap();
+        assert_eq!(tuning.batch_size, 1024);
+        assert_eq!(tuning.block_size, Tuning::default().block_size);
+
+        let error = Tuning::default()
+            .with_overrides([("LEAPFROG_BLOCK_SIZE", "0")])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid value \"0\" for LEAPFROG_BLOCK_SIZE"
+        );
+        assert!(
+            Tuning::default()
+                .with_overrides([("LEAPFROG_PARTITIONS", "x")])
+                .is_err()
+        );
+    }
+
+    #[cfg(all(feature = "fs", feature = "time"))]
+    #[test]
+    fn test_calibrated_at() {
+        let path = std::env::temp_dir().join(format!("leapfrog-tuning-{}", std::process::id()));
+        std::fs::write(&path, "LEAPFROG_BLOCK_SIZE=zero\n").unwrap();
+        let tuning = Tuning::calibrated_at(&path).unwrap();
+        assert!(tuning.block_size.is_power_of_two());
+        assert!((MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&tuning.block_size));
+        assert!(tuning.galloping_threshold < 1 << 10 && tuning.partitions > 0);
+        assert_eq!(Tuning::calibrated_at(&path).unwrap(), tuning);
+
+        let cached = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(cached.starts_with("LEAPFROG_GALLOPING_THRESHOLD="));
+        let read = Tuning::read_from(cached.as_bytes()).unwrap();
+        assert_eq!(read, Ok(tuning));
+    }
+
+    #[test]
+    fn test_tuned_builders() {
+        let a: Vec<u32> = (0..10_000).collect();
+        let b: Vec<u32> = (0..10_000).step_by(97).collect();
+        for threshold in [0, 1, 8, 1000] {
+            let tuning = Tuning {
+                galloping_threshold: threshold,
+                ..Tuning::default()
+            };
+            let mut iter = LinearIterator::with_tuning(&a, &tuning);
+            for seek_key in [0, 1, 5, 300, 9998, 9999] {
+                iter.seek(seek_key);
+                assert_eq!(iter.key(), seek_key);
+            }
+            iter.next();
+            assert!(iter.at_end());
+            let mut iter = LinearIterator::with_tuning(&b, &tuning);
+            iter.seek(98);
+            assert_eq!(iter.key(), 194);
+            iter.seek(10_000);
+            assert!(iter.at_end());
+            let join = LeapFrogJoin::tuned(vec![&a, &b], &tuning);
+            assert_eq!(
+                join.into_keys().collect::<Vec<_>>(),
+                intersect(vec![&a, &b])
+            );
+        }
+
+        let tuning = Tuning {
+            block_size: 100,
+            ..Tuning::default()
+        };
+        let relation = TrieRelation::new(1, a.iter().map(|&k| [k as u64]));
+        let mut bytes = Vec::new();
+        relation.write_tuned(&mut bytes, &tuning).unwrap();
+        let header = persist::read_header(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header.block_keys, 100);
+        assert_eq!(TrieRelation::read_from(bytes.as_slice()).unwrap(), relation);
+    }
+}
@@ -0,0 +1,155 @@
+//! One join input made of several sorted sources.
+
+use crate::Seekable;
+use crate::zonemap::Zone;
+
+/// UnionIterator merges several sorted iterators, e.g. the shards of one
+/// attribute, into a single sorted iterator on the fly. A key contained in
+/// several of them is returned once.
+///
+/// key() is O(1); next() and seek() touch every shard, which is fine for the
+/// small shard counts this is meant for.
+pub struct UnionIterator<I: Seekable> {
+    iters: Vec<I>,
+    key: Option<I::Key>,
+}
+
+impl<I: Seekable> UnionIterator<I> {
+    pub fn new(iters: Vec<I>) -> Self {
+        let mut union = Self { iters, key: None };
+        union.update_key();
+        union
+    }
+
+    pub fn into_iters(self) -> Vec<I> {
+        self.iters
+    }
+
+    fn update_key(&mut self) {
+        self.key = self
+            .iters
+            .iter()
+            .filter(|iter| !iter.at_end())
+            .map(|iter| iter.key())
+            .min();
+    }
+}
+
+impl<I: Seekable> Seekable for UnionIterator<I> {
+    type Key = I::Key;
+
+    fn key(&self) -> I::Key {
+        self.key.expect("Iterator is at end")
+    }
+
+    fn next(&mut self) {
+        let key = self.key();
+        for iter in self.iters.iter_mut() {
+            if !iter.at_end() && iter.key() == key {
+                iter.next();
+            }
+        }
+        self.update_key();
+    }
+
+    fn seek(&mut self, seek_key: I::Key) {
+        assert!(!self.at_end(), "Iterator is at end");
+        for iter in self.iters.iter_mut() {
+            if !iter.at_end() && iter.key() < seek_key {
+                iter.seek(seek_key);
+            }
+        }
+        self.update_key();
+    }
+
+    fn at_end(&self) -> bool {
+        self.key.is_none()
+    }
+
+    /// The zones of all shards, if every shard has a zone map.
+    fn zone_map(&self) -> Option<Vec<Zone<I::Key>>> {
+        let mut zones = Vec::new();
+        for iter in &self.iters {
+            zones.extend(iter.zone_map()?);
+        }
+        zones.sort_by_key(|zone| zone.min);
+        Some(zones)
+    }
+
+    /// The sum of the bounds of all shards, as they may overlap.
+    fn estimate(&self) -> Option<usize> {
+        self.iters.iter().map(|iter| iter.estimate()).sum()
+    }
+
+    fn will_need(&mut self, key: I::Key) {
+        for iter in &mut self.iters {
+            iter.will_need(key);
+        }
+    }
+
+    /// The largest bound of the iterators not at end, if all have one.
+    fn upper_bound(&self) -> Option<I::Key> {
+        let mut bound = None;
+        for iter in self.iters.iter().filter(|iter| !iter.at_end()) {
+            bound = bound.max(Some(iter.upper_bound()?));
+        }
+        bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LeapFrogJoin, LinearIterator, intersect};
+
+    #[test]
+    fn test_union_iterator() {
+        let shard1 = [1, 4, 7, 10];
+        let shard2 = [2, 4, 8];
+        let shard3 = [];
+        let mut union = UnionIterator::new(vec![
+            LinearIterator::new(&shard1),
+            LinearIterator::new(&shard2),
+            LinearIterator::new(&shard3),
+        ]);
+        let mut keys = vec![];
+        while !union.at_end() {
+            keys.push(union.key());
+            union.next();
+        }
+        assert_eq!(keys, vec![1, 2, 4, 7, 8, 10]);
+
+        let mut union = UnionIterator::new(vec![
+            LinearIterator::new(&shard1),
+            LinearIterator::new(&shard2),
+        ]);
+        union.seek(5);
+        assert_eq!(union.key(), 7);
+        union.seek(9);
+        assert_eq!(union.key(), 10);
+        union.seek(11);
+        assert!(union.at_end());
+    }
+
+    #[test]
+    fn test_union_join() {
+        let tab: Vec<i32> = (0..100).filter(|x| x % 3 == 0).collect();
+        let evens: Vec<i32> = (0..100).filter(|x| x % 4 == 0).collect();
+        let odds: Vec<i32> = (0..100).filter(|x| x % 4 == 1).collect();
+        let all: Vec<i32> = (0..100).filter(|x| x % 4 < 2).collect();
+
+        let mut join = LeapFrogJoin::from_iters(vec![
+            UnionIterator::new(vec![LinearIterator::new(&tab)]),
+            UnionIterator::new(vec![
+                LinearIterator::with_zone_map(&odds, 8),
+                LinearIterator::with_zone_map(&evens, 8),
+            ]),
+        ]);
+        let mut result = vec![];
+        while !join.at_end() {
+            result.push(join.key());
+            join.next();
+        }
+        assert_eq!(result, intersect(vec![&tab, &all]));
+    }
+}
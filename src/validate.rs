@@ -0,0 +1,277 @@
+//! Checks of the Seekable contract.
+//!
+//! A join trusts its inputs to be sorted and to seek forward. A source that
+//! breaks this, e.g. one whose seek() lands before the seek key, silently
+//! produces wrong results or keeps search() seeking in circles forever.
+//! ValidatingSource checks every move of a source and panics with what went
+//! wrong and where. Joins check their inputs the same way in debug builds,
+//! without the names and keys ValidatingSource adds to the message.
+
+use std::fmt;
+
+use crate::Seekable;
+use crate::zonemap::Zone;
+
+/// A break of the Seekable contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation<K> {
+    /// next() moved to a smaller key.
+    Decreasing { from: K, to: K },
+    /// seek() moved to a key below the seek key.
+    BeforeSeekKey { seek_key: K, to: K },
+    /// A key is greater than the upper bound the source reported.
+    PastBound { bound: K, key: K },
+}
+
+impl<K> Violation<K> {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Violation::Decreasing { .. } => "next() moved to a smaller key",
+            Violation::BeforeSeekKey { .. } => "seek() moved to a key below the seek key",
+            Violation::PastBound { .. } => "key is greater than the upper bound",
+        }
+    }
+}
+
+impl<K: fmt::Debug> fmt::Display for Violation<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Decreasing { from, to } => {
+                write!(f, "next() moved from {from:?} to {to:?}")
+            }
+            Violation::BeforeSeekKey { seek_key, to } => {
+                write!(f, "seek({seek_key:?}) moved to {to:?}")
+            }
+            Violation::PastBound { bound, key } => {
+                write!(f, "key {key:?} is greater than the upper bound {bound:?}")
+            }
+        }
+    }
+}
+
+/// Checks `iter` after next() moved it away from `from`. Sorted sources may
+/// repeat a key, so next() landing on an equal key is fine.
+pub fn check_next<I: Seekable>(iter: &I, from: I::Key) -> Result<(), Violation<I::Key>> {
+    if iter.at_end() || iter.key() >= from {
+        Ok(())
+    } else {
+        Err(Violation::Decreasing {
+            from,
+            to: iter.key(),
+        })
+    }
+}
+
+/// Checks `iter` after seek(`seek_key`).
+pub fn check_seek<I: Seekable>(iter: &I, seek_key: I::Key) -> Result<(), Violation<I::Key>> {
+    if iter.at_end() || iter.key() >= seek_key {
+        Ok(())
+    } else {
+        Err(Violation::BeforeSeekKey {
+            seek_key,
+            to: iter.key(),
+        })
+    }
+}
+
+/// ValidatingSource wraps a source and panics as soon as it breaks the
+/// Seekable contract, or is used against it. It is meant for sources from
+/// elsewhere, e.g. a third-party backend, and costs a few comparisons per
+/// move.
+pub struct ValidatingSource<I: Seekable> {
+    iter: I,
+    name: String,
+    bound: Option<I::Key>,
+}
+
+impl<I: Seekable<Key: fmt::Debug>> ValidatingSource<I> {
+    /// Wraps `iter`, naming it `name` in panic messages.
+    pub fn new(iter: I, name: &str) -> Self {
+        let bound = if iter.at_end() {
+            None
+        } else {
+            iter.upper_bound()
+        };
+        let source = Self {
+            iter,
+            name: name.to_string(),
+            bound,
+        };
+        source.check_bound();
+        source
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+
+    fn fail(&self, violation: Violation<I::Key>) -> ! {
+        panic!(
+            "Source {} broke the Seekable contract: {violation}",
+            self.name
+        )
+    }
+
+    fn check_bound(&self) {
+        if let Some(bound) = self.bound
+            && !self.iter.at_end()
+            && self.iter.key() > bound
+        {
+            self.fail(Violation::PastBound {
+                bound,
+                key: self.iter.key(),
+            });
+        }
+    }
+
+    fn check_not_at_end(&self, method: &str) {
+        assert!(
+            !self.iter.at_end(),
+            "Source {} is at end, {method}() must not be called",
+            self.name
+        );
+    }
+}
+
+impl<I: Seekable<Key: fmt::Debug>> Seekable for ValidatingSource<I> {
+    type Key = I::Key;
+
+    fn key(&self) -> I::Key {
+        self.check_not_at_end("key");
+        self.iter.key()
+    }
+
+    fn next(&mut self) {
+        self.check_not_at_end("next");
+        let from = self.iter.key();
+        self.iter.next();
+        if let Err(violation) = check_next(&self.iter, from) {
+            self.fail(violation);
+        }
+        self.check_bound();
+    }
+
+    fn seek(&mut self, seek_key: I::Key) {
+        self.check_not_at_end("seek");
+        let key = self.iter.key();
+        assert!(
+            seek_key >= key,
+            "Source {} was sought to {seek_key:?}, before its current key {key:?}",
+            self.name
+        );
+        self.iter.seek(seek_key);
+        if let Err(violation) = check_seek(&self.iter, seek_key) {
+            self.fail(violation);
+        }
+        self.check_bound();
+    }
+
+    fn at_end(&self) -> bool {
+        self.iter.at_end()
+    }
+
+    fn zone_map(&self) -> Option<Vec<Zone<I::Key>>> {
+        self.iter.zone_map()
+    }
+
+    fn estimate(&self) -> Option<usize> {
+        self.iter.estimate()
+    }
+
+    fn exact_len_hint(&self) -> Option<usize> {
+        self.iter.exact_len_hint()
+    }
+
+    fn will_need(&mut self, key: I::Key) {
+        self.iter.will_need(key)
+    }
+
+    fn upper_bound(&self) -> Option<I::Key> {
+        self.check_not_at_end("upper_bound");
+        self.iter.upper_bound()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LeapFrogJoin, LinearIterator};
+
+    /// A broken source: seek() only ever moves by one key.
+    struct Stubborn<'a> {
+        iter: LinearIterator<'a, i32>,
+    }
+
+    impl Seekable for Stubborn<'_> {
+        type Key = i32;
+
+        fn key(&self) -> i32 {
+            self.iter.key()
+        }
+
+        fn next(&mut self) {
+            self.iter.next()
+        }
+
+        fn seek(&mut self, _seek_key: i32) {
+            self.iter.next()
+        }
+
+        fn at_end(&self) -> bool {
+            self.iter.at_end()
+        }
+    }
+
+    #[test]
+    fn test_validating_source() {
+        let keys = [1, 3, 5, 7];
+        let mut source = ValidatingSource::new(LinearIterator::new(&keys), "keys");
+        source.seek(4);
+        assert_eq!(source.key(), 5);
+        let join = LeapFrogJoin::from_iters(vec![
+            source,
+            ValidatingSource::new(LinearIterator::new(&[5, 7, 9]), "others"),
+        ]);
+        assert_eq!(join.into_keys().collect::<Vec<_>>(), [5, 7]);
+
+        let unsorted = [1, 3, 2, 4];
+        let mut source = ValidatingSource::new(LinearIterator::new(&unsorted), "unsorted");
+        source.next();
+        let error = std::panic::catch_unwind(move || source.next()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<String>().unwrap(),
+            "Source unsorted broke the Seekable contract: next() moved from 3 to 2"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let (a, b) = ([1, 1, 2], [1, 2]);
+        let mut source = ValidatingSource::new(LinearIterator::new(&a), "duplicates");
+        source.next();
+        assert_eq!(source.key(), 1);
+        assert_eq!(crate::intersect(vec![&a, &b]), [1, 1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Source stubborn broke the Seekable contract: seek(6) moved to 2")]
+    fn test_validating_source_seek() {
+        let keys = [1, 2, 6];
+        let iter = LinearIterator::new(&keys);
+        ValidatingSource::new(Stubborn { iter }, "stubborn").seek(6);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "seek() moved to a key below the seek key")]
+    fn test_join_checks_inputs() {
+        let (a, b) = ([1, 2, 3, 4, 5, 6, 7, 8, 9], [9]);
+        let stubborn = Stubborn {
+            iter: LinearIterator::new(&a),
+        };
+        let others = Stubborn {
+            iter: LinearIterator::new(&b),
+        };
+        LeapFrogJoin::from_iters(vec![stubborn, others]);
+    }
+}
@@ -0,0 +1,313 @@
+//! Visualization of how the iterators of a join leapfrog over each other.
+//!
+//! visualize() runs a join over slices and records every move of every
+//! iterator. The recording can be rendered as a Graphviz DOT graph, with one
+//! row of key nodes per source and one numbered edge per move, or as a
+//! self-contained SVG "race track", where equal keys of all sources are
+//! aligned in columns and the moves are drawn as arcs jumping along the lanes.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt::{Display, Write};
+use std::rc::Rc;
+
+use crate::{LeapFrogJoin, Seekable};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveKind {
+    Next,
+    Seek,
+}
+
+/// A single move of one iterator from position `from` to position `to` of its
+/// source. A position equal to the source length means "at end".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub step: usize,
+    pub source: usize,
+    pub kind: MoveKind,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// The recorded execution of a join.
+pub struct Visualization<T> {
+    sources: Vec<Vec<T>>,
+    moves: Vec<Move>,
+    matches: Vec<T>,
+}
+
+/// Iterator over a slice that reports its moves to a shared log.
+struct TrackedIterator<'a, T> {
+    source: &'a [T],
+    index: usize,
+    pos: usize,
+    log: Rc<RefCell<Vec<Move>>>,
+}
+
+impl<T> TrackedIterator<'_, T> {
+    fn record(&self, kind: MoveKind, from: usize) {
+        let mut log = self.log.borrow_mut();
+        let step = log.len() + 1;
+        log.push(Move {
+            step,
+            source: self.index,
+            kind,
+            from,
+            to: self.pos,
+        });
+    }
+}
+
+impl<T: Ord + Copy> Seekable for TrackedIterator<'_, T> {
+    type Key = T;
+
+    fn key(&self) -> T {
+        assert!(!self.at_end(), "Iterator is at end");
+        self.source[self.pos]
+    }
+
+    fn next(&mut self) {
+        assert!(!self.at_end(), "Iterator is at end");
+        let from = self.pos;
+        self.pos += 1;
+        self.record(MoveKind::Next, from);
+    }
+
+    fn seek(&mut self, seek_key: T) {
+        assert!(!self.at_end(), "Iterator is at end");
+        let from = self.pos;
+        while !self.at_end() && self.source[self.pos] < seek_key {
+            self.pos += 1;
+        }
+        self.record(MoveKind::Seek, from);
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.source.len()
+    }
+}
+
+/// Runs the join over `sources` to completion and records all moves.
+pub fn visualize<T: Ord + Copy>(sources: Vec<&[T]>) -> Visualization<T> {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let iters = sources
+        .iter()
+        .enumerate()
+        .map(|(index, &source)| TrackedIterator {
+            source,
+            index,
+            pos: 0,
+            log: Rc::clone(&log),
+        })
+        .collect();
+    let mut join = LeapFrogJoin::from_iters(iters);
+    let mut matches = Vec::new();
+    while !join.at_end() {
+        matches.push(join.key());
+        join.next();
+    }
+    Visualization {
+        sources: sources.iter().map(|s| s.to_vec()).collect(),
+        moves: log.take(),
+        matches,
+    }
+}
+
+impl<T: Ord + Copy + Display> Visualization<T> {
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn matches(&self) -> &[T] {
+        &self.matches
+    }
+
+    /// Renders the recording as a Graphviz digraph.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph leapfrog {{").unwrap();
+        writeln!(dot, "  rankdir=LR;").unwrap();
+        writeln!(dot, "  node [shape=circle];").unwrap();
+        for (s, source) in self.sources.iter().enumerate() {
+            writeln!(dot, "  subgraph cluster_{s} {{").unwrap();
+            writeln!(dot, "    label=\"source {s}\";").unwrap();
+            for (p, key) in source.iter().enumerate() {
+                let style = if self.matches.binary_search(key).is_ok() {
+                    ", style=filled, fillcolor=palegreen"
+                } else {
+                    ""
+                };
+                writeln!(dot, "    s{s}_{p} [label=\"{key}\"{style}];").unwrap();
+            }
+            writeln!(dot, "    s{s}_{} [label=\"end\", shape=box];", source.len()).unwrap();
+            let chain: Vec<String> = (0..=source.len()).map(|p| format!("s{s}_{p}")).collect();
+            writeln!(dot, "    {} [style=invis];", chain.join(" -> ")).unwrap();
+            writeln!(dot, "  }}").unwrap();
+        }
+        for m in &self.moves {
+            let (verb, color) = match m.kind {
+                MoveKind::Next => ("next", "gray40"),
+                MoveKind::Seek => ("seek", "blue"),
+            };
+            writeln!(
+                dot,
+                "  s{s}_{f} -> s{s}_{t} [label=\"{step}: {verb}\", color={color}, constraint=false];",
+                s = m.source,
+                f = m.from,
+                t = m.to,
+                step = m.step
+            )
+            .unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// Renders the recording as an SVG race track.
+    pub fn to_svg(&self) -> String {
+        const COLUMN: usize = 48;
+        const LANE: usize = 90;
+        const MARGIN: usize = 80;
+
+        // One column per distinct key, plus one for "at end".
+        let columns: Vec<T> = self
+            .sources
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<BTreeSet<T>>()
+            .into_iter()
+            .collect();
+        let x_of = |source: &[T], pos: usize| {
+            let column = match source.get(pos) {
+                Some(key) => columns.binary_search(key).unwrap(),
+                None => columns.len(),
+            };
+            MARGIN + column * COLUMN + COLUMN / 2
+        };
+        let y_of = |lane: usize| LANE * lane + LANE * 2 / 3;
+        let width = MARGIN + (columns.len() + 1) * COLUMN + 20;
+        let height = LANE * self.sources.len() + 10;
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" font-family=\"sans-serif\" font-size=\"12\">"
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            "<defs><marker id=\"arrow\" markerWidth=\"8\" markerHeight=\"8\" refX=\"6\" refY=\"3\" orient=\"auto\"><path d=\"M0,0 L6,3 L0,6 z\" fill=\"context-stroke\"/></marker></defs>"
+        )
+        .unwrap();
+        for key in &self.matches {
+            let column = columns.binary_search(key).unwrap();
+            writeln!(
+                svg,
+                "<rect x=\"{}\" y=\"0\" width=\"{COLUMN}\" height=\"{height}\" fill=\"palegreen\" opacity=\"0.5\"/>",
+                MARGIN + column * COLUMN
+            )
+            .unwrap();
+        }
+        for (s, source) in self.sources.iter().enumerate() {
+            let y = y_of(s);
+            writeln!(svg, "<text x=\"8\" y=\"{}\">source {s}</text>", y + 4).unwrap();
+            writeln!(
+                svg,
+                "<line x1=\"{MARGIN}\" y1=\"{y}\" x2=\"{}\" y2=\"{y}\" stroke=\"lightgray\"/>",
+                width - 20
+            )
+            .unwrap();
+            for (p, key) in source.iter().enumerate() {
+                let x = x_of(source, p);
+                writeln!(
+                    svg,
+                    "<circle cx=\"{x}\" cy=\"{y}\" r=\"12\" fill=\"white\" stroke=\"black\"/><text x=\"{x}\" y=\"{}\" text-anchor=\"middle\">{key}</text>",
+                    y + 4
+                )
+                .unwrap();
+            }
+            let x = x_of(source, source.len());
+            writeln!(
+                svg,
+                "<rect x=\"{}\" y=\"{}\" width=\"24\" height=\"24\" fill=\"white\" stroke=\"black\"/><text x=\"{x}\" y=\"{}\" text-anchor=\"middle\">end</text>",
+                x - 12,
+                y - 12,
+                y + 4
+            )
+            .unwrap();
+        }
+        for m in &self.moves {
+            let source = &self.sources[m.source];
+            let (x1, x2) = (x_of(source, m.from), x_of(source, m.to));
+            let y = y_of(m.source) - 12;
+            let lift = 14 + (x2 - x1) / 6;
+            let color = match m.kind {
+                MoveKind::Next => "gray",
+                MoveKind::Seek => "blue",
+            };
+            writeln!(
+                svg,
+                "<path d=\"M{x1},{y} Q{},{} {x2},{y}\" fill=\"none\" stroke=\"{color}\" marker-end=\"url(#arrow)\"/><text x=\"{}\" y=\"{}\" fill=\"{color}\" text-anchor=\"middle\" font-size=\"10\">{}</text>",
+                (x1 + x2) / 2,
+                y.saturating_sub(lift),
+                (x1 + x2) / 2,
+                y.saturating_sub(lift / 2 + 2),
+                m.step
+            )
+            .unwrap();
+        }
+        writeln!(svg, "</svg>").unwrap();
+        svg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Visualization<i32> {
+        let tab1 = [0, 1, 3, 4, 5, 6, 7, 8, 9, 11];
+        let tab2 = [0, 2, 6, 7, 8, 9, 11];
+        let tab3 = [2, 4, 5, 8, 10];
+        visualize(vec![&tab1[..], &tab2[..], &tab3[..]])
+    }
+
+    #[test]
+    fn test_visualize_records_moves() {
+        let vis = example();
+        assert_eq!(vis.matches(), &[8]);
+        let moves = vis.moves();
+        assert!(!moves.is_empty());
+        for (i, m) in moves.iter().enumerate() {
+            assert_eq!(m.step, i + 1);
+            assert!(m.from <= m.to);
+        }
+        // The join ends when one iterator runs off its source.
+        let last = moves.last().unwrap();
+        assert_eq!(last.to, [10, 7, 5][last.source]);
+    }
+
+    #[test]
+    fn test_visualize_dot() {
+        let dot = example().to_dot();
+        assert!(dot.starts_with("digraph leapfrog {"));
+        assert!(dot.contains("subgraph cluster_2"));
+        assert!(dot.contains("s0_7 [label=\"8\", style=filled, fillcolor=palegreen];"));
+        assert_eq!(
+            dot.matches("constraint=false").count(),
+            example().moves().len()
+        );
+    }
+
+    #[test]
+    fn test_visualize_svg() {
+        let vis = example();
+        let svg = vis.to_svg();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<circle").count(), 10 + 7 + 5);
+        assert_eq!(svg.matches("marker-end").count(), vis.moves().len());
+    }
+}
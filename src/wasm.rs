@@ -0,0 +1,47 @@
+//! wasm-bindgen wrappers for running the join in the browser.
+//!
+//! Inputs are passed as typed arrays and results come back as a typed array of
+//! the same element type, e.g. from JavaScript:
+//!
+//! ```js
+//! const common = intersectInt32([new Int32Array([1, 2, 3]), new Int32Array([2, 3, 4])]);
+//! ```
+//!
+//! wasm32-unknown-unknown has no file system, threads or clock, so build
+//! without the default features, which use them:
+//!
+//! ```sh
+//! cargo build --target wasm32-unknown-unknown --no-default-features --features wasm
+//! ```
+//!
+//! That leaves out what needs them, e.g. Database::build_in_background(),
+//! prefetching of IndexSources, tracing and calibration, and spilling fails
+//! with io::ErrorKind::Unsupported.
+
+use js_sys::{BigInt64Array, Int32Array, Uint32Array};
+use wasm_bindgen::prelude::*;
+
+use crate::try_intersect;
+
+/// Intersects strictly ascending Int32Arrays.
+#[wasm_bindgen(js_name = intersectInt32)]
+pub fn intersect_i32(sources: Vec<Int32Array>) -> Result<Vec<i32>, JsError> {
+    intersect_sorted(sources.iter().map(Int32Array::to_vec).collect())
+}
+
+/// Intersects strictly ascending Uint32Arrays.
+#[wasm_bindgen(js_name = intersectUint32)]
+pub fn intersect_u32(sources: Vec<Uint32Array>) -> Result<Vec<u32>, JsError> {
+    intersect_sorted(sources.iter().map(Uint32Array::to_vec).collect())
+}
+
+/// Intersects strictly ascending BigInt64Arrays.
+#[wasm_bindgen(js_name = intersectBigInt64)]
+pub fn intersect_i64(sources: Vec<BigInt64Array>) -> Result<Vec<i64>, JsError> {
+    intersect_sorted(sources.iter().map(BigInt64Array::to_vec).collect())
+}
+
+fn intersect_sorted<T: Ord + Copy>(sources: Vec<Vec<T>>) -> Result<Vec<T>, JsError> {
+    try_intersect(sources.iter().map(Vec::as_slice).collect())
+        .map_err(|e| JsError::new(&e.to_string()))
+}
@@ -0,0 +1,146 @@
+//! Zone maps: the key range (min and max) of every block of a source.
+//!
+//! Sources publish their zone map through Seekable::zone_map(). Before
+//! leapfrogging, the join intersects the zone maps of all inputs that have
+//! one into a list of live ranges; a key outside of them is missing from at
+//! least one input, so the join jumps from gap to the start of the next live
+//! range instead of bouncing seeks between the inputs.
+//!
+//! Of the backends in this crate, LinearIterator publishes zone maps (see
+//! LinearIterator::with_zone_map()). RemoteIterator does not, as a remote
+//! source would need a request of its own to describe its blocks.
+
+/// The key range of one block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Zone<K> {
+    pub min: K,
+    pub max: K,
+}
+
+/// Computes the zone map of a sorted slice with `block_size` keys per block.
+pub fn zones_of_sorted<T: Copy>(sorted: &[T], block_size: usize) -> Vec<Zone<T>> {
+    sorted
+        .chunks(block_size)
+        .map(|block| Zone {
+            min: block[0],
+            max: block[block.len() - 1],
+        })
+        .collect()
+}
+
+/// Returns the key ranges that are covered by a zone in each of the zone
+/// maps, in ascending order and without overlaps.
+pub fn live_ranges<K: Ord + Copy>(zone_maps: &[Vec<Zone<K>>]) -> Vec<Zone<K>> {
+    let mut maps = zone_maps.iter().map(|zones| merge(zones));
+    let Some(mut live) = maps.next() else {
+        return Vec::new();
+    };
+    for zones in maps {
+        live = intersect(&live, &zones);
+    }
+    live
+}
+
+/// Sorts the zones and merges overlapping ones.
+fn merge<K: Ord + Copy>(zones: &[Zone<K>]) -> Vec<Zone<K>> {
+    let mut zones = zones.to_vec();
+    zones.sort_by_key(|zone| zone.min);
+    let mut merged: Vec<Zone<K>> = Vec::with_capacity(zones.len());
+    for zone in zones {
+        match merged.last_mut() {
+            Some(last) if zone.min <= last.max => last.max = last.max.max(zone.max),
+            _ => merged.push(zone),
+        }
+    }
+    merged
+}
+
+/// Intersects two sorted lists of disjoint ranges.
+fn intersect<K: Ord + Copy>(a: &[Zone<K>], b: &[Zone<K>]) -> Vec<Zone<K>> {
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let min = a[i].min.max(b[j].min);
+        let max = a[i].max.min(b[j].max);
+        if min <= max {
+            result.push(Zone { min, max });
+        }
+        if a[i].max < b[j].max {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "time")]
+    use crate::{LeapFrogJoin, intersect, trace::Tracer};
+    use crate::{LinearIterator, Seekable};
+
+    fn zone(min: i32, max: i32) -> Zone<i32> {
+        Zone { min, max }
+    }
+
+    #[test]
+    fn test_zones_of_sorted() {
+        let keys = [1, 2, 3, 5, 8, 13, 21];
+        assert_eq!(
+            zones_of_sorted(&keys, 3),
+            vec![zone(1, 3), zone(5, 13), zone(21, 21)]
+        );
+        let iter = LinearIterator::with_zone_map(&keys, 4);
+        assert_eq!(iter.zone_map(), Some(vec![zone(1, 5), zone(8, 21)]));
+        assert_eq!(LinearIterator::new(&keys).zone_map(), None);
+    }
+
+    #[test]
+    fn test_live_ranges() {
+        let a = vec![zone(0, 10), zone(10, 20), zone(40, 50)];
+        let b = vec![zone(5, 15), zone(18, 45)];
+        assert_eq!(
+            live_ranges(&[a.clone(), b]),
+            vec![zone(5, 15), zone(18, 20), zone(40, 45)]
+        );
+        assert_eq!(live_ranges(&[a, vec![zone(25, 30)]]), vec![]);
+        assert_eq!(live_ranges::<i32>(&[]), vec![]);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_join_prunes_blocks() {
+        // Clusters which only overlap at their edges, or not at all.
+        let tab1: Vec<i32> = (0..1000).chain(3000..3100).chain(5000..6000).collect();
+        let tab2: Vec<i32> = (900..1900).step_by(2).chain(5990..7000).collect();
+        let expected = intersect(vec![&tab1, &tab2]);
+
+        let run = |zones: bool| {
+            let tracer = Tracer::new();
+            let iter = |s| {
+                if zones {
+                    LinearIterator::with_zone_map(s, 100)
+                } else {
+                    LinearIterator::new(s)
+                }
+            };
+            let mut join = LeapFrogJoin::from_iters(vec![
+                tracer.wrap("tab1", iter(&tab1)),
+                tracer.wrap("tab2", iter(&tab2)),
+            ]);
+            let mut result = vec![];
+            while !join.at_end() {
+                result.push(join.key());
+                join.next();
+            }
+            (result, tracer.len())
+        };
+        let (plain, plain_ops) = run(false);
+        let (pruned, pruned_ops) = run(true);
+        assert_eq!(plain, expected);
+        assert_eq!(pruned, expected);
+        assert!(pruned_ops < plain_ops, "{pruned_ops} >= {plain_ops}");
+    }
+}